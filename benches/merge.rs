@@ -0,0 +1,138 @@
+//! Times merges of a large document that each branch changed in one place,
+//! repeated merges of many branches against one base, parsing the base for
+//! each merge or once, and reading a large input file as text or as bytes, as
+//! with `--read-bytes`. Run with `cargo bench`.
+
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::Instant;
+use three_way_merge::merge::{
+    merge_strings, merge_strings_with_base, three_way_merge_with_options, MergeOptions,
+};
+
+// An object tree `depth` levels deep with `fanout` children per object.
+fn tree(depth: usize, fanout: usize) -> Value {
+    if depth == 0 {
+        return json!("leaf");
+    }
+    let mut map = Map::new();
+    for index in 0..fanout {
+        map.insert(format!("key{}", index), tree(depth - 1, fanout));
+    }
+    Value::Object(map)
+}
+
+// Sets the leaf reached by following the last key `depth` times.
+fn change_last_leaf(document: &mut Value, depth: usize, fanout: usize, value: &str) {
+    let path: String = (0..depth).map(|_| format!("/key{}", fanout - 1)).collect();
+    *document.pointer_mut(&path).unwrap() = json!(value);
+}
+
+fn bench(name: &str, base: &Value, a: &Value, b: &Value, iterations: u32) {
+    let opts = MergeOptions::default();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        black_box(three_way_merge_with_options(base, a, b, &opts));
+    }
+    println!("{}: {:?} per merge", name, start.elapsed() / iterations);
+}
+
+// Merges `branches` against `base` as text, parsing base for every merge, then
+// with base parsed once and shared.
+fn bench_base_reuse(base: &Value, branches: &[String]) {
+    let opts = MergeOptions::default();
+    let base_str = base.to_string();
+    let b_str = base_str.clone();
+    let start = Instant::now();
+    for a_str in branches {
+        black_box(merge_strings(&base_str, a_str, &b_str, &opts).unwrap());
+    }
+    println!(
+        "{} branches, base parsed each time: {:?} per merge",
+        branches.len(),
+        start.elapsed() / branches.len() as u32
+    );
+
+    let start = Instant::now();
+    let shared = Arc::new(serde_json::from_str::<Value>(&base_str).unwrap());
+    for a_str in branches {
+        black_box(merge_strings_with_base(&shared, a_str, &b_str, &opts).unwrap());
+    }
+    println!(
+        "{} branches, base parsed once: {:?} per merge",
+        branches.len(),
+        start.elapsed() / branches.len() as u32
+    );
+}
+
+// Reads and parses `document` from a file, as text and then from its bytes.
+fn bench_reading(document: &Value, iterations: u32) {
+    let path = std::env::temp_dir().join(format!("twm-bench-{}.json", std::process::id()));
+    fs::write(&path, serde_json::to_string_pretty(document).unwrap()).unwrap();
+    let size = fs::metadata(&path).unwrap().len();
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let text = fs::read_to_string(&path).unwrap();
+        black_box(serde_json::from_str::<Value>(&text).unwrap());
+    }
+    println!(
+        "{} bytes, read as text: {:?} per read",
+        size,
+        start.elapsed() / iterations
+    );
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        let bytes = fs::read(&path).unwrap();
+        black_box(serde_json::from_slice::<Value>(&bytes).unwrap());
+    }
+    println!(
+        "{} bytes, read as bytes: {:?} per read",
+        size,
+        start.elapsed() / iterations
+    );
+    fs::remove_file(&path).unwrap();
+}
+
+fn main() {
+    // The last is a single object with 200,000 keys.
+    for (depth, fanout) in [(6, 6), (12, 2), (3, 40), (1, 200_000)] {
+        let base = tree(depth, fanout);
+        let mut a = base.clone();
+        let mut b = base.clone();
+        change_last_leaf(&mut a, depth, fanout, "changed in a");
+        // B changes a sibling of the leaf A changed, so both branches changed
+        // every object on the way down.
+        let mut sibling_path: Vec<String> =
+            (0..depth).map(|_| format!("key{}", fanout - 1)).collect();
+        *sibling_path.last_mut().unwrap() = "key0".to_string();
+        *b.pointer_mut(&format!("/{}", sibling_path.join("/")))
+            .unwrap() = json!("changed in b");
+
+        bench(
+            &format!("depth {}, fanout {}", depth, fanout),
+            &base,
+            &a,
+            &b,
+            20,
+        );
+    }
+
+    // Each branch changes one leaf of its own.
+    let (depth, fanout) = (3, 40);
+    let base = tree(depth, fanout);
+    let branches: Vec<String> = (0..50)
+        .map(|index| {
+            let mut a = base.clone();
+            *a.pointer_mut(&format!("/key{}/key0/key0", index % fanout))
+                .unwrap() = json!(format!("changed in branch {}", index));
+            a.to_string()
+        })
+        .collect();
+    bench_base_reuse(&base, &branches);
+
+    bench_reading(&tree(1, 200_000), 10);
+}
@@ -0,0 +1,58 @@
+//! Feeds arbitrary bytes to `merge_strings` as the three inputs, with options
+//! picked by the first byte. The merge must never panic, and must either fail
+//! with a parse error or produce valid JSON.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use three_way_merge::merge::{
+    merge_strings, ArrayMergeMode, ArrayObjectMismatch, ConflictStrategy, KeyScope, MergeOptions,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let Some((&flags, rest)) = data.split_first() else {
+        return;
+    };
+    let opts = options(flags);
+
+    // The inputs are separated by NUL bytes; missing ones are empty.
+    let mut inputs = rest.splitn(3, |&byte| byte == 0);
+    let mut next = || String::from_utf8_lossy(inputs.next().unwrap_or_default()).into_owned();
+    let (base, a, b) = (next(), next(), next());
+
+    if let Ok((merged, conflicts)) = merge_strings(&base, &a, &b, &opts) {
+        serde_json::from_str::<serde_json::Value>(&merged).expect("merge output is not JSON");
+        for conflict in &conflicts {
+            assert!(conflict.path.is_empty() || conflict.path.starts_with('/'));
+        }
+    }
+});
+
+fn options(flags: u8) -> MergeOptions {
+    MergeOptions {
+        array_mode: match flags & 0b11 {
+            0 => ArrayMergeMode::Atomic,
+            1 => ArrayMergeMode::Positional,
+            _ => ArrayMergeMode::Similarity { threshold: 0.5 },
+        },
+        key_scope: match (flags >> 2) & 0b11 {
+            0 => KeyScope::Union,
+            1 => KeyScope::Base,
+            2 => KeyScope::Intersection,
+            _ => KeyScope::ValuesOnly,
+        },
+        strategy: if flags & 0b1_0000 != 0 {
+            ConflictStrategy::Theirs
+        } else {
+            ConflictStrategy::Manual
+        },
+        record_key: (flags & 0b10_0000 != 0).then(|| "id".to_string()),
+        array_object_mismatch: if flags & 0b100_0000 != 0 {
+            ArrayObjectMismatch::TakeA
+        } else {
+            ArrayObjectMismatch::Conflict
+        },
+        treat_empty_string_as_absent: flags & 0b1000_0000 != 0,
+        ..Default::default()
+    }
+}
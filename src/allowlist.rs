@@ -0,0 +1,86 @@
+//! Allowlists of keys, for locked-down documents that must not pick up fields
+//! from a branch: every key of the merged document has to be one the list
+//! permits.
+
+use crate::pattern::PathPattern;
+use crate::pointer;
+use serde_json::Value;
+
+/// The paths of the keys in `document` that no pattern of `allowed` matches,
+/// in document order. Keys of objects inside arrays are paths like
+/// `/users/0/name`, which `/users/*/name` covers, and `/config/**` allows a
+/// whole subtree. Below a key that is not allowed nothing more is reported.
+pub fn disallowed(document: &Value, allowed: &[PathPattern]) -> Vec<String> {
+    let mut paths = Vec::new();
+    collect(document, "", allowed, &mut paths);
+    paths
+}
+
+fn collect(value: &Value, path: &str, allowed: &[PathPattern], paths: &mut Vec<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let child_path = pointer::push(path, key);
+                if allowed.iter().any(|pattern| pattern.matches(&child_path)) {
+                    collect(child, &child_path, allowed, paths);
+                } else {
+                    paths.push(child_path);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                collect(
+                    item,
+                    &pointer::push(path, &index.to_string()),
+                    allowed,
+                    paths,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::three_way_merge;
+    use crate::pattern::parse_pattern_list;
+    use serde_json::json;
+
+    #[test]
+    fn test_branch_introduces_disallowed_key() {
+        let allowed = parse_pattern_list(
+            "# the service config\n/name\n/server\n/server/port\n/users\n/users/*/name\n/extra/**\n",
+        )
+        .unwrap();
+        let base = json!({
+            "name": "svc",
+            "server": {"port": 80},
+            "users": [{"name": "ann"}],
+            "extra": {"anything": {"goes": 1}}
+        });
+        let a = json!({
+            "name": "svc",
+            "server": {"port": 8080},
+            "users": [{"name": "ann"}],
+            "extra": {"anything": {"goes": 1}}
+        });
+        let b = json!({
+            "name": "svc",
+            "server": {"port": 80, "debug": {"level": 3}},
+            "users": [{"name": "ann", "admin": true}],
+            "extra": {"anything": {"goes": 1}},
+            "backdoor": true
+        });
+        assert!(disallowed(&base, &allowed).is_empty());
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert!(!had_conflicts);
+        assert_eq!(
+            disallowed(&merged, &allowed),
+            vec!["/backdoor", "/server/debug", "/users/0/admin"]
+        );
+        assert_eq!(disallowed(&json!([1, {"x": 2}]), &[]), vec!["/1/x"]);
+    }
+}
@@ -0,0 +1,219 @@
+//! Where each value of a document sits in its source text, so that conflicts
+//! can be reported by line and column in each input.
+
+use crate::pointer;
+use std::collections::HashMap;
+
+/// The line and column, both counted from 1, at which each value of a JSON text
+/// starts, by path.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Positions(HashMap<String, (usize, usize)>);
+
+impl Positions {
+    /// Scans `text`, which must be valid JSON. Columns count characters, and
+    /// for a key given more than once the last one is used, as when parsing.
+    pub fn of(text: &str) -> Positions {
+        let mut scanner = Scanner {
+            text,
+            bytes: text.as_bytes(),
+            position: 0,
+            offsets: Vec::new(),
+        };
+        // Invalid JSON gives as many positions as could be read before it.
+        let _ = scanner.value(String::new());
+
+        let line_starts: Vec<usize> = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+        let positions = scanner
+            .offsets
+            .into_iter()
+            .map(|(path, offset)| {
+                let line = line_starts.partition_point(|start| *start <= offset);
+                let column = text[line_starts[line - 1]..offset].chars().count() + 1;
+                (path, (line, column))
+            })
+            .collect();
+        Positions(positions)
+    }
+
+    pub fn get(&self, path: &str) -> Option<(usize, usize)> {
+        self.0.get(path).copied()
+    }
+}
+
+/// Where the value at `path` is in each input, given by name with its
+/// positions, such as `/port: base.json:3:11, a.json:3:11, absent from b.json`.
+pub fn describe(path: &str, inputs: &[(&str, &Positions)]) -> String {
+    let places: Vec<String> = inputs
+        .iter()
+        .map(|(name, positions)| match positions.get(path) {
+            Some((line, column)) => format!("{}:{}:{}", name, line, column),
+            None => format!("absent from {}", name),
+        })
+        .collect();
+    let location = if path.is_empty() { "/" } else { path };
+    format!("{}: {}", location, places.join(", "))
+}
+
+struct Scanner<'a> {
+    text: &'a str,
+    bytes: &'a [u8],
+    position: usize,
+    offsets: Vec<(String, usize)>,
+}
+
+impl Scanner<'_> {
+    fn value(&mut self, path: String) -> Option<()> {
+        self.skip_whitespace();
+        self.offsets.push((path.clone(), self.position));
+        match self.bytes.get(self.position)? {
+            b'{' => {
+                self.position += 1;
+                if self.close(b'}') {
+                    return Some(());
+                }
+                loop {
+                    self.skip_whitespace();
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(b':')?;
+                    self.value(pointer::push(&path, &key))?;
+                    if !self.separator(b'}')? {
+                        return Some(());
+                    }
+                }
+            }
+            b'[' => {
+                self.position += 1;
+                if self.close(b']') {
+                    return Some(());
+                }
+                for index in 0.. {
+                    self.value(pointer::push(&path, &index.to_string()))?;
+                    if !self.separator(b']')? {
+                        break;
+                    }
+                }
+                Some(())
+            }
+            b'"' => self.string().map(|_| ()),
+            _ => {
+                // A number or literal runs until a delimiter.
+                while self
+                    .bytes
+                    .get(self.position)
+                    .is_some_and(|byte| !b",}] \t\r\n".contains(byte))
+                {
+                    self.position += 1;
+                }
+                Some(())
+            }
+        }
+    }
+
+    // After a member: whether another follows, or `None` if neither a comma
+    // nor `close` does.
+    fn separator(&mut self, close: u8) -> Option<bool> {
+        self.skip_whitespace();
+        if self.close(close) {
+            return Some(false);
+        }
+        self.expect(b',')?;
+        Some(true)
+    }
+
+    fn close(&mut self, close: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.bytes.get(self.position) == Some(&close);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, byte: u8) -> Option<()> {
+        (self.bytes.get(self.position) == Some(&byte)).then(|| self.position += 1)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let start = self.position;
+        self.expect(b'"')?;
+        while *self.bytes.get(self.position)? != b'"' {
+            self.position += if self.bytes[self.position] == b'\\' {
+                2
+            } else {
+                1
+            };
+        }
+        self.position += 1;
+        serde_json::from_str(self.text.get(start..self.position)?).ok()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.position)
+            .is_some_and(|byte| byte.is_ascii_whitespace())
+        {
+            self.position += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURE: &str = r#"{
+  "name": "svc",
+  "server": {
+    "port": 8080,
+    "hosts": ["a", "é",
+      {"x": null}]
+  },
+  "a/b": true, "empty": {}, "none": []
+}
+"#;
+
+    #[test]
+    fn test_positions() {
+        let positions = Positions::of(FIXTURE);
+        let cases = [
+            ("", (1, 1)),
+            ("/name", (2, 11)),
+            ("/server", (3, 13)),
+            ("/server/port", (4, 13)),
+            ("/server/hosts", (5, 14)),
+            ("/server/hosts/1", (5, 20)),
+            ("/server/hosts/2", (6, 7)),
+            ("/server/hosts/2/x", (6, 13)),
+            ("/a~1b", (8, 10)),
+            ("/empty", (8, 25)),
+            ("/none", (8, 37)),
+        ];
+        for (path, position) in cases {
+            assert_eq!(positions.get(path), Some(position), "{}", path);
+        }
+        assert_eq!(positions.get("/missing"), None);
+        assert_eq!(Positions::of("[1, 2]").get("/1"), Some((1, 5)));
+        assert_eq!(
+            Positions::of("{\"a\": 1, \"a\": 2}").get("/a"),
+            Some((1, 15))
+        );
+    }
+
+    #[test]
+    fn test_describe() {
+        let base = Positions::of("{\n  \"port\": 80\n}");
+        let a = Positions::of("{\"port\": 81}");
+        let b = Positions::of("{}");
+        assert_eq!(
+            describe(
+                "/port",
+                &[("base.json", &base), ("a.json", &a), ("b.json", &b)]
+            ),
+            "/port: base.json:2:11, a.json:1:10, absent from b.json"
+        );
+    }
+}
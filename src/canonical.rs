@@ -0,0 +1,184 @@
+//! Canonical JSON as specified by RFC 8785 (the JSON Canonicalization Scheme),
+//! for outputs that are hashed or signed: equal documents always serialize to
+//! the same bytes.
+
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// Serializes `value` as canonical JSON: no whitespace, object keys sorted by
+/// their UTF-16 code units, strings with only the escapes JSON requires, and
+/// numbers written as ECMAScript writes doubles, so `1.50` is `1.5`, `1e21` is
+/// `1e+21` and integers beyond 2^53 lose precision as doubles do.
+pub fn to_string(value: &Value) -> String {
+    let mut out = String::new();
+    write_value(value, &mut out);
+    out
+}
+
+fn write_value(value: &Value, out: &mut String) {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Bool(flag) => out.push_str(if *flag { "true" } else { "false" }),
+        Value::Number(number) => out.push_str(&number_to_string(number.as_f64().unwrap_or(0.0))),
+        Value::String(text) => write_string(text, out),
+        Value::Array(items) => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_value(item, out);
+            }
+            out.push(']');
+        }
+        Value::Object(map) => {
+            let mut entries: Vec<(&String, &Value)> = map.iter().collect();
+            entries.sort_by(|(x, _), (y, _)| utf16_cmp(x, y));
+            out.push('{');
+            for (index, (key, val)) in entries.into_iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                write_string(key, out);
+                out.push(':');
+                write_value(val, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+fn utf16_cmp(x: &str, y: &str) -> Ordering {
+    x.encode_utf16().cmp(y.encode_utf16())
+}
+
+fn write_string(text: &str, out: &mut String) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// ECMAScript's Number.prototype.toString, from the shortest digits that
+// round-trip, which Rust's exponent formatting gives.
+fn number_to_string(number: f64) -> String {
+    if number == 0.0 {
+        return "0".to_string();
+    }
+    let sign = if number < 0.0 { "-" } else { "" };
+    let scientific = format!("{:e}", number.abs());
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits: String = mantissa.chars().filter(|c| *c != '.').collect();
+    // The value is 0.<digits> * 10^point.
+    let point = exponent.parse::<i32>().unwrap_or(0) + 1;
+    let count = digits.len() as i32;
+
+    let body = if count <= point && point <= 21 {
+        format!("{}{}", digits, "0".repeat((point - count) as usize))
+    } else if 0 < point && point <= 21 {
+        format!(
+            "{}.{}",
+            &digits[..point as usize],
+            &digits[point as usize..]
+        )
+    } else if -6 < point && point <= 0 {
+        format!("0.{}{}", "0".repeat(-point as usize), digits)
+    } else {
+        let exponent = point - 1;
+        let exponent_sign = if exponent < 0 { "-" } else { "+" };
+        let fraction = if count == 1 {
+            String::new()
+        } else {
+            format!(".{}", &digits[1..])
+        };
+        format!(
+            "{}{}e{}{}",
+            &digits[..1],
+            fraction,
+            exponent_sign,
+            exponent.abs()
+        )
+    };
+    format!("{}{}", sign, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_numbers() {
+        // Vectors from RFC 8785, appendix B.
+        let cases = [
+            (0.0, "0"),
+            (-0.0, "0"),
+            (5e-324, "5e-324"),
+            (-5e-324, "-5e-324"),
+            (1.7976931348623157e308, "1.7976931348623157e+308"),
+            (9007199254740992.0, "9007199254740992"),
+            (-9007199254740992.0, "-9007199254740992"),
+            (295147905179352830000.0, "295147905179352830000"),
+            (9.999999999999997e22, "9.999999999999997e+22"),
+            (1e23, "1e+23"),
+            (1e21, "1e+21"),
+            (999999999999999700000.0, "999999999999999700000"),
+            (1e-7, "1e-7"),
+            (0.000001, "0.000001"),
+            (333333333.3333332, "333333333.3333332"),
+            (4.5, "4.5"),
+            (0.002, "0.002"),
+            (1e30, "1e+30"),
+        ];
+        for (number, expected) in cases {
+            assert_eq!(number_to_string(number), expected, "{:e}", number);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::excessive_precision)]
+    fn test_to_string() {
+        // The example of RFC 8785, section 3.2.2. The numbers are written as
+        // Rust literals, as serde_json parses some floats one unit off.
+        let mut value: Value = serde_json::from_str(
+            r#"{
+                "string": "\u20ac$\u000F\u000aA'\u0042\u0022\u005c\\\"\/",
+                "literals": [null, true, false]
+            }"#,
+        )
+        .unwrap();
+        value["numbers"] = json!([
+            333333333.33333329,
+            1E30,
+            4.50,
+            2e-3,
+            0.000000000000000000000000001
+        ]);
+        assert_eq!(
+            to_string(&value),
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+        );
+    }
+
+    #[test]
+    fn test_keys_sort_by_utf16() {
+        // U+1F600 is a surrogate pair, which sorts before U+FB33 in UTF-16
+        // though after it in UTF-8.
+        let value = json!({"\u{fb33}": 1, "\u{1f600}": 2, "b": 3, "a": {"z": [], "y": {}}});
+        assert_eq!(
+            to_string(&value),
+            "{\"a\":{\"y\":{},\"z\":[]},\"b\":3,\"\u{1f600}\":2,\"\u{fb33}\":1}"
+        );
+    }
+}
@@ -0,0 +1,159 @@
+//! Scalar coercion tables: sets of scalar values that mean the same thing, so
+//! that a branch writing `"yes"` where base had `true` has not changed it.
+
+use crate::config;
+use serde_json::{json, Map, Number, Value};
+
+/// Classes of equivalent scalars. Two scalars are equivalent if they are equal
+/// or belong to the same class; each value belongs to at most one class.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coercions {
+    classes: Vec<Vec<Value>>,
+}
+
+impl Coercions {
+    /// `true`, `"true"`, `"yes"` and `1` for true, and `false`, `"false"`,
+    /// `"no"` and `0` for false.
+    pub fn booleans() -> Coercions {
+        Coercions {
+            classes: vec![
+                vec![json!(true), json!("true"), json!("yes"), json!(1)],
+                vec![json!(false), json!("false"), json!("no"), json!(0)],
+            ],
+        }
+    }
+
+    /// Reads a table from a JSON object mapping a name for each class to the
+    /// array of its values, such as `{"on": ["on", "enabled", 1]}`.
+    pub fn parse(contents: &str) -> Result<Coercions, String> {
+        let classes: Map<String, Value> = config::from_value(&config::parse(contents)?, "")?;
+        let mut coercions = Coercions::default();
+        for (name, values) in &classes {
+            let path = config::field("", name);
+            let values: Vec<Value> = config::from_value(values, &path)?;
+            for (i, value) in values.iter().enumerate() {
+                let path = config::index(&path, i);
+                if value.is_object() || value.is_array() {
+                    return Err(config::at(&path, format!("{} is not a scalar", value)));
+                }
+                if coercions.class_of(value).is_some() {
+                    return Err(config::at(
+                        &path,
+                        format!("{} is in more than one class", value),
+                    ));
+                }
+            }
+            coercions.classes.push(values);
+        }
+        Ok(coercions)
+    }
+
+    /// Adds the classes of `other`.
+    pub fn extend(&mut self, other: Coercions) -> Result<(), String> {
+        other
+            .classes
+            .into_iter()
+            .try_for_each(|class| self.add(class))
+    }
+
+    fn add(&mut self, class: Vec<Value>) -> Result<(), String> {
+        if let Some(value) = class.iter().find(|value| self.class_of(value).is_some()) {
+            return Err(format!("{} is in more than one class", value));
+        }
+        self.classes.push(class);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.classes.is_empty()
+    }
+
+    fn class_of(&self, value: &Value) -> Option<usize> {
+        self.classes.iter().position(|class| class.contains(value))
+    }
+
+    /// Whether the different values `x` and `y` are in the same class.
+    pub fn equivalent(&self, x: &Value, y: &Value) -> bool {
+        self.class_of(x)
+            .is_some_and(|class| self.class_of(y) == Some(class))
+    }
+}
+
+/// Whether `text` is a JSON number literal, with nothing around it, equal to
+/// `number`: `"42"` and `"4.2e1"` equal `42`, but `" 42"`, `"042"` and `"+42"`
+/// are not numbers.
+pub fn numeric_string_equals(text: &str, number: &Number) -> bool {
+    if text.trim() != text {
+        return false;
+    }
+    let Ok(parsed) = serde_json::from_str::<Number>(text) else {
+        return false;
+    };
+    parsed == *number
+        || ((parsed.is_f64() || number.is_f64()) && parsed.as_f64() == number.as_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_booleans() {
+        let coercions = Coercions::booleans();
+        assert!(coercions.equivalent(&json!("yes"), &json!(true)));
+        assert!(coercions.equivalent(&json!(0), &json!("no")));
+        assert!(!coercions.equivalent(&json!("yes"), &json!("no")));
+        assert!(!coercions.equivalent(&json!("y"), &json!("y")));
+    }
+
+    #[test]
+    fn test_numeric_string_equals() {
+        let number = |value: Value| value.as_number().unwrap().clone();
+        for (text, value) in [
+            ("42", json!(42)),
+            ("-1.5", json!(-1.5)),
+            ("4.2e1", json!(42)),
+        ] {
+            assert!(numeric_string_equals(text, &number(value)), "{}", text);
+        }
+        for text in [
+            "43",
+            " 42",
+            "42 ",
+            "042",
+            "+42",
+            "0x2a",
+            "42abc",
+            "",
+            "forty-two",
+        ] {
+            assert!(!numeric_string_equals(text, &number(json!(42))), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut coercions =
+            Coercions::parse(r#"{"on": ["on", "enabled"], "off": ["off", null]}"#).unwrap();
+        assert!(coercions.equivalent(&json!("enabled"), &json!("on")));
+        assert!(coercions.equivalent(&Value::Null, &json!("off")));
+        coercions.extend(Coercions::booleans()).unwrap();
+        assert!(coercions.equivalent(&json!(1), &json!(true)));
+
+        let cases = [
+            (
+                r#"{"on": "on"}"#,
+                "on: invalid type: string \"on\", expected a sequence",
+            ),
+            (r#"{"on": [1, [1]]}"#, "on[1]: [1] is not a scalar"),
+            (
+                r#"{"a": [1, 2], "b": [3, 2]}"#,
+                "b[1]: 2 is in more than one class",
+            ),
+            ("[]", "invalid type: sequence, expected a map"),
+        ];
+        for (contents, expected) in cases {
+            assert_eq!(Coercions::parse(contents).unwrap_err(), expected);
+        }
+    }
+}
@@ -0,0 +1,456 @@
+//! Shell completion scripts, generated from the clap definition of the command
+//! line so that every flag is covered as the flag set grows.
+//!
+//! The scripts complete the options of the command, the values of options
+//! that take one of a fixed set, file names for options that take a path, and
+//! the subcommands with their positional values.
+
+use clap::{Arg, Command, ValueHint};
+
+/// The shells a script can be generated for.
+pub const SHELLS: [&str; 4] = ["bash", "zsh", "fish", "powershell"];
+
+/// The completion script of `command` for `shell`, one of `SHELLS`.
+pub fn generate(command: &Command, shell: &str) -> String {
+    let mut command = command.clone();
+    command.build();
+    match shell {
+        "bash" => bash(&command),
+        "zsh" => zsh(&command),
+        "fish" => fish(&command),
+        _ => powershell(&command),
+    }
+}
+
+// The visible options of `command`, including `--help`.
+fn options(command: &Command) -> Vec<&Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+        .collect()
+}
+
+fn subcommands(command: &Command) -> Vec<&Command> {
+    command
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set())
+        .collect()
+}
+
+// The spellings of an option, short first.
+fn spellings(arg: &Arg) -> Vec<String> {
+    let mut names: Vec<String> = arg
+        .get_short_and_visible_aliases()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|short| format!("-{}", short))
+        .collect();
+    names.extend(
+        arg.get_long_and_visible_aliases()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|long| format!("--{}", long)),
+    );
+    names
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_num_args().is_some_and(|range| range.takes_values())
+}
+
+fn takes_path(arg: &Arg) -> bool {
+    matches!(
+        arg.get_value_hint(),
+        ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath
+    )
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+fn help(arg: &Arg) -> String {
+    arg.get_help()
+        .map(|help| help.to_string())
+        .unwrap_or_default()
+}
+
+// `text` in single quotes for a POSIX shell.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+fn bash(command: &Command) -> String {
+    let name = command.get_name();
+    let mut words: Vec<String> = options(command).into_iter().flat_map(spellings).collect();
+    words.extend(
+        subcommands(command)
+            .iter()
+            .map(|sub| sub.get_name().to_string()),
+    );
+
+    let mut out = format!(
+        "_{}() {{\n    local cur prev\n    cur=\"${{COMP_WORDS[COMP_CWORD]}}\"\n    prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n",
+        name.replace('-', "_")
+    );
+    if !subcommands(command).is_empty() {
+        out.push_str("    case \"${COMP_WORDS[1]}\" in\n");
+        for sub in subcommands(command) {
+            let mut sub_words: Vec<String> = options(sub).into_iter().flat_map(spellings).collect();
+            for arg in sub.get_positionals() {
+                sub_words.extend(possible_values(arg));
+            }
+            out.push_str(&format!(
+                "        {})\n            COMPREPLY=($(compgen -W {} -- \"$cur\"))\n            return\n            ;;\n",
+                sub.get_name(),
+                quote(&sub_words.join(" "))
+            ));
+        }
+        out.push_str("    esac\n");
+    }
+
+    out.push_str("    case \"$prev\" in\n");
+    for arg in options(command).into_iter().filter(|arg| takes_value(arg)) {
+        let values = possible_values(arg);
+        let reply = if !values.is_empty() {
+            format!("$(compgen -W {} -- \"$cur\")", quote(&values.join(" ")))
+        } else if takes_path(arg) {
+            "$(compgen -f -- \"$cur\")".to_string()
+        } else {
+            String::new()
+        };
+        out.push_str(&format!(
+            "        {})\n            COMPREPLY=({})\n            return\n            ;;\n",
+            spellings(arg).join("|"),
+            reply
+        ));
+    }
+    out.push_str(&format!(
+        "    esac\n    COMPREPLY=($(compgen -W {} -- \"$cur\"))\n}}\n\ncomplete -F _{} {}\n",
+        quote(&words.join(" ")),
+        name.replace('-', "_"),
+        name
+    ));
+    out
+}
+
+// A `_arguments` description, which may not contain unescaped brackets.
+fn zsh_help(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+        .replace('\'', "'\\''")
+}
+
+// The `_arguments` specs of the options of `command`.
+fn zsh_specs(command: &Command) -> Vec<String> {
+    let mut specs = Vec::new();
+    for arg in options(command) {
+        let repeatable = matches!(
+            arg.get_action(),
+            clap::ArgAction::Append | clap::ArgAction::Count
+        );
+        let value = if !takes_value(arg) {
+            String::new()
+        } else if !possible_values(arg).is_empty() {
+            format!(":{}:({})", arg.get_id(), possible_values(arg).join(" "))
+        } else if takes_path(arg) {
+            format!(":{}:_files", arg.get_id())
+        } else {
+            format!(":{}: ", arg.get_id())
+        };
+        for spelling in spellings(arg) {
+            let separator = match (takes_value(arg), spelling.starts_with("--")) {
+                (false, _) => "",
+                (true, true) => "=",
+                (true, false) => "+",
+            };
+            specs.push(format!(
+                "'{}{}{}[{}]{}'",
+                if repeatable { "*" } else { "" },
+                spelling,
+                separator,
+                zsh_help(&help(arg)),
+                value
+            ));
+        }
+    }
+    specs
+}
+
+fn zsh(command: &Command) -> String {
+    let name = command.get_name();
+    let function = format!("_{}", name.replace('-', "_"));
+    let mut specs = zsh_specs(command);
+    let subs = subcommands(command);
+    if !subs.is_empty() {
+        specs.push("'1: :->command'".to_string());
+        specs.push("'*:: :->args'".to_string());
+    }
+
+    let mut out = format!(
+        "#compdef {}\n\n{}() {{\n    local state line\n    _arguments -s -C \\\n        {}\n",
+        name,
+        function,
+        specs.join(" \\\n        ")
+    );
+    if !subs.is_empty() {
+        let descriptions: Vec<String> = subs
+            .iter()
+            .map(|sub| {
+                let about = sub.get_about().map(|about| about.to_string());
+                format!(
+                    "'{}[{}]'",
+                    sub.get_name(),
+                    zsh_help(&about.unwrap_or_default())
+                )
+            })
+            .collect();
+        out.push_str(&format!(
+            "    case $state in\n        command)\n            _values 'command' {}\n            ;;\n        args)\n            case $line[1] in\n",
+            descriptions.join(" ")
+        ));
+        for sub in subs {
+            let mut sub_specs = zsh_specs(sub);
+            for (index, arg) in sub.get_positionals().enumerate() {
+                sub_specs.push(format!(
+                    "'{}:{}:({})'",
+                    index + 1,
+                    arg.get_id(),
+                    possible_values(arg).join(" ")
+                ));
+            }
+            out.push_str(&format!(
+                "                {})\n                    _arguments {}\n                    ;;\n",
+                sub.get_name(),
+                sub_specs.join(" ")
+            ));
+        }
+        out.push_str("            esac\n            ;;\n    esac\n");
+    }
+    out.push_str(&format!("}}\n\n{} \"$@\"\n", function));
+    out
+}
+
+fn fish_option(name: &str, condition: &str, arg: &Arg) -> String {
+    let mut line = format!("complete -c {} -n {}", name, quote(condition));
+    if let Some(short) = arg.get_short() {
+        line.push_str(&format!(" -s {}", short));
+    }
+    if let Some(long) = arg.get_long() {
+        line.push_str(&format!(" -l {}", long));
+    }
+    line.push_str(&format!(" -d {}", quote(&help(arg))));
+    if takes_value(arg) {
+        let values = possible_values(arg);
+        if !values.is_empty() {
+            line.push_str(&format!(" -r -f -a {}", quote(&values.join(" "))));
+        } else if takes_path(arg) {
+            line.push_str(" -r -F");
+        } else {
+            line.push_str(" -r -f");
+        }
+    }
+    line.push('\n');
+    line
+}
+
+fn fish(command: &Command) -> String {
+    let name = command.get_name();
+    let mut out = String::new();
+    for arg in options(command) {
+        out.push_str(&fish_option(name, "__fish_use_subcommand", arg));
+    }
+    for sub in subcommands(command) {
+        let about = sub.get_about().map(|about| about.to_string());
+        out.push_str(&format!(
+            "complete -c {} -n '__fish_use_subcommand' -f -a {} -d {}\n",
+            name,
+            sub.get_name(),
+            quote(&about.unwrap_or_default())
+        ));
+        let condition = format!("__fish_seen_subcommand_from {}", sub.get_name());
+        for arg in options(sub) {
+            out.push_str(&fish_option(name, &condition, arg));
+        }
+        for arg in sub.get_positionals() {
+            out.push_str(&format!(
+                "complete -c {} -n {} -f -a {}\n",
+                name,
+                quote(&condition),
+                quote(&possible_values(arg).join(" "))
+            ));
+        }
+    }
+    out
+}
+
+// A PowerShell `CompletionResult` for `text`.
+fn powershell_result(text: &str, kind: &str, tooltip: &str) -> String {
+    let tooltip = if tooltip.is_empty() { text } else { tooltip };
+    format!(
+        "[CompletionResult]::new('{}', '{}', [CompletionResultType]::{}, '{}')",
+        text.replace('\'', "''"),
+        text.replace('\'', "''"),
+        kind,
+        tooltip.replace('\'', "''")
+    )
+}
+
+fn powershell_options(command: &Command) -> Vec<String> {
+    options(command)
+        .into_iter()
+        .flat_map(|arg| {
+            spellings(arg)
+                .into_iter()
+                .map(|spelling| powershell_result(&spelling, "ParameterName", &help(arg)))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+fn powershell(command: &Command) -> String {
+    let indent = "\n                ";
+    let mut cases = String::new();
+    for sub in subcommands(command) {
+        let mut results = powershell_options(sub);
+        for arg in sub.get_positionals() {
+            for value in possible_values(arg) {
+                results.push(powershell_result(&value, "ParameterValue", ""));
+            }
+        }
+        cases.push_str(&format!(
+            "        {{ $elements -contains '{}' }} {{{}{}\n            break\n        }}\n",
+            sub.get_name(),
+            indent,
+            results.join(indent)
+        ));
+    }
+    for arg in options(command).into_iter().filter(|arg| takes_value(arg)) {
+        let values = possible_values(arg);
+        if values.is_empty() {
+            continue;
+        }
+        let results: Vec<String> = values
+            .iter()
+            .map(|value| powershell_result(value, "ParameterValue", ""))
+            .collect();
+        let names: Vec<String> = spellings(arg)
+            .iter()
+            .map(|spelling| format!("'{}'", spelling))
+            .collect();
+        cases.push_str(&format!(
+            "        {{ {} -contains $previous }} {{{}{}\n            break\n        }}\n",
+            names.join(", "),
+            indent,
+            results.join(indent)
+        ));
+    }
+
+    let mut top = powershell_options(command);
+    for sub in subcommands(command) {
+        let about = sub.get_about().map(|about| about.to_string());
+        top.push(powershell_result(
+            sub.get_name(),
+            "ParameterValue",
+            &about.unwrap_or_default(),
+        ));
+    }
+    format!(
+        "using namespace System.Management.Automation\n\n\
+         Register-ArgumentCompleter -Native -CommandName '{}' -ScriptBlock {{\n    \
+         param($wordToComplete, $commandAst, $cursorPosition)\n    \
+         $elements = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})\n    \
+         $previous = if ($wordToComplete) {{ $elements[-2] }} else {{ $elements[-1] }}\n    \
+         $candidates = switch ($true) {{\n{}        default {{{}{}\n        }}\n    }}\n    \
+         $candidates | Where-Object {{ $_.CompletionText -like \"$wordToComplete*\" }}\n}}\n",
+        command.get_name(),
+        cases,
+        indent,
+        top.join(indent)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::builder::PossibleValuesParser;
+    use std::path::PathBuf;
+
+    fn command() -> Command {
+        Command::new("tool")
+            .arg(
+                Arg::new("base")
+                    .help("Base file")
+                    .short('b')
+                    .long("base")
+                    .value_parser(clap::value_parser!(PathBuf)),
+            )
+            .arg(
+                Arg::new("mode")
+                    .help("Merge mode [default: fast]")
+                    .long("mode")
+                    .value_parser(PossibleValuesParser::new(["fast", "slow"])),
+            )
+            .arg(
+                Arg::new("verbose")
+                    .help("Don't be quiet")
+                    .long("verbose")
+                    .action(clap::ArgAction::SetTrue),
+            )
+            .subcommand(
+                Command::new("completions")
+                    .about("Print a completion script")
+                    .arg(Arg::new("shell").required(true).value_parser(SHELLS)),
+            )
+    }
+
+    #[test]
+    fn test_bash_covers_options_values_and_subcommands() {
+        let script = generate(&command(), "bash");
+        assert!(
+            script.contains("compgen -W '-b --base --mode --verbose -h --help completions help'")
+        );
+        assert!(script
+            .contains("        -b|--base)\n            COMPREPLY=($(compgen -f -- \"$cur\"))"));
+        assert!(script.contains(
+            "        --mode)\n            COMPREPLY=($(compgen -W 'fast slow' -- \"$cur\"))"
+        ));
+        assert!(script.contains("compgen -W '-h --help bash zsh fish powershell'"));
+        assert!(script.ends_with("complete -F _tool tool\n"));
+    }
+
+    #[test]
+    fn test_zsh_and_fish_escape_help() {
+        let zsh = generate(&command(), "zsh");
+        assert!(zsh.starts_with("#compdef tool\n"));
+        assert!(zsh.contains("'-b+[Base file]:base:_files'"));
+        assert!(zsh.contains("'--mode=[Merge mode \\[default\\: fast\\]]:mode:(fast slow)'"));
+        assert!(zsh.contains("'--verbose[Don'\\''t be quiet]'"));
+        assert!(zsh.contains("'1:shell:(bash zsh fish powershell)'"));
+
+        let fish = generate(&command(), "fish");
+        assert!(fish.contains(
+            "complete -c tool -n '__fish_use_subcommand' -s b -l base -d 'Base file' -r -F\n"
+        ));
+        assert!(fish.contains("-l mode -d 'Merge mode [default: fast]' -r -f -a 'fast slow'\n"));
+        assert!(fish.contains(
+            "complete -c tool -n '__fish_seen_subcommand_from completions' -f -a 'bash zsh fish powershell'\n"
+        ));
+    }
+
+    #[test]
+    fn test_powershell_lists_every_option() {
+        let script = generate(&command(), "powershell");
+        for spelling in ["-b", "--base", "--mode", "--verbose", "--help"] {
+            assert!(script.contains(&format!("[CompletionResult]::new('{}', ", spelling)));
+        }
+        assert!(script.contains("'Don''t be quiet'"));
+        assert!(script.contains("{ '--mode' -contains $previous }"));
+    }
+}
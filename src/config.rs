@@ -0,0 +1,321 @@
+//! Strict loading of the JSON configuration files: rules files, profiles,
+//! coercion tables and conflict files. Each level of a file is read through serde with unknown
+//! fields denied, so a misspelt field fails instead of being ignored, and every
+//! error names the path of the offending value, as in
+//! `rules[2].strategy: unknown value 'takeC'`.
+
+use crate::merge::{ArrayMergeMode, ConflictStrategy, PathRule};
+use crate::pattern::PathPattern;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use std::fmt::Display;
+
+/// Parses the text of a configuration file.
+pub fn parse(contents: &str) -> Result<Value, String> {
+    serde_json::from_str(contents).map_err(|e| e.to_string())
+}
+
+/// Reads `value`, found at `path` of the file, as a `T`.
+pub fn from_value<T: DeserializeOwned>(value: &Value, path: &str) -> Result<T, String> {
+    T::deserialize(value).map_err(|e| at(path, e))
+}
+
+/// Reads the object `value`, found at `path` of the file, as a `T`; serde
+/// would otherwise also read a struct from an array of its fields.
+pub fn from_object<T: DeserializeOwned>(value: &Value, path: &str) -> Result<T, String> {
+    if !value.is_object() {
+        return Err(at(path, format!("expected an object, not {}", value)));
+    }
+    from_value(value, path)
+}
+
+/// Reads `value`, found at `path` of the file, as a string.
+pub fn string<'a>(value: &'a Value, path: &str) -> Result<&'a str, String> {
+    value
+        .as_str()
+        .ok_or_else(|| at(path, format!("expected a string, not {}", value)))
+}
+
+/// `error` annotated with the path where it was found.
+pub fn at(path: &str, error: impl Display) -> String {
+    if path.is_empty() {
+        error.to_string()
+    } else {
+        format!("{}: {}", path, error)
+    }
+}
+
+/// The path of the field `name` of the object at `path`.
+pub fn field(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+/// The path of element `index` of the array at `path`.
+pub fn index(path: &str, index: usize) -> String {
+    format!("{}[{}]", path, index)
+}
+
+/// Deserializes an optional field so that an explicit `null` is `Some(Null)`
+/// rather than the same as leaving the field out.
+pub fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    rules: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+    pattern: Value,
+    strategy: Option<Value>,
+    array_mode: Option<Value>,
+}
+
+/// Reads a rules file: an object with a `rules` array, each rule an object
+/// with a `pattern` and a `strategy`, an `array_mode` or both, written as in
+/// `--rule`, such as `{"pattern": "/tags", "array_mode": "ordered-union"}`.
+pub fn rules(contents: &str) -> Result<Vec<PathRule>, String> {
+    let file: RulesFile = from_object(&parse(contents)?, "")?;
+    file.rules
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| rule(entry, &index("rules", i)))
+        .collect()
+}
+
+fn rule(entry: &Value, path: &str) -> Result<PathRule, String> {
+    let entry: RuleEntry = from_object(entry, path)?;
+    let pattern_path = field(path, "pattern");
+    let pattern = PathPattern::parse(string(&entry.pattern, &pattern_path)?)
+        .map_err(|e| at(&pattern_path, e))?;
+    let strategy = match &entry.strategy {
+        Some(strategy) => Some(setting(
+            strategy,
+            &field(path, "strategy"),
+            ConflictStrategy::parse,
+        )?),
+        None => None,
+    };
+    let array_mode = match &entry.array_mode {
+        Some(mode) => Some(setting(
+            mode,
+            &field(path, "array_mode"),
+            ArrayMergeMode::parse,
+        )?),
+        None => None,
+    };
+    if strategy.is_none() && array_mode.is_none() {
+        return Err(at(path, "a rule needs a 'strategy' or an 'array_mode'"));
+    }
+    Ok(PathRule {
+        pattern,
+        strategy,
+        array_mode,
+    })
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesFile {
+    profiles: Map<String, Value>,
+}
+
+/// An option of the command line set by a profile: its long name, and its
+/// values, none for a flag.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileOption {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+/// Reads the profile `name` of a profiles file: an object with a `profiles`
+/// object mapping names to profiles, each an object from option names, as
+/// written on the command line without the dashes, to their values, such as
+/// `{"profiles": {"ci-strict": {"strategy": "manual", "safe-types": true}}}`.
+/// A flag is `true`, or `false` to leave it out, and a repeatable option may
+/// have an array of values.
+pub fn profile(contents: &str, name: &str) -> Result<Vec<ProfileOption>, String> {
+    let file: ProfilesFile = from_object(&parse(contents)?, "")?;
+    let path = field("profiles", name);
+    let settings: Map<String, Value> = match file.profiles.get(name) {
+        Some(settings) => from_object(settings, &path)?,
+        None => return Err(at("profiles", format!("no profile named '{}'", name))),
+    };
+    let mut options = Vec::new();
+    for (option, value) in &settings {
+        let option_path = field(&path, option);
+        let values = match value {
+            Value::Bool(false) => continue,
+            Value::Bool(true) => Vec::new(),
+            Value::Array(items) => items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| scalar(item, &index(&option_path, i)))
+                .collect::<Result<_, _>>()?,
+            _ => vec![scalar(value, &option_path)?],
+        };
+        options.push(ProfileOption {
+            name: option.clone(),
+            values,
+        });
+    }
+    Ok(options)
+}
+
+fn scalar(value: &Value, path: &str) -> Result<String, String> {
+    match value {
+        Value::String(text) => Ok(text.clone()),
+        Value::Number(number) => Ok(number.to_string()),
+        _ => Err(at(
+            path,
+            format!("expected a string, number or flag, not {}", value),
+        )),
+    }
+}
+
+fn setting<T>(
+    value: &Value,
+    path: &str,
+    parse: fn(&str) -> Result<Option<T>, String>,
+) -> Result<T, String> {
+    let name = string(value, path)?;
+    parse(name)
+        .map_err(|e| at(path, e))?
+        .ok_or_else(|| at(path, format!("unknown value '{}'", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_file() {
+        let rules = rules(
+            r#"{"rules": [
+                {"pattern": "/tags", "array_mode": "ordered-union"},
+                {"pattern": "/config/**", "strategy": "ours", "array_mode": "keyed:id"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].strategy, None);
+        assert_eq!(rules[0].array_mode, Some(ArrayMergeMode::OrderedUnion));
+        assert_eq!(rules[1].strategy, Some(ConflictStrategy::Ours));
+        assert_eq!(
+            rules[1].array_mode,
+            Some(ArrayMergeMode::Keyed {
+                key: "id".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_malformed_rules_files() {
+        let cases = [
+            ("[]", "expected an object, not []"),
+            (r#"{"rule": []}"#, "unknown field `rule`, expected `rules`"),
+            (r#"{"rules": {}}"#, "invalid type: map, expected a sequence"),
+            (
+                r#"{"rules": [{"pattern": "/a", "strategy": "ours"}, {"pattern": "/b", "strategy": "takeC"}]}"#,
+                "rules[1].strategy: unknown value 'takeC'",
+            ),
+            (
+                r#"{"rules": [{"pattern": "/a", "stratgy": "ours"}]}"#,
+                "rules[0]: unknown field `stratgy`, expected one of `pattern`, `strategy`, `array_mode`",
+            ),
+            (r#"{"rules": [["/a", "ours"]]}"#, "rules[0]: expected an object, not [\"/a\",\"ours\"]"),
+            (
+                r#"{"rules": [{"strategy": "ours"}]}"#,
+                "rules[0]: missing field `pattern`",
+            ),
+            (
+                r#"{"rules": [{"pattern": 1, "strategy": "ours"}]}"#,
+                "rules[0].pattern: expected a string, not 1",
+            ),
+            (
+                r#"{"rules": [{"pattern": "a", "strategy": "ours"}]}"#,
+                "rules[0].pattern: pattern 'a' must be a JSON pointer starting with '/'",
+            ),
+            (
+                r#"{"rules": [{"pattern": "/a", "strategy": "positional"}]}"#,
+                "rules[0].strategy: unknown value 'positional'",
+            ),
+            (
+                r#"{"rules": [{"pattern": "/a", "array_mode": "similarity:2"}]}"#,
+                "rules[0].array_mode: '2' is not a number between 0 and 1",
+            ),
+            (
+                r#"{"rules": [{"pattern": "/a"}]}"#,
+                "rules[0]: a rule needs a 'strategy' or an 'array_mode'",
+            ),
+        ];
+        for (contents, expected) in cases {
+            assert_eq!(rules(contents).unwrap_err(), expected, "{}", contents);
+        }
+        assert!(rules("{").is_err());
+    }
+
+    #[test]
+    fn test_profiles_file() {
+        let contents = r#"{"profiles": {
+            "ci-strict": {"strategy": "manual", "safe-types": true, "verify": false,
+                          "rule": ["/tags=ordered-union", "/a=ours"], "max-severity": 3},
+            "dev": {}
+        }}"#;
+        let option = |name: &str, values: &[&str]| ProfileOption {
+            name: name.to_string(),
+            values: values.iter().map(|v| v.to_string()).collect(),
+        };
+        assert_eq!(
+            profile(contents, "ci-strict").unwrap(),
+            vec![
+                option("max-severity", &["3"]),
+                option("rule", &["/tags=ordered-union", "/a=ours"]),
+                option("safe-types", &[]),
+                option("strategy", &["manual"]),
+            ]
+        );
+        assert_eq!(profile(contents, "dev").unwrap(), vec![]);
+
+        let cases = [
+            (contents, "ci", "profiles: no profile named 'ci'"),
+            (
+                r#"{"profile": {}}"#,
+                "ci",
+                "unknown field `profile`, expected `profiles`",
+            ),
+            (
+                r#"{"profiles": {"ci": []}}"#,
+                "ci",
+                "profiles.ci: expected an object, not []",
+            ),
+            (
+                r#"{"profiles": {"ci": {"rule": [true]}}}"#,
+                "ci",
+                "profiles.ci.rule[0]: expected a string, number or flag, not true",
+            ),
+            (
+                r#"{"profiles": {"ci": {"strategy": {"name": "ours"}}}}"#,
+                "ci",
+                "profiles.ci.strategy: expected a string, number or flag, not {\"name\":\"ours\"}",
+            ),
+        ];
+        for (contents, name, expected) in cases {
+            assert_eq!(
+                profile(contents, name).unwrap_err(),
+                expected,
+                "{}",
+                contents
+            );
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! An editable file of unresolved conflicts, for resolving them by hand and
+//! applying the decisions on a later run over the same inputs.
+//!
+//! The file is a JSON object with a `version` (currently 1) and a `conflicts`
+//! array. Each entry has the conflict's `path` and `kind` (see
+//! `ConflictKind::name`), the `base`, `a` and `b` values where the version has
+//! one, and a `take` field, initially `null`. To decide a conflict, set `take`
+//! to `"a"`, `"b"` or `"base"`, or replace it with a `value` field holding the
+//! literal value to use. Taking a version that has no value deletes the path.
+//! Entries left undecided stay conflicts.
+
+use crate::config;
+use crate::merge::{Conflict, Side};
+use crate::patch::{self, Operation};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+const VERSION: u64 = 1;
+
+/// What to do with the conflict at `path`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub path: String,
+    pub choice: Choice,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Choice {
+    Take(Side),
+    Value(Value),
+}
+
+/// The file contents for the unresolved conflicts among `conflicts`.
+pub fn emit(conflicts: &[Conflict]) -> Value {
+    let entries: Vec<Value> = conflicts
+        .iter()
+        .filter(|c| !c.is_resolved())
+        .map(|conflict| {
+            let mut entry = Map::new();
+            entry.insert("path".to_string(), json!(conflict.path));
+            entry.insert("kind".to_string(), json!(conflict.kind.name()));
+            for (field, value) in [
+                ("base", &conflict.base),
+                ("a", &conflict.a),
+                ("b", &conflict.b),
+            ] {
+                if let Some(value) = value {
+                    entry.insert(field.to_string(), value.clone());
+                }
+            }
+            entry.insert("take".to_string(), Value::Null);
+            Value::Object(entry)
+        })
+        .collect();
+    json!({"version": VERSION, "conflicts": entries})
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    version: u64,
+    conflicts: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Entry {
+    path: Value,
+    take: Option<Value>,
+    #[serde(default, deserialize_with = "config::present")]
+    value: Option<Value>,
+    // Written for whoever edits the file, and not read back.
+    #[serde(rename = "kind", default)]
+    _kind: IgnoredAny,
+    #[serde(rename = "base", default)]
+    _base: IgnoredAny,
+    #[serde(rename = "a", default)]
+    _a: IgnoredAny,
+    #[serde(rename = "b", default)]
+    _b: IgnoredAny,
+}
+
+/// Reads the decisions from an edited file; undecided entries are skipped.
+pub fn parse(contents: &str) -> Result<Vec<Decision>, String> {
+    let file: File = config::from_object(&config::parse(contents)?, "")?;
+    if file.version != VERSION {
+        return Err(config::at(
+            "version",
+            format!("unsupported version {}", file.version),
+        ));
+    }
+
+    let mut decisions = Vec::new();
+    for (i, entry) in file.conflicts.iter().enumerate() {
+        let path = config::index("conflicts", i);
+        let entry: Entry = config::from_object(entry, &path)?;
+        let take = entry.take.filter(|take| !take.is_null());
+        let choice = match (take, entry.value) {
+            (Some(_), Some(_)) => {
+                return Err(config::at(&path, "both 'take' and 'value' are given"))
+            }
+            (Some(take), None) => Choice::Take(match take.as_str() {
+                Some("a") => Side::A,
+                Some("b") => Side::B,
+                Some("base") => Side::Base,
+                _ => {
+                    return Err(config::at(
+                        &config::field(&path, "take"),
+                        format!("unknown value {}, expected \"a\", \"b\" or \"base\"", take),
+                    ))
+                }
+            }),
+            (None, Some(value)) => Choice::Value(value),
+            (None, None) => continue,
+        };
+        decisions.push(Decision {
+            path: config::string(&entry.path, &config::field(&path, "path"))?.to_string(),
+            choice,
+        });
+    }
+    Ok(decisions)
+}
+
+/// Applies `decisions` to `merged`, marking the matching conflicts resolved.
+/// Fails if a decision names a path with no unresolved conflict, which means
+/// the file was made for different inputs or options.
+pub fn apply(
+    merged: &mut Value,
+    conflicts: &mut [Conflict],
+    decisions: &[Decision],
+) -> Result<(), String> {
+    for decision in decisions {
+        let conflict = conflicts
+            .iter_mut()
+            .find(|c| !c.is_resolved() && c.path == decision.path)
+            .ok_or_else(|| format!("no unresolved conflict at '{}'", decision.path))?;
+
+        let (value, side) = match &decision.choice {
+            Choice::Take(side) => (
+                match side {
+                    Side::A => conflict.a.clone(),
+                    Side::B => conflict.b.clone(),
+                    Side::Base => conflict.base.clone(),
+                    Side::Custom => None,
+                },
+                *side,
+            ),
+            Choice::Value(value) => (Some(value.clone()), Side::Custom),
+        };
+
+        let path = decision.path.clone();
+        let operation = match value {
+            Some(value) if merged.pointer(&path).is_some() => Operation::Replace { path, value },
+            Some(value) => Operation::Add { path, value },
+            None if merged.pointer(&path).is_some() => Operation::Remove { path },
+            None => {
+                conflict.resolution = Some(side);
+                continue;
+            }
+        };
+        *merged = patch::apply(merged, &[operation])
+            .map_err(|e| format!("cannot apply decision for '{}': {}", decision.path, e))?;
+        conflict.resolution = Some(side);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{three_way_merge_with_options, MergeOptions};
+
+    fn fixture() -> (Value, Value, Value) {
+        let base = json!({"x": 1, "y": 1, "z": 1, "gone": 1, "clean": 1});
+        let a = json!({"x": 2, "y": 2, "z": 2, "gone": 2, "clean": 2});
+        let b = json!({"x": 3, "y": 3, "z": 3, "clean": 1});
+        (base, a, b)
+    }
+
+    #[test]
+    fn test_round_trip_through_file() {
+        let (base, a, b) = fixture();
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        let emitted = emit(&conflicts);
+        assert_eq!(emitted["version"], json!(1));
+        assert_eq!(
+            emitted["conflicts"][0],
+            json!({"path": "/gone", "kind": "modified_a_deleted_b", "base": 1, "a": 2, "take": null})
+        );
+
+        // Someone edits the file: takes B for /gone (deleting it) and /x, types
+        // a value for /y and leaves /z undecided.
+        let mut edited = emitted.clone();
+        edited["conflicts"][0]["take"] = json!("b");
+        edited["conflicts"][1]["take"] = json!("b");
+        let entry = edited["conflicts"][2].as_object_mut().unwrap();
+        entry.remove("take");
+        entry.insert("value".to_string(), json!(23));
+        let contents = serde_json::to_string_pretty(&edited).unwrap();
+
+        let decisions = parse(&contents).unwrap();
+        assert_eq!(decisions.len(), 3);
+
+        // The second run merges the same inputs and applies the decisions.
+        let (mut merged, mut conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        apply(&mut merged, &mut conflicts, &decisions).unwrap();
+        assert_eq!(merged, json!({"x": 3, "y": 23, "z": 2, "clean": 2}));
+        let resolutions: Vec<Option<Side>> = conflicts.iter().map(|c| c.resolution).collect();
+        assert_eq!(
+            resolutions,
+            vec![Some(Side::B), Some(Side::B), Some(Side::Custom), None]
+        );
+        assert_eq!(emit(&conflicts)["conflicts"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_literal_null_value() {
+        let decisions =
+            parse(r#"{"version": 1, "conflicts": [{"path": "/x", "value": null}]}"#).unwrap();
+        assert_eq!(decisions[0].choice, Choice::Value(Value::Null));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let cases = [
+            ("[]", "expected an object, not []"),
+            (
+                r#"{"version": 2, "conflicts": []}"#,
+                "version: unsupported version 2",
+            ),
+            (r#"{"version": 1}"#, "missing field `conflicts`"),
+            (
+                r#"{"version": 1, "conflicts": [], "extra": 1}"#,
+                "unknown field `extra`, expected `version` or `conflicts`",
+            ),
+            (
+                r#"{"version": 1, "conflicts": [{"path": "/x"}, {"take": "a"}]}"#,
+                "conflicts[1]: missing field `path`",
+            ),
+            (
+                r#"{"version": 1, "conflicts": [{"path": 2, "take": "a"}]}"#,
+                "conflicts[0].path: expected a string, not 2",
+            ),
+            (
+                r#"{"version": 1, "conflicts": [{"path": "/x", "take": "c"}]}"#,
+                "conflicts[0].take: unknown value \"c\", expected \"a\", \"b\" or \"base\"",
+            ),
+            (
+                r#"{"version": 1, "conflicts": [{"path": "/x", "taek": "a"}]}"#,
+                "conflicts[0]: unknown field `taek`, expected one of `path`, `take`, `value`, `kind`, `base`, `a`, `b`",
+            ),
+            (
+                r#"{"version": 1, "conflicts": [{"path": "/x", "take": "a", "value": 1}]}"#,
+                "conflicts[0]: both 'take' and 'value' are given",
+            ),
+        ];
+        for (contents, expected) in cases {
+            assert_eq!(parse(contents).unwrap_err(), expected);
+        }
+    }
+
+    #[test]
+    fn test_apply_stale_decision() {
+        let (base, a, b) = fixture();
+        let (mut merged, mut conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        let decisions = vec![Decision {
+            path: "/clean".to_string(),
+            choice: Choice::Take(Side::A),
+        }];
+        assert_eq!(
+            apply(&mut merged, &mut conflicts, &decisions).unwrap_err(),
+            "no unresolved conflict at '/clean'"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+//! Strings holding serialized JSON, decoded so their contents can be compared
+//! and merged structurally, then encoded again for output.
+
+use crate::pattern::PathPattern;
+use crate::pointer;
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Replaces every string at a path matching `patterns` that holds a JSON object
+/// or array with its parsed value, recording the path in `decoded`. Strings
+/// holding anything else, including JSON scalars, are left alone.
+pub fn decode(value: &mut Value, patterns: &[PathPattern], decoded: &mut HashSet<String>) {
+    decode_at(value, "", patterns, decoded);
+}
+
+fn decode_at(
+    value: &mut Value,
+    path: &str,
+    patterns: &[PathPattern],
+    decoded: &mut HashSet<String>,
+) {
+    if let Value::String(text) = value {
+        if patterns.iter().any(|pattern| pattern.matches(path)) {
+            if let Ok(parsed @ (Value::Object(_) | Value::Array(_))) =
+                serde_json::from_str::<Value>(text)
+            {
+                *value = parsed;
+                decoded.insert(path.to_string());
+            }
+        }
+    }
+
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                decode_at(child, &pointer::push(path, key), patterns, decoded);
+            }
+        }
+        Value::Array(items) => {
+            for (index, child) in items.iter_mut().enumerate() {
+                decode_at(
+                    child,
+                    &pointer::push(path, &index.to_string()),
+                    patterns,
+                    decoded,
+                );
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Serializes the objects and arrays found at `decoded` paths back into
+/// compact JSON strings.
+pub fn encode(value: &mut Value, decoded: &HashSet<String>) {
+    encode_at(value, "", decoded);
+}
+
+fn encode_at(value: &mut Value, path: &str, decoded: &HashSet<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                encode_at(child, &pointer::push(path, key), decoded);
+            }
+        }
+        Value::Array(items) => {
+            for (index, child) in items.iter_mut().enumerate() {
+                encode_at(child, &pointer::push(path, &index.to_string()), decoded);
+            }
+        }
+        _ => {}
+    }
+
+    if (value.is_object() || value.is_array()) && decoded.contains(path) {
+        *value = Value::String(value.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{three_way_merge_with_options, MergeOptions};
+    use serde_json::json;
+
+    fn all_paths() -> Vec<PathPattern> {
+        vec![PathPattern::parse("/**").unwrap()]
+    }
+
+    #[test]
+    fn test_decode_only_objects_and_arrays() {
+        let mut value = json!({
+            "settings": "{\"a\": 1}",
+            "list": "[1, 2]",
+            "number": "42",
+            "text": "not json"
+        });
+        let mut decoded = HashSet::new();
+        decode(&mut value, &all_paths(), &mut decoded);
+
+        assert_eq!(
+            value,
+            json!({
+                "settings": {"a": 1},
+                "list": [1, 2],
+                "number": "42",
+                "text": "not json"
+            })
+        );
+        assert_eq!(decoded.len(), 2);
+    }
+
+    #[test]
+    fn test_decode_respects_patterns() {
+        let mut value = json!({
+            "settings": "{\"a\": 1}",
+            "other": "{\"b\": 2}"
+        });
+        let mut decoded = HashSet::new();
+        decode(
+            &mut value,
+            &[PathPattern::parse("/settings").unwrap()],
+            &mut decoded,
+        );
+        assert_eq!(value["settings"], json!({"a": 1}));
+        assert_eq!(value["other"], json!("{\"b\": 2}"));
+    }
+
+    #[test]
+    fn test_reformatted_embedded_json_merges() {
+        let mut base = json!({"settings": "{\"host\": \"a\", \"port\": 1}"});
+        // A only reformats the embedded document and changes the host.
+        let mut a = json!({"settings": "{\n  \"host\": \"b\",\n  \"port\": 1\n}"});
+        let mut b = json!({"settings": "{\"host\":\"a\",\"port\":2}"});
+
+        let mut decoded = HashSet::new();
+        for value in [&mut base, &mut a, &mut b] {
+            decode(value, &all_paths(), &mut decoded);
+        }
+
+        let (mut merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert!(conflicts.is_empty());
+
+        encode(&mut merged, &decoded);
+        assert_eq!(merged, json!({"settings": "{\"host\":\"b\",\"port\":2}"}));
+    }
+
+    #[test]
+    fn test_embedded_conflict_has_inner_path() {
+        let mut base = json!({"settings": "{\"port\": 1}"});
+        let mut a = json!({"settings": "{\"port\": 2}"});
+        let mut b = json!({"settings": "{\"port\": 3}"});
+
+        let mut decoded = HashSet::new();
+        for value in [&mut base, &mut a, &mut b] {
+            decode(value, &all_paths(), &mut decoded);
+        }
+
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/settings/port");
+    }
+
+    #[test]
+    fn test_encode_leaves_plain_objects() {
+        let mut value = json!({"settings": {"a": 1}, "plain": {"b": 2}});
+        let decoded: HashSet<String> = ["/settings".to_string()].into();
+        encode(&mut value, &decoded);
+        assert_eq!(value, json!({"settings": "{\"a\":1}", "plain": {"b": 2}}));
+    }
+}
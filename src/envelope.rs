@@ -0,0 +1,121 @@
+//! Documents wrapped in an envelope such as `{"version": 2, "data": {...}}`,
+//! where only the payload field is merged, and combined documents holding all
+//! three versions to merge.
+
+use serde_json::Value;
+
+pub fn unwrap<'a>(document: &'a Value, field: &str) -> Result<&'a Value, String> {
+    document
+        .as_object()
+        .and_then(|map| map.get(field))
+        .ok_or_else(|| format!("document has no envelope field '{}'", field))
+}
+
+/// Fails unless the envelope key has the same value in all three documents.
+/// A key missing from every document counts as equal.
+pub fn check_equal(key: &str, base: &Value, a: &Value, b: &Value) -> Result<(), String> {
+    let base_val = base.get(key);
+    let a_val = a.get(key);
+    let b_val = b.get(key);
+    if base_val == a_val && a_val == b_val {
+        return Ok(());
+    }
+
+    let show = |value: Option<&Value>| match value {
+        Some(value) => value.to_string(),
+        None => "missing".to_string(),
+    };
+    Err(format!(
+        "envelope field '{}' differs: base {}, branch A {}, branch B {}",
+        key,
+        show(base_val),
+        show(a_val),
+        show(b_val)
+    ))
+}
+
+/// Puts the merged payload back into a copy of `envelope`, keeping the rest of
+/// its metadata.
+pub fn rewrap(envelope: &Value, field: &str, merged: Value) -> Value {
+    let mut wrapped = envelope.clone();
+    if let Value::Object(map) = &mut wrapped {
+        map.insert(field.to_string(), merged);
+    }
+    wrapped
+}
+
+/// The base, A and B documents of a combined document such as
+/// `{"base": ..., "a": ..., "b": ...}`, found under `keys` in that order.
+pub fn sections<'a>(combined: &'a Value, keys: [&str; 3]) -> Result<[&'a Value; 3], String> {
+    let map = combined
+        .as_object()
+        .ok_or("the combined document is not an object")?;
+    let section = |key: &str| {
+        map.get(key)
+            .ok_or_else(|| format!("the combined document has no '{}' section", key))
+    };
+    Ok([section(keys[0])?, section(keys[1])?, section(keys[2])?])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unwrap_and_rewrap() {
+        let document = json!({"version": 2, "data": {"file1.txt": "id1"}});
+        assert_eq!(
+            unwrap(&document, "data").unwrap(),
+            &json!({"file1.txt": "id1"})
+        );
+        assert_eq!(
+            rewrap(&document, "data", json!({"file1.txt": "id2"})),
+            json!({"version": 2, "data": {"file1.txt": "id2"}})
+        );
+    }
+
+    #[test]
+    fn test_unwrap_missing_field() {
+        assert!(unwrap(&json!({"version": 2}), "data").is_err());
+        assert!(unwrap(&json!([1, 2]), "data").is_err());
+    }
+
+    #[test]
+    fn test_matching_metadata() {
+        let base = json!({"version": 2, "data": {}});
+        let a = json!({"version": 2, "data": {"file1.txt": "id1"}});
+        let b = json!({"version": 2, "data": {"file2.txt": "id2"}});
+        assert!(check_equal("version", &base, &a, &b).is_ok());
+        assert!(check_equal("schema", &base, &a, &b).is_ok());
+    }
+
+    #[test]
+    fn test_mismatching_metadata() {
+        let base = json!({"version": 2, "data": {}});
+        let a = json!({"version": 2, "data": {}});
+        let b = json!({"version": 3, "data": {}});
+        let err = check_equal("version", &base, &a, &b).unwrap_err();
+        assert_eq!(
+            err,
+            "envelope field 'version' differs: base 2, branch A 2, branch B 3"
+        );
+
+        let b = json!({"data": {}});
+        assert!(check_equal("version", &base, &a, &b).is_err());
+    }
+
+    #[test]
+    fn test_combined_sections() {
+        let combined = json!({"old": {"x": 1}, "ours": {"x": 2}, "theirs": [3]});
+        assert_eq!(
+            sections(&combined, ["old", "ours", "theirs"]).unwrap(),
+            [&json!({"x": 1}), &json!({"x": 2}), &json!([3])]
+        );
+        assert_eq!(
+            sections(&combined, ["base", "ours", "theirs"]),
+            Err("the combined document has no 'base' section".to_string())
+        );
+        assert!(sections(&json!([1, 2, 3]), ["base", "a", "b"]).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+//! Pretty-printing merged documents with selected subtrees kept on one line.
+
+use crate::pattern::PathPattern;
+use crate::pointer;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Compact,
+    Pretty,
+}
+
+/// Formats values at paths matching `pattern` in `style`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormatRule {
+    pub pattern: PathPattern,
+    pub style: Style,
+}
+
+impl FormatRule {
+    /// Parses `<pattern>=compact` or `<pattern>=pretty`.
+    pub fn parse(rule: &str) -> Result<FormatRule, String> {
+        let (pattern, style) = rule
+            .rsplit_once('=')
+            .ok_or_else(|| format!("rule '{}' must look like <pattern>=compact|pretty", rule))?;
+        let style = match style {
+            "compact" => Style::Compact,
+            "pretty" => Style::Pretty,
+            _ => {
+                return Err(format!(
+                    "unknown style '{}', expected 'compact' or 'pretty'",
+                    style
+                ))
+            }
+        };
+        Ok(FormatRule {
+            pattern: PathPattern::parse(pattern)?,
+            style,
+        })
+    }
+}
+
+/// Serializes `value` like `serde_json::to_string_pretty`, except that a value
+/// whose path matches a compact rule is written on a single line, along with
+/// everything inside it. When several rules match a path the last one wins, so
+/// a later pretty rule can exempt a path from an earlier, broader compact rule.
+pub fn to_string(value: &Value, rules: &[FormatRule]) -> String {
+    to_string_at(value, rules, "")
+}
+
+/// Like `to_string`, for a value found at `path` in the document, which the
+/// rules' patterns are matched against.
+pub fn to_string_at(value: &Value, rules: &[FormatRule], path: &str) -> String {
+    let mut out = String::new();
+    write_value(value, rules, path, 0, &mut out);
+    out
+}
+
+/// Rounds every non-integer number in `value` to `places` decimal places,
+/// to the nearest such number by its exact binary value, with exact ties
+/// going to the even digit: to 2 places, `0.125` becomes `0.12` and `2.675`,
+/// stored as slightly less, `2.67`. Integers are left alone. Numbers are still
+/// written in their shortest form, so `2.50` is written `2.5`.
+pub fn round_numbers(value: &mut Value, places: usize) {
+    match value {
+        Value::Number(number) if number.is_f64() => {
+            let Some(float) = number.as_f64() else {
+                return;
+            };
+            let rounded: f64 = format!("{:.*}", places, float).parse().unwrap_or(float);
+            // Rounding a small negative number must not leave `-0.0`.
+            let rounded = if rounded == 0.0 { 0.0 } else { rounded };
+            if let Some(rounded) = serde_json::Number::from_f64(rounded) {
+                *number = rounded;
+            }
+        }
+        Value::Object(map) => map.values_mut().for_each(|val| round_numbers(val, places)),
+        Value::Array(items) => items
+            .iter_mut()
+            .for_each(|item| round_numbers(item, places)),
+        _ => {}
+    }
+}
+
+fn style_at(rules: &[FormatRule], path: &str) -> Style {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.pattern.matches(path))
+        .map_or(Style::Pretty, |rule| rule.style)
+}
+
+fn write_value(value: &Value, rules: &[FormatRule], path: &str, depth: usize, out: &mut String) {
+    if style_at(rules, path) == Style::Compact {
+        out.push_str(&value.to_string());
+        return;
+    }
+
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            out.push('{');
+            for (index, (key, val)) in map.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                newline(depth + 1, out);
+                out.push_str(&Value::String(key.clone()).to_string());
+                out.push_str(": ");
+                write_value(val, rules, &pointer::push(path, key), depth + 1, out);
+            }
+            newline(depth, out);
+            out.push('}');
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push(',');
+                }
+                newline(depth + 1, out);
+                let item_path = pointer::push(path, &index.to_string());
+                write_value(item, rules, &item_path, depth + 1, out);
+            }
+            newline(depth, out);
+            out.push(']');
+        }
+        _ => out.push_str(&value.to_string()),
+    }
+}
+
+fn newline(depth: usize, out: &mut String) {
+    out.push('\n');
+    for _ in 0..depth {
+        out.push_str("  ");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rules(specs: &[&str]) -> Vec<FormatRule> {
+        specs
+            .iter()
+            .map(|s| FormatRule::parse(s).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_without_rules_matches_serde_pretty() {
+        let value = json!({
+            "name": "a \"quoted\" name",
+            "empty": {},
+            "list": [1, 2.5, null, [], {"nested": [true]}],
+            "deep": {"x": {"y": "z"}}
+        });
+        assert_eq!(
+            to_string(&value, &[]),
+            serde_json::to_string_pretty(&value).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compact_subtree() {
+        let value = json!({
+            "packages": {
+                "left-pad": {"version": "1.3.0", "deps": ["a", "b"]}
+            },
+            "name": "app"
+        });
+        let expected = r#"{
+  "name": "app",
+  "packages": {
+    "left-pad": {"deps":["a","b"],"version":"1.3.0"}
+  }
+}"#;
+        assert_eq!(
+            to_string(&value, &rules(&["/packages/*=compact"])),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_last_matching_rule_wins() {
+        let value = json!({"a": [1, 2], "b": [3]});
+        assert_eq!(
+            to_string(&value, &rules(&["/*=compact", "/b=pretty"])),
+            "{\n  \"a\": [1,2],\n  \"b\": [\n    3\n  ]\n}"
+        );
+    }
+
+    #[test]
+    fn test_compact_root() {
+        let value = json!({"a": {"b": [1]}});
+        assert_eq!(to_string(&value, &rules(&["=compact"])), value.to_string());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(FormatRule::parse("/a").is_err());
+        assert!(FormatRule::parse("/a=dense").is_err());
+        assert!(FormatRule::parse("a=compact").is_err());
+    }
+
+    #[test]
+    fn test_round_numbers() {
+        let mut value = json!({
+            "ratio": 0.333333,
+            "ties": [0.125, 0.375, 2.675, -0.001],
+            "count": 7,
+            "name": "1.23456"
+        });
+        round_numbers(&mut value, 2);
+        assert_eq!(
+            value,
+            json!({
+                "ratio": 0.33,
+                "ties": [0.12, 0.38, 2.67, 0.0],
+                "count": 7,
+                "name": "1.23456"
+            })
+        );
+        round_numbers(&mut value, 0);
+        assert_eq!(value["ratio"], json!(0.0));
+        assert_eq!(value["ties"], json!([0.0, 0.0, 3.0, 0.0]));
+        assert_eq!(to_string(&value["ties"][2], &[]), "3.0");
+    }
+}
@@ -0,0 +1,127 @@
+//! Reading the three versions of a file from a git repository, by running the
+//! `git` command.
+
+use std::path::Path;
+use std::process::Command;
+
+/// The contents of a file at the merge base of two refs and at each ref.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Versions {
+    pub base: String,
+    pub a: String,
+    pub b: String,
+}
+
+/// Reads `file`, relative to `repo`, as of `ref_a`, `ref_b` and their merge base.
+pub fn versions(repo: &Path, file: &str, ref_a: &str, ref_b: &str) -> Result<Versions, String> {
+    let base = merge_base(repo, ref_a, ref_b)?;
+    Ok(Versions {
+        base: show(repo, &base, file)?,
+        a: show(repo, ref_a, file)?,
+        b: show(repo, ref_b, file)?,
+    })
+}
+
+/// The commit id of the best common ancestor of `ref_a` and `ref_b`.
+pub fn merge_base(repo: &Path, ref_a: &str, ref_b: &str) -> Result<String, String> {
+    let output = run(repo, &["merge-base", ref_a, ref_b])?;
+    Ok(output.trim().to_string())
+}
+
+/// The contents of `file`, relative to `repo`, at revision `rev`.
+pub fn show(repo: &Path, rev: &str, file: &str) -> Result<String, String> {
+    let file = file.strip_prefix("./").unwrap_or(file);
+    run(repo, &["show", &format!("{}:./{}", rev, file)])
+}
+
+fn run(repo: &Path, args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        .output()
+        .map_err(|e| format!("failed to run git: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).map_err(|e| format!("git {}: {}", args[0], e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // A repository with a `config.json` changed differently on `feature` and
+    // `main` since the commit they branched from.
+    fn fixture_repo(name: &str) -> PathBuf {
+        let repo = std::env::temp_dir().join(format!("twm-git-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&repo);
+        fs::create_dir_all(repo.join("conf")).unwrap();
+
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?}", args);
+        };
+        let commit = |contents: &str| {
+            fs::write(repo.join("conf/config.json"), contents).unwrap();
+            git(&["add", "."]);
+            git(&["commit", "-q", "-m", contents]);
+        };
+
+        git(&["init", "-q", "-b", "main"]);
+        commit(r#"{"a": 1, "b": 1}"#);
+        git(&["branch", "feature"]);
+        commit(r#"{"a": 1, "b": 2}"#);
+        git(&["checkout", "-q", "feature"]);
+        commit(r#"{"a": 3, "b": 1}"#);
+        repo
+    }
+
+    #[test]
+    fn test_versions_from_refs() {
+        let repo = fixture_repo("versions");
+        let versions = versions(&repo, "conf/config.json", "feature", "main").unwrap();
+        assert_eq!(
+            versions,
+            Versions {
+                base: r#"{"a": 1, "b": 1}"#.to_string(),
+                a: r#"{"a": 3, "b": 1}"#.to_string(),
+                b: r#"{"a": 1, "b": 2}"#.to_string(),
+            }
+        );
+
+        let (merged, conflicts) = crate::merge::merge_strings(
+            &versions.base,
+            &versions.a,
+            &versions.b,
+            &Default::default(),
+        )
+        .unwrap();
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, "{\n  \"a\": 3,\n  \"b\": 2\n}");
+        fs::remove_dir_all(&repo).unwrap();
+    }
+
+    #[test]
+    fn test_errors() {
+        let repo = fixture_repo("errors");
+        let err = versions(&repo, "conf/config.json", "feature", "missing").unwrap_err();
+        assert!(err.starts_with("git merge-base failed: "), "{}", err);
+        let err = show(&repo, "main", "conf/missing.json").unwrap_err();
+        assert!(err.starts_with("git show failed: "), "{}", err);
+        fs::remove_dir_all(&repo).unwrap();
+    }
+}
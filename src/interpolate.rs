@@ -0,0 +1,139 @@
+//! `${NAME}` placeholders in string values, resolved before merging so that
+//! branches are compared by what their values expand to: one branch writing
+//! `${DB_HOST}` and the other the host itself have made the same change.
+
+use crate::pointer;
+use serde_json::Value;
+
+/// Replaces every `${NAME}` in the strings of `document` with `lookup(NAME)`,
+/// failing on a name that `lookup` has no value for. Object keys are left as
+/// they are, and `$${` stands for a literal `${`.
+pub fn interpolate(
+    document: &mut Value,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    walk(document, "", lookup)
+}
+
+fn walk(
+    value: &mut Value,
+    path: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<(), String> {
+    match value {
+        Value::String(text) if text.contains('$') => *text = expand(text, path, lookup)?,
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                walk(child, &pointer::push(path, key), lookup)?;
+            }
+        }
+        Value::Array(items) => {
+            for (index, child) in items.iter_mut().enumerate() {
+                walk(child, &pointer::push(path, &index.to_string()), lookup)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn expand(
+    text: &str,
+    path: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        if let Some(tail) = after.strip_prefix("${") {
+            expanded.push_str("${");
+            rest = tail;
+            continue;
+        }
+        match after
+            .strip_prefix('{')
+            .and_then(|inner| inner.split_once('}'))
+        {
+            Some((name, tail)) if is_name(name) => {
+                let value = lookup(name)
+                    .ok_or_else(|| format!("'{}' uses ${{{}}}, which is not set", path, name))?;
+                expanded.push_str(&value);
+                rest = tail;
+            }
+            _ => {
+                expanded.push('$');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::three_way_merge;
+    use serde_json::json;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some("db.internal".to_string()),
+            "DB_HOST" => Some("db.internal".to_string()),
+            "PORT" => Some("5432".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_interpolate() {
+        let mut document = json!({
+            "url": "postgres://${HOST}:${PORT}/app",
+            "price": "$5 or $${HOST} or ${not a name} or ${",
+            "${HOST}": [1, "${PORT}"]
+        });
+        interpolate(&mut document, &lookup).unwrap();
+        assert_eq!(
+            document,
+            json!({
+                "url": "postgres://db.internal:5432/app",
+                "price": "$5 or ${HOST} or ${not a name} or ${",
+                "${HOST}": [1, "5432"]
+            })
+        );
+
+        let mut document = json!({"a": [{"b": "${MISSING}"}]});
+        assert_eq!(
+            interpolate(&mut document, &lookup),
+            Err("'/a/0/b' uses ${MISSING}, which is not set".to_string())
+        );
+    }
+
+    #[test]
+    fn test_interpolation_decides_conflicts() {
+        // A writes out the host B refers to by another variable, so the
+        // literal values conflict but their expansions are the same.
+        let mut base = json!({"host": "${HOST}", "port": "${PORT}"});
+        let mut a = json!({"host": "db.internal", "port": "${PORT}"});
+        let mut b = json!({"host": "${DB_HOST}", "port": "5433"});
+        let (_, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert!(had_conflicts);
+
+        for document in [&mut base, &mut a, &mut b] {
+            interpolate(document, &lookup).unwrap();
+        }
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert!(!had_conflicts);
+        assert_eq!(merged, json!({"host": "db.internal", "port": "5433"}));
+    }
+}
@@ -0,0 +1,156 @@
+//! JSON Lines datasets, one record per line, merged as a map from each record's
+//! key to the record. The key is read from one or more fields of the record,
+//! so records are matched across versions wherever they are in the file, added
+//! or deleted records follow the usual add and delete rules, and records in
+//! both branches are merged field by field, conflicting under their key.
+
+use serde_json::{Map, Value};
+
+/// The fields whose values together identify a record, such as `region,id`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordKey(Vec<String>);
+
+impl RecordKey {
+    /// Parses a comma-separated list of field names.
+    pub fn parse(list: &str) -> Result<RecordKey, String> {
+        let fields: Vec<String> = list.split(',').map(|f| f.trim().to_string()).collect();
+        if fields.iter().any(String::is_empty) {
+            return Err(format!("'{}' is not a list of field names", list));
+        }
+        Ok(RecordKey(fields))
+    }
+
+    // The key of `record`: its values of the fields as a JSON array, such as
+    // `["eu",42]`, so that no two different keys are written the same.
+    fn of(&self, record: &Value) -> Result<String, String> {
+        let values = self
+            .0
+            .iter()
+            .map(|field| {
+                record
+                    .get(field)
+                    .cloned()
+                    .ok_or_else(|| format!("record has no field '{}'", field))
+            })
+            .collect::<Result<Vec<Value>, String>>()?;
+        Ok(Value::Array(values).to_string())
+    }
+}
+
+/// The records of `text` by key, and the keys in the order of the lines.
+/// Blank lines are skipped. Every other line must be an object with all the
+/// fields of `key`, and no two may have the same key.
+pub fn records(text: &str, key: &RecordKey) -> Result<(Map<String, Value>, Vec<String>), String> {
+    let mut records = Map::new();
+    let mut order = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let line_error = |e: String| format!("line {}: {}", index + 1, e);
+        let record: Value = serde_json::from_str(line).map_err(|e| line_error(e.to_string()))?;
+        if !record.is_object() {
+            return Err(line_error("record is not an object".to_string()));
+        }
+        let id = key.of(&record).map_err(line_error)?;
+        if records.insert(id.clone(), record).is_some() {
+            return Err(line_error(format!("another record has the key {}", id)));
+        }
+        order.push(id);
+    }
+    Ok((records, order))
+}
+
+/// Writes the records of `merged`, a map from key to record, one per line. They
+/// follow the first of `orders` that has their key, so A's order comes first
+/// when given first; keys in none of them come last.
+pub fn to_lines(merged: &Value, orders: &[&[String]]) -> String {
+    let Some(map) = merged.as_object() else {
+        return String::new();
+    };
+    let mut written = std::collections::HashSet::new();
+    let mut out = String::new();
+    let keys = orders
+        .iter()
+        .flat_map(|order| order.iter())
+        .chain(map.keys());
+    for key in keys {
+        if let Some(record) = map.get(key).filter(|_| written.insert(key)) {
+            out.push_str(&record.to_string());
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{three_way_merge_with_options, ConflictKind, MergeOptions};
+    use serde_json::json;
+
+    fn key() -> RecordKey {
+        RecordKey::parse("region, id").unwrap()
+    }
+
+    #[test]
+    fn test_records() {
+        let text = "{\"region\": \"eu\", \"id\": 1, \"n\": 1}\n\n{\"id\": 1, \"region\": \"us\"}\n";
+        let (records, order) = records(text, &key()).unwrap();
+        assert_eq!(order, vec![r#"["eu",1]"#, r#"["us",1]"#]);
+        assert_eq!(
+            records[r#"["eu",1]"#],
+            json!({"region": "eu", "id": 1, "n": 1})
+        );
+
+        let error = |text| super::records(text, &key()).unwrap_err();
+        assert_eq!(error("{\"id\": 1}"), "line 1: record has no field 'region'");
+        assert_eq!(error("\n[1]"), "line 2: record is not an object");
+        assert_eq!(
+            error("{\"region\": \"eu\", \"id\": 1}\n{\"id\": 1, \"region\": \"eu\", \"n\": 2}"),
+            r#"line 2: another record has the key ["eu",1]"#
+        );
+        assert!(error("{").starts_with("line 1: "));
+        assert!(RecordKey::parse("id,").is_err());
+    }
+
+    #[test]
+    fn test_merge_composite_keyed_records() {
+        let base = "\
+{\"region\":\"eu\",\"id\":1,\"name\":\"a\",\"size\":1}
+{\"region\":\"us\",\"id\":1,\"name\":\"b\",\"size\":1}
+{\"region\":\"eu\",\"id\":2,\"name\":\"c\",\"size\":1}
+";
+        // A moves a record up and edits two; B edits them too, deletes one and
+        // adds another.
+        let a = "\
+{\"region\":\"eu\",\"id\":2,\"name\":\"c\",\"size\":1}
+{\"region\":\"eu\",\"id\":1,\"name\":\"a2\",\"size\":1}
+{\"region\":\"us\",\"id\":1,\"name\":\"b\",\"size\":2}
+";
+        let b = "\
+{\"region\":\"eu\",\"id\":1,\"name\":\"a\",\"size\":5}
+{\"region\":\"us\",\"id\":1,\"name\":\"b\",\"size\":3}
+{\"region\":\"us\",\"id\":2,\"name\":\"d\",\"size\":1}
+";
+        let [(base, base_order), (a, a_order), (b, b_order)] =
+            [base, a, b].map(|text| records(text, &key()).unwrap());
+        let (merged, conflicts) = three_way_merge_with_options(
+            &Value::Object(base),
+            &Value::Object(a),
+            &Value::Object(b),
+            &MergeOptions::default(),
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/[\"us\",1]/size");
+        assert_eq!(conflicts[0].kind, ConflictKind::BothModified);
+        assert_eq!(
+            to_lines(&merged, &[&a_order, &b_order, &base_order]),
+            "\
+{\"id\":1,\"name\":\"a2\",\"region\":\"eu\",\"size\":5}
+{\"id\":1,\"name\":\"b\",\"region\":\"us\",\"size\":2}
+{\"id\":2,\"name\":\"d\",\"region\":\"us\",\"size\":1}
+"
+        );
+    }
+}
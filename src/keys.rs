@@ -0,0 +1,81 @@
+//! Checking that object keys follow a naming convention, such as snake_case,
+//! so malformed documents are caught before they are merged.
+
+use crate::pointer;
+use regex::Regex;
+use serde_json::Value;
+
+/// A regular expression every object key must match as a whole.
+#[derive(Debug, Clone)]
+pub struct KeyPattern(Regex);
+
+impl KeyPattern {
+    pub fn parse(pattern: &str) -> Result<KeyPattern, String> {
+        // Checked on its own first, so errors point into the pattern as given.
+        Regex::new(pattern)
+            .and_then(|_| Regex::new(&format!("^(?:{})$", pattern)))
+            .map(KeyPattern)
+            .map_err(|e| format!("invalid key pattern '{}': {}", pattern, e))
+    }
+
+    pub fn matches(&self, key: &str) -> bool {
+        self.0.is_match(key)
+    }
+
+    /// The paths of every key in `document`, at any depth, that does not match,
+    /// in document order.
+    pub fn violations(&self, document: &Value) -> Vec<String> {
+        let mut paths = Vec::new();
+        self.collect(document, "", &mut paths);
+        paths
+    }
+
+    fn collect(&self, value: &Value, path: &str, paths: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                for (key, child) in map {
+                    let child_path = pointer::push(path, key);
+                    if !self.matches(key) {
+                        paths.push(child_path.clone());
+                    }
+                    self.collect(child, &child_path, paths);
+                }
+            }
+            Value::Array(items) => {
+                for (index, child) in items.iter().enumerate() {
+                    self.collect(child, &pointer::push(path, &index.to_string()), paths);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_whole_key_must_match() {
+        let pattern = KeyPattern::parse("[a-z]+(_[a-z]+)*").unwrap();
+        assert!(pattern.matches("max_retries"));
+        assert!(!pattern.matches("maxRetries"));
+        assert!(!pattern.matches("retries_"));
+        assert!(KeyPattern::parse("(").is_err());
+    }
+
+    #[test]
+    fn test_violations_at_any_depth() {
+        let pattern = KeyPattern::parse("[a-z_]+").unwrap();
+        let document = json!({
+            "server": {"hostName": "x", "port": 1},
+            "Users": [{"name": "a"}, {"e/mail": "b"}]
+        });
+        assert_eq!(
+            pattern.violations(&document),
+            vec!["/Users", "/Users/1/e~1mail", "/server/hostName"]
+        );
+        assert!(pattern.violations(&json!(["camelCase"])).is_empty());
+    }
+}
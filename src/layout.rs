@@ -0,0 +1,213 @@
+//! Writing the merged document in branch A's original layout, so that a merge
+//! touching a few top-level keys leaves the text of the others as it was.
+//!
+//! Only the top level is preserved: the entries of A's root object whose value
+//! is unchanged are copied from A's text byte for byte, changed values are
+//! pretty-printed in place, new keys are appended after A's entries and removed
+//! ones are dropped. Anything inside a changed value is reformatted.
+
+use crate::format::{self, FormatRule};
+use crate::pointer;
+use serde_json::Value;
+
+// One entry of the root object, as byte offsets into the text.
+struct Entry {
+    key: String,
+    // Where the whitespace before the key starts, just after `{` or `,`.
+    lead_start: usize,
+    key_start: usize,
+    value_start: usize,
+    value_end: usize,
+}
+
+/// Serializes `merged` reusing the text `a_text` of branch A, which parses to
+/// `a`, for every top-level entry whose value is the same in both. Returns
+/// `None` if either root is not an object.
+pub fn to_string(a_text: &str, a: &Value, merged: &Value, rules: &[FormatRule]) -> Option<String> {
+    let (Value::Object(a_map), Value::Object(merged_map)) = (a, merged) else {
+        return None;
+    };
+    let (open, entries, close) = scan_root(a_text)?;
+
+    // New entries copy the whitespace before A's last key, if it has one.
+    let lead = entries
+        .last()
+        .map_or("\n  ", |entry| &a_text[entry.lead_start..entry.key_start]);
+    let indent = &lead[lead.rfind('\n').map_or(0, |i| i + 1)..];
+    let render = |key: &str, value: &Value| {
+        format::to_string_at(value, rules, &pointer::push("", key))
+            .replace('\n', &format!("\n{}", indent))
+    };
+
+    let mut parts = Vec::new();
+    for entry in &entries {
+        let Some(merged_val) = merged_map.get(&entry.key) else {
+            continue;
+        };
+        if a_map.get(&entry.key) == Some(merged_val) {
+            parts.push(a_text[entry.lead_start..entry.value_end].to_string());
+        } else {
+            parts.push(format!(
+                "{}{}",
+                &a_text[entry.lead_start..entry.value_start],
+                render(&entry.key, merged_val)
+            ));
+        }
+    }
+    for (key, merged_val) in merged_map {
+        if !entries.iter().any(|entry| entry.key == *key) {
+            parts.push(format!(
+                "{}{}: {}",
+                lead,
+                Value::String(key.clone()),
+                render(key, merged_val)
+            ));
+        }
+    }
+
+    // The whitespace before `}`, unless it was only there to separate the
+    // braces of an empty object.
+    let tail = match entries.last() {
+        Some(entry) => &a_text[entry.value_end..close],
+        None if parts.is_empty() => &a_text[open + 1..close],
+        None => "\n",
+    };
+    Some(format!(
+        "{}{}{}{}",
+        &a_text[..=open],
+        parts.join(","),
+        tail,
+        &a_text[close..]
+    ))
+}
+
+// The offsets of the root object's braces and its entries. `text` must be
+// valid JSON.
+fn scan_root(text: &str) -> Option<(usize, Vec<Entry>, usize)> {
+    let bytes = text.as_bytes();
+    let open = skip_whitespace(bytes, 0);
+    if bytes.get(open) != Some(&b'{') {
+        return None;
+    }
+
+    let mut entries = Vec::new();
+    let mut position = open + 1;
+    loop {
+        let lead_start = position;
+        let key_start = skip_whitespace(bytes, position);
+        match bytes.get(key_start)? {
+            b'}' => return Some((open, entries, key_start)),
+            b'"' => {}
+            _ => return None,
+        }
+        let key_end = value_end(bytes, key_start)?;
+        let key: String = serde_json::from_str(&text[key_start..key_end]).ok()?;
+        let colon = skip_whitespace(bytes, key_end);
+        let value_start = skip_whitespace(bytes, colon + 1);
+        let end = value_end(bytes, value_start)?;
+        entries.push(Entry {
+            key,
+            lead_start,
+            key_start,
+            value_start,
+            value_end: end,
+        });
+
+        let next = skip_whitespace(bytes, end);
+        match bytes.get(next)? {
+            b',' => position = next + 1,
+            b'}' => return Some((open, entries, next)),
+            _ => return None,
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut position: usize) -> usize {
+    while bytes
+        .get(position)
+        .is_some_and(|byte| byte.is_ascii_whitespace())
+    {
+        position += 1;
+    }
+    position
+}
+
+// The offset just past the JSON value starting at `start`.
+fn value_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut position = start;
+    loop {
+        match bytes.get(position)? {
+            b'"' => {
+                position += 1;
+                while *bytes.get(position)? != b'"' {
+                    position += if bytes[position] == b'\\' { 2 } else { 1 };
+                }
+            }
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth = depth.checked_sub(1)?,
+            _ => {
+                // A number or literal runs until a delimiter.
+                while bytes
+                    .get(position + 1)
+                    .is_some_and(|byte| !b",}] \t\r\n".contains(byte))
+                {
+                    position += 1;
+                }
+            }
+        }
+        position += 1;
+        if depth == 0 {
+            return Some(position);
+        }
+        position = skip_whitespace(bytes, position);
+        // Separators inside containers.
+        while matches!(bytes.get(position), Some(b',' | b':')) {
+            position = skip_whitespace(bytes, position + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn render(a_text: &str, merged: &Value) -> String {
+        let a: Value = serde_json::from_str(a_text).unwrap();
+        to_string(a_text, &a, merged, &[]).unwrap()
+    }
+
+    #[test]
+    fn test_unchanged_entries_keep_their_text() {
+        let a_text = "{\n    \"zeta\": [1,2,   3],\n    \"alpha\": {\"x\": \"a, \\\"b\\\" }\"},\n    \"port\": 80\n}\n";
+        let a: Value = serde_json::from_str(a_text).unwrap();
+        let mut merged = a.clone();
+        merged["port"] = json!({"http": 81});
+        assert_eq!(
+            render(a_text, &merged),
+            "{\n    \"zeta\": [1,2,   3],\n    \"alpha\": {\"x\": \"a, \\\"b\\\" }\"},\n    \"port\": {\n      \"http\": 81\n    }\n}\n"
+        );
+        assert_eq!(render(a_text, &a), a_text);
+    }
+
+    #[test]
+    fn test_added_and_removed_keys() {
+        let a_text = "{\"b\": 1, \"a\": [true, null]}";
+        assert_eq!(
+            render(a_text, &json!({"a": [true, null], "c": 3})),
+            "{ \"a\": [true, null], \"c\": 3}"
+        );
+        assert_eq!(render(a_text, &json!({"b": 1})), "{\"b\": 1}");
+        assert_eq!(render(a_text, &json!({})), "{}");
+        assert_eq!(render(" {} ", &json!({"k": 1})), " {\n  \"k\": 1\n} ");
+        assert_eq!(render("{ }", &json!({})), "{ }");
+    }
+
+    #[test]
+    fn test_non_object_roots() {
+        let a = json!([1]);
+        assert_eq!(to_string("[1]", &a, &json!([2]), &[]), None);
+        assert_eq!(to_string("[1]", &a, &json!({}), &[]), None);
+    }
+}
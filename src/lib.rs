@@ -0,0 +1,39 @@
+#[macro_use]
+mod logging;
+
+pub mod allowlist;
+pub mod blame;
+pub mod canonical;
+pub mod coercion;
+pub mod config;
+pub mod conflict_file;
+pub mod embedded;
+pub mod envelope;
+pub mod format;
+pub mod git;
+pub mod interpolate;
+pub mod jsonl;
+pub mod keys;
+pub mod layout;
+pub mod line_diff;
+pub mod markers;
+pub mod merge;
+pub mod numeric_keys;
+pub mod ordered;
+#[cfg(feature = "otel")]
+pub mod otel;
+pub mod output;
+pub mod patch;
+pub mod pattern;
+pub mod pointer;
+pub mod records;
+pub mod references;
+pub mod report;
+pub mod resolver;
+pub mod severity;
+pub mod similarity;
+pub mod split;
+pub mod timestamp;
+pub mod tree;
+pub mod verify;
+pub mod version;
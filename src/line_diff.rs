@@ -0,0 +1,385 @@
+//! Unified diffs between texts, line by line, for review in line-based tools
+//! and for applying with `patch` or `git apply`, and the same changes as byte
+//! range edits for updating a text in place.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Keep,
+    Delete,
+    Insert,
+}
+
+/// A unified diff turning `from` into `to`, with `context` unchanged lines
+/// around each change and headers naming the files `a/<from_name>` and
+/// `b/<to_name>`. Empty when the texts are equal.
+pub fn unified(from: &str, to: &str, from_name: &str, to_name: &str, context: usize) -> String {
+    let from_lines: Vec<&str> = from.split_inclusive('\n').collect();
+    let to_lines: Vec<&str> = to.split_inclusive('\n').collect();
+    let edits = edits(&from_lines, &to_lines);
+    if edits.iter().all(|edit| *edit == Edit::Keep) {
+        return String::new();
+    }
+
+    let mut out = format!("--- a/{}\n+++ b/{}\n", from_name, to_name);
+    // Positions in `from` and `to` before each edit.
+    let mut positions = Vec::with_capacity(edits.len() + 1);
+    let (mut i, mut j) = (0, 0);
+    for edit in &edits {
+        positions.push((i, j));
+        match edit {
+            Edit::Keep => (i, j) = (i + 1, j + 1),
+            Edit::Delete => i += 1,
+            Edit::Insert => j += 1,
+        }
+    }
+    positions.push((i, j));
+
+    let mut start = 0;
+    while let Some(offset) = edits[start..].iter().position(|edit| *edit != Edit::Keep) {
+        let first_change = start + offset;
+        let hunk_start = first_change.saturating_sub(context).max(start);
+
+        // The hunk runs until a stretch of more than twice the context of kept
+        // lines, or the end.
+        let mut hunk_end = first_change;
+        let mut kept = 0;
+        for (index, edit) in edits.iter().enumerate().skip(first_change) {
+            if *edit == Edit::Keep {
+                kept += 1;
+                if kept > 2 * context {
+                    break;
+                }
+            } else {
+                kept = 0;
+                hunk_end = index + 1;
+            }
+        }
+        let hunk_end = (hunk_end + context).min(edits.len());
+
+        let (from_start, to_start) = positions[hunk_start];
+        let (from_end, to_end) = positions[hunk_end];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(from_start, from_end - from_start),
+            range(to_start, to_end - to_start)
+        ));
+        for (index, edit) in edits.iter().enumerate().take(hunk_end).skip(hunk_start) {
+            let (i, j) = positions[index];
+            let (prefix, line) = match edit {
+                Edit::Keep => (' ', from_lines[i]),
+                Edit::Delete => ('-', from_lines[i]),
+                Edit::Insert => ('+', to_lines[j]),
+            };
+            out.push(prefix);
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+        start = hunk_end;
+    }
+    out
+}
+
+/// Replacing the bytes `start..end` of a text with `text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub text: String,
+}
+
+/// The edits turning `from` into `to`, in order and not overlapping, with
+/// ranges into `from`. Changes are found line by line, then each is narrowed to
+/// the bytes that differ, so an edit to one value of a line does not replace
+/// the whole line. Empty when the texts are equal.
+pub fn text_edits(from: &str, to: &str) -> Vec<TextEdit> {
+    let from_lines: Vec<&str> = from.split_inclusive('\n').collect();
+    let to_lines: Vec<&str> = to.split_inclusive('\n').collect();
+    let edits = edits(&from_lines, &to_lines);
+
+    let mut text_edits = Vec::new();
+    let (mut i, mut j, mut offset) = (0, 0, 0);
+    let mut index = 0;
+    while index < edits.len() {
+        if edits[index] == Edit::Keep {
+            offset += from_lines[i].len();
+            (i, j, index) = (i + 1, j + 1, index + 1);
+            continue;
+        }
+        let start = offset;
+        let mut inserted = String::new();
+        while index < edits.len() && edits[index] != Edit::Keep {
+            if edits[index] == Edit::Delete {
+                offset += from_lines[i].len();
+                i += 1;
+            } else {
+                inserted.push_str(to_lines[j]);
+                j += 1;
+            }
+            index += 1;
+        }
+        let removed = &from[start..offset];
+        let prefix = common_prefix(removed, &inserted);
+        let suffix = common_suffix(&removed[prefix..], &inserted[prefix..]);
+        text_edits.push(TextEdit {
+            start: start + prefix,
+            end: offset - suffix,
+            text: inserted[prefix..inserted.len() - suffix].to_string(),
+        });
+    }
+    text_edits
+}
+
+/// `text` with `edits`, as returned by `text_edits`, applied.
+pub fn apply_edits(text: &str, edits: &[TextEdit]) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    for edit in edits {
+        out.push_str(&text[copied..edit.start]);
+        out.push_str(&edit.text);
+        copied = edit.end;
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+// The length of the longest common prefix of `x` and `y`, ending on a
+// character boundary of both.
+fn common_prefix(x: &str, y: &str) -> usize {
+    let mut len = x.bytes().zip(y.bytes()).take_while(|(a, b)| a == b).count();
+    while !x.is_char_boundary(len) {
+        len -= 1;
+    }
+    len
+}
+
+fn common_suffix(x: &str, y: &str) -> usize {
+    let mut len = x
+        .bytes()
+        .rev()
+        .zip(y.bytes().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    while !x.is_char_boundary(x.len() - len) {
+        len -= 1;
+    }
+    len
+}
+
+/// The first `max_lines` lines of `diff`, followed by a line saying how many
+/// were left out, if any.
+pub fn truncate(diff: &str, max_lines: usize) -> String {
+    let lines: Vec<&str> = diff.split_inclusive('\n').collect();
+    if lines.len() <= max_lines {
+        return diff.to_string();
+    }
+    format!(
+        "{}... {} more lines\n",
+        lines[..max_lines].concat(),
+        lines.len() - max_lines
+    )
+}
+
+// A hunk range: the first line (1-based, or the line before an empty range)
+// and the count when it is not 1.
+fn range(start: usize, len: usize) -> String {
+    match len {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, len),
+    }
+}
+
+// A shortest edit script from `from` to `to`, by Myers' algorithm, with
+// deletions before insertions within each change. Common leading and trailing
+// lines are kept without searching.
+fn edits(from: &[&str], to: &[&str]) -> Vec<Edit> {
+    let prefix = from.iter().zip(to).take_while(|(x, y)| x == y).count();
+    let suffix = from[prefix..]
+        .iter()
+        .rev()
+        .zip(to[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let from_mid = &from[prefix..from.len() - suffix];
+    let to_mid = &to[prefix..to.len() - suffix];
+
+    let mut edits = vec![Edit::Keep; prefix];
+    let mut middle = middle_edits(from_mid, to_mid);
+    for change in middle.split_mut(|edit| *edit == Edit::Keep) {
+        change.sort_by_key(|edit| *edit == Edit::Insert);
+    }
+    edits.extend(middle);
+    edits.extend(vec![Edit::Keep; suffix]);
+    edits
+}
+
+fn middle_edits(from: &[&str], to: &[&str]) -> Vec<Edit> {
+    let (n, m) = (from.len() as isize, to.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    // `v[k + offset]` is the furthest `x` reached on diagonal `k = x - y`; the
+    // trace keeps a copy per edit distance for the backtrack.
+    let mut v = vec![0isize; 2 * max + 3];
+    let mut trace = Vec::new();
+    'search: for d in 0..=max as isize {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1]
+            } else {
+                v[index - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && from[x as usize] == to[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().skip(1).rev() {
+        let d = d as isize;
+        let k = x - y;
+        let index = (k + offset) as usize;
+        let previous_k = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = v[(previous_k + offset) as usize];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            edits.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        edits.push(if x == previous_x {
+            Edit::Insert
+        } else {
+            Edit::Delete
+        });
+        (x, y) = (previous_x, previous_y);
+    }
+    edits.extend(std::iter::repeat_n(Edit::Keep, x as usize));
+    edits.reverse();
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_equal_texts() {
+        assert_eq!(unified("a\nb\n", "a\nb\n", "x", "y", 3), "");
+    }
+
+    #[test]
+    fn test_hunks_with_context() {
+        let from = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let to = "1\nTWO\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n";
+        assert_eq!(
+            unified(from, to, "base.json", "merged.json", 2),
+            "--- a/base.json\n+++ b/merged.json\n\
+             @@ -1,4 +1,4 @@\n 1\n-2\n+TWO\n 3\n 4\n\
+             @@ -11,2 +11,3 @@\n 11\n 12\n+13\n"
+        );
+    }
+
+    #[test]
+    fn test_close_changes_share_a_hunk() {
+        let from = "a\nb\nc\nd\ne\n";
+        let to = "A\nb\nc\nD\ne\n";
+        assert_eq!(
+            unified(from, to, "f", "f", 1),
+            "--- a/f\n+++ b/f\n@@ -1,5 +1,5 @@\n-a\n+A\n b\n c\n-d\n+D\n e\n"
+        );
+    }
+
+    #[test]
+    fn test_missing_final_newline() {
+        assert_eq!(
+            unified("{\n  \"a\": 1\n}", "{\n  \"a\": 2\n}", "f", "f", 3),
+            "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n {\n-  \"a\": 1\n+  \"a\": 2\n }\n\\ No newline at end of file\n"
+        );
+        assert_eq!(
+            unified("", "x", "f", "f", 3),
+            "--- a/f\n+++ b/f\n@@ -0,0 +1 @@\n+x\n\\ No newline at end of file\n"
+        );
+    }
+
+    #[test]
+    fn test_truncate() {
+        let diff = unified("a\nb\n", "c\nd\n", "f", "f", 3);
+        assert_eq!(truncate(&diff, 8), diff);
+        assert_eq!(
+            truncate(&diff, 3),
+            "--- a/f\n+++ b/f\n@@ -1,2 +1,2 @@\n... 4 more lines\n"
+        );
+    }
+
+    #[test]
+    fn test_edit_script_is_minimal() {
+        let from: Vec<&str> = "a b c a b b a".split(' ').collect();
+        let to: Vec<&str> = "c b a b a c".split(' ').collect();
+        let script = edits(&from, &to);
+        let changes = script.iter().filter(|edit| **edit != Edit::Keep).count();
+        assert_eq!(changes, 5);
+
+        // Replaying the script gives back `to`.
+        let (mut i, mut j) = (0, 0);
+        let mut rebuilt = Vec::new();
+        for edit in script {
+            match edit {
+                Edit::Keep => {
+                    rebuilt.push(from[i]);
+                    i += 1;
+                    j += 1;
+                }
+                Edit::Delete => i += 1,
+                Edit::Insert => {
+                    rebuilt.push(to[j]);
+                    j += 1;
+                }
+            }
+        }
+        assert_eq!(rebuilt, to);
+    }
+
+    #[test]
+    fn test_text_edits() {
+        let from = "{\n  \"a\": 1,\n  \"b\": 2,\n  \"c\": \"été\"\n}";
+        let to = "{\n  \"a\": 10,\n  \"b\": 2,\n  \"c\": \"éta\"\n}\n";
+        let edits = text_edits(from, to);
+        assert_eq!(apply_edits(from, &edits), to);
+        assert_eq!(
+            edits[0],
+            TextEdit {
+                start: 10,
+                end: 10,
+                text: "0".to_string()
+            }
+        );
+        assert_eq!(&from[edits[1].start..edits[1].end], "é\"\n}");
+        assert_eq!(edits[1].text, "a\"\n}\n");
+        assert!(text_edits(from, from).is_empty());
+
+        for (from, to) in [
+            ("", "x\n"),
+            ("x\ny\n", ""),
+            ("a\nb\nc", "c\nb\na"),
+            ("ü", "ö"),
+        ] {
+            assert_eq!(apply_edits(from, &text_edits(from, to)), to);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+//! Logging macros for the merge core. With the `logging` feature they forward
+//! to the `log` facade; without it they compile to nothing, and callers rely on
+//! the structured conflicts alone. The module is `#[macro_use]`, so the macros
+//! are in scope throughout the crate.
+
+#[cfg(feature = "logging")]
+macro_rules! error {
+    ($($arg:tt)*) => { log::error!($($arg)*) };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! warn {
+    ($($arg:tt)*) => { log::warn!($($arg)*) };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! debug {
+    ($($arg:tt)*) => { log::debug!($($arg)*) };
+}
+
+#[cfg(feature = "logging")]
+macro_rules! trace {
+    ($($arg:tt)*) => { log::trace!($($arg)*) };
+}
+
+// The arguments are still type-checked, so both builds see the same code.
+#[cfg(not(feature = "logging"))]
+macro_rules! error {
+    ($($arg:tt)*) => {
+        if false {
+            let _ = format_args!($($arg)*);
+        }
+    };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! warn {
+    ($($arg:tt)*) => { error!($($arg)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! debug {
+    ($($arg:tt)*) => { error!($($arg)*) };
+}
+
+#[cfg(not(feature = "logging"))]
+macro_rules! trace {
+    ($($arg:tt)*) => { error!($($arg)*) };
+}
@@ -1,30 +1,453 @@
 use clap::{Arg, Command};
 use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use three_way_merge::{
+    allowlist, blame, canonical, coercion, config, conflict_file, embedded, envelope, format, git,
+    interpolate, jsonl, keys, layout, line_diff, markers, merge, numeric_keys, ordered, output,
+    patch, pattern, pointer, references, report, resolver, severity, split, tree, verify, version,
+};
 
-mod merge;
+mod completions;
 
-fn parse_path(v: &str) -> Result<PathBuf, String> {
-    let path = PathBuf::from(v);
-    if path.exists() {
-        Ok(path)
+const IGNORE_FILE: &str = ".twmignore";
+const PROFILE_FILE: &str = ".twmprofiles.json";
+/// The exit code when the output exceeds `--max-output-bytes`.
+const EXIT_OUTPUT_TOO_LARGE: i32 = 3;
+
+/// Time spent in each phase of the run, for `--profile`.
+#[derive(Default)]
+struct Profile {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Profile {
+    // Adds the time since `start` to `phase`.
+    fn record(&mut self, phase: &'static str, start: Instant) {
+        let elapsed = start.elapsed();
+        match self.phases.iter_mut().find(|(name, _)| *name == phase) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((phase, elapsed)),
+        }
+    }
+
+    fn render(&self) -> String {
+        let total: Duration = self.phases.iter().map(|(_, duration)| *duration).sum();
+        let mut out = String::new();
+        for (name, duration) in self.phases.iter().chain([&("total", total)]) {
+            out.push_str(&format!(
+                "{:<10} {:>10.3} ms\n",
+                name,
+                duration.as_secs_f64() * 1000.0
+            ));
+        }
+        out
+    }
+}
+
+/// The files a run writes. With `atomic` set each is first written next to
+/// its destination under a temporary name, and only `commit` moves them all
+/// into place, so a run that fails partway leaves none of them behind.
+struct Outputs {
+    atomic: bool,
+    // Temporary files and where they go.
+    staged: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Outputs {
+    fn new(atomic: bool) -> Outputs {
+        Outputs {
+            atomic,
+            staged: Vec::new(),
+        }
+    }
+
+    fn write(&mut self, path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+        let path = path.as_ref();
+        if !self.atomic {
+            return fs::write(path, contents);
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+        fs::write(&temp, contents)?;
+        self.staged.push((temp, path.to_path_buf()));
+        Ok(())
+    }
+
+    // Renames are not undone if a later one fails, but as they stay within a
+    // directory they fail only when its permissions change mid-run.
+    fn commit(&mut self) -> std::io::Result<()> {
+        while !self.staged.is_empty() {
+            let (temp, path) = self.staged.remove(0);
+            if let Err(e) = fs::rename(&temp, &path) {
+                let _ = fs::remove_file(&temp);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Outputs {
+    fn drop(&mut self) {
+        for (temp, _) in &self.staged {
+            let _ = fs::remove_file(temp);
+        }
+    }
+}
+
+// Where the merged document goes. Destinations other than the filesystem, such
+// as a remote store, are supported by adding writers.
+trait OutputWriter {
+    // The destination, as named in messages.
+    fn name(&self) -> &str;
+
+    fn write(&mut self, outputs: &mut Outputs, contents: String) -> std::io::Result<()>;
+
+    // Whether the document goes to stdout, so other output must keep off it.
+    fn uses_stdout(&self) -> bool {
+        false
+    }
+}
+
+// Writes to a file, through `Outputs` like the other artifacts.
+struct FileWriter(String);
+
+impl OutputWriter for FileWriter {
+    fn name(&self) -> &str {
+        &self.0
+    }
+
+    fn write(&mut self, outputs: &mut Outputs, contents: String) -> std::io::Result<()> {
+        outputs.write(&self.0, contents)
+    }
+}
+
+// Writes to a stream, stdout outside tests.
+struct StdoutWriter<W: Write = std::io::Stdout>(W);
+
+impl<W: Write> OutputWriter for StdoutWriter<W> {
+    fn name(&self) -> &str {
+        "stdout"
+    }
+
+    fn write(&mut self, _: &mut Outputs, contents: String) -> std::io::Result<()> {
+        self.0.write_all(contents.as_bytes())?;
+        if !contents.ends_with('\n') {
+            self.0.write_all(b"\n")?;
+        }
+        self.0.flush()
+    }
+
+    fn uses_stdout(&self) -> bool {
+        true
+    }
+}
+
+// The writer for `--output`, where `-` is stdout.
+fn output_writer(output: &str) -> Box<dyn OutputWriter> {
+    match output {
+        "-" => Box::new(StdoutWriter(std::io::stdout())),
+        path => Box::new(FileWriter(path.to_string())),
+    }
+}
+
+// The arguments setting the options of a profile, leaving out those given on
+// the command line.
+fn profile_args(
+    command: &Command,
+    matches: &clap::ArgMatches,
+    options: &[config::ProfileOption],
+) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for option in options {
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(option.name.as_str()))
+            .ok_or_else(|| format!("unknown option '{}'", option.name))?;
+        let id = arg.get_id().as_str();
+        if id == "options_profile" || id == "profile_file" {
+            return Err(format!("a profile cannot set '{}'", option.name));
+        }
+        if matches.value_source(id) == Some(clap::parser::ValueSource::CommandLine) {
+            continue;
+        }
+        match (arg.get_action().takes_values(), option.values.is_empty()) {
+            (false, true) => args.push(format!("--{}", option.name)),
+            (true, false) => args.extend(
+                option
+                    .values
+                    .iter()
+                    .map(|value| format!("--{}={}", option.name, value)),
+            ),
+            (false, false) => return Err(format!("'{}' is a flag, so true or false", option.name)),
+            (true, true) => return Err(format!("'{}' needs a value", option.name)),
+        }
+    }
+    Ok(args)
+}
+
+fn read_input(path: &Path, name: &str) -> Result<String, String> {
+    if !path.exists() {
+        return Err(format!("File '{}' does not exist", path.display()));
+    }
+    fs::read_to_string(path).map_err(|e| format!("Failed to read {} file: {}", name, e))
+}
+
+// Reads and parses an input file from its bytes, for --read-bytes, so its text
+// is never held as well.
+fn read_document(path: &Path, name: &str, allow_empty: bool) -> Result<Value, String> {
+    if !path.exists() {
+        return Err(format!("File '{}' does not exist", path.display()));
+    }
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {} file: {}", name, e))?;
+    if bytes.trim_ascii().is_empty() {
+        return check_not_empty(String::new(), name, allow_empty)
+            .map(|_| Value::Object(Default::default()));
+    }
+    serde_json::from_slice(&bytes).map_err(|e| format!("Failed to parse {} JSON: {}", name, e))
+}
+
+// Rejects an input that is empty or only whitespace, which usually means it was
+// not written properly, or with `allow_empty` reads it as `{}`.
+fn check_not_empty(text: String, name: &str, allow_empty: bool) -> Result<String, String> {
+    if !text.trim().is_empty() {
+        Ok(text)
+    } else if allow_empty {
+        Ok("{}".to_string())
     } else {
-        Err(format!("File '{}' does not exist", v))
+        Err(format!(
+            "The {} file is empty; pass --allow-empty to read it as {{}}",
+            name
+        ))
     }
 }
 
+// Reads and parses every input and option file given, for --validate-only,
+// with each file's name and whether it parsed, in argument order.
+fn validate(matches: &clap::ArgMatches) -> Vec<(String, Result<(), String>)> {
+    type Check = fn(&str) -> Result<(), String>;
+    let json: Check = |text| {
+        serde_json::from_str::<Value>(text)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    };
+    let allow_empty = matches.get_flag("allow_empty");
+    let mut results = Vec::new();
+    for (arg, name) in [
+        ("base", "base"),
+        ("branch_a", "branch A"),
+        ("branch_b", "branch B"),
+    ] {
+        if arg == "base" && matches.contains_id("infer_base") {
+            continue;
+        }
+        let path = matches.get_one::<PathBuf>(arg).unwrap();
+        let result = read_input(path, name)
+            .and_then(|text| check_not_empty(text, name, allow_empty))
+            .and_then(
+                |text| match matches.get_one::<jsonl::RecordKey>("jsonl_key") {
+                    Some(key) => jsonl::records(&text, key).map(|_| ()),
+                    None => json(&text),
+                },
+            );
+        results.push((path.display().to_string(), result));
+    }
+    let patch_check: Check = |text| {
+        serde_json::from_str::<Vec<patch::Operation>>(text)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    };
+    let files: [(&str, Check); 8] = [
+        ("rules", |text| config::rules(text).map(|_| ())),
+        ("coercions", |text| {
+            coercion::Coercions::parse(text).map(|_| ())
+        }),
+        ("pre_patch_base", patch_check),
+        ("pre_patch_a", patch_check),
+        ("pre_patch_b", patch_check),
+        ("post_patch", patch_check),
+        ("apply_conflicts", |text| {
+            conflict_file::parse(text).map(|_| ())
+        }),
+        ("allowed_keys", |text| {
+            pattern::parse_pattern_list(text).map(|_| ())
+        }),
+    ];
+    let mut paths: Vec<(String, Check)> = files
+        .into_iter()
+        .filter_map(|(arg, check)| Some((matches.get_one::<String>(arg)?.clone(), check)))
+        .collect();
+    if !matches.get_flag("no_ignore_file") && Path::new(IGNORE_FILE).exists() {
+        paths.push((IGNORE_FILE.to_string(), |text| {
+            pattern::parse_pattern_list(text).map(|_| ())
+        }));
+    }
+    for (path, check) in paths {
+        let result = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| check(&text));
+        results.push((path, result));
+    }
+    results
+}
+
+fn parse_preference(v: &str) -> Result<Vec<merge::Side>, String> {
+    let mut order = Vec::new();
+    for name in v.split(',') {
+        let side = match name.trim() {
+            "a" => merge::Side::A,
+            "b" => merge::Side::B,
+            "base" => merge::Side::Base,
+            other => {
+                return Err(format!(
+                    "unknown version '{}', expected a, b or base",
+                    other
+                ))
+            }
+        };
+        if order.contains(&side) {
+            return Err(format!("'{}' is listed more than once", name.trim()));
+        }
+        order.push(side);
+    }
+    Ok(order)
+}
+
+fn parse_combined_keys(v: &str) -> Result<[String; 3], String> {
+    let keys: Vec<String> = v.split(',').map(|key| key.trim().to_string()).collect();
+    match <[String; 3]>::try_from(keys) {
+        Ok(keys) if keys[0] != keys[1] && keys[0] != keys[2] && keys[1] != keys[2] => Ok(keys),
+        _ => Err(format!(
+            "'{}' is not three different keys for base, A and B",
+            v
+        )),
+    }
+}
+
+fn parse_timeout(v: &str) -> Result<Duration, String> {
+    match v.parse::<f64>() {
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(Duration::from_secs_f64(seconds)),
+        _ => Err(format!("'{}' is not a positive number of seconds", v)),
+    }
+}
+
+// A key name matches that key in every object; anything starting with `/` is
+// a pattern over the keys' paths.
+fn parse_compare_key(v: &str) -> Result<pattern::PathPattern, String> {
+    if v.starts_with('/') {
+        pattern::PathPattern::parse(v)
+    } else if v.is_empty() {
+        Err("a key name cannot be empty".to_string())
+    } else {
+        pattern::PathPattern::parse(&format!("/**/{}", pointer::escape(v)))
+    }
+}
+
+fn parse_threshold(v: &str) -> Result<f64, String> {
+    match v.parse::<f64>() {
+        Ok(threshold) if (0.0..=1.0).contains(&threshold) => Ok(threshold),
+        _ => Err(format!("'{}' is not a number between 0 and 1", v)),
+    }
+}
+
+// Appends the span of a merge that started at `start` to `path`, in the trace
+// of the `TRACEPARENT` variable if it is set, for the service named by
+// `OTEL_SERVICE_NAME`.
+#[cfg(feature = "otel")]
+fn write_span(
+    path: &str,
+    start: std::time::SystemTime,
+    conflicts: &[merge::Conflict],
+    input_bytes: [usize; 3],
+    partial: bool,
+) -> Result<(), String> {
+    use std::io::Write;
+    use three_way_merge::otel;
+
+    let unresolved = conflicts.iter().filter(|c| !c.is_resolved()).count();
+    let mut attributes = vec![
+        ("merge.conflicts".to_string(), conflicts.len().into()),
+        ("merge.conflicts.unresolved".to_string(), unresolved.into()),
+    ];
+    for (name, bytes) in ["base", "a", "b"].iter().zip(input_bytes) {
+        attributes.push((format!("merge.input.{}.bytes", name), bytes.into()));
+    }
+    attributes.push(("merge.partial".to_string(), partial.into()));
+    let span = otel::Span {
+        name: "merge".to_string(),
+        start,
+        end: std::time::SystemTime::now(),
+        attributes,
+        error: (unresolved > 0).then(|| format!("unresolved conflicts: {}", unresolved)),
+    };
+    let context = otel::Context::from_traceparent(std::env::var("TRACEPARENT").ok().as_deref());
+    let service_name =
+        std::env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "three-way-merge".to_string());
+    let line = otel::to_otlp(&span, &context, &otel::random_id(8), &service_name).to_string();
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| writeln!(file, "{}", line))
+        .map_err(|e| e.to_string())
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    colog::init();
+    // The level is set from the flags below, so the logger itself lets
+    // everything through unless RUST_LOG says otherwise.
+    let mut logger = colog::default_builder();
+    if std::env::var_os("RUST_LOG").is_none() {
+        logger.filter_level(log::LevelFilter::Trace);
+    }
+    logger.init();
+
+    let command = command();
+    let matches = command.clone().get_matches();
+
+    if let Some(("completions", sub_matches)) = matches.subcommand() {
+        let shell = sub_matches.get_one::<String>("shell").unwrap();
+        print!("{}", completions::generate(&command, shell));
+        return Ok(());
+    }
 
-    let matches = Command::new("three-way-merge")
+    let matches = with_options_profile(&command, matches)?;
+    let mut profile = Profile::default();
+    let code = run(&matches, &mut profile);
+    // Printed however the run ends, with the phases it got through.
+    if matches.get_flag("profile") {
+        eprint!("{}", profile.render());
+    }
+    match code? {
+        0 => Ok(()),
+        code => std::process::exit(code),
+    }
+}
+
+fn command() -> Command {
+    let command = Command::new("three-way-merge")
         .about("Performs a three-way merge of JSON files")
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("completions")
+                .about("Print a shell completion script")
+                .arg(
+                    Arg::new("shell")
+                        .help("Shell to complete for")
+                        .required(true)
+                        .value_parser(completions::SHELLS),
+                ),
+        )
         .arg(
             Arg::new("base")
                 .help("Base version of the file")
                 .short('b')
                 .long("base")
-                .value_parser(parse_path)
+                .value_parser(clap::value_parser!(PathBuf))
                 .default_value("base.json"),
         )
         .arg(
@@ -32,7 +455,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Branch A version of the file")
                 .short('a')
                 .long("branch-a")
-                .value_parser(parse_path)
+                .value_parser(clap::value_parser!(PathBuf))
                 .default_value("branch_a.json"),
         )
         .arg(
@@ -40,12 +463,12 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .help("Branch B version of the file")
                 .short('c')
                 .long("branch-b")
-                .value_parser(parse_path)
+                .value_parser(clap::value_parser!(PathBuf))
                 .default_value("branch_b.json"),
         )
         .arg(
             Arg::new("output")
-                .help("Output file path")
+                .help("Output file path, or - for stdout")
                 .short('o')
                 .long("output")
                 .default_value("merged.json"),
@@ -57,59 +480,2407 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .long("verbose")
                 .action(clap::ArgAction::SetTrue),
         )
-        .get_matches();
+        .arg(
+            Arg::new("trace_keys")
+                .help("Log each object key with the versions that have it and what the merge decided")
+                .long("trace-keys")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("warn_on_drop")
+                .help("Warn of every key or element that a version has and the output leaves out, and why")
+                .long("warn-on-drop")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("allow_empty")
+                .help("Read empty or whitespace-only inputs as {} instead of failing")
+                .long("allow-empty")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("no_ignore_file")
+                .help("Do not read path exclusions from .twmignore")
+                .long("no-ignore-file")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("ignore_compare_keys")
+                .help("Leave these object keys, by name or pointer pattern, out of comparisons (comma-separated, repeatable)")
+                .long("ignore-compare-keys")
+                .value_name("keys")
+                .value_delimiter(',')
+                .action(clap::ArgAction::Append)
+                .value_parser(parse_compare_key),
+        )
+        .arg(
+            Arg::new("ignore_compare_keys_from")
+                .help("The version whose values of --ignore-compare-keys keys are kept")
+                .long("ignore-compare-keys-from")
+                .value_parser(["a", "b", "base"])
+                .default_value("a")
+                .requires("ignore_compare_keys"),
+        )
+        .arg(
+            Arg::new("array_mode")
+                .help("How arrays modified in both branches are merged")
+                .long("array-mode")
+                .value_parser([
+                    "atomic",
+                    "positional",
+                    "similarity",
+                    "ordered-union",
+                    "keyed",
+                    "keyed-ordered",
+                ])
+                .default_value("atomic"),
+        )
+        .arg(
+            Arg::new("array_key")
+                .help("Field identifying array elements in the keyed modes")
+                .long("array-key")
+                .value_name("field")
+                .default_value("id"),
+        )
+        .arg(
+            Arg::new("similarity_threshold")
+                .help("Minimum similarity for pairing array elements in similarity mode")
+                .long("similarity-threshold")
+                .value_parser(parse_threshold)
+                .default_value("0.5"),
+        )
+        .arg(
+            Arg::new("rule")
+                .help("Use a strategy or array mode at paths matching a pattern, e.g. /tags=positional (repeatable)")
+                .long("rule")
+                .value_name("pattern=setting")
+                .value_parser(merge::PathRule::parse)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("options_profile")
+                .help("Apply the options of this profile from the profiles file, except those given on the command line")
+                .long("options-profile")
+                .value_name("name"),
+        )
+        .arg(
+            Arg::new("profile_file")
+                .help("The JSON file of profiles for --options-profile")
+                .long("profile-file")
+                .value_name("file")
+                .default_value(PROFILE_FILE),
+        )
+        .arg(
+            Arg::new("rules")
+                .help("Read per-path rules from this JSON file, before any --rule")
+                .long("rules")
+                .value_name("file"),
+        )
+        .arg(
+            Arg::new("strategy")
+                .help("How conflicts are resolved")
+                .long("strategy")
+                .value_parser(["manual", "ours", "theirs", "average-numeric", "delete-wins", "prefer-longer-string"])
+                .default_value("manual"),
+        )
+        .arg(
+            Arg::new("average_weight_a")
+                .help("Weight of branch A's number in the average-numeric strategy, from 0 to 1")
+                .long("average-weight-a")
+                .value_parser(parse_threshold)
+                .default_value("0.5"),
+        )
+        .arg(
+            Arg::new("prefer_matching")
+                .help("Resolve scalar conflicts with --prefer-matching-side if its value matches this regex, else with the other branch")
+                .long("prefer-matching")
+                .value_name("regex")
+                .value_parser(merge::ValuePattern::parse)
+                .conflicts_with_all(["strategy", "prefer"]),
+        )
+        .arg(
+            Arg::new("prefer_matching_side")
+                .help("The branch --prefer-matching prefers")
+                .long("prefer-matching-side")
+                .value_parser(["a", "b"])
+                .default_value("a")
+                .requires("prefer_matching"),
+        )
+        .arg(
+            Arg::new("prefer")
+                .help("Resolve conflicts with the first of these versions that has a value, e.g. a,b,base")
+                .long("prefer")
+                .value_name("order")
+                .value_parser(parse_preference)
+                .conflicts_with("strategy"),
+        )
+        .arg(
+            Arg::new("prefer_changed")
+                .help("With --prefer, skip branches whose value is the same as base's")
+                .long("prefer-changed")
+                .requires("prefer")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("resolver_cmd")
+                .help("Run this shell command to resolve each conflict the strategy left")
+                .long("resolver-cmd")
+                .value_name("command"),
+        )
+        .arg(
+            Arg::new("conflict_markers")
+                .help("Write unresolved conflicts into the output as marker values holding each version")
+                .long("conflict-markers")
+                .value_name("style")
+                .value_parser(["array", "object"]),
+        )
+        .arg(
+            Arg::new("marker_template")
+                .help("Set the text of a conflict marker: a, b, base (also adds base's version) or separator (repeatable)")
+                .long("marker-template")
+                .value_name("part=text")
+                .requires("conflict_markers")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("keep_both")
+                .help("Replace each unresolved conflicting key with suffixed keys holding each branch's value")
+                .long("keep-both")
+                .conflicts_with("conflict_markers")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("keep_both_suffix_a")
+                .help("Suffix of the key holding branch A's value with --keep-both")
+                .long("keep-both-suffix-a")
+                .value_name("suffix")
+                .default_value(".A"),
+        )
+        .arg(
+            Arg::new("keep_both_suffix_b")
+                .help("Suffix of the key holding branch B's value with --keep-both")
+                .long("keep-both-suffix-b")
+                .value_name("suffix")
+                .default_value(".B"),
+        )
+        .arg(
+            Arg::new("emit_conflicts")
+                .help("Write unresolved conflicts to an editable file for --apply-conflicts")
+                .long("emit-conflicts")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("apply_conflicts")
+                .help("Resolve conflicts with the decisions in a file written by --emit-conflicts")
+                .long("apply-conflicts")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("safe_types")
+                .help("Never auto-resolve type mismatches; refuse to write output if any occur")
+                .long("safe-types")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("force_key")
+                .help("Let a branch override the other at a value it wraps as {\"<key>\": <value>} [default key: __force__]")
+                .long("force-key")
+                .value_name("key")
+                .num_args(0..=1)
+                .default_missing_value("__force__"),
+        )
+        .arg(
+            Arg::new("null_coalesce")
+                .help("Resolve a conflict where one branch set the value to null by taking the other branch's value")
+                .long("null-coalesce")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("pre_patch_base")
+                .help("Apply the RFC 6902 JSON patch in this file to base before merging")
+                .long("pre-patch-base")
+                .value_name("file")
+                .conflicts_with("dir_mode"),
+        )
+        .arg(
+            Arg::new("pre_patch_a")
+                .help("Apply the RFC 6902 JSON patch in this file to branch A before merging, such as renaming fields to match branch B")
+                .long("pre-patch-a")
+                .value_name("file")
+                .conflicts_with("dir_mode"),
+        )
+        .arg(
+            Arg::new("pre_patch_b")
+                .help("Apply the RFC 6902 JSON patch in this file to branch B before merging")
+                .long("pre-patch-b")
+                .value_name("file")
+                .conflicts_with("dir_mode"),
+        )
+        .arg(
+            Arg::new("post_patch")
+                .help("Apply the RFC 6902 JSON patch in this file to the merged document, such as a schema migration")
+                .long("post-patch")
+                .value_name("file"),
+        )
+        .arg(
+            Arg::new("bump_version")
+                .help("Bump the version number at this JSON pointer when the merge succeeds")
+                .long("bump-version")
+                .value_name("pointer")
+                .conflicts_with("dir_mode"),
+        )
+        .arg(
+            Arg::new("bump_version_mode")
+                .help("The version for --bump-version where a branch changed it: the higher of the branches' versions, or one more")
+                .long("bump-version-mode")
+                .value_parser(["max", "increment"])
+                .default_value("max")
+                .requires("bump_version"),
+        )
+        .arg(
+            Arg::new("jsonl_key")
+                .help("Read the inputs as JSON Lines, merging records identified by these fields")
+                .long("jsonl-key")
+                .value_name("field,...")
+                .value_parser(jsonl::RecordKey::parse)
+                .conflicts_with_all([
+                    "dir_mode",
+                    "combined",
+                    "infer_base",
+                    "envelope_field",
+                    "canonical",
+                    "minimal_output",
+                    "preserve_layout",
+                    "verbatim_unchanged",
+                ]),
+        )
+        .arg(
+            Arg::new("envelope_field")
+                .help("Merge only this field of each document, keeping the envelope from branch A")
+                .long("envelope-field")
+                .value_name("field"),
+        )
+        .arg(
+            Arg::new("require_equal")
+                .help("Envelope field that must be equal in all inputs (repeatable)")
+                .long("require-equal")
+                .value_name("field")
+                .requires("envelope_field")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("report")
+                .help("Write a conflict report to this file")
+                .long("report")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("report_junit")
+                .help("Write the conflicts to this file as a JUnit XML report, failing on unresolved ones")
+                .long("report-junit")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("resolutions_out")
+                .help("Write the conflicts resolved automatically, and how, to this file as JSON")
+                .long("resolutions-out")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("conflicts_resolved_out")
+                .help("Write a document holding only the conflicting paths, with the values the merge chose, to this file")
+                .long("conflicts-resolved-out")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("changelog_out")
+                .help("Write what the merge changed and how its conflicts ended, in sentences, to this file")
+                .long("changelog-out")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("plan_out")
+                .help("Write where the merge takes each value of its result from, as JSON, to this file")
+                .long("plan-out")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("html_out")
+                .help("Write a self-contained HTML page showing each change in base, both branches and the output, with conflicts highlighted")
+                .long("html-out")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("full_output")
+                .help("Write the merged document, conflicts, stats, input sources and resolutions to this file as one JSON object")
+                .long("full-output")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("split_output")
+                .help("Also write each top-level entry of the merged document to its own file in this directory")
+                .long("split-output")
+                .value_name("dir"),
+        )
+        .arg(
+            Arg::new("group_by_section")
+                .help("Group the conflict report by top-level section")
+                .long("group-by-section")
+                .requires("report")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("parse_embedded_json")
+                .help("Merge strings holding JSON at paths matching this pattern as JSON (repeatable)")
+                .long("parse-embedded-json")
+                .value_name("pattern")
+                .value_parser(pattern::PathPattern::parse)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("numeric_key_objects_as_arrays")
+                .help("Merge objects keyed \"0\", \"1\" and on as arrays, writing them back as objects")
+                .long("numeric-key-objects-as-arrays")
+                .conflicts_with("envelope_field")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("blame")
+                .help("Print where each conflicting value is in the three inputs, by line and column, to stderr")
+                .long("blame")
+                // Records and sections are read back from text other than the
+                // files, so their lines are not the files' lines.
+                .conflicts_with_all(["dir_mode", "jsonl_key", "combined"])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("interpolate_env")
+                .help("Replace ${NAME} in string values with environment variables before merging")
+                .long("interpolate-env")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("authoritative_a")
+                .help("Always take branch A's value at paths matching this pattern (repeatable)")
+                .long("authoritative-a")
+                .value_name("pattern")
+                .value_parser(pattern::PathPattern::parse)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("authoritative_b")
+                .help("Always take branch B's value at paths matching this pattern (repeatable)")
+                .long("authoritative-b")
+                .value_name("pattern")
+                .value_parser(pattern::PathPattern::parse)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("key_scope")
+                .help("Which keys may appear in merged objects")
+                .long("key-scope")
+                .value_parser(["union", "base", "intersection"])
+                .default_value("union"),
+        )
+        .arg(
+            Arg::new("values_only")
+                .help("Keep exactly base's keys, merging only changes to existing values")
+                .long("values-only")
+                .conflicts_with("key_scope")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("undo_patch")
+                .help("Write a JSON patch that turns the merged output back into branch A")
+                .long("undo-patch")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("preserve_layout")
+                .help("Copy top-level entries the merge left unchanged from branch A's text as is")
+                .long("preserve-layout")
+                .conflicts_with("minimal_output")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("canonical")
+                .help("Write the output as canonical JSON (RFC 8785), byte-stable for hashing and signing")
+                .long("canonical")
+                .conflicts_with_all(["preserve_layout", "format_rule", "verbatim_unchanged"])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max_output_bytes")
+                .help("Refuse to write an output larger than this many bytes, exiting with code 3")
+                .long("max-output-bytes")
+                .value_name("bytes")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("max_severity")
+                .help("Succeed despite unresolved conflicts as long as their total severity is at most this")
+                .long("max-severity")
+                .value_name("score")
+                .value_parser(clap::value_parser!(u64)),
+        )
+        .arg(
+            Arg::new("severity")
+                .help("Set the severity score of a conflict kind, e.g. both_modified=2 (repeatable)")
+                .long("severity")
+                .value_name("kind=score")
+                .requires("max_severity")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("minimal_output")
+                .help("Write only what the merge changed in branch A, as a JSON merge patch")
+                .long("minimal-output")
+                .conflicts_with("verbatim_unchanged")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("unified_diff_out")
+                .help("Write a unified diff from base to the merged output, both pretty-printed")
+                .long("unified-diff-out")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("preview")
+                .help("Print a unified diff from base to the merged output to stderr before writing it")
+                .long("preview")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("preview_lines")
+                .help("Maximum number of diff lines printed by --preview")
+                .long("preview-lines")
+                .value_name("lines")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("40"),
+        )
+        .arg(
+            Arg::new("diff_a_out")
+                .help("Write a JSON patch of branch A's changes from base")
+                .long("diff-a-out")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("diff_b_out")
+                .help("Write a JSON patch of branch B's changes from base")
+                .long("diff-b-out")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("record_key")
+                .help("Treat the top level as a map of records identified by this field, detecting renamed keys")
+                .long("record-key")
+                .value_name("field"),
+        )
+        .arg(
+            Arg::new("list_conflict_paths")
+                .help("Print only the paths of unresolved conflicts, one per line")
+                .long("list-conflict-paths")
+                .conflicts_with("verbose")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("atomic_outputs")
+                .help("Write the output and every report only once all of them have been written in full")
+                .long("atomic-outputs")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verbatim_unchanged")
+                .help("When all inputs are identical, copy them to the output byte for byte")
+                .long("verbatim-unchanged")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("format_rule")
+                .help("Format values at paths matching a pattern as compact or pretty (repeatable)")
+                .long("format-rule")
+                .value_name("pattern=style")
+                .value_parser(format::FormatRule::parse)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("number_precision")
+                .help("Round non-integer numbers in the output to this many decimal places")
+                .long("number-precision")
+                .value_name("places")
+                .value_parser(clap::value_parser!(usize))
+                .conflicts_with("verbatim_unchanged"),
+        )
+        .arg(
+            Arg::new("validate_only")
+                .help("Check that the inputs and option files parse, reporting on each, without merging")
+                .long("validate-only")
+                .conflicts_with_all(["git", "combined", "dir_mode", "pre_commit", "summary_only"])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry_run")
+                .help("Print the value the strategy takes for each conflict without writing any file; exit 1 if any is left unresolved")
+                .long("dry-run")
+                .conflicts_with_all([
+                    "dir_mode",
+                    "verbose",
+                    "pre_commit",
+                    "summary_only",
+                    "list_conflict_paths",
+                    "report",
+                    "undo_patch",
+                    "diff_a_out",
+                    "diff_b_out",
+                    "emit_conflicts",
+                    "resolutions_out",
+                    "conflicts_resolved_out",
+                    "changelog_out",
+                    "html_out",
+                    "plan_out",
+                    "unified_diff_out",
+                    "report_junit",
+                    "full_output",
+                    "split_output",
+                ])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("pre_commit")
+                .help("Check for conflicts without writing any file; exit 1 if there are any")
+                .long("pre-commit")
+                .conflicts_with_all([
+                    "verbose",
+                    "list_conflict_paths",
+                    "report",
+                    "undo_patch",
+                    "diff_a_out",
+                    "diff_b_out",
+                    "emit_conflicts",
+                    "resolutions_out",
+                    "conflicts_resolved_out",
+                    "changelog_out",
+                    "html_out",
+                    "plan_out",
+                    "unified_diff_out",
+                    "report_junit",
+                    "full_output",
+                    "split_output",
+                ])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("summary_only")
+                .help("Print how many values each branch added, removed and modified and how many conflicts merging them gives, without writing any file")
+                .long("summary-only")
+                .conflicts_with_all(["dir_mode", "pre_commit", "list_conflict_paths", "stats"])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("status_json")
+                .help("Print only a JSON status object with the conflict count and output file to stdout")
+                .long("status-json")
+                .conflicts_with_all(["list_conflict_paths", "stats"])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verify")
+                .help("Check that re-merging the result with each branch gives it back, failing otherwise")
+                .long("verify")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("verify_roundtrip")
+                .help("Check that the output as written parses back to the merged document, failing otherwise")
+                .long("verify-roundtrip")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("timeout")
+                .help("Stop merging after this many seconds, failing unless --allow-partial")
+                .long("timeout")
+                .value_name("seconds")
+                .value_parser(parse_timeout),
+        )
+        .arg(
+            Arg::new("allow_partial")
+                .help("When --timeout stops the merge, write what was merged so far and then fail")
+                .long("allow-partial")
+                .requires("timeout")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("profile")
+                .help("Print the time spent reading, parsing, merging, serializing and writing to stderr")
+                .long("profile")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("read_bytes")
+                .help("Parse each input file from its bytes as read, without keeping its text, for the largest inputs")
+                .long("read-bytes")
+                // These read the inputs from elsewhere or need their text.
+                .conflicts_with_all([
+                    "git",
+                    "combined",
+                    "infer_base",
+                    "dir_mode",
+                    "jsonl_key",
+                    "ordered_object",
+                    "blame",
+                    "changelog_out",
+                    "html_out",
+                    "preview",
+                    "unified_diff_out",
+                    "preserve_layout",
+                    "verbatim_unchanged",
+                ])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("stats")
+                .help("Print a summary of the conflicts and document depth")
+                .long("stats")
+                .conflicts_with_all(["list_conflict_paths", "pre_commit"])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("string_whitespace_insensitive")
+                .help("Treat strings differing only in trailing whitespace or line endings as equal")
+                .long("string-whitespace-insensitive")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("array_set_semantics")
+                .help("Compare arrays ignoring element order, keeping branch A's order in the output")
+                .long("array-set-semantics")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("max_array_len")
+                .help("Report an ordered-union array longer than this as a conflict, at every path or at paths matching the pattern (repeatable)")
+                .long("max-array-len")
+                .value_name("[pattern=]max")
+                .action(clap::ArgAction::Append)
+                .value_parser(merge::ArrayLengthLimit::parse),
+        )
+        .arg(
+            Arg::new("truncate_long_arrays")
+                .help("Keep the first elements of an array over --max-array-len instead of conflicting")
+                .long("truncate-long-arrays")
+                .requires("max_array_len")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("empty_string_as_absent")
+                .help("Treat empty strings as absent values, so blanking a value deletes it")
+                .long("empty-string-as-absent")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("coerce_bool")
+                .help("Treat \"yes\", \"true\" and 1 as true and \"no\", \"false\" and 0 as false when comparing values")
+                .long("coerce-bool")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("review_numbers")
+                .help("Report a number changed in one branch as a number_changed conflict, keeping base's number, instead of taking the change")
+                .long("review-numbers")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("coerce_numeric_strings")
+                .help("Treat a string holding a number, such as \"42\", as equal to that number when comparing values")
+                .long("coerce-numeric-strings")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("coercions")
+                .help("Treat the scalars in each class of this JSON file as equal when comparing values")
+                .long("coercions")
+                .value_name("file"),
+        )
+        .arg(
+            Arg::new("timestamp_tolerance")
+                .help("Treat ISO 8601 timestamps at paths matching the pattern as equal when at most this many seconds apart (repeatable)")
+                .long("timestamp-tolerance")
+                .value_name("pattern=seconds")
+                .action(clap::ArgAction::Append)
+                .value_parser(merge::TimestampTolerance::parse),
+        )
+        .arg(
+            Arg::new("ref_field")
+                .help("Check that every value of this field names an id defined by --id-field after merging (repeatable)")
+                .long("ref-field")
+                .value_name("field")
+                .requires("id_field")
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("allowed_keys")
+                .help("Fail if the merged document has a key no pattern in this file allows")
+                .long("allowed-keys")
+                .value_name("path"),
+        )
+        .arg(
+            Arg::new("id_field")
+                .help("Field whose values are the ids --ref-field values refer to")
+                .long("id-field")
+                .value_name("field")
+                .requires("ref_field"),
+        )
+        .arg(
+            Arg::new("key_pattern")
+                .help("Warn about object keys in the inputs that do not match this regular expression as a whole")
+                .long("key-pattern")
+                .value_name("REGEX")
+                .value_parser(keys::KeyPattern::parse),
+        )
+        .arg(
+            Arg::new("strict_keys")
+                .help("Fail instead of merging when a key does not match --key-pattern")
+                .long("strict-keys")
+                .requires("key_pattern")
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("git")
+                .help("Merge this file as of --ref-a and --ref-b, reading all three versions from git")
+                .long("git")
+                .value_name("file")
+                .requires_all(["ref_a", "ref_b"])
+                .conflicts_with_all(["base", "branch_a", "branch_b"]),
+        )
+        .arg(
+            Arg::new("combined")
+                .help("Read base, A and B from sections of this one JSON file")
+                .long("combined")
+                .value_name("file")
+                .value_parser(clap::value_parser!(PathBuf))
+                .conflicts_with_all(["base", "branch_a", "branch_b", "git", "infer_base"]),
+        )
+        .arg(
+            Arg::new("combined_keys")
+                .help("Keys of the base, A and B sections of the --combined file")
+                .long("combined-keys")
+                .value_name("base,a,b")
+                .value_parser(parse_combined_keys)
+                .default_value("base,a,b")
+                .requires("combined"),
+        )
+        .arg(
+            Arg::new("dir_mode")
+                .help("Merge directories: the inputs and the output are directory trees, with .json files merged as documents")
+                .long("dir-mode")
+                // Directories are merged and written as trees with the merge
+                // options alone, which none of the other inputs, checks and
+                // outputs apply to.
+                .conflicts_with_all([
+                    "git",
+                    "infer_base",
+                    "combined",
+                    "apply_conflicts",
+                    "resolver_cmd",
+                    "conflict_markers",
+                    "keep_both",
+                    "emit_conflicts",
+                    "post_patch",
+                    "envelope_field",
+                    "report",
+                    "report_junit",
+                    "resolutions_out",
+                    "conflicts_resolved_out",
+                    "changelog_out",
+                    "plan_out",
+                    "html_out",
+                    "full_output",
+                    "split_output",
+                    "parse_embedded_json",
+                    "numeric_key_objects_as_arrays",
+                    "interpolate_env",
+                    "undo_patch",
+                    "preserve_layout",
+                    "canonical",
+                    "max_output_bytes",
+                    "max_severity",
+                    "minimal_output",
+                    "unified_diff_out",
+                    "preview",
+                    "diff_a_out",
+                    "diff_b_out",
+                    "list_conflict_paths",
+                    "atomic_outputs",
+                    "verbatim_unchanged",
+                    "format_rule",
+                    "number_precision",
+                    "pre_commit",
+                    "status_json",
+                    "verify",
+                    "verify_roundtrip",
+                    "timeout",
+                    "stats",
+                    "allowed_keys",
+                    "id_field",
+                    "key_pattern",
+                    "ordered_object",
+                ])
+                .action(clap::ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("infer_base")
+                .help("Merge without a base file, using what the branches have in common as the base")
+                .long("infer-base")
+                .value_parser(["intersection"])
+                // The inferred base has no key order of its own to compare with.
+                .conflicts_with_all(["base", "git", "ordered_object"]),
+        )
+        .arg(
+            Arg::new("ref_a")
+                .help("Git ref for branch A, used with --git")
+                .long("ref-a")
+                .value_name("ref")
+                .requires("git"),
+        )
+        .arg(
+            Arg::new("ref_b")
+                .help("Git ref for branch B, used with --git")
+                .long("ref-b")
+                .value_name("ref")
+                .requires("git"),
+        )
+        .arg(
+            Arg::new("ordered_object")
+                .help("Treat key order of objects at paths matching this pattern as significant (repeatable)")
+                .long("ordered-object")
+                .visible_alias("order-significant")
+                .value_name("pattern")
+                .value_parser(pattern::PathPattern::parse)
+                .action(clap::ArgAction::Append),
+        )
+        .arg(
+            Arg::new("array_object_mismatch")
+                .help("What to do when one branch has an array and the other an object")
+                .long("array-object-mismatch")
+                .value_parser(["conflict", "take-a", "take-b"])
+                .default_value("conflict"),
+        )
+        .arg(
+            Arg::new("array_null_mismatch")
+                .help("What to do when one branch nulls an array the other edited")
+                .long("array-null-mismatch")
+                .value_parser(["conflict", "take-edit", "take-null"])
+                .default_value("conflict"),
+        );
+    #[cfg(feature = "otel")]
+    let command = command.arg(
+        Arg::new("otel_out")
+            .help("Append the merge as an OpenTelemetry span, in OTLP/JSON, to this file")
+            .long("otel-out")
+            .value_name("path")
+            .conflicts_with("dir_mode"),
+    );
+    command
+}
 
-    let base_path = matches.get_one::<PathBuf>("base").unwrap();
-    let branch_a_path = matches.get_one::<PathBuf>("branch_a").unwrap();
-    let branch_b_path = matches.get_one::<PathBuf>("branch_b").unwrap();
+// The arguments of a profile's options go before the command line's, which are
+// parsed again after them.
+fn with_options_profile(
+    command: &Command,
+    matches: clap::ArgMatches,
+) -> Result<clap::ArgMatches, String> {
+    let Some(name) = matches.get_one::<String>("options_profile") else {
+        return Ok(matches);
+    };
+    let file = matches.get_one::<String>("profile_file").unwrap();
+    let contents =
+        fs::read_to_string(file).map_err(|e| format!("Failed to read profiles file: {}", e))?;
+    let options = config::profile(&contents, name)
+        .map_err(|e| format!("Failed to parse profiles file: {}", e))?;
+    let profile_args = profile_args(command, &matches, &options)
+        .map_err(|e| format!("Failed to apply profile '{}': {}", name, e))?;
+    let mut args = std::env::args_os();
+    let program = args.next().unwrap_or_default();
+    Ok(command.clone().get_matches_from(
+        std::iter::once(program)
+            .chain(profile_args.into_iter().map(Into::into))
+            .chain(args),
+    ))
+}
+
+// Runs what the command line asks for, returning the exit code when it does
+// not fail.
+fn run(
+    matches: &clap::ArgMatches,
+    profile: &mut Profile,
+) -> Result<i32, Box<dyn std::error::Error>> {
     let output_path = matches.get_one::<String>("output").unwrap();
-    let verbose = matches.get_flag("verbose");
+    let writer = output_writer(output_path);
+    if writer.uses_stdout() {
+        let stdout_flags = ["dir_mode", "status_json", "list_conflict_paths", "stats"];
+        if let Some(flag) = stdout_flags.iter().find(|flag| matches.get_flag(flag)) {
+            return Err(format!(
+                "--output - cannot be combined with --{}, which also writes to stdout or a directory",
+                flag.replace('_', "-")
+            )
+            .into());
+        }
+    }
+    set_log_level(matches);
 
-    if verbose {
+    if matches.get_flag("validate_only") {
+        let parse_start = Instant::now();
+        let code = validate_only(matches);
+        profile.record("parse", parse_start);
+        return code;
+    }
+
+    let mut options = merge_options(matches)?;
+    let (merged, source) = if matches.get_flag("dir_mode") {
+        let (merged, entries) = merge_trees(matches, &mut options, profile)?;
+        (merged, Source::Tree(entries))
+    } else {
+        let read_start = Instant::now();
+        let mut inputs = read_inputs(matches)?;
+        profile.record("read", read_start);
+        match merge_inputs(matches, &mut inputs, &mut options, profile)? {
+            Some(merged) => (merged, Source::Text(inputs)),
+            None => return Ok(0),
+        }
+    };
+    finish(matches, &options, merged, &source, writer, profile)
+}
+
+fn set_log_level(matches: &clap::ArgMatches) {
+    let quiet = [
+        "list_conflict_paths",
+        "pre_commit",
+        "summary_only",
+        "dry_run",
+    ];
+    if quiet.iter().any(|flag| matches.get_flag(flag)) {
+        log::set_max_level(log::LevelFilter::Off);
+    } else if matches.get_flag("trace_keys") {
+        log::set_max_level(log::LevelFilter::Trace);
+    } else if matches.get_flag("verbose") {
         log::set_max_level(log::LevelFilter::Debug);
     } else {
         log::set_max_level(log::LevelFilter::Info);
     }
+}
+
+fn validate_only(matches: &clap::ArgMatches) -> Result<i32, Box<dyn std::error::Error>> {
+    let results = validate(matches);
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    for (path, result) in &results {
+        match result {
+            Ok(()) => println!("{}: ok", path),
+            Err(e) => println!("{}: {}", path, e),
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} files failed to parse", failed, results.len()).into());
+    }
+    Ok(0)
+}
 
-    let base_str =
-        fs::read_to_string(base_path).map_err(|e| format!("Failed to read base file: {}", e))?;
+// What the three versions were read from, as named in outputs.
+fn provenance(matches: &clap::ArgMatches) -> output::Provenance {
+    let base_path = matches.get_one::<PathBuf>("base").unwrap();
+    let branch_a_path = matches.get_one::<PathBuf>("branch_a").unwrap();
+    let branch_b_path = matches.get_one::<PathBuf>("branch_b").unwrap();
+    if let Some(file) = matches.get_one::<String>("git") {
+        let ref_a = matches.get_one::<String>("ref_a").unwrap();
+        let ref_b = matches.get_one::<String>("ref_b").unwrap();
+        return output::Provenance {
+            base: format!("{} at the merge base of {} and {}", file, ref_a, ref_b),
+            a: format!("{} at {}", file, ref_a),
+            b: format!("{} at {}", file, ref_b),
+        };
+    }
+    if let Some(path) = matches.get_one::<PathBuf>("combined") {
+        let keys = matches.get_one::<[String; 3]>("combined_keys").unwrap();
+        let path = path.display();
+        return output::Provenance {
+            base: format!("section '{}' of {}", keys[0], path),
+            a: format!("section '{}' of {}", keys[1], path),
+            b: format!("section '{}' of {}", keys[2], path),
+        };
+    }
+    output::Provenance {
+        base: match matches.get_one::<String>("infer_base") {
+            Some(method) => format!("inferred from both branches ({})", method),
+            None => base_path.display().to_string(),
+        },
+        a: branch_a_path.display().to_string(),
+        b: branch_b_path.display().to_string(),
+    }
+}
 
-    let branch_a_str = fs::read_to_string(branch_a_path)
-        .map_err(|e| format!("Failed to read branch A file: {}", e))?;
+// The name branch A's conflicts are reported under by --pre-commit.
+fn branch_a_name(matches: &clap::ArgMatches) -> String {
+    match (
+        matches.get_one::<String>("git"),
+        matches.get_one::<PathBuf>("combined"),
+    ) {
+        (Some(file), _) => file.clone(),
+        (None, Some(path)) => path.display().to_string(),
+        (None, None) => matches
+            .get_one::<PathBuf>("branch_a")
+            .unwrap()
+            .display()
+            .to_string(),
+    }
+}
 
-    let branch_b_str = fs::read_to_string(branch_b_path)
-        .map_err(|e| format!("Failed to read branch B file: {}", e))?;
+fn merge_options(matches: &clap::ArgMatches) -> Result<merge::MergeOptions, String> {
+    let mut options = merge::MergeOptions {
+        array_mode: match matches.get_one::<String>("array_mode").unwrap().as_str() {
+            "positional" => merge::ArrayMergeMode::Positional,
+            "ordered-union" => merge::ArrayMergeMode::OrderedUnion,
+            "keyed" => merge::ArrayMergeMode::Keyed {
+                key: matches.get_one::<String>("array_key").unwrap().clone(),
+            },
+            "keyed-ordered" => merge::ArrayMergeMode::KeyedOrdered {
+                key: matches.get_one::<String>("array_key").unwrap().clone(),
+            },
+            "similarity" => merge::ArrayMergeMode::Similarity {
+                threshold: *matches.get_one::<f64>("similarity_threshold").unwrap(),
+            },
+            _ => merge::ArrayMergeMode::Atomic,
+        },
+        strategy: match (
+            matches.get_one::<Vec<merge::Side>>("prefer"),
+            matches.get_one::<String>("strategy").unwrap().as_str(),
+        ) {
+            (Some(order), _) => merge::ConflictStrategy::Prefer {
+                order: order.clone(),
+                changed_only: matches.get_flag("prefer_changed"),
+            },
+            _ if matches.contains_id("prefer_matching") => {
+                merge::ConflictStrategy::PreferMatching {
+                    pattern: matches
+                        .get_one::<merge::ValuePattern>("prefer_matching")
+                        .unwrap()
+                        .clone(),
+                    prefer: match matches
+                        .get_one::<String>("prefer_matching_side")
+                        .unwrap()
+                        .as_str()
+                    {
+                        "b" => merge::Side::B,
+                        _ => merge::Side::A,
+                    },
+                }
+            }
+            (None, "ours") => merge::ConflictStrategy::Ours,
+            (None, "delete-wins") => merge::ConflictStrategy::DeleteWins,
+            (None, "prefer-longer-string") => merge::ConflictStrategy::PreferLongerString,
+            (None, "theirs") => merge::ConflictStrategy::Theirs,
+            (None, "average-numeric") => merge::ConflictStrategy::AverageNumeric {
+                weight_a: *matches.get_one::<f64>("average_weight_a").unwrap(),
+            },
+            (None, _) => merge::ConflictStrategy::Manual,
+        },
+        safe_types: matches.get_flag("safe_types"),
+        null_coalesce: matches.get_flag("null_coalesce"),
+        key_scope: match matches.get_one::<String>("key_scope").unwrap().as_str() {
+            _ if matches.get_flag("values_only") => merge::KeyScope::ValuesOnly,
+            "base" => merge::KeyScope::Base,
+            "intersection" => merge::KeyScope::Intersection,
+            _ => merge::KeyScope::Union,
+        },
+        record_key: matches.get_one::<String>("record_key").cloned(),
+        array_object_mismatch: match matches
+            .get_one::<String>("array_object_mismatch")
+            .unwrap()
+            .as_str()
+        {
+            "take-a" => merge::ArrayObjectMismatch::TakeA,
+            "take-b" => merge::ArrayObjectMismatch::TakeB,
+            _ => merge::ArrayObjectMismatch::Conflict,
+        },
+        array_null_mismatch: match matches
+            .get_one::<String>("array_null_mismatch")
+            .unwrap()
+            .as_str()
+        {
+            "take-edit" => merge::ArrayNullMismatch::TakeEdit,
+            "take-null" => merge::ArrayNullMismatch::TakeNull,
+            _ => merge::ArrayNullMismatch::Conflict,
+        },
+        string_whitespace_insensitive: matches.get_flag("string_whitespace_insensitive"),
+        treat_empty_string_as_absent: matches.get_flag("empty_string_as_absent"),
+        array_set_semantics: matches.get_flag("array_set_semantics"),
+        coerce_numeric_strings: matches.get_flag("coerce_numeric_strings"),
+        review_numbers: matches.get_flag("review_numbers"),
+        array_length_limits: matches
+            .get_many::<merge::ArrayLengthLimit>("max_array_len")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        truncate_long_arrays: matches.get_flag("truncate_long_arrays"),
+        authoritative_a: matches
+            .get_many::<pattern::PathPattern>("authoritative_a")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        authoritative_b: matches
+            .get_many::<pattern::PathPattern>("authoritative_b")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        rules: matches
+            .get_many::<merge::PathRule>("rule")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        log_sink: Some(merge::LogSink::new(|event| match event.level {
+            merge::LogLevel::Error => log::error!("{}", event.message),
+            merge::LogLevel::Warn => log::warn!("{}", event.message),
+            merge::LogLevel::Debug => log::debug!("{}", event.message),
+            merge::LogLevel::Trace => log::trace!("{}", event.message),
+        })),
+        trace_keys: matches.get_flag("trace_keys"),
+        warn_on_drop: matches.get_flag("warn_on_drop"),
+        force_key: matches.get_one::<String>("force_key").cloned(),
+        timestamp_tolerances: matches
+            .get_many::<merge::TimestampTolerance>("timestamp_tolerance")
+            .unwrap_or_default()
+            .cloned()
+            .collect(),
+        ignore_compare: matches
+            .get_many::<pattern::PathPattern>("ignore_compare_keys")
+            .map(|keys| merge::IgnoreCompare {
+                keys: keys.cloned().collect(),
+                source: match matches
+                    .get_one::<String>("ignore_compare_keys_from")
+                    .unwrap()
+                    .as_str()
+                {
+                    "b" => merge::Side::B,
+                    "base" => merge::Side::Base,
+                    _ => merge::Side::A,
+                },
+            }),
+        ..Default::default()
+    };
+    if let Some(rules_path) = matches.get_one::<String>("rules") {
+        let rules_str = fs::read_to_string(rules_path)
+            .map_err(|e| format!("Failed to read rules file: {}", e))?;
+        let rules =
+            config::rules(&rules_str).map_err(|e| format!("Failed to parse rules file: {}", e))?;
+        options.rules.splice(0..0, rules);
+    }
+    if matches.get_flag("coerce_bool") {
+        options.coercions = coercion::Coercions::booleans();
+    }
+    if let Some(coercions_path) = matches.get_one::<String>("coercions") {
+        let coercions_str = fs::read_to_string(coercions_path)
+            .map_err(|e| format!("Failed to read coercions file: {}", e))?;
+        let coercions = coercion::Coercions::parse(&coercions_str)
+            .map_err(|e| format!("Failed to parse coercions file: {}", e))?;
+        options
+            .coercions
+            .extend(coercions)
+            .map_err(|e| format!("Failed to parse coercions file: {}", e))?;
+    }
+    if !matches.get_flag("no_ignore_file") && Path::new(IGNORE_FILE).exists() {
+        let ignore_str = fs::read_to_string(IGNORE_FILE)
+            .map_err(|e| format!("Failed to read {}: {}", IGNORE_FILE, e))?;
+        options.ignore = pattern::parse_pattern_list(&ignore_str)
+            .map_err(|e| format!("Failed to parse {}: {}", IGNORE_FILE, e))?;
+        log::debug!(
+            "Loaded {} ignore patterns from {}",
+            options.ignore.len(),
+            IGNORE_FILE
+        );
+    }
+    Ok(options)
+}
 
-    let base_json: Value =
-        serde_json::from_str(&base_str).map_err(|e| format!("Failed to parse base JSON: {}", e))?;
+/// The three versions of a document, as text.
+struct Inputs {
+    base: String,
+    a: String,
+    b: String,
+    // With --jsonl-key, the order of each version's records, as base, A, B.
+    record_orders: Vec<Vec<String>>,
+    // With --read-bytes, the versions parsed as read, as base, A, B, and the
+    // texts are left empty.
+    documents: Option<[Value; 3]>,
+}
 
-    let branch_a_json: Value = serde_json::from_str(&branch_a_str)
-        .map_err(|e| format!("Failed to parse branch A JSON: {}", e))?;
+fn read_inputs(matches: &clap::ArgMatches) -> Result<Inputs, String> {
+    let base_path = matches.get_one::<PathBuf>("base").unwrap();
+    let branch_a_path = matches.get_one::<PathBuf>("branch_a").unwrap();
+    let branch_b_path = matches.get_one::<PathBuf>("branch_b").unwrap();
+    let allow_empty = matches.get_flag("allow_empty");
+    if matches.get_flag("read_bytes") {
+        let documents = [
+            read_document(base_path, "base", allow_empty)?,
+            read_document(branch_a_path, "branch A", allow_empty)?,
+            read_document(branch_b_path, "branch B", allow_empty)?,
+        ];
+        return Ok(Inputs {
+            base: String::new(),
+            a: String::new(),
+            b: String::new(),
+            record_orders: Vec::new(),
+            documents: Some(documents),
+        });
+    }
+    let input = |text: String, name: &str| check_not_empty(text, name, allow_empty);
+    let (base, a, b) = match matches.get_one::<String>("git") {
+        Some(file) => {
+            let ref_a = matches.get_one::<String>("ref_a").unwrap();
+            let ref_b = matches.get_one::<String>("ref_b").unwrap();
+            let versions = git::versions(Path::new("."), file, ref_a, ref_b)
+                .map_err(|e| format!("Failed to read '{}' from git: {}", file, e))?;
+            (
+                input(versions.base, "base")?,
+                input(versions.a, "branch A")?,
+                input(versions.b, "branch B")?,
+            )
+        }
+        None if matches.contains_id("combined") => {
+            let path = matches.get_one::<PathBuf>("combined").unwrap();
+            let text = input(read_input(path, "combined")?, "combined")?;
+            let document: Value = serde_json::from_str(&text)
+                .map_err(|e| format!("Failed to parse combined JSON: {}", e))?;
+            let combined_keys = matches.get_one::<[String; 3]>("combined_keys").unwrap();
+            let keys = combined_keys.each_ref().map(String::as_str);
+            let [base, a, b] = envelope::sections(&document, keys)
+                .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+            (base.to_string(), a.to_string(), b.to_string())
+        }
+        // Paths are only checked here, as the defaults need not exist with --git
+        // or --infer-base.
+        None if matches.contains_id("infer_base") => {
+            let branch_a_str = input(read_input(branch_a_path, "branch A")?, "branch A")?;
+            let branch_b_str = input(read_input(branch_b_path, "branch B")?, "branch B")?;
+            let parse = |text: &str, name: &str| {
+                serde_json::from_str::<Value>(text)
+                    .map_err(|e| format!("Failed to parse {} JSON: {}", name, e))
+            };
+            let base = merge::infer_base(
+                &parse(&branch_a_str, "branch A")?,
+                &parse(&branch_b_str, "branch B")?,
+            );
+            (base.to_string(), branch_a_str, branch_b_str)
+        }
+        None => (
+            input(read_input(base_path, "base")?, "base")?,
+            input(read_input(branch_a_path, "branch A")?, "branch A")?,
+            input(read_input(branch_b_path, "branch B")?, "branch B")?,
+        ),
+    };
 
-    let branch_b_json: Value = serde_json::from_str(&branch_b_str)
-        .map_err(|e| format!("Failed to parse branch B JSON: {}", e))?;
+    // JSON Lines inputs are merged as maps from record key to record, and each
+    // version's order of keys is kept to write the records back in.
+    let mut texts = [base, a, b];
+    let mut record_orders = Vec::new();
+    if let Some(key) = matches.get_one::<jsonl::RecordKey>("jsonl_key") {
+        for (text, name) in texts.iter_mut().zip(["base", "branch A", "branch B"]) {
+            let (records, order) = jsonl::records(text, key)
+                .map_err(|e| format!("Failed to parse {} JSON Lines: {}", name, e))?;
+            *text = Value::Object(records).to_string();
+            record_orders.push(order);
+        }
+    }
+    let [base, a, b] = texts;
+    Ok(Inputs {
+        base,
+        a,
+        b,
+        record_orders,
+        documents: None,
+    })
+}
+
+/// A merge's result, and what its outputs need to know of how it was made.
+struct Merged {
+    document: Value,
+    conflicts: Vec<merge::Conflict>,
+    // The depth of the deepest input.
+    depth: usize,
+    // What the merge takes from where, for --plan-out.
+    plan: Vec<merge::PlanStep>,
+    // Each branch's changes from base as read, empty when the inputs are the
+    // same.
+    branch_diffs: (Vec<patch::Operation>, Vec<patch::Operation>),
+    // Whether the merge timed out, and only holds what was merged in time.
+    partial: bool,
+    // With outputs relative to it, branch A before any preprocessing.
+    original_a: Option<Value>,
+    // Whether the inputs were identical, so branch A was taken as it is.
+    unchanged: bool,
+}
+
+/// What the merged document is written as.
+enum Source {
+    // One document, written as the inputs were read.
+    Text(Inputs),
+    // A directory tree, whose files the entries of A, B and base describe.
+    Tree([HashMap<String, tree::Entry>; 3]),
+}
+
+fn merge_trees(
+    matches: &clap::ArgMatches,
+    options: &mut merge::MergeOptions,
+    profile: &mut Profile,
+) -> Result<(Merged, [HashMap<String, tree::Entry>; 3]), String> {
+    let read_start = Instant::now();
+    let read_tree = |arg: &str, name: &str| {
+        let path = matches.get_one::<PathBuf>(arg).unwrap();
+        tree::read(path).map_err(|e| format!("Failed to read {} directory: {}", name, e))
+    };
+    let base_tree = read_tree("base", "base")?;
+    let a_tree = read_tree("branch_a", "branch A")?;
+    let b_tree = read_tree("branch_b", "branch B")?;
+    profile.record("read", read_start);
+
+    let merge_start = Instant::now();
+    // Conflicts are reported here rather than by the merge, so they can name
+    // the file they are in.
+    options.log_sink = Some(merge::LogSink::new(|event| match event.level {
+        merge::LogLevel::Debug => log::debug!("{}", event.message),
+        merge::LogLevel::Trace => log::trace!("{}", event.message),
+        _ => {}
+    }));
+    let (document, conflicts) = merge::three_way_merge_with_options(
+        &base_tree.document,
+        &a_tree.document,
+        &b_tree.document,
+        options,
+    );
+    let entries = [&a_tree.entries, &b_tree.entries, &base_tree.entries];
+    for conflict in &conflicts {
+        let label = tree::label(&conflict.path, &entries);
+        match conflict.resolution {
+            Some(side) => log::warn!(
+                "Conflict: file '{}' {}; taking {}",
+                label,
+                conflict.kind.description(),
+                side
+            ),
+            None => log::error!("Conflict: file '{}' {}", label, conflict.kind.description()),
+        }
+    }
+    profile.record("merge", merge_start);
+    let depth = [&base_tree.document, &a_tree.document, &b_tree.document]
+        .into_iter()
+        .map(report::depth)
+        .max()
+        .unwrap_or(0);
+    let merged = Merged {
+        document,
+        conflicts,
+        depth,
+        plan: Vec::new(),
+        branch_diffs: Default::default(),
+        partial: false,
+        original_a: None,
+        unchanged: false,
+    };
+    Ok((merged, [a_tree.entries, b_tree.entries, base_tree.entries]))
+}
+
+// Merges the inputs, taking any documents already parsed, or with
+// --summary-only prints the summary of the merge instead and returns `None`.
+fn merge_inputs(
+    matches: &clap::ArgMatches,
+    inputs: &mut Inputs,
+    options: &mut merge::MergeOptions,
+    profile: &mut Profile,
+) -> Result<Option<Merged>, Box<dyn std::error::Error>> {
+    let documents = inputs.documents.take();
+    let Inputs {
+        base: base_str,
+        a: branch_a_str,
+        b: branch_b_str,
+        ..
+    } = &*inputs;
+    let from_bytes = documents.is_some();
+    let summary_only = matches.get_flag("summary_only");
+    let parse_start = Instant::now();
+    let (parsed_base, branch_a_json, parsed_b) = match documents {
+        Some([base, a, b]) => (Some(base), a, Some(b)),
+        None => (
+            None,
+            serde_json::from_str(branch_a_str)
+                .map_err(|e| format!("Failed to parse branch A JSON: {}", e))?,
+            None,
+        ),
+    };
+    profile.record("parse", parse_start);
+
+    // Keys not following the naming convention are reported for every input
+    // before merging, and with --strict-keys stop the merge.
+    let check_keys = |documents: &[(&str, &Value)]| -> Result<(), String> {
+        let Some(key_pattern) = matches.get_one::<keys::KeyPattern>("key_pattern") else {
+            return Ok(());
+        };
+        let mut violations = 0;
+        for (name, document) in documents {
+            for path in key_pattern.violations(document) {
+                log::warn!("Key '{}' in {} does not match --key-pattern", path, name);
+                violations += 1;
+            }
+        }
+        if violations > 0 && matches.get_flag("strict_keys") {
+            return Err(format!("{} keys do not match --key-pattern", violations));
+        }
+        Ok(())
+    };
+
+    // Kept before any preprocessing, so the undo patch restores the file as read
+    // and the minimal and layout-preserving outputs are relative to it.
+    let original_a = (matches.contains_id("undo_patch")
+        || matches.get_flag("minimal_output")
+        || matches.get_flag("preserve_layout"))
+    .then(|| branch_a_json.clone());
+
+    let envelope_field = matches.get_one::<String>("envelope_field");
+    let interpolate_env = matches.get_flag("interpolate_env");
+    let embedded_patterns: Vec<pattern::PathPattern> = matches
+        .get_many::<pattern::PathPattern>("parse_embedded_json")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    let read_patch = |arg: &str, name: &str| -> Result<Option<Vec<patch::Operation>>, String> {
+        let Some(patch_path) = matches.get_one::<String>(arg) else {
+            return Ok(None);
+        };
+        let patch_str = fs::read_to_string(patch_path)
+            .map_err(|e| format!("Failed to read {}: {}", name, e))?;
+        serde_json::from_str(&patch_str)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse {}: {}", name, e))
+    };
+    let post_patch = read_patch("post_patch", "post-merge patch")?;
+    let pre_patches = [
+        ("base", read_patch("pre_patch_base", "pre-patch for base")?),
+        (
+            "branch A",
+            read_patch("pre_patch_a", "pre-patch for branch A")?,
+        ),
+        (
+            "branch B",
+            read_patch("pre_patch_b", "pre-patch for branch B")?,
+        ),
+    ];
+
+    // Identical inputs merge to themselves, so the other two need not even be
+    // parsed, unless an option rewrites the documents.
+    let unchanged = !from_bytes
+        && base_str == branch_a_str
+        && branch_a_str == branch_b_str
+        && envelope_field.is_none()
+        && embedded_patterns.is_empty()
+        && !interpolate_env
+        && !summary_only
+        && options.force_key.is_none()
+        && !matches.contains_id("bump_version")
+        && post_patch.is_none()
+        && pre_patches
+            .iter()
+            .all(|(_, operations)| operations.is_none());
+
+    let mut branch_diffs = (Vec::new(), Vec::new());
+    let mut plan = Vec::new();
+    let mut partial = false;
+    #[cfg(feature = "otel")]
+    let span_start = std::time::SystemTime::now();
+    let (merged, conflicts, document_depth) = if unchanged {
+        log::debug!("All inputs are identical, skipping the merge");
+        check_keys(&[("branch A", &branch_a_json)])?;
+        if matches.contains_id("plan_out") {
+            plan = merge::plan(&branch_a_json, &branch_a_json, &branch_a_json, options);
+        }
+        let document_depth = report::depth(&branch_a_json);
+        (branch_a_json, Vec::new(), document_depth)
+    } else {
+        let mut branch_a_json = branch_a_json;
+        let parse_start = Instant::now();
+        let mut base_json: Value = match parsed_base {
+            Some(base) => base,
+            None => serde_json::from_str(base_str)
+                .map_err(|e| format!("Failed to parse base JSON: {}", e))?,
+        };
+        let mut branch_b_json: Value = match parsed_b {
+            Some(b) => b,
+            None => serde_json::from_str(branch_b_str)
+                .map_err(|e| format!("Failed to parse branch B JSON: {}", e))?,
+        };
+        profile.record("parse", parse_start);
+        let merge_start = Instant::now();
+        check_keys(&[
+            ("base", &base_json),
+            ("branch A", &branch_a_json),
+            ("branch B", &branch_b_json),
+        ])?;
+        if matches.contains_id("diff_a_out") || matches.contains_id("diff_b_out") {
+            branch_diffs = (
+                patch::diff(&base_json, &branch_a_json),
+                patch::diff(&base_json, &branch_b_json),
+            );
+        }
+        let document_depth = [&base_json, &branch_a_json, &branch_b_json]
+            .into_iter()
+            .map(report::depth)
+            .max()
+            .unwrap_or(0);
+
+        let ordered_patterns: Vec<pattern::PathPattern> = matches
+            .get_many::<pattern::PathPattern>("ordered_object")
+            .unwrap_or_default()
+            .cloned()
+            .collect();
+        // Key orders are read from the text, and followed through the
+        // pre-patches.
+        let mut orders: [_; 3] = Default::default();
+        for (((name, operations), document), (text, order)) in pre_patches
+            .iter()
+            .zip([&mut base_json, &mut branch_a_json, &mut branch_b_json])
+            .zip(
+                [&base_str, &branch_a_str, &branch_b_str]
+                    .into_iter()
+                    .zip(&mut orders),
+            )
+        {
+            if !ordered_patterns.is_empty() {
+                *order = match operations {
+                    Some(operations) => {
+                        ordered::patched_key_orders(text, document, operations, &ordered_patterns)?
+                    }
+                    None => ordered::key_orders(text, &ordered_patterns)?,
+                };
+            }
+            if let Some(operations) = operations {
+                *document = patch::apply(document, operations)
+                    .map_err(|e| format!("Failed to apply pre-patch for {}: {}", name, e))?;
+            }
+        }
+
+        if interpolate_env {
+            let lookup = |name: &str| std::env::var(name).ok();
+            for (name, document) in [
+                ("base", &mut base_json),
+                ("branch A", &mut branch_a_json),
+                ("branch B", &mut branch_b_json),
+            ] {
+                interpolate::interpolate(document, &lookup)
+                    .map_err(|e| format!("Failed to interpolate {}: {}", name, e))?;
+            }
+        }
+
+        let mut embedded_paths = HashSet::new();
+        if !embedded_patterns.is_empty() {
+            for document in [&mut base_json, &mut branch_a_json, &mut branch_b_json] {
+                embedded::decode(document, &embedded_patterns, &mut embedded_paths);
+            }
+        }
+
+        let mut ordered_paths = HashSet::new();
+        if !ordered_patterns.is_empty() {
+            ordered_paths = ordered::mark(
+                [&mut base_json, &mut branch_a_json, &mut branch_b_json],
+                &orders,
+            );
+        }
+        let numeric_key_paths = if matches.get_flag("numeric_key_objects_as_arrays") {
+            numeric_keys::to_arrays([&mut base_json, &mut branch_a_json, &mut branch_b_json])
+        } else {
+            HashSet::new()
+        };
+
+        let (merge_base, merge_a, merge_b) = match envelope_field {
+            Some(field) => {
+                for key in matches
+                    .get_many::<String>("require_equal")
+                    .unwrap_or_default()
+                {
+                    envelope::check_equal(key, &base_json, &branch_a_json, &branch_b_json)?;
+                }
+                (
+                    envelope::unwrap(&base_json, field).map_err(|e| format!("Base: {}", e))?,
+                    envelope::unwrap(&branch_a_json, field)
+                        .map_err(|e| format!("Branch A: {}", e))?,
+                    envelope::unwrap(&branch_b_json, field)
+                        .map_err(|e| format!("Branch B: {}", e))?,
+                )
+            }
+            None => (&base_json, &branch_a_json, &branch_b_json),
+        };
+
+        if summary_only {
+            let conflicts = merge::detect_conflicts(merge_base, merge_a, merge_b, options);
+            print!(
+                "{}",
+                report::render_summary(
+                    report::changes(merge_base, merge_a),
+                    report::changes(merge_base, merge_b),
+                    &conflicts,
+                )
+            );
+            return Ok(None);
+        }
+
+        if matches.contains_id("plan_out") {
+            // The plan's conflicts are reported by the merge itself.
+            let level = log::max_level();
+            log::set_max_level(log::LevelFilter::Off);
+            plan = merge::plan(merge_base, merge_a, merge_b, options);
+            log::set_max_level(level);
+        }
+
+        options.deadline = matches
+            .get_one::<Duration>("timeout")
+            .map(|timeout| Instant::now() + *timeout);
+        let mut conflicts = Vec::new();
+        let (mut merged, flow) =
+            merge::three_way_merge_visit(merge_base, merge_a, merge_b, options, &mut |conflict| {
+                conflicts.push(conflict);
+                ControlFlow::Continue(())
+            });
+        if flow.is_break() {
+            if !matches.get_flag("allow_partial") {
+                return Err(
+                    "The merge timed out; pass --allow-partial to write what was merged so far"
+                        .into(),
+                );
+            }
+            log::warn!("The merge timed out; the output only holds what was merged in time");
+            partial = true;
+        }
+        if matches.get_flag("verify") {
+            // The conflicts of the re-merges are not news, so they are not logged.
+            let level = log::max_level();
+            log::set_max_level(log::LevelFilter::Off);
+            let violations =
+                verify::check(merge_base, merge_a, merge_b, &merged, &conflicts, options);
+            log::set_max_level(level);
+            for violation in &violations {
+                log::error!("Verification: {}", violation);
+            }
+            if !violations.is_empty() {
+                return Err("Merge verification failed".into());
+            }
+            log::debug!("Verified that the merge keeps both branches' changes");
+        }
+        if let Some(decisions_path) = matches.get_one::<String>("apply_conflicts") {
+            let decisions_str = fs::read_to_string(decisions_path)
+                .map_err(|e| format!("Failed to read conflicts file: {}", e))?;
+            let decisions = conflict_file::parse(&decisions_str)
+                .map_err(|e| format!("Failed to parse conflicts file: {}", e))?;
+            conflict_file::apply(&mut merged, &mut conflicts, &decisions)
+                .map_err(|e| format!("Failed to apply conflicts file: {}", e))?;
+        }
+        if let Some(command) = matches.get_one::<String>("resolver_cmd") {
+            resolver::resolve_all(command, &mut merged, &mut conflicts);
+        }
+        if let Some(path) = matches.get_one::<String>("bump_version") {
+            // The bump decides the version, so only conflicts elsewhere keep
+            // the merge from succeeding.
+            if conflicts
+                .iter()
+                .any(|c| !c.is_resolved() && c.path != *path)
+            {
+                log::info!("Not bumping '{}', as the merge has conflicts", path);
+            } else {
+                let mode = match matches
+                    .get_one::<String>("bump_version_mode")
+                    .unwrap()
+                    .as_str()
+                {
+                    "increment" => version::BumpMode::Increment,
+                    _ => version::BumpMode::Max,
+                };
+                let bump_error = |e| format!("Failed to bump version: {}", e);
+                let bumped = version::bumped(path, [merge_base, merge_a, merge_b], mode)
+                    .map_err(bump_error)?;
+                version::set(&mut merged, path, bumped).map_err(bump_error)?;
+                for conflict in conflicts.iter_mut().filter(|c| c.path == *path) {
+                    conflict.resolution.get_or_insert(merge::Side::Custom);
+                }
+                log::debug!("Bumped '{}' to version {}", path, bumped);
+            }
+        }
+        if let Some(style) = matches.get_one::<String>("conflict_markers") {
+            let mut template = markers::Template::new(match style.as_str() {
+                "object" => markers::Style::Object,
+                _ => markers::Style::Array,
+            });
+            for assignment in matches
+                .get_many::<String>("marker_template")
+                .unwrap_or_default()
+            {
+                template
+                    .set(assignment)
+                    .map_err(|e| format!("Invalid marker template: {}", e))?;
+            }
+            markers::insert(&mut merged, &conflicts, &template);
+        }
+        if matches.get_flag("keep_both") {
+            markers::keep_both(
+                &mut merged,
+                &conflicts,
+                matches.get_one::<String>("keep_both_suffix_a").unwrap(),
+                matches.get_one::<String>("keep_both_suffix_b").unwrap(),
+            )
+            .map_err(|e| format!("Failed to keep both versions: {}", e))?;
+        }
+        let mut merged = match envelope_field {
+            Some(field) => envelope::rewrap(&branch_a_json, field, merged),
+            None => merged,
+        };
+        numeric_keys::to_objects(&mut merged, &numeric_key_paths);
+        ordered::unmark(&mut merged, &ordered_paths);
+        embedded::encode(&mut merged, &embedded_paths);
+        if let Some(operations) = &post_patch {
+            merged = patch::apply(&merged, operations)
+                .map_err(|e| format!("Failed to apply post-merge patch: {}", e))?;
+        }
+        profile.record("merge", merge_start);
+        (merged, conflicts, document_depth)
+    };
+    #[cfg(feature = "otel")]
+    if let Some(otel_path) = matches.get_one::<String>("otel_out") {
+        write_span(
+            otel_path,
+            span_start,
+            &conflicts,
+            [&base_str, &branch_a_str, &branch_b_str].map(|text| text.len()),
+            partial,
+        )
+        .map_err(|e| format!("Failed to write span: {}", e))?;
+    }
+    Ok(Some(Merged {
+        document: merged,
+        conflicts,
+        depth: document_depth,
+        plan,
+        branch_diffs,
+        partial,
+        original_a,
+        unchanged,
+    }))
+}
+
+// Checks, writes and reports the merged document and the other outputs,
+// returning the exit code.
+fn finish(
+    matches: &clap::ArgMatches,
+    options: &merge::MergeOptions,
+    merged: Merged,
+    source: &Source,
+    mut writer: Box<dyn OutputWriter>,
+    profile: &mut Profile,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let Merged {
+        document: mut merged,
+        conflicts,
+        depth: document_depth,
+        mut plan,
+        branch_diffs,
+        partial,
+        original_a,
+        unchanged,
+    } = merged;
+    let inputs = match source {
+        Source::Text(inputs) => Some(inputs),
+        Source::Tree(_) => None,
+    };
+    let output_path = matches.get_one::<String>("output").unwrap();
+    let list_conflict_paths = matches.get_flag("list_conflict_paths");
+    let envelope_field = matches.get_one::<String>("envelope_field");
+    if let Some(&places) = matches.get_one::<usize>("number_precision") {
+        format::round_numbers(&mut merged, places);
+    }
+    if let Some(id_field) = matches.get_one::<String>("id_field") {
+        let ref_fields: Vec<String> = matches
+            .get_many::<String>("ref_field")
+            .unwrap_or_default()
+            .cloned()
+            .collect();
+        let dangling = references::dangling(&merged, id_field, &ref_fields);
+        for (path, id) in &dangling {
+            log::error!("Reference '{}' names {}, which no object defines", path, id);
+        }
+        if !dangling.is_empty() {
+            return Err(format!("Merged output has {} dangling references", dangling.len()).into());
+        }
+    }
+    if let Some(allowed_path) = matches.get_one::<String>("allowed_keys") {
+        let allowed_str = fs::read_to_string(allowed_path)
+            .map_err(|e| format!("Failed to read allowed keys file: {}", e))?;
+        let allowed = pattern::parse_pattern_list(&allowed_str)
+            .map_err(|e| format!("Failed to parse allowed keys file: {}", e))?;
+        let disallowed = allowlist::disallowed(&merged, &allowed);
+        for path in &disallowed {
+            log::error!("Key '{}' is not in the allowed keys", path);
+        }
+        if !disallowed.is_empty() {
+            return Err(format!(
+                "Merged output has {} keys that are not allowed",
+                disallowed.len()
+            )
+            .into());
+        }
+    }
+    let mut had_conflicts = conflicts.iter().any(|c| !c.is_resolved());
+    // Unresolved conflicts that are low-risk enough are accepted, and the
+    // merge counts as clean.
+    let mut accepted_severity = None;
+    if let Some(&max_severity) = matches.get_one::<u64>("max_severity") {
+        let mut severities = severity::Severities::default();
+        for assignment in matches.get_many::<String>("severity").unwrap_or_default() {
+            severities
+                .set(assignment)
+                .map_err(|e| format!("Invalid severity: {}", e))?;
+        }
+        let score = severities.total(&conflicts);
+        if had_conflicts && score <= max_severity {
+            accepted_severity = Some(score);
+            had_conflicts = false;
+        } else if had_conflicts {
+            log::error!(
+                "Conflict severity {} exceeds --max-severity {}",
+                score,
+                max_severity
+            );
+        }
+    }
+
+    if list_conflict_paths {
+        for conflict in conflicts.iter().filter(|c| !c.is_resolved()) {
+            println!("{}", conflict.path);
+        }
+    }
+
+    if matches.get_flag("blame") && !conflicts.is_empty() {
+        // --blame conflicts with --dir-mode, so the inputs are text.
+        let inputs = inputs.unwrap();
+        let provenance = provenance(matches);
+        let positions = [&inputs.base, &inputs.a, &inputs.b].map(|text| blame::Positions::of(text));
+        let versions = [
+            (provenance.base.as_str(), &positions[0]),
+            (provenance.a.as_str(), &positions[1]),
+            (provenance.b.as_str(), &positions[2]),
+        ];
+        for conflict in &conflicts {
+            // Conflict paths are relative to the payload of an envelope.
+            let path = match envelope_field {
+                Some(field) => pointer::push("", field) + &conflict.path,
+                None => conflict.path.clone(),
+            };
+            eprintln!("{}", blame::describe(&path, &versions));
+        }
+    }
 
-    let (merged, had_conflicts) =
-        merge::three_way_merge(&base_json, &branch_a_json, &branch_b_json);
+    // With --status-json stdout carries nothing but the status object.
+    let status_json = matches.get_flag("status_json");
+    let print_status = |output: Option<&str>| {
+        if status_json {
+            println!("{}", report::status(&conflicts, output));
+        }
+    };
+
+    if matches.get_flag("pre_commit") {
+        let branch_a_name = branch_a_name(matches);
+        if status_json {
+            print_status(None);
+        } else {
+            for conflict in conflicts.iter().filter(|c| !c.is_resolved()) {
+                println!("{}: {}", branch_a_name, report::render_line(conflict));
+            }
+        }
+        return Ok(if had_conflicts { 1 } else { 0 });
+    }
+
+    if matches.get_flag("dry_run") {
+        print!("{}", report::render_dry_run(&merged, &conflicts));
+        return Ok(if had_conflicts { 1 } else { 0 });
+    }
+
+    if matches.get_flag("stats") {
+        print!("{}", report::render_stats(&conflicts, document_depth));
+    }
+
+    let mut outputs = Outputs::new(matches.get_flag("atomic_outputs"));
+    if let Some(conflicts_path) = matches.get_one::<String>("emit_conflicts") {
+        let conflicts_str = serde_json::to_string_pretty(&conflict_file::emit(&conflicts))
+            .map_err(|e| format!("Failed to serialize conflicts file: {}", e))?;
+        outputs
+            .write(conflicts_path, conflicts_str)
+            .map_err(|e| format!("Failed to write conflicts file: {}", e))?;
+    }
+
+    if let Some(report_path) = matches.get_one::<String>("report") {
+        let report_str = report::render(&conflicts, matches.get_flag("group_by_section"));
+        outputs
+            .write(report_path, report_str)
+            .map_err(|e| format!("Failed to write conflict report: {}", e))?;
+    }
+
+    if let Some(junit_path) = matches.get_one::<String>("report_junit") {
+        outputs
+            .write(junit_path, report::render_junit(&conflicts))
+            .map_err(|e| format!("Failed to write JUnit report: {}", e))?;
+    }
+
+    if let Some(resolutions_path) = matches.get_one::<String>("resolutions_out") {
+        let resolutions_str = serde_json::to_string_pretty(&report::resolutions(&conflicts))
+            .map_err(|e| format!("Failed to serialize resolutions: {}", e))?;
+        outputs
+            .write(resolutions_path, resolutions_str)
+            .map_err(|e| format!("Failed to write resolutions: {}", e))?;
+    }
+
+    if let Some(subtrees_path) = matches.get_one::<String>("conflicts_resolved_out") {
+        let subtrees = report::conflicting_subtrees(&merged, &conflicts);
+        let subtrees_str = format::to_string(&subtrees, &[]);
+        outputs
+            .write(subtrees_path, subtrees_str)
+            .map_err(|e| format!("Failed to write conflicting subtrees: {}", e))?;
+    }
+
+    if let Some(plan_path) = matches.get_one::<String>("plan_out") {
+        // Plan paths are relative to the payload of an envelope.
+        if let Some(field) = envelope_field {
+            for step in &mut plan {
+                step.path = pointer::push("", field) + &step.path;
+            }
+        }
+        let plan_str = serde_json::to_string_pretty(&plan)
+            .map_err(|e| format!("Failed to serialize merge plan: {}", e))?;
+        outputs
+            .write(plan_path, plan_str)
+            .map_err(|e| format!("Failed to write merge plan: {}", e))?;
+    }
+
+    let changelog_path = matches.get_one::<String>("changelog_out");
+    let html_path = matches.get_one::<String>("html_out");
+    if let (Some(inputs), true) = (inputs, changelog_path.is_some() || html_path.is_some()) {
+        let parse = |text: &str, name: &str| -> Result<Value, String> {
+            serde_json::from_str(text).map_err(|e| format!("Failed to parse {} JSON: {}", name, e))
+        };
+        // Conflict paths are relative to the payload of an envelope.
+        let mut located = conflicts.clone();
+        if let Some(field) = envelope_field {
+            for conflict in &mut located {
+                conflict.path = pointer::push("", field) + &conflict.path;
+            }
+        }
+        let base_json = parse(&inputs.base, "base")?;
+        let branch_a_json = parse(&inputs.a, "branch A")?;
+        let branch_b_json = parse(&inputs.b, "branch B")?;
+        if let Some(changelog_path) = changelog_path {
+            let changelog = report::changelog(
+                &base_json,
+                &branch_a_json,
+                &branch_b_json,
+                &merged,
+                &located,
+            );
+            outputs
+                .write(changelog_path, changelog)
+                .map_err(|e| format!("Failed to write changelog: {}", e))?;
+        }
+        if let Some(html_path) = html_path {
+            let html = report::render_html(
+                &base_json,
+                &branch_a_json,
+                &branch_b_json,
+                &merged,
+                &located,
+            );
+            outputs
+                .write(html_path, html)
+                .map_err(|e| format!("Failed to write HTML report: {}", e))?;
+        }
+    }
+
+    if let Some(full_output_path) = matches.get_one::<String>("full_output") {
+        let full_output = output::MergeOutput::new(
+            merged.clone(),
+            conflicts.clone(),
+            document_depth,
+            provenance(matches),
+        );
+        let full_output_str = serde_json::to_string_pretty(&full_output)
+            .map_err(|e| format!("Failed to serialize full output: {}", e))?;
+        outputs
+            .write(full_output_path, full_output_str)
+            .map_err(|e| format!("Failed to write full output: {}", e))?;
+    }
+
+    if merge::blocks_output(&conflicts, options) {
+        log::error!("Type mismatch conflicts must be resolved by hand. Output not written.");
+        outputs
+            .commit()
+            .map_err(|e| format!("Failed to move outputs into place: {}", e))?;
+        print_status(None);
+        if list_conflict_paths {
+            return Ok(1);
+        }
+        return Err("Type mismatch conflicts detected".into());
+    }
 
     if had_conflicts {
         log::error!("Merge completed with conflicts. See log for details.");
+    } else if let Some(score) = accepted_severity {
+        log::warn!(
+            "Merge completed with conflicts of total severity {}, within --max-severity.",
+            score
+        );
     } else {
         log::info!("Merge completed successfully with no conflicts.");
     }
 
-    let merged_str = serde_json::to_string_pretty(&merged)
-        .map_err(|e| format!("Failed to serialize merged JSON: {}", e))?;
+    if let (Some(undo_path), Some(original_a)) =
+        (matches.get_one::<String>("undo_patch"), &original_a)
+    {
+        let undo_str = serde_json::to_string_pretty(&patch::diff(&merged, original_a))
+            .map_err(|e| format!("Failed to serialize undo patch: {}", e))?;
+        outputs
+            .write(undo_path, undo_str)
+            .map_err(|e| format!("Failed to write undo patch: {}", e))?;
+    }
+
+    for (arg, operations, branch) in [
+        ("diff_a_out", &branch_diffs.0, "A"),
+        ("diff_b_out", &branch_diffs.1, "B"),
+    ] {
+        if let Some(diff_path) = matches.get_one::<String>(arg) {
+            let diff_str = serde_json::to_string_pretty(operations)
+                .map_err(|e| format!("Failed to serialize branch {} patch: {}", branch, e))?;
+            outputs
+                .write(diff_path, diff_str)
+                .map_err(|e| format!("Failed to write branch {} patch: {}", branch, e))?;
+        }
+    }
+
+    let format_rules: Vec<format::FormatRule> = matches
+        .get_many::<format::FormatRule>("format_rule")
+        .unwrap_or_default()
+        .cloned()
+        .collect();
+
+    let preview = matches.get_flag("preview");
+    if let (Some(inputs), true) = (inputs, preview || matches.contains_id("unified_diff_out")) {
+        let base_json: Value = serde_json::from_str(&inputs.base)
+            .map_err(|e| format!("Failed to parse base JSON: {}", e))?;
+        // With --git the base is the file itself, as of the merge base.
+        let base_name = matches
+            .get_one::<String>("git")
+            .cloned()
+            .unwrap_or_else(|| {
+                matches
+                    .get_one::<PathBuf>("base")
+                    .unwrap()
+                    .display()
+                    .to_string()
+            });
+        let diff_str = line_diff::unified(
+            &format::to_string(&base_json, &format_rules),
+            &format::to_string(&merged, &format_rules),
+            &base_name,
+            output_path,
+            3,
+        );
+        if preview && diff_str.is_empty() {
+            eprintln!("No changes from base");
+        } else if preview {
+            let max_lines = *matches.get_one::<usize>("preview_lines").unwrap();
+            eprint!("{}", line_diff::truncate(&diff_str, max_lines));
+        }
+        if let Some(diff_path) = matches.get_one::<String>("unified_diff_out") {
+            outputs
+                .write(diff_path, diff_str)
+                .map_err(|e| format!("Failed to write unified diff: {}", e))?;
+        }
+    }
+
+    if let Some(split_dir) = matches.get_one::<String>("split_output") {
+        let files = split::files(&merged, &format_rules)
+            .ok_or("Failed to split output: the merged document is not an object")?;
+        fs::create_dir_all(split_dir)
+            .map_err(|e| format!("Failed to create split output directory: {}", e))?;
+        for (name, contents) in files {
+            outputs
+                .write(Path::new(split_dir).join(&name), contents)
+                .map_err(|e| format!("Failed to write split output file {}: {}", name, e))?;
+        }
+    }
+
+    match source {
+        Source::Text(inputs) => {
+            let serialize_start = Instant::now();
+            let merged_str = serialize(
+                matches,
+                &merged,
+                original_a.as_ref(),
+                unchanged,
+                inputs,
+                &format_rules,
+            )?;
+            profile.record("serialize", serialize_start);
+
+            if let Some(&max_bytes) = matches.get_one::<u64>("max_output_bytes") {
+                if merged_str.len() as u64 > max_bytes {
+                    log::error!(
+                        "Merged output is {} bytes, over the limit of {} set by --max-output-bytes. Output not written.",
+                        merged_str.len(),
+                        max_bytes
+                    );
+                    print_status(None);
+                    outputs
+                        .commit()
+                        .map_err(|e| format!("Failed to move outputs into place: {}", e))?;
+                    return Ok(EXIT_OUTPUT_TOO_LARGE);
+                }
+            }
 
-    log::info!("Writing output to {}", output_path);
-    fs::write(output_path, merged_str)
-        .map_err(|e| format!("Failed to write merged output: {}", e))?;
+            log::info!("Writing output to {}", writer.name());
+            let write_start = Instant::now();
+            writer
+                .write(&mut outputs, merged_str)
+                .map_err(|e| format!("Failed to write merged output: {}", e))?;
+            outputs
+                .commit()
+                .map_err(|e| format!("Failed to move outputs into place: {}", e))?;
+            profile.record("write", write_start);
+        }
+        Source::Tree(entries) => {
+            log::info!("Writing output to {}", output_path);
+            let write_start = Instant::now();
+            tree::write(Path::new(output_path), &merged, &entries.each_ref())
+                .map_err(|e| format!("Failed to write merged directory: {}", e))?;
+            outputs
+                .commit()
+                .map_err(|e| format!("Failed to move outputs into place: {}", e))?;
+            profile.record("write", write_start);
+        }
+    }
+
+    // With the document on stdout, messages go to stderr.
+    let say = |message: String| {
+        if writer.uses_stdout() {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    };
+    if partial {
+        say(format!("Partial merge written to {}", writer.name()));
+        return Err("Merge timed out".into());
+    }
 
-    println!("Merge completed. Output written to {}", output_path);
+    if list_conflict_paths {
+        if had_conflicts {
+            return Ok(1);
+        }
+        return Ok(0);
+    }
+
+    if status_json {
+        print_status(Some(output_path));
+    } else {
+        say(format!(
+            "Merge completed. Output written to {}",
+            writer.name()
+        ));
+    }
     if had_conflicts {
-        println!("Note: Conflicts occurred during merge. See logs for details.");
+        if !status_json {
+            say("Note: Conflicts occurred during merge. See logs for details.".to_string());
+        }
         return Err("Merge conflicts detected".into());
     }
 
-    Ok(())
+    Ok(0)
+}
+
+// The merged document as written, in the form of the inputs, checked to read
+// back as the document with --verify-roundtrip.
+fn serialize(
+    matches: &clap::ArgMatches,
+    merged: &Value,
+    original_a: Option<&Value>,
+    unchanged: bool,
+    inputs: &Inputs,
+    format_rules: &[format::FormatRule],
+) -> Result<String, String> {
+    let verbatim = unchanged && matches.get_flag("verbatim_unchanged");
+    let merged_str = if let [base_order, a_order, b_order] = &inputs.record_orders[..] {
+        jsonl::to_lines(merged, &[a_order, b_order, base_order])
+    } else if verbatim {
+        inputs.a.clone()
+    } else if matches.get_flag("canonical") {
+        match original_a {
+            Some(original_a) if matches.get_flag("minimal_output") => {
+                canonical::to_string(&patch::merge_patch(original_a, merged))
+            }
+            _ => canonical::to_string(merged),
+        }
+    } else {
+        match original_a {
+            Some(original_a) if matches.get_flag("minimal_output") => {
+                format::to_string(&patch::merge_patch(original_a, merged), format_rules)
+            }
+            Some(original_a) if matches.get_flag("preserve_layout") => {
+                layout::to_string(&inputs.a, original_a, merged, format_rules)
+                    .unwrap_or_else(|| format::to_string(merged, format_rules))
+            }
+            _ => format::to_string(merged, format_rules),
+        }
+    };
+    if matches.get_flag("verify_roundtrip") {
+        let read = match matches.get_one::<jsonl::RecordKey>("jsonl_key") {
+            Some(key) => {
+                jsonl::records(&merged_str, key).map(|(records, _)| Value::Object(records))
+            }
+            None => serde_json::from_str(&merged_str).map_err(|e| e.to_string()),
+        };
+        let minimal = original_a.filter(|_| matches.get_flag("minimal_output") && !verbatim);
+        let expected = match minimal {
+            Some(original_a) => &patch::merge_patch(original_a, merged),
+            None => merged,
+        };
+        let result = read
+            .map_err(|e| format!("the output does not parse back: {}", e))
+            .and_then(|read| verify::roundtrip(expected, &read));
+        if let Err(e) = result {
+            log::error!("Round trip: {}", e);
+            return Err("Round-trip verification failed".to_string());
+        }
+        log::debug!("Verified that the output reads back as the merged document");
+    }
+
+    Ok(merged_str)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_inputs() {
+        for text in ["", " \n\t\r\n"] {
+            assert_eq!(
+                check_not_empty(text.to_string(), "branch A", false),
+                Err("The branch A file is empty; pass --allow-empty to read it as {}".to_string())
+            );
+            assert_eq!(
+                check_not_empty(text.to_string(), "branch A", true),
+                Ok("{}".to_string())
+            );
+        }
+        assert_eq!(
+            check_not_empty(" [] ".to_string(), "base", false),
+            Ok(" [] ".to_string())
+        );
+    }
+
+    #[test]
+    fn test_timeout() {
+        assert_eq!(parse_timeout("1.5"), Ok(Duration::from_millis(1500)));
+        for bad in ["0", "-1", "soon", "inf"] {
+            assert!(parse_timeout(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_combined_keys() {
+        assert_eq!(
+            parse_combined_keys("old, ours,theirs"),
+            Ok(["old".to_string(), "ours".to_string(), "theirs".to_string()])
+        );
+        assert!(parse_combined_keys("base,a").is_err());
+        assert!(parse_combined_keys("base,a,b,c").is_err());
+        assert!(parse_combined_keys("a,a,b").is_err());
+    }
+
+    #[test]
+    fn test_profile_args() {
+        let command = Command::new("tool")
+            .arg(
+                Arg::new("strategy")
+                    .long("strategy")
+                    .default_value("manual"),
+            )
+            .arg(
+                Arg::new("rule")
+                    .long("rule")
+                    .action(clap::ArgAction::Append),
+            )
+            .arg(
+                Arg::new("safe_types")
+                    .long("safe-types")
+                    .action(clap::ArgAction::SetTrue),
+            )
+            .arg(Arg::new("options_profile").long("options-profile"));
+        let option = |name: &str, values: &[&str]| config::ProfileOption {
+            name: name.to_string(),
+            values: values.iter().map(|v| v.to_string()).collect(),
+        };
+        let options = [
+            option("strategy", &["ours"]),
+            option("rule", &["/a=theirs", "/b=ours"]),
+            option("safe-types", &[]),
+        ];
+        let args = |command_line: &[&str], options: &[config::ProfileOption]| {
+            let matches = command
+                .clone()
+                .get_matches_from(std::iter::once("tool").chain(command_line.iter().copied()));
+            profile_args(&command, &matches, options)
+        };
+        assert_eq!(
+            args(&[], &options),
+            Ok(vec![
+                "--strategy=ours".to_string(),
+                "--rule=/a=theirs".to_string(),
+                "--rule=/b=ours".to_string(),
+                "--safe-types".to_string(),
+            ])
+        );
+        // Options given on the command line win.
+        assert_eq!(
+            args(&["--strategy", "theirs", "--rule", "/c=ours"], &options),
+            Ok(vec!["--safe-types".to_string()])
+        );
+
+        let error = |option| args(&[], &[option]).unwrap_err();
+        assert_eq!(
+            error(option("strategi", &["ours"])),
+            "unknown option 'strategi'"
+        );
+        assert_eq!(error(option("strategy", &[])), "'strategy' needs a value");
+        assert_eq!(
+            error(option("safe-types", &["yes"])),
+            "'safe-types' is a flag, so true or false"
+        );
+        assert_eq!(
+            error(option("options-profile", &["other"])),
+            "a profile cannot set 'options-profile'"
+        );
+    }
+
+    #[test]
+    fn test_output_writers() {
+        assert!(output_writer("-").uses_stdout());
+        let file = output_writer("merged.json");
+        assert!(!file.uses_stdout());
+        assert_eq!(file.name(), "merged.json");
+
+        let mut outputs = Outputs::new(false);
+        let mut writer = StdoutWriter(Vec::new());
+        writer.write(&mut outputs, "{}".to_string()).unwrap();
+        writer.write(&mut outputs, "[]\n".to_string()).unwrap();
+        assert_eq!(writer.0, b"{}\n[]\n");
+        assert_eq!(writer.name(), "stdout");
+    }
 }
@@ -0,0 +1,256 @@
+//! Conflict markers: unresolved conflicts written into the merged document as
+//! values holding every version, like the `<<<<<<<` markers git leaves in text
+//! files, to be edited by hand.
+
+use crate::merge::{Conflict, ConflictKind};
+use crate::patch::{self, Operation};
+use crate::pointer;
+use serde_json::{json, Map, Value};
+
+/// How the versions of a conflicting value are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    /// `["<<<<<<< branch A", a, "=======", b, ">>>>>>> branch B"]`, with a
+    /// version that has no value left out.
+    Array,
+    /// `{"<<<<<<< branch A": a, ">>>>>>> branch B": b}`, keyed by the markers.
+    Object,
+}
+
+/// The marker texts and layout. With `base` set, base's version is included
+/// too, after A's, like git's diff3 conflict style.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    pub style: Style,
+    pub a: String,
+    pub separator: String,
+    pub b: String,
+    pub base: Option<String>,
+}
+
+impl Template {
+    pub fn new(style: Style) -> Template {
+        Template {
+            style,
+            a: "<<<<<<< branch A".to_string(),
+            separator: "=======".to_string(),
+            b: ">>>>>>> branch B".to_string(),
+            base: None,
+        }
+    }
+
+    /// Sets one marker from `<part>=<text>`, where the part is `a`, `b`, `base`
+    /// or `separator`. Setting `base` includes base's version.
+    pub fn set(&mut self, assignment: &str) -> Result<(), String> {
+        let (part, text) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' must look like <part>=<text>", assignment))?;
+        match part {
+            "a" => self.a = text.to_string(),
+            "b" => self.b = text.to_string(),
+            "base" => self.base = Some(text.to_string()),
+            "separator" => self.separator = text.to_string(),
+            _ => {
+                return Err(format!(
+                    "unknown marker '{}', expected 'a', 'b', 'base' or 'separator'",
+                    part
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// The marker value for `conflict`.
+    pub fn render(&self, conflict: &Conflict) -> Value {
+        let mut sections = vec![(&self.a, &conflict.a)];
+        if let Some(base) = &self.base {
+            sections.push((base, &conflict.base));
+        }
+        match self.style {
+            Style::Array => {
+                let mut items = Vec::new();
+                for (marker, value) in sections {
+                    items.push(json!(marker));
+                    items.extend(value.clone());
+                }
+                items.push(json!(self.separator));
+                items.extend(conflict.b.clone());
+                items.push(json!(self.b));
+                Value::Array(items)
+            }
+            Style::Object => {
+                sections.push((&self.b, &conflict.b));
+                let entries: Map<String, Value> = sections
+                    .into_iter()
+                    .filter_map(|(marker, value)| Some((marker.clone(), value.clone()?)))
+                    .collect();
+                Value::Object(entries)
+            }
+        }
+    }
+}
+
+/// Replaces the value at each unresolved conflict's path in `merged` with its
+/// marker value. Renamed-record conflicts are left alone, as their versions are
+/// key names rather than values.
+pub fn insert(merged: &mut Value, conflicts: &[Conflict], template: &Template) {
+    for conflict in conflicts {
+        if conflict.is_resolved() || conflict.kind == ConflictKind::BothRenamed {
+            continue;
+        }
+        let markers = template.render(conflict);
+        match merged.pointer_mut(&conflict.path) {
+            Some(target) => *target = markers,
+            None => {
+                let operation = Operation::Add {
+                    path: conflict.path.clone(),
+                    value: markers,
+                };
+                if let Ok(patched) = patch::apply(merged, &[operation]) {
+                    *merged = patched;
+                }
+            }
+        }
+    }
+}
+
+/// Keeps both versions of each unresolved conflict under suffixed keys: the
+/// conflicting key `<key>` is replaced by `<key><suffix_a>` holding A's value
+/// and `<key><suffix_b>` holding B's, leaving out a version that has no value.
+/// Conflicts at the root or at array elements have no key to split and are left
+/// alone, as are renamed-record conflicts. Fails if a suffixed key is already
+/// in use.
+pub fn keep_both(
+    merged: &mut Value,
+    conflicts: &[Conflict],
+    suffix_a: &str,
+    suffix_b: &str,
+) -> Result<(), String> {
+    if suffix_a == suffix_b {
+        return Err(format!("both suffixes are '{}'", suffix_a));
+    }
+    for conflict in conflicts {
+        if conflict.is_resolved() || conflict.kind == ConflictKind::BothRenamed {
+            continue;
+        }
+        let Some(index) = conflict.path.rfind('/') else {
+            continue;
+        };
+        let parent_path = &conflict.path[..index];
+        let Some(Value::Object(parent)) = merged.pointer_mut(parent_path) else {
+            continue;
+        };
+        let key = pointer::unescape(&conflict.path[index + 1..]);
+        parent.remove(&key);
+        for (suffix, value) in [(suffix_a, &conflict.a), (suffix_b, &conflict.b)] {
+            let split_key = format!("{}{}", key, suffix);
+            if parent.contains_key(&split_key) {
+                return Err(format!(
+                    "'{}' already exists",
+                    pointer::push(parent_path, &split_key)
+                ));
+            }
+            if let Some(value) = value {
+                parent.insert(split_key, value.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{three_way_merge_with_options, MergeOptions};
+
+    fn conflicts() -> (Value, Vec<Conflict>) {
+        let base = json!({"port": 80, "host": "a", "gone": 1});
+        let a = json!({"port": 81, "host": "a"});
+        let b = json!({"port": 82, "host": "a", "gone": 2});
+        three_way_merge_with_options(&base, &a, &b, &MergeOptions::default())
+    }
+
+    #[test]
+    fn test_array_markers() {
+        let (mut merged, conflicts) = conflicts();
+        insert(&mut merged, &conflicts, &Template::new(Style::Array));
+        assert_eq!(
+            merged,
+            json!({
+                "gone": ["<<<<<<< branch A", "=======", 2, ">>>>>>> branch B"],
+                "host": "a",
+                "port": ["<<<<<<< branch A", 81, "=======", 82, ">>>>>>> branch B"]
+            })
+        );
+    }
+
+    #[test]
+    fn test_object_markers_with_custom_labels() {
+        let (mut merged, conflicts) = conflicts();
+        let mut template = Template::new(Style::Object);
+        template.set("a=ours (feature)").unwrap();
+        template.set("b=theirs (main)").unwrap();
+        template.set("base=ancestor").unwrap();
+        insert(&mut merged, &conflicts, &template);
+        assert_eq!(
+            merged,
+            json!({
+                "gone": {"ancestor": 1, "theirs (main)": 2},
+                "host": "a",
+                "port": {"ours (feature)": 81, "ancestor": 80, "theirs (main)": 82}
+            })
+        );
+    }
+
+    #[test]
+    fn test_diff3_array_markers() {
+        let (_, conflicts) = conflicts();
+        let mut template = Template::new(Style::Array);
+        template.set("base=||||||| base").unwrap();
+        template.set("separator=---").unwrap();
+        assert_eq!(
+            template.render(&conflicts[1]),
+            json!([
+                "<<<<<<< branch A",
+                81,
+                "||||||| base",
+                80,
+                "---",
+                82,
+                ">>>>>>> branch B"
+            ])
+        );
+        assert!(template.set("middle=x").is_err());
+        assert!(template.set("a").is_err());
+    }
+
+    #[test]
+    fn test_keep_both_under_suffixed_keys() {
+        let (mut merged, conflicts) = conflicts();
+        keep_both(&mut merged, &conflicts, ".A", ".B").unwrap();
+        assert_eq!(
+            merged,
+            json!({"gone.B": 2, "host": "a", "port.A": 81, "port.B": 82})
+        );
+
+        let base = json!({"list": [{"n": 1}], "port": 80, "port-ours": 1});
+        let a = json!({"list": [{"n": 2}], "port": 81, "port-ours": 1});
+        let b = json!({"list": [{"n": 3}], "port": 82, "port-ours": 1});
+        let (mut merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(
+            keep_both(&mut merged, &conflicts, "-ours", "-theirs"),
+            Err("'/port-ours' already exists".to_string())
+        );
+        assert!(keep_both(&mut merged, &conflicts, "", "").is_err());
+    }
+
+    #[test]
+    fn test_resolved_conflicts_keep_their_value() {
+        let (mut merged, mut conflicts) = conflicts();
+        conflicts[1].resolution = Some(crate::merge::Side::B);
+        merged["port"] = json!(82);
+        insert(&mut merged, &conflicts, &Template::new(Style::Array));
+        assert_eq!(merged["port"], json!(82));
+    }
+}
@@ -1,465 +1,5518 @@
+use crate::coercion::{self, Coercions};
+use crate::line_diff::{self, TextEdit};
+use crate::pattern::PathPattern;
+use crate::pointer;
+use crate::records;
+use crate::similarity;
+use crate::timestamp;
+use regex::Regex;
+use serde::{Serialize, Serializer};
 use serde_json::{Map, Value};
-use std::collections::HashSet;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::ops::ControlFlow;
+use std::sync::{Arc, LazyLock};
+use std::time::Instant;
+
+#[derive(Debug, Clone, Default)]
+pub struct MergeOptions {
+    /// Paths matching any of these patterns are not merged; base's value is kept.
+    pub ignore: Vec<PathPattern>,
+    /// Paths owned by branch A: at a matching path A's value, or its absence,
+    /// is taken whole, whatever B did there. Checked after `ignore`.
+    pub authoritative_a: Vec<PathPattern>,
+    /// Paths owned by branch B, like `authoritative_a`. Where both match, A wins.
+    pub authoritative_b: Vec<PathPattern>,
+    pub array_mode: ArrayMergeMode,
+    pub strategy: ConflictStrategy,
+    /// Never auto-resolve `ConflictKind::TypeMismatch` conflicts, whatever the
+    /// strategy.
+    pub safe_types: bool,
+    /// Where the branches conflict because one set a value to `null` and the
+    /// other to anything else, resolve the conflict by taking the other value,
+    /// before the strategy. Type mismatches stay unresolved with `safe_types`.
+    pub null_coalesce: bool,
+    pub key_scope: KeyScope,
+    /// Treats the top-level object as a map of id to record, identified by this
+    /// field. A branch that deletes a key and adds another whose record has the
+    /// same value for the field has renamed it, and edits made to the record
+    /// under its old key in the other branch follow it to the new key.
+    pub record_key: Option<String>,
+    pub array_object_mismatch: ArrayObjectMismatch,
+    pub array_null_mismatch: ArrayNullMismatch,
+    /// Compare strings ignoring whitespace at the end of each line, line ending
+    /// style (CRLF or LF) and trailing newlines.
+    pub string_whitespace_insensitive: bool,
+    /// Treat a value of `""` as if the key or element were absent, so setting a
+    /// value to `""` counts as deleting it and `""` in both branches as the same
+    /// absence. Where the merge leaves nothing, an `""` in branch A is kept.
+    pub treat_empty_string_as_absent: bool,
+    /// Compare arrays as multisets, so a reordered array is equal to the
+    /// original and a reordering alone neither conflicts nor counts as a
+    /// modification. Where both branches hold equal arrays, A's order is kept.
+    pub array_set_semantics: bool,
+    /// Scalars that compare equal though they differ, such as `"yes"` and
+    /// `true`. Where both branches hold equivalent values, A's is kept.
+    pub coercions: Coercions,
+    /// Compare a string holding a JSON number literal, such as `"42"`, equal to
+    /// that number, see `coercion::numeric_string_equals`. Where both branches
+    /// hold the same number written both ways, A's is kept.
+    pub coerce_numeric_strings: bool,
+    /// Overrides of `strategy` and `array_mode` for particular paths, see
+    /// `PathRule`.
+    pub rules: Vec<PathRule>,
+    /// Applied to every value of the merged document before it is returned,
+    /// see `ValueTransform`.
+    pub value_transform: Option<ValueTransform>,
+    /// Receives the merge's diagnostics in place of the `log` facade, see
+    /// `LogSink`.
+    pub log_sink: Option<LogSink>,
+    /// Logs, at trace level, every key of each object merged entry by entry:
+    /// which versions have it and what the merge decided, as fields such as
+    /// `key="/x" base=present a=absent b=present decision=deleted-in-a`.
+    pub trace_keys: bool,
+    /// Stops the merge once this instant has passed. It is checked before each
+    /// entry of an object, and a merge stopped by it ends as one stopped by the
+    /// visitor of `three_way_merge_visit`: the result is partial.
+    pub deadline: Option<Instant>,
+    /// Object keys left out of the comparison, see `IgnoreCompare`.
+    pub ignore_compare: Option<IgnoreCompare>,
+    /// Timestamps that count as equal though they differ slightly, see
+    /// `TimestampTolerance`.
+    pub timestamp_tolerances: Vec<TimestampTolerance>,
+    /// Lets the data say which branch wins: a branch whose value for a key or
+    /// element is an object with only this key, as in `{"__force__": 8080}`,
+    /// overrides the other branch there with the value it wraps. Where both
+    /// branches force different values, they conflict as if both had modified
+    /// it. Such wrappers are removed from every value taken into the result.
+    /// Checked after `ignore` and the authoritative paths.
+    pub force_key: Option<String>,
+    /// Caps on the length of `ArrayMergeMode::OrderedUnion` arrays, see
+    /// `ArrayLengthLimit`.
+    pub array_length_limits: Vec<ArrayLengthLimit>,
+    /// Where an ordered union is longer than its cap, keep its first elements
+    /// up to the cap instead of conflicting.
+    pub truncate_long_arrays: bool,
+    /// Warns of every key or element that some version has and the result
+    /// leaves out, and why: deleted in a branch, outside the key scope, at an
+    /// ignored or authoritative path, or by a conflict resolution. Objects are
+    /// then always merged key by key, so no key is dropped by taking a
+    /// branch's object whole; elements are reported where arrays are merged
+    /// element by element.
+    pub warn_on_drop: bool,
+    /// Holds a number that only one branch changed to another number for
+    /// review, as a `ConflictKind::NumberChanged` conflict, instead of taking
+    /// the change. Objects, and arrays merged element by element, are then
+    /// always merged entry by entry, so that no number inside is taken whole.
+    pub review_numbers: bool,
+}
+
+/// A function applied to each value of the merged document, with its path in
+/// the result, such as one redacting secrets or normalizing strings. Children
+/// are transformed before their parent, which receives the transformed
+/// children; the document root comes last. Values are transformed whether
+/// they were merged entry by entry or taken whole from one version.
+#[derive(Clone)]
+pub struct ValueTransform(Arc<TransformFn>);
+
+type TransformFn = dyn Fn(&str, Value) -> Value + Send + Sync;
+
+impl ValueTransform {
+    pub fn new(transform: impl Fn(&str, Value) -> Value + Send + Sync + 'static) -> Self {
+        ValueTransform(Arc::new(transform))
+    }
+
+    fn apply(&self, path: &str, value: Value) -> Value {
+        let value = match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(key, val)| {
+                        let val = self.apply(&pointer::push(path, &key), val);
+                        (key, val)
+                    })
+                    .collect(),
+            ),
+            Value::Array(items) => Value::Array(
+                items
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, val)| self.apply(&pointer::push(path, &index.to_string()), val))
+                    .collect(),
+            ),
+            other => other,
+        };
+        (self.0)(path, value)
+    }
+}
+
+impl std::fmt::Debug for ValueTransform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ValueTransform(..)")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Debug,
+    /// Only with `MergeOptions::trace_keys`.
+    Trace,
+}
+
+/// A diagnostic from the merge, such as a conflict being found or resolved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogEvent {
+    pub level: LogLevel,
+    pub message: String,
+}
+
+/// A function receiving every `LogEvent` of a merge, for embedders without a
+/// global logger. It gets the events whether or not the `logging` feature is
+/// enabled, and nothing is logged to the `log` facade while it is set.
+#[derive(Clone)]
+pub struct LogSink(Arc<SinkFn>);
+
+type SinkFn = dyn Fn(&LogEvent) + Send + Sync;
+
+impl LogSink {
+    pub fn new(sink: impl Fn(&LogEvent) + Send + Sync + 'static) -> Self {
+        LogSink(Arc::new(sink))
+    }
+}
+
+impl std::fmt::Debug for LogSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LogSink(..)")
+    }
+}
+
+/// Object keys that do not count when comparing versions, such as a
+/// `last_modified` field rewritten on every save. Keys at paths matching `keys`
+/// are removed from all three versions before merging, so a change to them
+/// alone neither makes an object modified nor conflicts. Afterwards each merged
+/// object gets them back as `source` (A, B or base) has them at the same path,
+/// or as the first of the other versions that has an object there does, with
+/// the other branch before base.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IgnoreCompare {
+    pub keys: Vec<PathPattern>,
+    pub source: Side,
+}
+
+impl IgnoreCompare {
+    fn ignored(&self, path: &str) -> bool {
+        self.keys.iter().any(|pattern| pattern.matches(path))
+    }
+
+    fn stripped(&self, value: &Value, path: &str) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .filter_map(|(key, child)| {
+                        let child_path = pointer::push(path, key);
+                        (!self.ignored(&child_path))
+                            .then(|| (key.clone(), self.stripped(child, &child_path)))
+                    })
+                    .collect(),
+            ),
+            Value::Array(items) => Value::Array(
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(index, item)| {
+                        self.stripped(item, &pointer::push(path, &index.to_string()))
+                    })
+                    .collect(),
+            ),
+            _ => value.clone(),
+        }
+    }
+
+    // Puts the ignored keys back into `merged` from the first of `versions`
+    // that has an object at each path.
+    fn restore(&self, merged: &mut Value, versions: &[Option<&Value>], path: &str) {
+        let children = |token: &str| -> Vec<Option<&Value>> {
+            versions
+                .iter()
+                .map(|version| match version {
+                    Some(Value::Object(map)) => map.get(token),
+                    Some(Value::Array(items)) => {
+                        token.parse().ok().and_then(|i: usize| items.get(i))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+        match merged {
+            Value::Object(map) => {
+                let supplier = versions
+                    .iter()
+                    .flatten()
+                    .find_map(|version| version.as_object());
+                let mut restored = Vec::new();
+                for (key, child) in map.iter_mut() {
+                    let child_path = pointer::push(path, key);
+                    self.restore(child, &children(key), &child_path);
+                }
+                for (key, value) in supplier.into_iter().flatten() {
+                    if self.ignored(&pointer::push(path, key)) {
+                        restored.push((key.clone(), value.clone()));
+                    }
+                }
+                map.extend(restored);
+            }
+            Value::Array(items) => {
+                for (index, item) in items.iter_mut().enumerate() {
+                    let token = index.to_string();
+                    self.restore(item, &children(&token), &pointer::push(path, &token));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// At paths matching `pattern`, two strings that are both ISO 8601 timestamps
+/// (see `timestamp::parse`) are equal if they are at most `seconds` apart, so
+/// clock skew between machines does not conflict. A value is kept as its
+/// version wrote it, and strings that are not timestamps compare exactly.
+/// When several tolerances match a path the last one applies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimestampTolerance {
+    pub pattern: PathPattern,
+    pub seconds: f64,
+}
+
+impl TimestampTolerance {
+    /// Parses `<pattern>=<seconds>`, such as `/**/updated_at=5`.
+    pub fn parse(tolerance: &str) -> Result<TimestampTolerance, String> {
+        let (pattern, seconds) = tolerance.rsplit_once('=').ok_or_else(|| {
+            format!(
+                "tolerance '{}' must look like <pattern>=<seconds>",
+                tolerance
+            )
+        })?;
+        let seconds = match seconds.parse::<f64>() {
+            Ok(seconds) if seconds >= 0.0 && seconds.is_finite() => seconds,
+            _ => return Err(format!("'{}' is not a number of seconds", seconds)),
+        };
+        Ok(TimestampTolerance {
+            pattern: PathPattern::parse(pattern)?,
+            seconds,
+        })
+    }
+}
+
+/// At paths matching `pattern`, an array merged as an ordered union may hold at
+/// most `max` elements. A longer union is a `ConflictKind::TooLong` conflict
+/// between the three arrays, resolved by the strategy like any other, unless
+/// `MergeOptions::truncate_long_arrays` is set. When several limits match a
+/// path the last one applies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArrayLengthLimit {
+    pub pattern: PathPattern,
+    pub max: usize,
+}
+
+impl ArrayLengthLimit {
+    /// Parses `<pattern>=<max>`, such as `/tags=50`, or a bare `<max>` for
+    /// arrays at every path.
+    pub fn parse(limit: &str) -> Result<ArrayLengthLimit, String> {
+        let (pattern, max) = limit.rsplit_once('=').unwrap_or(("/**", limit));
+        let max = max
+            .parse()
+            .map_err(|_| format!("'{}' is not a number of elements", max))?;
+        Ok(ArrayLengthLimit {
+            pattern: PathPattern::parse(pattern)?,
+            max,
+        })
+    }
+}
+
+/// Sets the conflict strategy or the array mode used at paths matching
+/// `pattern`, in place of the global one in `MergeOptions`. A rule applies to
+/// the matched path only, so `/config/**` is needed to cover a whole subtree.
+/// When several rules set the same thing for a path the last one wins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathRule {
+    pub pattern: PathPattern,
+    pub strategy: Option<ConflictStrategy>,
+    pub array_mode: Option<ArrayMergeMode>,
+}
+
+impl PathRule {
+    /// Parses `<pattern>=<setting>`, where the setting is a strategy (`manual`,
+    /// `ours`, `theirs`, `average-numeric`, `average-numeric:<weight_a>`,
+    /// `delete-wins`, `prefer-longer-string` or `prefer-matching:<a|b>:<regex>`,
+    /// without `=` in the regex) or an array mode (`atomic`, `positional`,
+    /// `similarity`, `similarity:<threshold>`, `ordered-union`, `keyed:<field>`
+    /// or `keyed-ordered:<field>`).
+    pub fn parse(rule: &str) -> Result<PathRule, String> {
+        let (pattern, setting) = rule
+            .rsplit_once('=')
+            .ok_or_else(|| format!("rule '{}' must look like <pattern>=<setting>", rule))?;
+        let (strategy, array_mode) = if let Some(strategy) = ConflictStrategy::parse(setting)? {
+            (Some(strategy), None)
+        } else if let Some(array_mode) = ArrayMergeMode::parse(setting)? {
+            (None, Some(array_mode))
+        } else {
+            return Err(format!("unknown setting '{}'", setting));
+        };
+        Ok(PathRule {
+            pattern: PathPattern::parse(pattern)?,
+            strategy,
+            array_mode,
+        })
+    }
+}
+
+impl ConflictStrategy {
+    /// Parses a strategy as written in a rule, or `None` if `name` is not one.
+    pub fn parse(name: &str) -> Result<Option<ConflictStrategy>, String> {
+        Ok(Some(match name {
+            "manual" => ConflictStrategy::Manual,
+            "ours" => ConflictStrategy::Ours,
+            "theirs" => ConflictStrategy::Theirs,
+            "average-numeric" => ConflictStrategy::AverageNumeric { weight_a: 0.5 },
+            "delete-wins" => ConflictStrategy::DeleteWins,
+            "prefer-longer-string" => ConflictStrategy::PreferLongerString,
+            _ => {
+                if let Some(weight_a) = name.strip_prefix("average-numeric:") {
+                    ConflictStrategy::AverageNumeric {
+                        weight_a: unit_interval(weight_a)?,
+                    }
+                } else if let Some(setting) = name.strip_prefix("prefer-matching:") {
+                    let (prefer, pattern) = match setting.split_once(':') {
+                        Some(("a", pattern)) => (Side::A, pattern),
+                        Some(("b", pattern)) => (Side::B, pattern),
+                        _ => {
+                            return Err(
+                                "prefer-matching needs a side and a pattern, as in prefer-matching:a:<regex>"
+                                    .to_string(),
+                            )
+                        }
+                    };
+                    ConflictStrategy::PreferMatching {
+                        pattern: ValuePattern::parse(pattern)?,
+                        prefer,
+                    }
+                } else {
+                    return Ok(None);
+                }
+            }
+        }))
+    }
+}
+
+impl ArrayMergeMode {
+    /// Parses an array mode as written in a rule, or `None` if `name` is not one.
+    pub fn parse(name: &str) -> Result<Option<ArrayMergeMode>, String> {
+        Ok(Some(match name {
+            "atomic" => ArrayMergeMode::Atomic,
+            "positional" => ArrayMergeMode::Positional,
+            "similarity" => ArrayMergeMode::Similarity { threshold: 0.5 },
+            "ordered-union" => ArrayMergeMode::OrderedUnion,
+            _ => {
+                if let Some(threshold) = name.strip_prefix("similarity:") {
+                    ArrayMergeMode::Similarity {
+                        threshold: unit_interval(threshold)?,
+                    }
+                } else if let Some(key) = name.strip_prefix("keyed-ordered:") {
+                    if key.is_empty() {
+                        return Err(
+                            "keyed-ordered needs a field, as in keyed-ordered:<field>".to_string()
+                        );
+                    }
+                    ArrayMergeMode::KeyedOrdered {
+                        key: key.to_string(),
+                    }
+                } else if let Some(key) = name.strip_prefix("keyed:") {
+                    if key.is_empty() {
+                        return Err("keyed needs a field, as in keyed:<field>".to_string());
+                    }
+                    ArrayMergeMode::Keyed {
+                        key: key.to_string(),
+                    }
+                } else {
+                    return Ok(None);
+                }
+            }
+        }))
+    }
+}
+
+fn unit_interval(text: &str) -> Result<f64, String> {
+    text.parse::<f64>()
+        .ok()
+        .filter(|number| (0.0..=1.0).contains(number))
+        .ok_or_else(|| format!("'{}' is not a number between 0 and 1", text))
+}
+
+/// What to do when one branch has an array and the other an object at a path
+/// where base has either, whether or not both branches changed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayObjectMismatch {
+    /// Report a `ConflictKind::TypeMismatch` conflict.
+    #[default]
+    Conflict,
+    /// Take branch A's value without a conflict.
+    TakeA,
+    /// Take branch B's value without a conflict.
+    TakeB,
+}
+
+/// What to do when base has an array, one branch replaced it with `null` and
+/// the other edited the array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayNullMismatch {
+    /// Report a `ConflictKind::TypeMismatch` conflict.
+    #[default]
+    Conflict,
+    /// Take the edited array without a conflict.
+    TakeEdit,
+    /// Take `null` without a conflict.
+    TakeNull,
+}
+
+/// Which keys of an object may appear in the merged object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KeyScope {
+    /// Keys present in any version.
+    #[default]
+    Union,
+    /// Only keys present in base; keys added by either branch are dropped.
+    Base,
+    /// Only keys present in all three versions.
+    Intersection,
+    /// Exactly the keys present in base: additions are dropped and deletions are
+    /// ignored, so only modifications of existing values are merged.
+    ValuesOnly,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ConflictStrategy {
+    /// Conflicts are left unresolved: A's value (or the modified one, if A
+    /// deleted it) is kept in place and the conflict is reported.
+    #[default]
+    Manual,
+    /// Conflicts are resolved in favour of branch A.
+    Ours,
+    /// Conflicts are resolved in favour of branch B.
+    Theirs,
+    /// Conflicts are resolved in favour of the first version in `order` that
+    /// has a value at the path, so deletions are never picked this way. With
+    /// `changed_only`, a branch whose value is the same as base's is skipped too.
+    /// A conflict no listed version qualifies for is left unresolved.
+    Prefer {
+        order: Vec<Side>,
+        changed_only: bool,
+    },
+    /// Conflicts between two numbers are resolved with their weighted
+    /// average, `weight_a * a + (1 - weight_a) * b`, as a `Side::Custom`
+    /// value. The average of two integers is written as an integer when it is
+    /// whole. Other conflicts are left unresolved.
+    AverageNumeric { weight_a: f64 },
+    /// Conflicts between two scalars are resolved in favour of `prefer`, A or
+    /// B, if its value matches `pattern`, and of the other branch otherwise:
+    /// for example, taking whichever side looks like a version number. Other
+    /// conflicts are left unresolved.
+    PreferMatching { pattern: ValuePattern, prefer: Side },
+    /// A branch's deletion wins over the other's modification, so the value is
+    /// removed, for workflows where deletions are deliberate cleanups. Other
+    /// conflicts are left unresolved.
+    DeleteWins,
+    /// Conflicts between two strings are resolved in favour of the longer,
+    /// counted in characters, as the more detailed edit of a free-text field.
+    /// Strings of the same length and other conflicts are left unresolved.
+    PreferLongerString,
+}
+
+/// A regular expression searched for in scalar values: in strings as they
+/// are, and in numbers and booleans as written in JSON. `null` never matches.
+#[derive(Debug, Clone)]
+pub struct ValuePattern(Regex);
+
+impl ValuePattern {
+    pub fn parse(pattern: &str) -> Result<ValuePattern, String> {
+        Regex::new(pattern)
+            .map(ValuePattern)
+            .map_err(|e| format!("invalid value pattern '{}': {}", pattern, e))
+    }
+
+    pub fn matches(&self, value: &Value) -> bool {
+        match value {
+            Value::String(text) => self.0.is_match(text),
+            Value::Number(_) | Value::Bool(_) => self.0.is_match(&value.to_string()),
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq for ValuePattern {
+    fn eq(&self, other: &ValuePattern) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ArrayMergeMode {
+    /// Arrays are compared as whole values; differing edits conflict.
+    #[default]
+    Atomic,
+    /// Elements at the same index are merged with each other, like object keys.
+    Positional,
+    /// A heuristic for arrays of objects without an id field. Elements are paired
+    /// up across versions by structural similarity (the share of fields with
+    /// equal values, see `similarity::similarity`) when it reaches `threshold`,
+    /// then merged pairwise. Unpaired elements count as added or deleted.
+    ///
+    /// Pairing is greedy and deterministic, but it is a guess: elements that
+    /// changed most of their fields are taken for new ones. When an element has
+    /// several equally good candidates the whole array is merged positionally.
+    ///
+    /// The result follows A's order; elements only B still has (deleted in A but
+    /// modified in B), then elements B added, are appended.
+    Similarity { threshold: f64 },
+    /// The elements of base, then A, then B, each kept once in the order first
+    /// seen, for arrays used as sets. Nothing is ever removed and nothing
+    /// conflicts: an element deleted in a branch is still in base.
+    OrderedUnion,
+    /// For arrays of objects identified by the field `key`. Elements with the
+    /// same id are merged with each other, like object keys: an element missing
+    /// from a branch was deleted there, and conflicts if the other branch
+    /// modified it. Ids added in both branches with different contents conflict.
+    ///
+    /// The result follows A's order, then elements only B still has, then the
+    /// ones B added. An array with an element lacking the field, or two
+    /// elements with the same id, is merged positionally instead.
+    Keyed { key: String },
+    /// Like `Keyed`, for lists whose order matters too: elements are matched
+    /// by id, but the result keeps base's order, so reorderings in a branch do
+    /// not count. Each element A added goes right after the nearest element
+    /// before it in A that base has, or first if there is none, so elements
+    /// added next to each other stay together in A's order. Elements B added
+    /// are then placed the same way, after the nearest element before them in
+    /// B that is already placed, and after any A added at the same place.
+    KeyedOrdered { key: String },
+}
+
+impl MergeOptions {
+    fn is_ignored(&self, path: &str) -> bool {
+        self.ignore.iter().any(|pattern| pattern.matches(path))
+    }
+
+    // The branch that owns `path`, if any.
+    fn authority_at(&self, path: &str) -> Option<Side> {
+        if self.authoritative_a.iter().any(|p| p.matches(path)) {
+            Some(Side::A)
+        } else if self.authoritative_b.iter().any(|p| p.matches(path)) {
+            Some(Side::B)
+        } else {
+            None
+        }
+    }
+
+    // Whether some paths are treated specially whatever the branches did, so
+    // containers must be merged entry by entry to find them.
+    fn has_fixed_paths(&self) -> bool {
+        !self.ignore.is_empty()
+            || !self.authoritative_a.is_empty()
+            || !self.authoritative_b.is_empty()
+    }
+
+    // Whether `wanted` holds for the array mode or the mode of any rule.
+    fn has_array_mode(&self, wanted: impl Fn(&ArrayMergeMode) -> bool) -> bool {
+        wanted(&self.array_mode)
+            || self
+                .rules
+                .iter()
+                .filter_map(|rule| rule.array_mode.as_ref())
+                .any(wanted)
+    }
+
+    // The conflict strategy and array mode in effect at `path`.
+    fn settings_at(&self, path: &str) -> (&ConflictStrategy, &ArrayMergeMode) {
+        let mut strategy = &self.strategy;
+        let mut array_mode = &self.array_mode;
+        for rule in self.rules.iter().filter(|rule| rule.pattern.matches(path)) {
+            if let Some(rule_strategy) = &rule.strategy {
+                strategy = rule_strategy;
+            }
+            if let Some(rule_mode) = &rule.array_mode {
+                array_mode = rule_mode;
+            }
+        }
+        (strategy, array_mode)
+    }
+
+    fn array_mode_at(&self, path: &str) -> &ArrayMergeMode {
+        self.settings_at(path).1
+    }
+
+    fn max_array_len_at(&self, path: &str) -> Option<usize> {
+        self.array_length_limits
+            .iter()
+            .rev()
+            .find(|limit| limit.pattern.matches(path))
+            .map(|limit| limit.max)
+    }
+
+    // Whether `x` and `y`, at `path`, are timestamps no further apart than a
+    // tolerance for the path allows.
+    fn timestamps_equal(&self, path: &str, x: &Value, y: &Value) -> bool {
+        let (Value::String(x), Value::String(y)) = (x, y) else {
+            return false;
+        };
+        let Some(tolerance) = self
+            .timestamp_tolerances
+            .iter()
+            .rev()
+            .find(|tolerance| tolerance.pattern.matches(path))
+        else {
+            return false;
+        };
+        match (timestamp::parse(x), timestamp::parse(y)) {
+            (Some(x), Some(y)) => (x - y).abs() <= tolerance.seconds,
+            _ => false,
+        }
+    }
+
+    fn values_equal(&self, x: &Value, y: &Value) -> bool {
+        if !self.string_whitespace_insensitive
+            && !self.array_set_semantics
+            && self.coercions.is_empty()
+            && !self.coerce_numeric_strings
+        {
+            return x == y;
+        }
+        match (x, y) {
+            _ if self.coercions.equivalent(x, y) => true,
+            (Value::String(text), Value::Number(number))
+            | (Value::Number(number), Value::String(text))
+                if self.coerce_numeric_strings =>
+            {
+                coercion::numeric_string_equals(text, number)
+            }
+            (Value::String(x), Value::String(y)) if !self.string_whitespace_insensitive => x == y,
+            (Value::String(x), Value::String(y)) => {
+                normalize_whitespace(x).eq(normalize_whitespace(y))
+            }
+            (Value::Object(x), Value::Object(y)) => {
+                x.len() == y.len()
+                    && x.iter().all(|(key, x_val)| {
+                        y.get(key)
+                            .is_some_and(|y_val| self.values_equal(x_val, y_val))
+                    })
+            }
+            (Value::Array(x), Value::Array(y)) if self.array_set_semantics => {
+                // Each element of `x` takes the first equal element of `y` not
+                // taken yet.
+                let mut taken = vec![false; y.len()];
+                x.len() == y.len()
+                    && x.iter().all(|x_val| {
+                        let found = (0..y.len())
+                            .find(|&index| !taken[index] && self.values_equal(x_val, &y[index]));
+                        found.map(|index| taken[index] = true).is_some()
+                    })
+            }
+            (Value::Array(x), Value::Array(y)) => {
+                x.len() == y.len() && x.iter().zip(y).all(|(x, y)| self.values_equal(x, y))
+            }
+            _ => x == y,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    BothModified,
+    BothAdded,
+    ModifiedADeletedB,
+    ModifiedBDeletedA,
+    /// Both branches changed the value, and they disagree on its JSON type.
+    TypeMismatch,
+    /// Both branches renamed a record to different keys. The conflict's values
+    /// are the keys: old in `base`, new in `a` and `b`.
+    BothRenamed,
+    /// The merged array would be longer than an `ArrayLengthLimit` allows.
+    TooLong,
+    /// Only one branch changed the number, and `MergeOptions::review_numbers`
+    /// holds such changes for review. Left unresolved, base's number is kept.
+    NumberChanged,
+}
+
+impl ConflictKind {
+    /// Every kind, in declaration order.
+    pub const ALL: [ConflictKind; 8] = [
+        ConflictKind::BothModified,
+        ConflictKind::BothAdded,
+        ConflictKind::ModifiedADeletedB,
+        ConflictKind::ModifiedBDeletedA,
+        ConflictKind::TypeMismatch,
+        ConflictKind::BothRenamed,
+        ConflictKind::TooLong,
+        ConflictKind::NumberChanged,
+    ];
+
+    /// A stable identifier for tools, such as `both_modified`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ConflictKind::BothModified => "both_modified",
+            ConflictKind::BothAdded => "both_added",
+            ConflictKind::ModifiedADeletedB => "modified_a_deleted_b",
+            ConflictKind::ModifiedBDeletedA => "modified_b_deleted_a",
+            ConflictKind::TypeMismatch => "type_mismatch",
+            ConflictKind::BothRenamed => "both_renamed",
+            ConflictKind::TooLong => "too_long",
+            ConflictKind::NumberChanged => "number_changed",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ConflictKind::BothModified => "modified in both branches with different contents",
+            ConflictKind::BothAdded => "added in both branches with different contents",
+            ConflictKind::ModifiedADeletedB => "modified in branch A but deleted in branch B",
+            ConflictKind::ModifiedBDeletedA => "modified in branch B but deleted in branch A",
+            ConflictKind::TypeMismatch => "changed to different types in both branches",
+            ConflictKind::BothRenamed => "renamed to different keys in both branches",
+            ConflictKind::TooLong => "merged into more elements than allowed",
+            ConflictKind::NumberChanged => "changed to another number in one branch",
+        }
+    }
+}
+
+/// Serialized as its `name`.
+impl Serialize for ConflictKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    A,
+    B,
+    Base,
+    /// A value from outside the three versions, such as one chosen by an
+    /// external resolver. Strategies never pick it.
+    Custom,
+}
+
+impl Side {
+    /// A stable identifier for tools: `a`, `b`, `base` or `custom`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Side::A => "a",
+            Side::B => "b",
+            Side::Base => "base",
+            Side::Custom => "custom",
+        }
+    }
+}
+
+/// Serialized as its `name`.
+impl Serialize for Side {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+impl std::fmt::Display for Side {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Side::A => write!(f, "branch A"),
+            Side::B => write!(f, "branch B"),
+            Side::Base => write!(f, "base"),
+            Side::Custom => write!(f, "a custom value"),
+        }
+    }
+}
+
+/// How a branch changed the value at a conflict's path from base's, comparing
+/// the values exactly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Unchanged,
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl Change {
+    pub fn of(base: Option<&Value>, branch: Option<&Value>) -> Change {
+        match (base, branch) {
+            (None, Some(_)) => Change::Added,
+            (Some(_), None) => Change::Deleted,
+            (Some(base), Some(branch)) if base != branch => Change::Modified,
+            _ => Change::Unchanged,
+        }
+    }
+
+    /// A stable identifier for tools: `unchanged`, `added`, `modified` or
+    /// `deleted`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Change::Unchanged => "unchanged",
+            Change::Added => "added",
+            Change::Modified => "modified",
+            Change::Deleted => "deleted",
+        }
+    }
+}
+
+/// Serialized as its `name`.
+impl Serialize for Change {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+/// How each branch diverged from base at a conflict's path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Divergence {
+    pub a: Change,
+    pub b: Change,
+}
+
+impl Divergence {
+    pub fn of(base: Option<&Value>, a: Option<&Value>, b: Option<&Value>) -> Divergence {
+        Divergence {
+            a: Change::of(base, a),
+            b: Change::of(base, b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Conflict {
+    /// JSON pointer to the conflicting value; empty for the document root.
+    pub path: String,
+    pub kind: ConflictKind,
+    pub base: Option<Value>,
+    pub a: Option<Value>,
+    pub b: Option<Value>,
+    /// How `a` and `b` each differ from `base`.
+    pub changes: Divergence,
+    /// The version whose value the strategy picked, or `None` if unresolved.
+    pub resolution: Option<Side>,
+}
+
+impl Conflict {
+    pub fn is_resolved(&self) -> bool {
+        self.resolution.is_some()
+    }
+
+    /// `changes` for people, such as `A: 1 → 2, B: deleted`. Scalars are
+    /// written out; a changed object or array is only said to be modified or
+    /// added.
+    pub fn describe_changes(&self) -> String {
+        let describe = |change: Change, value: &Option<Value>| {
+            let scalar = |value: &Option<Value>| {
+                value
+                    .as_ref()
+                    .filter(|value| !value.is_object() && !value.is_array())
+                    .map(Value::to_string)
+            };
+            match (change, scalar(&self.base), scalar(value)) {
+                (Change::Modified, Some(base), Some(value)) => format!("{} → {}", base, value),
+                (Change::Added, _, Some(value)) => format!("added {}", value),
+                _ => change.name().to_string(),
+            }
+        };
+        format!(
+            "A: {}, B: {}",
+            describe(self.changes.a, &self.a),
+            describe(self.changes.b, &self.b)
+        )
+    }
+}
 
 pub fn three_way_merge(base: &Value, a: &Value, b: &Value) -> (Value, bool) {
-    three_way_merge_recursive(base, a, b, "")
-}
-
-fn three_way_merge_recursive(base: &Value, a: &Value, b: &Value, path: &str) -> (Value, bool) {
-    match (base, a, b) {
-        (Value::Object(base_map), Value::Object(a_map), Value::Object(b_map)) => {
-            let mut merged = Map::new();
-            let mut had_conflict = false;
-            let keys: HashSet<String> = base_map
-                .keys()
-                .chain(a_map.keys())
-                .chain(b_map.keys())
-                .map(|k| k.to_string())
-                .collect();
-
-            for key in keys {
-                let base_val = base_map.get(&key);
-                let a_val = a_map.get(&key);
-                let b_val = b_map.get(&key);
-
-                let current_path = if path.is_empty() {
-                    key.clone()
+    let (merged, conflicts) = three_way_merge_with_options(base, a, b, &MergeOptions::default());
+    (merged, conflicts.iter().any(|c| !c.is_resolved()))
+}
+
+/// A stand-in base for merging two documents that have no common ancestor: what
+/// `a` and `b` have in common. Objects keep the keys both have, with the common
+/// part of their values; other values are kept where they are equal and left
+/// out where they differ. An unequal root that isn't an object in both is
+/// `null`.
+///
+/// This is a guess. Whatever both sides have is taken as unchanged, so values
+/// both sides added independently look like history, and a value one side
+/// deleted looks like an addition by the other rather than a conflict.
+pub fn infer_base(a: &Value, b: &Value) -> Value {
+    common(a, b).unwrap_or(Value::Null)
+}
+
+// `weight_a * a + (1 - weight_a) * b` if both are numbers. The average of two
+// integers that is itself an integer is worked out exactly, whatever their
+// size.
+fn weighted_average(weight_a: f64, a: &Value, b: &Value) -> Option<Value> {
+    if let (Some(a), Some(b)) = (integer(a), integer(b)) {
+        if let Some(average) = integer_average(weight_a, a, b) {
+            // Between `a` and `b`, so it fits one of the two.
+            return u64::try_from(average)
+                .map(Value::from)
+                .or_else(|_| i64::try_from(average).map(Value::from))
+                .ok();
+        }
+    }
+    let average = weight_a * a.as_f64()? + (1.0 - weight_a) * b.as_f64()?;
+    serde_json::Number::from_f64(average).map(Value::Number)
+}
+
+fn integer(value: &Value) -> Option<i128> {
+    value
+        .as_u64()
+        .map(i128::from)
+        .or_else(|| value.as_i64().map(i128::from))
+}
+
+// `b + weight_a * (a - b)` if it is an integer. A weight from 0 to 1 is
+// exactly `m / 2^k` for integers `m < 2^53` and `k`, so with `a - b` under
+// 2^65 the product fits an i128.
+fn integer_average(weight_a: f64, a: i128, b: i128) -> Option<i128> {
+    if !(0.0..=1.0).contains(&weight_a) {
+        return None;
+    }
+    let difference = a - b;
+    if weight_a == 0.0 || difference == 0 {
+        return Some(b);
+    }
+    let bits = weight_a.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as u32;
+    let mut mantissa = bits & ((1 << 52) - 1);
+    if exponent != 0 {
+        mantissa |= 1 << 52;
+    }
+    let shift = mantissa.trailing_zeros();
+    let numerator = i128::from(mantissa >> shift);
+    let power = (1075 - exponent.max(1)) - shift;
+    let product = difference * numerator;
+    (power < 128 && product.trailing_zeros() >= power).then(|| b + (product >> power))
+}
+
+fn common(a: &Value, b: &Value) -> Option<Value> {
+    match (a, b) {
+        (Value::Object(a_map), Value::Object(b_map)) => Some(Value::Object(
+            a_map
+                .iter()
+                .filter_map(|(key, a_val)| {
+                    let shared = common(a_val, b_map.get(key)?)?;
+                    Some((key.clone(), shared))
+                })
+                .collect(),
+        )),
+        _ if a == b => Some(a.clone()),
+        _ => None,
+    }
+}
+
+/// Whether the merge result must not be written at all. With `safe_types`, a
+/// type mismatch left unresolved blocks the output even where other conflicts
+/// would still let it through.
+pub fn blocks_output(conflicts: &[Conflict], opts: &MergeOptions) -> bool {
+    opts.safe_types
+        && conflicts
+            .iter()
+            .any(|c| c.kind == ConflictKind::TypeMismatch && !c.is_resolved())
+}
+
+pub fn three_way_merge_with_options(
+    base: &Value,
+    a: &Value,
+    b: &Value,
+    opts: &MergeOptions,
+) -> (Value, Vec<Conflict>) {
+    let mut conflicts = Vec::new();
+    let (merged, _) = three_way_merge_visit(base, a, b, opts, &mut |conflict| {
+        conflicts.push(conflict);
+        ControlFlow::Continue(())
+    });
+    (merged, conflicts)
+}
+
+/// Why `merge_strings` could not produce a merged document.
+#[derive(Debug)]
+pub enum MergeError {
+    /// One of the inputs is not valid JSON.
+    Parse {
+        /// Which input failed: "base", "branch A" or "branch B".
+        input: &'static str,
+        source: serde_json::Error,
+    },
+    Serialize(serde_json::Error),
+}
+
+impl std::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MergeError::Parse { input, source } => {
+                write!(f, "failed to parse {}: {}", input, source)
+            }
+            MergeError::Serialize(source) => {
+                write!(f, "failed to serialize merged JSON: {}", source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MergeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MergeError::Parse { source, .. } | MergeError::Serialize(source) => Some(source),
+        }
+    }
+}
+
+/// Parses the three documents, merges them with `opts` and returns the merged
+/// document pretty-printed, along with every conflict found. Conflicts do not
+/// make this fail; check them, or `blocks_output`, before using the result.
+pub fn merge_strings(
+    base: &str,
+    a: &str,
+    b: &str,
+    opts: &MergeOptions,
+) -> Result<(String, Vec<Conflict>), MergeError> {
+    let base = parse_input("base", base)?;
+    merge_strings_with_base(&base, a, b, opts)
+}
+
+/// Like `merge_strings`, with base already parsed, for merging many branches
+/// against the same base, such as in a server: parse it once, keep it in an
+/// `Arc<Value>` shared by the threads, and pass it to each merge. The merge
+/// only reads base, copying just the values the result takes from it.
+pub fn merge_strings_with_base(
+    base: &Value,
+    a: &str,
+    b: &str,
+    opts: &MergeOptions,
+) -> Result<(String, Vec<Conflict>), MergeError> {
+    let a = parse_input("branch A", a)?;
+    let b = parse_input("branch B", b)?;
+
+    let (merged, conflicts) = three_way_merge_with_options(base, &a, &b, opts);
+    let merged = serde_json::to_string_pretty(&merged).map_err(MergeError::Serialize)?;
+    Ok((merged, conflicts))
+}
+
+fn parse_input(input: &'static str, text: &str) -> Result<Value, MergeError> {
+    serde_json::from_str(text).map_err(|source| MergeError::Parse { input, source })
+}
+
+/// Like `merge_strings`, but returns the merged document as a value, and the
+/// edits turning the text of `a` into the pretty-printed merged document, for
+/// editors updating a buffer holding `a` in place.
+pub fn merge_strings_with_edits(
+    base: &str,
+    a: &str,
+    b: &str,
+    opts: &MergeOptions,
+) -> Result<(Value, Vec<TextEdit>, Vec<Conflict>), MergeError> {
+    let base = parse_input("base", base)?;
+    let a_value = parse_input("branch A", a)?;
+    let b = parse_input("branch B", b)?;
+
+    let (merged, conflicts) = three_way_merge_with_options(&base, &a_value, &b, opts);
+    let text = serde_json::to_string_pretty(&merged).map_err(MergeError::Serialize)?;
+    let edits = line_diff::text_edits(a, &text);
+    Ok((merged, edits, conflicts))
+}
+
+/// Merges the documents, handing each conflict to `visitor` as soon as it is
+/// found. Conflicts are visited in document order, keys sorted within objects.
+///
+/// Returning `ControlFlow::Break` from the visitor stops the merge: no further
+/// conflicts are visited, and the returned value only contains the entries merged
+/// up to and including the one that conflicted; everything after it is omitted.
+/// Passing `MergeOptions::deadline` stops it the same way, before the first
+/// object entry not reached in time. The second element of the result tells
+/// whether the merge was stopped.
+///
+/// A partial result is not a merge of the documents: the omitted entries are
+/// missing from it as if deleted, in whichever objects were being merged when
+/// it stopped, so it should not be written in place of a complete merge.
+///
+/// Conflicts resolved by the strategy are visited too, see `Conflict::resolution`.
+pub fn three_way_merge_visit<F>(
+    base: &Value,
+    a: &Value,
+    b: &Value,
+    opts: &MergeOptions,
+    visitor: &mut F,
+) -> (Value, ControlFlow<()>)
+where
+    F: FnMut(Conflict) -> ControlFlow<()>,
+{
+    let stripped = opts
+        .ignore_compare
+        .as_ref()
+        .map(|ignore| [base, a, b].map(|version| ignore.stripped(version, "")));
+    let [base_cmp, a_cmp, b_cmp] = match &stripped {
+        Some([base, a, b]) => [base, a, b],
+        None => [base, a, b],
+    };
+    let mut merger = Merger::new(opts, visitor, true);
+    let (mut merged, _) = merger.three_way_merge_recursive(base_cmp, a_cmp, b_cmp, "");
+    if let Some(ignore) = &opts.ignore_compare {
+        let versions = match ignore.source {
+            Side::B => [b, a, base],
+            Side::Base => [base, a, b],
+            _ => [a, b, base],
+        };
+        ignore.restore(&mut merged, &versions.map(Some), "");
+    }
+    let merged = match &opts.value_transform {
+        Some(transform) => transform.apply("", merged),
+        None => merged,
+    };
+    let flow = if merger.stopped {
+        ControlFlow::Break(())
+    } else {
+        ControlFlow::Continue(())
+    };
+    (merged, flow)
+}
+
+/// The conflicts `three_way_merge_with_options` would report, found without
+/// building the merged document. Cheaper than a full merge when only whether
+/// the documents conflict matters.
+pub fn detect_conflicts(base: &Value, a: &Value, b: &Value, opts: &MergeOptions) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    let mut visitor = |conflict| {
+        conflicts.push(conflict);
+        ControlFlow::Continue(())
+    };
+    match &opts.ignore_compare {
+        Some(ignore) => {
+            let [base, a, b] = [base, a, b].map(|version| ignore.stripped(version, ""));
+            Merger::new(opts, &mut visitor, false).three_way_merge_recursive(&base, &a, &b, "");
+        }
+        None => {
+            Merger::new(opts, &mut visitor, false).three_way_merge_recursive(base, a, b, "");
+        }
+    }
+    conflicts
+}
+
+/// Where the merge takes a value of its result from, see `plan`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanSource {
+    A,
+    B,
+    Base,
+    /// The value is in conflict, whatever the strategy would make of it.
+    Conflict,
+}
+
+/// A value of the merge result and where it comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlanStep {
+    pub path: String,
+    #[serde(rename = "take")]
+    pub source: PlanSource,
+}
+
+/// What merging the documents would do, found without building the merged
+/// document, for showing a merge before making it: each scalar and empty
+/// container of the result, in document order, with the branch its value
+/// comes from, or `Base` where it is base's value, and each conflict, left
+/// unresolved, instead of the values under it. Values are listed at their
+/// paths in the version they come from, which for some array modes differ from
+/// their paths in the result. Keys left out of comparisons are not listed.
+pub fn plan(base: &Value, a: &Value, b: &Value, opts: &MergeOptions) -> Vec<PlanStep> {
+    let mut conflicts = Vec::new();
+    let mut visitor = |conflict: Conflict| {
+        conflicts.push(conflict.path);
+        ControlFlow::Continue(())
+    };
+    let stripped = opts
+        .ignore_compare
+        .as_ref()
+        .map(|ignore| [base, a, b].map(|version| ignore.stripped(version, "")));
+    let [base, a, b] = match &stripped {
+        Some([base, a, b]) => [base, a, b],
+        None => [base, a, b],
+    };
+    let mut merger = Merger::new(opts, &mut visitor, false);
+    merger.taken = Some(HashSet::new());
+    merger.three_way_merge_recursive(base, a, b, "");
+    let taken = merger.taken.take().unwrap_or_default();
+
+    let mut steps: Vec<PlanStep> = conflicts
+        .into_iter()
+        .map(|path| PlanStep {
+            path,
+            source: PlanSource::Conflict,
+        })
+        .collect();
+    for (version, source) in [
+        (a, PlanSource::A),
+        (b, PlanSource::B),
+        (base, PlanSource::Base),
+    ] {
+        taken_leaves(version, "", false, &taken, &mut |path, value| {
+            let source = if base.pointer(path) == Some(value) {
+                PlanSource::Base
+            } else {
+                source
+            };
+            steps.push(PlanStep {
+                path: path.to_string(),
+                source,
+            });
+        });
+    }
+    steps.sort_by_cached_key(|step| pointer::order_key(&step.path));
+    steps.dedup_by(|x, y| x.path == y.path);
+    steps
+}
+
+// Calls `leaf` with each scalar and empty container of `value` that was taken
+// into the result, itself or as part of a container.
+fn taken_leaves(
+    value: &Value,
+    path: &str,
+    under_taken: bool,
+    taken: &HashSet<*const Value>,
+    leaf: &mut dyn FnMut(&str, &Value),
+) {
+    let under_taken = under_taken || taken.contains(&(value as *const Value));
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, val) in map {
+                taken_leaves(val, &pointer::push(path, key), under_taken, taken, leaf);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (index, item) in items.iter().enumerate() {
+                let item_path = pointer::push(path, &index.to_string());
+                taken_leaves(item, &item_path, under_taken, taken, leaf);
+            }
+        }
+        _ if under_taken => leaf(path, value),
+        _ => {}
+    }
+}
+
+// The base that objects replacing a value of another type are merged against.
+// It lives as long as the inputs, as the equality cache needs.
+static EMPTY_OBJECT: LazyLock<Value> = LazyLock::new(|| Value::Object(Map::new()));
+
+struct Merger<'a> {
+    opts: &'a MergeOptions,
+    visitor: &'a mut dyn FnMut(Conflict) -> ControlFlow<()>,
+    stopped: bool,
+    // Whether to build the merged value. Without it only the conflicts are
+    // wanted, and every value taken from an input is `null`.
+    build: bool,
+    // Results of `equal` for pairs of containers, by address. Every value the
+    // merge compares lives in one of the input documents, which outlive it.
+    equal_cache: HashMap<(*const Value, *const Value), bool>,
+    // With `plan`, the addresses of the input values taken into the result.
+    taken: Option<HashSet<*const Value>>,
+}
+
+impl<'a> Merger<'a> {
+    fn new(
+        opts: &'a MergeOptions,
+        visitor: &'a mut dyn FnMut(Conflict) -> ControlFlow<()>,
+        build: bool,
+    ) -> Self {
+        Merger {
+            opts,
+            visitor,
+            stopped: false,
+            build,
+            equal_cache: HashMap::new(),
+            taken: None,
+        }
+    }
+
+    // Reports an event to the sink in the options, or else to the `log` facade.
+    fn log(&self, level: LogLevel, message: std::fmt::Arguments) {
+        match &self.opts.log_sink {
+            Some(sink) => (sink.0)(&LogEvent {
+                level,
+                message: message.to_string(),
+            }),
+            None => match level {
+                LogLevel::Error => error!("{}", message),
+                LogLevel::Warn => warn!("{}", message),
+                LogLevel::Debug => debug!("{}", message),
+                LogLevel::Trace => trace!("{}", message),
+            },
+        }
+    }
+
+    // With `warn_on_drop`, reports that the value at `path`, which some version
+    // has, is left out of the result.
+    fn dropped(&self, path: &str, why: std::fmt::Arguments) {
+        if self.opts.warn_on_drop {
+            self.log(
+                LogLevel::Warn,
+                format_args!("Dropped file '{}': {}", path, why),
+            );
+        }
+    }
+
+    // Reports the keys of an object at `path` that the key scope leaves out of
+    // `keys`, which are sorted.
+    fn report_out_of_scope(&self, maps: [&Map<String, Value>; 3], keys: &[&String], path: &str) {
+        let mut dropped: Vec<&String> = maps
+            .iter()
+            .flat_map(|map| map.keys())
+            .filter(|key| keys.binary_search(key).is_err())
+            .collect();
+        dropped.sort_unstable();
+        dropped.dedup();
+        for key in dropped {
+            let key_path = pointer::push(path, key);
+            if self.opts.key_scope == KeyScope::Intersection {
+                let missing: Vec<String> = [Side::Base, Side::A, Side::B]
+                    .into_iter()
+                    .zip(maps)
+                    .filter(|(_, map)| !map.contains_key(key))
+                    .map(|(side, _)| side.to_string())
+                    .collect();
+                self.dropped(
+                    &key_path,
+                    format_args!(
+                        "missing from {}, and only keys of every version are merged",
+                        missing.join(" and ")
+                    ),
+                );
+            } else {
+                self.dropped(
+                    &key_path,
+                    format_args!("not in base, whose keys alone are merged"),
+                );
+            }
+        }
+    }
+
+    fn trace_key(&mut self, path: &str, versions: [Option<&Value>; 3], conflict: bool) {
+        let decision = if self.opts.is_ignored(path) {
+            "ignored"
+        } else if let Some(side) = self.opts.authority_at(path) {
+            if side == Side::A {
+                "authoritative-a"
+            } else {
+                "authoritative-b"
+            }
+        } else if conflict {
+            "conflict"
+        } else {
+            match versions {
+                [None, Some(_), None] => "added-in-a",
+                [None, None, Some(_)] => "added-in-b",
+                [None, _, _] => "added-in-both",
+                [Some(_), None, None] => "deleted-in-both",
+                [Some(_), None, Some(_)] => "deleted-in-a",
+                [Some(_), Some(_), None] => "deleted-in-b",
+                [Some(base), Some(a), Some(b)] if self.equal(a, base) && self.equal(b, base) => {
+                    "unchanged"
+                }
+                [Some(_), Some(_), Some(_)] => "merged",
+            }
+        };
+        let [base, a, b] = versions.map(|version| match version {
+            Some(_) => "present",
+            None => "absent",
+        });
+        self.log(
+            LogLevel::Trace,
+            format_args!(
+                "key={} base={} a={} b={} decision={}",
+                Value::from(path),
+                base,
+                a,
+                b,
+                decision
+            ),
+        );
+    }
+
+    // A value taken from one of the inputs into the result.
+    fn keep(&mut self, value: &Value) -> Value {
+        if let Some(taken) = &mut self.taken {
+            taken.insert(value);
+        }
+        if !self.build {
+            Value::Null
+        } else if let Some(key) = &self.opts.force_key {
+            unwrap_forced(value.clone(), key)
+        } else {
+            value.clone()
+        }
+    }
+
+    fn resolve(
+        &self,
+        path: &str,
+        kind: ConflictKind,
+        base: Option<&Value>,
+        a: Option<&Value>,
+        b: Option<&Value>,
+    ) -> Option<Side> {
+        if self.opts.safe_types && kind == ConflictKind::TypeMismatch {
+            return None;
+        }
+        if self.opts.null_coalesce {
+            match (a, b) {
+                (Some(Value::Null), Some(value)) if !value.is_null() => return Some(Side::B),
+                (Some(value), Some(Value::Null)) if !value.is_null() => return Some(Side::A),
+                _ => {}
+            }
+        }
+        match self.opts.settings_at(path).0 {
+            ConflictStrategy::Manual => None,
+            ConflictStrategy::Ours => Some(Side::A),
+            ConflictStrategy::Theirs => Some(Side::B),
+            ConflictStrategy::Prefer {
+                order,
+                changed_only,
+            } => order.iter().copied().find(|side| {
+                let value = match side {
+                    Side::A => a,
+                    Side::B => b,
+                    Side::Base => return base.is_some(),
+                    Side::Custom => return false,
+                };
+                value.is_some_and(|value| {
+                    !changed_only || !base.is_some_and(|base| self.opts.values_equal(base, value))
+                })
+            }),
+            ConflictStrategy::AverageNumeric { weight_a } => {
+                weighted_average(*weight_a, a?, b?).map(|_| Side::Custom)
+            }
+            ConflictStrategy::PreferMatching { pattern, prefer } => {
+                let (a, b) = (a?, b?);
+                if [a, b]
+                    .iter()
+                    .any(|value| value.is_object() || value.is_array())
+                {
+                    return None;
+                }
+                let (preferred, other) = match prefer {
+                    Side::B => ((b, Side::B), Side::A),
+                    _ => ((a, Side::A), Side::B),
+                };
+                Some(if pattern.matches(preferred.0) {
+                    preferred.1
+                } else {
+                    other
+                })
+            }
+            ConflictStrategy::DeleteWins => match kind {
+                ConflictKind::ModifiedADeletedB => Some(Side::B),
+                ConflictKind::ModifiedBDeletedA => Some(Side::A),
+                _ => None,
+            },
+            ConflictStrategy::PreferLongerString => {
+                let (a, b) = (a?.as_str()?.chars().count(), b?.as_str()?.chars().count());
+                match a.cmp(&b) {
+                    Ordering::Greater => Some(Side::A),
+                    Ordering::Less => Some(Side::B),
+                    Ordering::Equal => None,
+                }
+            }
+        }
+    }
+
+    // Records a conflict and returns the value to keep, plus whether the
+    // conflict was left unresolved. The value is built even when the result
+    // is not, as a renamed record's key is chosen from it.
+    fn conflict(
+        &mut self,
+        path: &str,
+        kind: ConflictKind,
+        base: Option<&Value>,
+        a: Option<&Value>,
+        b: Option<&Value>,
+    ) -> (Option<Value>, bool) {
+        // A document that is a single value conflicts as a whole.
+        let location = if path.is_empty() {
+            "the whole document".to_string()
+        } else {
+            format!("file '{}'", path)
+        };
+        let description = kind.description();
+
+        let resolution = self.resolve(path, kind, base, a, b);
+        let value = match resolution {
+            Some(side) => {
+                self.log(
+                    LogLevel::Warn,
+                    format_args!("Conflict: {} {}; taking {}", location, description, side),
+                );
+                match side {
+                    Side::A => a.cloned(),
+                    Side::B => b.cloned(),
+                    Side::Base => base.cloned(),
+                    Side::Custom => match (self.opts.settings_at(path).0, a, b) {
+                        (ConflictStrategy::AverageNumeric { weight_a }, Some(a), Some(b)) => {
+                            weighted_average(*weight_a, a, b)
+                        }
+                        _ => a.or(b).cloned(),
+                    },
+                }
+            }
+            None => {
+                self.log(
+                    LogLevel::Error,
+                    format_args!("Conflict: {} {}", location, description),
+                );
+                match kind {
+                    ConflictKind::NumberChanged => base.cloned(),
+                    _ => a.or(b).cloned(),
+                }
+            }
+        };
+        if let (Some(side), None) = (resolution, &value) {
+            self.dropped(path, format_args!("{} by taking {}", description, side));
+        }
+        let value = match &self.opts.force_key {
+            Some(key) => value.map(|value| unwrap_forced(value, key)),
+            None => value,
+        };
+
+        let conflict = Conflict {
+            path: path.to_string(),
+            kind,
+            base: base.cloned(),
+            a: a.cloned(),
+            b: b.cloned(),
+            changes: Divergence::of(base, a, b),
+            resolution,
+        };
+        if (self.visitor)(conflict).is_break() {
+            self.stopped = true;
+        }
+
+        (value, resolution.is_none())
+    }
+
+    // Whether the merge has been stopped, by the visitor or now by the deadline.
+    fn out_of_time(&mut self) -> bool {
+        if !self.stopped {
+            self.stopped = self
+                .opts
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        }
+        self.stopped
+    }
+
+    fn three_way_merge_recursive(
+        &mut self,
+        base: &Value,
+        a: &Value,
+        b: &Value,
+        path: &str,
+    ) -> (Value, bool) {
+        match (base, a, b) {
+            (Value::Object(base_map), Value::Object(a_map), Value::Object(b_map))
+                if path.is_empty() && self.opts.record_key.is_some() =>
+            {
+                self.merge_record_map(base_map, a_map, b_map)
+            }
+
+            (Value::Object(base_map), Value::Object(a_map), Value::Object(b_map)) => {
+                // When only one branch touched the object, take its side whole
+                // instead of rebuilding an equal map key by key.
+                if !self.must_descend(base, a, b, path) {
+                    if self.equal(a, base) && self.can_take_whole(base, b, path) {
+                        return (self.keep(b), false);
+                    }
+                    if self.equal(b, base) && self.can_take_whole(base, a, path) {
+                        return (self.keep(a), false);
+                    }
+                }
+
+                let mut merged = Map::new();
+                let mut had_conflict = false;
+                // Keys are borrowed from the inputs, and only copied for the
+                // entries of the result, as objects can be very wide.
+                let mut keys: Vec<&String> = match self.opts.key_scope {
+                    KeyScope::Union => {
+                        let mut keys =
+                            Vec::with_capacity(base_map.len() + a_map.len() + b_map.len());
+                        keys.extend(base_map.keys().chain(a_map.keys()).chain(b_map.keys()));
+                        keys
+                    }
+                    KeyScope::Base | KeyScope::ValuesOnly => base_map.keys().collect(),
+                    KeyScope::Intersection => base_map
+                        .keys()
+                        .filter(|k| a_map.contains_key(*k) && b_map.contains_key(*k))
+                        .collect(),
+                };
+                keys.sort_unstable();
+                keys.dedup();
+                if self.opts.warn_on_drop && self.opts.key_scope != KeyScope::Union {
+                    self.report_out_of_scope([base_map, a_map, b_map], &keys, path);
+                }
+
+                for key in keys {
+                    if self.out_of_time() {
+                        break;
+                    }
+
+                    let base_val = base_map.get(key);
+                    let a_val = a_map.get(key);
+                    let b_val = b_map.get(key);
+                    // A branch that deleted the key is taken to have left it alone.
+                    let (a_val, b_val) = match self.opts.key_scope {
+                        KeyScope::ValuesOnly => (a_val.or(base_val), b_val.or(base_val)),
+                        _ => (a_val, b_val),
+                    };
+
+                    let current_path = pointer::push(path, key);
+
+                    let (merged_val, conflict) =
+                        self.merge_entry(base_val, a_val, b_val, &current_path);
+                    if conflict {
+                        had_conflict = true;
+                    }
+                    if self.opts.trace_keys {
+                        self.trace_key(&current_path, [base_val, a_val, b_val], conflict);
+                    }
+
+                    if let Some(val) = merged_val.filter(|_| self.build) {
+                        merged.insert(key.clone(), val);
+                    }
+                }
+                (Value::Object(merged), had_conflict)
+            }
+
+            (Value::Array(base_arr), Value::Array(a_arr), Value::Array(b_arr))
+                if *self.opts.array_mode_at(path) != ArrayMergeMode::Atomic =>
+            {
+                self.merge_arrays(base_arr, a_arr, b_arr, path)
+            }
+
+            _ => {
+                if self.equal_at(a, b, path) {
+                    (self.keep(a), false)
+                } else if let Some((merged_val, conflict)) = self
+                    .array_object_mismatch(path, base, a, b)
+                    .or_else(|| self.array_null_mismatch(path, base, a, b))
+                {
+                    (merged_val.unwrap_or(Value::Null), conflict)
+                } else if self.held_for_review(base, a, b, path)
+                    || self.held_for_review(base, b, a, path)
+                {
+                    let (merged_val, conflict) = self.conflict(
+                        path,
+                        ConflictKind::NumberChanged,
+                        Some(base),
+                        Some(a),
+                        Some(b),
+                    );
+                    (merged_val.unwrap_or(Value::Null), conflict)
+                } else if self.equal_at(a, base, path) {
+                    (self.keep(b), false)
+                } else if self.equal_at(b, base, path) {
+                    (self.keep(a), false)
+                } else if self.both_replaced_with_objects(base, a, b) {
+                    self.three_way_merge_recursive(&EMPTY_OBJECT, a, b, path)
+                } else {
+                    let kind = modified_kind(a, b, ConflictKind::BothModified);
+                    let (merged_val, conflict) =
+                        self.conflict(path, kind, Some(base), Some(a), Some(b));
+                    (merged_val.unwrap_or(Value::Null), conflict)
+                }
+            }
+        }
+    }
+
+    // `MergeOptions::values_equal`, remembering the results for the pair and
+    // for each pair of children compared along the way. Children that are
+    // merged are compared again, so without the cache every unchanged subtree
+    // would be walked once for each level above it.
+    fn equal(&mut self, x: &Value, y: &Value) -> bool {
+        if std::ptr::eq(x, y) {
+            return true;
+        }
+        let key: (*const Value, *const Value) = (x, y);
+        if let Some(&known) = self.equal_cache.get(&key) {
+            return known;
+        }
+
+        let equal = match (x, y) {
+            (Value::Object(x_map), Value::Object(y_map)) => {
+                x_map.len() == y_map.len()
+                    && x_map.iter().all(|(key, x_val)| {
+                        y_map
+                            .get(key)
+                            .is_some_and(|y_val| self.child_equal(x_val, y_val))
+                    })
+            }
+            (Value::Array(x_arr), Value::Array(y_arr)) if !self.opts.array_set_semantics => {
+                x_arr.len() == y_arr.len()
+                    && x_arr
+                        .iter()
+                        .zip(y_arr)
+                        .all(|(x_val, y_val)| self.child_equal(x_val, y_val))
+            }
+            _ => return self.opts.values_equal(x, y),
+        };
+        self.equal_cache.insert(key, equal);
+        equal
+    }
+
+    // `equal` for the values at `path`, where scalars may also be equal
+    // within a tolerance. Tolerances only apply here, where a value is
+    // compared on its own: it is enough for branches that skewed a timestamp
+    // not to conflict, and a subtree holding it still counts as changed.
+    fn equal_at(&mut self, x: &Value, y: &Value, path: &str) -> bool {
+        self.equal(x, y) || self.opts.timestamps_equal(path, x, y)
+    }
+
+    fn child_equal(&mut self, x: &Value, y: &Value) -> bool {
+        if !(x.is_object() || x.is_array()) {
+            return self.opts.values_equal(x, y);
+        }
+        let key: (*const Value, *const Value) = (x, y);
+        if let Some(&known) = self.equal_cache.get(&key) {
+            return known;
+        }
+        let equal = self.opts.values_equal(x, y);
+        self.equal_cache.insert(key, equal);
+        equal
+    }
+
+    // Whether containers must be merged entry by entry even if a branch left
+    // them unchanged, because ignored or authoritative paths, a key scope other
+    // than `Union`, warnings of dropped keys or numbers held for review can make
+    // the result differ from either branch's value. `OrderedUnion` and
+    // `KeyedOrdered` arrays keep base's elements or order where the only branch
+    // changing them removed or moved some, and caps on array lengths apply to
+    // arrays only one branch grew, so objects that may hold such arrays are
+    // merged key by key too. With array set semantics a branch may only have
+    // reordered arrays inside, and where both agree A's order must win over the
+    // other branch's.
+    fn must_descend(&self, base: &Value, a: &Value, b: &Value, path: &str) -> bool {
+        let objects = base.is_object() && a.is_object() && b.is_object();
+        let arrays = base.is_array()
+            && a.is_array()
+            && b.is_array()
+            && *self.opts.array_mode_at(path) != ArrayMergeMode::Atomic;
+        (objects
+            && (self.opts.has_fixed_paths()
+                || self.opts.key_scope != KeyScope::Union
+                || self.opts.array_set_semantics
+                || self.opts.warn_on_drop
+                || self.opts.review_numbers
+                || self.opts.has_array_mode(keeps_base_elements)
+                || !self.opts.array_length_limits.is_empty()))
+            || (arrays
+                && (self.opts.has_fixed_paths()
+                    || self.opts.review_numbers
+                    || keeps_base_elements(self.opts.array_mode_at(path))))
+    }
+
+    // Whether the change of `base` to `changed` in one branch, where the other
+    // left it `unchanged`, is held for review: with `review_numbers`, one
+    // number changed to another.
+    fn held_for_review(
+        &mut self,
+        base: &Value,
+        changed: &Value,
+        unchanged: &Value,
+        path: &str,
+    ) -> bool {
+        self.opts.review_numbers
+            && base.is_number()
+            && changed.is_number()
+            && self.equal_at(unchanged, base, path)
+            && !self.equal_at(changed, base, path)
+    }
+
+    // Whether both branches replaced a `base` that is not an object with
+    // different objects. Base then has nothing to say about their keys, which
+    // are merged as added in both. Other key scopes only merge keys of base.
+    fn both_replaced_with_objects(&self, base: &Value, a: &Value, b: &Value) -> bool {
+        !base.is_object()
+            && a.is_object()
+            && b.is_object()
+            && self.opts.key_scope == KeyScope::Union
+    }
+
+    // Whether `other`, the value of the only branch that changed `base`, can be
+    // taken as it is. It cannot if it swaps an array and an object somewhere
+    // inside, which must be reported as a conflict.
+    fn can_take_whole(&self, base: &Value, other: &Value, path: &str) -> bool {
+        self.opts.array_object_mismatch != ArrayObjectMismatch::Conflict
+            || !self.has_array_object_swap(base, other, path)
+    }
+
+    // Whether `other` turned an array of `base` into an object or the reverse,
+    // anywhere the merge would descend to.
+    fn has_array_object_swap(&self, base: &Value, other: &Value, path: &str) -> bool {
+        match (base, other) {
+            (Value::Object(_), Value::Array(_)) | (Value::Array(_), Value::Object(_)) => true,
+            (Value::Object(base_map), Value::Object(other_map)) => {
+                base_map.iter().any(|(key, base_val)| {
+                    other_map.get(key).is_some_and(|other_val| {
+                        self.has_array_object_swap(base_val, other_val, &pointer::push(path, key))
+                    })
+                })
+            }
+            (Value::Array(base_arr), Value::Array(other_arr))
+                if *self.opts.array_mode_at(path) != ArrayMergeMode::Atomic =>
+            {
+                base_arr
+                    .iter()
+                    .zip(other_arr)
+                    .enumerate()
+                    .any(|(index, (base_val, other_val))| {
+                        let item_path = pointer::push(path, &index.to_string());
+                        self.has_array_object_swap(base_val, other_val, &item_path)
+                    })
+            }
+            _ => false,
+        }
+    }
+
+    // Handles one branch holding an array and the other an object where base had
+    // one of the two, which would otherwise resolve silently when only one
+    // branch changed the type.
+    fn array_object_mismatch(
+        &mut self,
+        path: &str,
+        base: &Value,
+        a: &Value,
+        b: &Value,
+    ) -> Option<(Option<Value>, bool)> {
+        let swapped = (a.is_array() && b.is_object()) || (a.is_object() && b.is_array());
+        if !swapped || !(base.is_array() || base.is_object()) {
+            return None;
+        }
+
+        Some(match self.opts.array_object_mismatch {
+            ArrayObjectMismatch::Conflict => self.conflict(
+                path,
+                ConflictKind::TypeMismatch,
+                Some(base),
+                Some(a),
+                Some(b),
+            ),
+            ArrayObjectMismatch::TakeA => (Some(self.keep(a)), false),
+            ArrayObjectMismatch::TakeB => (Some(self.keep(b)), false),
+        })
+    }
+
+    // Handles one branch replacing an array of base with `null` while the other
+    // edited it.
+    fn array_null_mismatch(
+        &mut self,
+        path: &str,
+        base: &Value,
+        a: &Value,
+        b: &Value,
+    ) -> Option<(Option<Value>, bool)> {
+        if !base.is_array() {
+            return None;
+        }
+        let edited = match (a, b) {
+            (Value::Null, edited @ Value::Array(_)) | (edited @ Value::Array(_), Value::Null) => {
+                edited
+            }
+            _ => return None,
+        };
+        if self.equal(edited, base) {
+            return None;
+        }
+
+        Some(match self.opts.array_null_mismatch {
+            ArrayNullMismatch::Conflict => self.conflict(
+                path,
+                ConflictKind::TypeMismatch,
+                Some(base),
+                Some(a),
+                Some(b),
+            ),
+            ArrayNullMismatch::TakeEdit => (Some(self.keep(edited)), false),
+            ArrayNullMismatch::TakeNull => (Some(Value::Null), false),
+        })
+    }
+
+    fn merge_record_map(
+        &mut self,
+        base_map: &Map<String, Value>,
+        a_map: &Map<String, Value>,
+        b_map: &Map<String, Value>,
+    ) -> (Value, bool) {
+        let record_key = self.opts.record_key.as_deref().unwrap_or_default();
+        let mut renames_a = records::detect_renames(base_map, a_map, record_key);
+        let mut renames_b = records::detect_renames(base_map, b_map, record_key);
+        // A rename onto a key the other branch uses for something else would
+        // merge two different records, so it is treated as delete plus add.
+        renames_a.retain(|old, new| b_map.get(new).is_none() || renames_b.get(old) == Some(new));
+        renames_b.retain(|old, new| a_map.get(new).is_none() || renames_a.get(old) == Some(new));
+
+        let mut keys: Vec<&String> = base_map
+            .keys()
+            .chain(
+                a_map
+                    .keys()
+                    .filter(|k| !renames_a.values().any(|new| new == *k)),
+            )
+            .chain(
+                b_map
+                    .keys()
+                    .filter(|k| !renames_b.values().any(|new| new == *k)),
+            )
+            .collect::<HashSet<&String>>()
+            .into_iter()
+            .collect();
+        keys.sort();
+
+        let mut merged = Map::new();
+        let mut had_conflict = false;
+        for key in keys {
+            if self.out_of_time() {
+                break;
+            }
+
+            let a_key = renames_a.get(key).unwrap_or(key);
+            let b_key = renames_b.get(key).unwrap_or(key);
+            let target = if a_key == b_key || b_key == key {
+                a_key.clone()
+            } else if a_key == key {
+                b_key.clone()
+            } else {
+                let (chosen, conflict) = self.conflict(
+                    &pointer::push("", key),
+                    ConflictKind::BothRenamed,
+                    Some(&Value::String(key.clone())),
+                    Some(&Value::String(a_key.clone())),
+                    Some(&Value::String(b_key.clone())),
+                );
+                had_conflict |= conflict;
+                match chosen {
+                    Some(Value::String(name)) => name,
+                    _ => a_key.clone(),
+                }
+            };
+            if target != *key {
+                self.log(
+                    LogLevel::Debug,
+                    format_args!("Record '{}' renamed to '{}'", key, target),
+                );
+            }
+
+            let (merged_val, conflict) = self.merge_entry(
+                base_map.get(key),
+                a_map.get(a_key),
+                b_map.get(b_key),
+                &pointer::push("", &target),
+            );
+            had_conflict |= conflict;
+            if let Some(val) = merged_val.filter(|_| self.build) {
+                merged.insert(target, val);
+            }
+        }
+        (Value::Object(merged), had_conflict)
+    }
+
+    fn merge_entry(
+        &mut self,
+        base: Option<&Value>,
+        a: Option<&Value>,
+        b: Option<&Value>,
+        path: &str,
+    ) -> (Option<Value>, bool) {
+        if self.opts.is_ignored(path) {
+            if base.is_none() {
+                self.dropped(path, format_args!("ignored, so base's absence is kept"));
+            }
+            return (base.map(|v| self.keep(v)), false);
+        }
+        if let Some(side) = self.opts.authority_at(path) {
+            let taken = if side == Side::A { a } else { b };
+            if taken.is_none() {
+                self.dropped(path, format_args!("absent from {}, which owns it", side));
+            }
+            return (taken.map(|v| self.keep(v)), false);
+        }
+        let opts = self.opts;
+        if let Some(key) = &opts.force_key {
+            let forced = [a, b].map(|value| value.and_then(|v| forced_value(v, key)));
+            match (forced[0], forced[1]) {
+                (Some(a_val), None) => return (Some(self.keep(a_val)), false),
+                (None, Some(b_val)) => return (Some(self.keep(b_val)), false),
+                (Some(a_val), Some(b_val)) if self.equal_at(a_val, b_val, path) => {
+                    return (Some(self.keep(a_val)), false)
+                }
+                (Some(a_val), Some(b_val)) => {
+                    let kind = modified_kind(a_val, b_val, ConflictKind::BothModified);
+                    return self.conflict(path, kind, base, Some(a_val), Some(b_val));
+                }
+                (None, None) => {}
+            }
+        }
+
+        if self.opts.treat_empty_string_as_absent {
+            let [base_present, a_present, b_present] =
+                [base, a, b].map(|value| value.filter(|v| !is_empty_string(v)));
+            let (merged_val, conflict) = match (base_present, a_present, b_present) {
+                (None, None, None) => (None, false),
+                (base, a, b) => self.merge_versions(base, a, b, path),
+            };
+            // An absence is written the way branch A wrote it.
+            let a_empty = a.filter(|v| is_empty_string(v));
+            return (
+                merged_val.or_else(|| a_empty.map(|v| self.keep(v))),
+                conflict,
+            );
+        }
+        self.merge_versions(base, a, b, path)
+    }
+
+    fn merge_versions(
+        &mut self,
+        base: Option<&Value>,
+        a: Option<&Value>,
+        b: Option<&Value>,
+        path: &str,
+    ) -> (Option<Value>, bool) {
+        match (base, a, b) {
+            (Some(base_val), Some(a_val), Some(b_val)) => {
+                if self.must_descend(base_val, a_val, b_val, path) {
+                    let (merged_val, conflict) =
+                        self.three_way_merge_recursive(base_val, a_val, b_val, path);
+                    (Some(merged_val), conflict)
+                } else if self.equal_at(a_val, b_val, path) {
+                    (Some(self.keep(a_val)), false)
+                } else if let Some(result) = self
+                    .array_object_mismatch(path, base_val, a_val, b_val)
+                    .or_else(|| self.array_null_mismatch(path, base_val, a_val, b_val))
+                {
+                    result
+                } else if self.held_for_review(base_val, a_val, b_val, path)
+                    || self.held_for_review(base_val, b_val, a_val, path)
+                {
+                    self.conflict(path, ConflictKind::NumberChanged, base, a, b)
+                } else if self.equal_at(a_val, base_val, path)
+                    && self.can_take_whole(base_val, b_val, path)
+                {
+                    (Some(self.keep(b_val)), false)
+                } else if self.equal_at(b_val, base_val, path)
+                    && self.can_take_whole(base_val, a_val, path)
+                {
+                    (Some(self.keep(a_val)), false)
+                } else if (a_val.is_object() && b_val.is_object() && base_val.is_object())
+                    || (a_val.is_array() && b_val.is_array() && base_val.is_array())
+                {
+                    let (merged_val, conflict) =
+                        self.three_way_merge_recursive(base_val, a_val, b_val, path);
+                    (Some(merged_val), conflict)
+                } else if self.both_replaced_with_objects(base_val, a_val, b_val) {
+                    let (merged_val, conflict) =
+                        self.three_way_merge_recursive(&EMPTY_OBJECT, a_val, b_val, path);
+                    (Some(merged_val), conflict)
+                } else {
+                    let kind = modified_kind(a_val, b_val, ConflictKind::BothModified);
+                    self.conflict(path, kind, base, a, b)
+                }
+            }
+
+            (None, Some(a_val), Some(b_val)) => {
+                if self.equal_at(a_val, b_val, path) {
+                    (Some(self.keep(a_val)), false)
+                } else {
+                    let kind = modified_kind(a_val, b_val, ConflictKind::BothAdded);
+                    self.conflict(path, kind, base, a, b)
+                }
+            }
+
+            (None, Some(a_val), None) => (Some(self.keep(a_val)), false),
+            (None, None, Some(b_val)) => (Some(self.keep(b_val)), false),
+
+            (Some(base_val), Some(a_val), None) => {
+                if self.equal_at(a_val, base_val, path) {
+                    self.dropped(path, format_args!("deleted in branch B"));
+                    (None, false)
+                } else {
+                    self.conflict(path, ConflictKind::ModifiedADeletedB, base, a, b)
+                }
+            }
+
+            (Some(base_val), None, Some(b_val)) => {
+                if self.equal_at(b_val, base_val, path) {
+                    self.dropped(path, format_args!("deleted in branch A"));
+                    (None, false)
+                } else {
+                    self.conflict(path, ConflictKind::ModifiedBDeletedA, base, a, b)
+                }
+            }
+
+            (Some(_), None, None) => {
+                self.dropped(path, format_args!("deleted in both branches"));
+                (None, false)
+            }
+
+            (None, None, None) => (None, false),
+        }
+    }
+
+    fn merge_arrays(
+        &mut self,
+        base: &[Value],
+        a: &[Value],
+        b: &[Value],
+        path: &str,
+    ) -> (Value, bool) {
+        let mode = self.opts.array_mode_at(path);
+        if *mode == ArrayMergeMode::OrderedUnion {
+            let mut merged: Vec<&Value> = Vec::new();
+            for value in base.iter().chain(a).chain(b) {
+                if !merged.iter().any(|kept| self.equal(kept, value)) {
+                    merged.push(value);
+                }
+            }
+            if let Some(max) = self.opts.max_array_len_at(path) {
+                if merged.len() > max && self.opts.truncate_long_arrays {
+                    self.log(
+                        LogLevel::Warn,
+                        format_args!(
+                            "Array '{}' merged into {} elements, keeping the first {}",
+                            path,
+                            merged.len(),
+                            max
+                        ),
+                    );
+                    merged.truncate(max);
+                } else if merged.len() > max {
+                    let [base, a, b] = [base, a, b].map(|items| Value::Array(items.to_vec()));
+                    let (value, unresolved) =
+                        self.conflict(path, ConflictKind::TooLong, Some(&base), Some(&a), Some(&b));
+                    return (value.unwrap_or_default(), unresolved);
+                }
+            }
+            let merged = merged.into_iter().map(|value| self.keep(value)).collect();
+            return (Value::Array(merged), false);
+        }
+        if let ArrayMergeMode::Keyed { key } | ArrayMergeMode::KeyedOrdered { key } = mode {
+            let ids = [base, a, b].map(|items| records::element_ids(items, key));
+            if let [Some(base_ids), Some(a_ids), Some(b_ids)] = ids {
+                let ids = [&base_ids[..], &a_ids, &b_ids];
+                return match mode {
+                    ArrayMergeMode::KeyedOrdered { .. } => {
+                        self.merge_keyed_ordered_arrays(base, a, b, ids, path)
+                    }
+                    _ => self.merge_keyed_arrays(base, a, b, ids, path),
+                };
+            }
+            self.log(
+                LogLevel::Debug,
+                format_args!(
+                    "Elements of '{}' are not all identified by '{}', merging positionally",
+                    path, key
+                ),
+            );
+        }
+        if let ArrayMergeMode::Similarity { threshold } = *mode {
+            let a_match = similarity::match_elements(base, a, threshold);
+            let b_match = similarity::match_elements(base, b, threshold);
+            if let (Some(a_match), Some(b_match)) = (a_match, b_match) {
+                return self.merge_matched_arrays(base, a, b, &a_match, &b_match, path);
+            }
+            self.log(
+                LogLevel::Debug,
+                format_args!(
+                    "Ambiguous element pairing in '{}', merging positionally",
+                    path
+                ),
+            );
+        }
+
+        let mut merged = Vec::new();
+        let mut had_conflict = false;
+        for index in 0..base.len().max(a.len()).max(b.len()) {
+            if self.stopped {
+                break;
+            }
+            let current_path = pointer::push(path, &index.to_string());
+            let (merged_val, conflict) =
+                self.merge_entry(base.get(index), a.get(index), b.get(index), &current_path);
+            had_conflict |= conflict;
+            merged.extend(merged_val.filter(|_| self.build));
+        }
+        (Value::Array(merged), had_conflict)
+    }
+
+    fn merge_matched_arrays(
+        &mut self,
+        base: &[Value],
+        a: &[Value],
+        b: &[Value],
+        a_match: &[Option<usize>],
+        b_match: &[Option<usize>],
+        path: &str,
+    ) -> (Value, bool) {
+        let base_of_a: Vec<Option<usize>> = (0..a.len())
+            .map(|j| a_match.iter().position(|m| *m == Some(j)))
+            .collect();
+        let b_additions: Vec<&Value> = (0..b.len())
+            .filter(|j| !b_match.contains(&Some(*j)))
+            .map(|j| &b[j])
+            .collect();
+        let a_additions: Vec<&Value> = (0..a.len())
+            .filter(|j| base_of_a[*j].is_none())
+            .map(|j| &a[j])
+            .collect();
+
+        let mut entries: Vec<(Option<&Value>, Option<&Value>, Option<&Value>)> = Vec::new();
+        for (j, a_val) in a.iter().enumerate() {
+            match base_of_a[j] {
+                Some(i) => entries.push((Some(&base[i]), Some(a_val), b_match[i].map(|k| &b[k]))),
+                None => entries.push((None, Some(a_val), None)),
+            }
+        }
+        for (i, base_val) in base.iter().enumerate() {
+            if a_match[i].is_none() {
+                entries.push((Some(base_val), None, b_match[i].map(|k| &b[k])));
+            }
+        }
+        for b_val in b_additions {
+            if !a_additions.contains(&b_val) {
+                entries.push((None, None, Some(b_val)));
+            }
+        }
+        self.merge_array_entries(entries, path)
+    }
+
+    // Matches elements by id. Ids are unique within each array.
+    fn merge_keyed_arrays(
+        &mut self,
+        base: &[Value],
+        a: &[Value],
+        b: &[Value],
+        [base_ids, a_ids, b_ids]: [&[&Value]; 3],
+        path: &str,
+    ) -> (Value, bool) {
+        let mut entries: Vec<(Option<&Value>, Option<&Value>, Option<&Value>)> = Vec::new();
+        for (a_val, id) in a.iter().zip(a_ids) {
+            entries.push((
+                find_by_id(base, base_ids, id),
+                Some(a_val),
+                find_by_id(b, b_ids, id),
+            ));
+        }
+        for (base_val, id) in base.iter().zip(base_ids) {
+            if !a_ids.contains(id) {
+                entries.push((Some(base_val), None, find_by_id(b, b_ids, id)));
+            }
+        }
+        for (b_val, id) in b.iter().zip(b_ids) {
+            if !a_ids.contains(id) && !base_ids.contains(id) {
+                entries.push((None, None, Some(b_val)));
+            }
+        }
+        self.merge_array_entries(entries, path)
+    }
+
+    // Matches elements by id like `merge_keyed_arrays`, in base's order with
+    // each branch's additions placed after their neighbors in the branch.
+    fn merge_keyed_ordered_arrays(
+        &mut self,
+        base: &[Value],
+        a: &[Value],
+        b: &[Value],
+        [base_ids, a_ids, b_ids]: [&[&Value]; 3],
+        path: &str,
+    ) -> (Value, bool) {
+        let mut order: Vec<&Value> = base_ids.to_vec();
+        for (branch_ids, after_a) in [(a_ids, false), (b_ids, true)] {
+            let mut insert_at = 0;
+            for id in branch_ids {
+                match order.iter().position(|other| other == id) {
+                    Some(index) => insert_at = index + 1,
+                    None => {
+                        // B's additions go after A's at the same place.
+                        while after_a
+                            && order.get(insert_at).is_some_and(|next| {
+                                !base_ids.contains(next) && !b_ids.contains(next)
+                            })
+                        {
+                            insert_at += 1;
+                        }
+                        order.insert(insert_at, id);
+                        insert_at += 1;
+                    }
+                }
+            }
+        }
+        let entries = order
+            .into_iter()
+            .map(|id| {
+                (
+                    find_by_id(base, base_ids, id),
+                    find_by_id(a, a_ids, id),
+                    find_by_id(b, b_ids, id),
+                )
+            })
+            .collect();
+        self.merge_array_entries(entries, path)
+    }
+
+    // Merges each (base, A, B) triple of paired elements in turn, numbering the
+    // paths by position in the result.
+    fn merge_array_entries(
+        &mut self,
+        entries: Vec<(Option<&Value>, Option<&Value>, Option<&Value>)>,
+        path: &str,
+    ) -> (Value, bool) {
+        let mut merged = Vec::new();
+        let mut had_conflict = false;
+        let mut index = 0;
+        for (base_val, a_val, b_val) in entries {
+            if self.stopped {
+                break;
+            }
+            let current_path = pointer::push(path, &index.to_string());
+            let (merged_val, conflict) = self.merge_entry(base_val, a_val, b_val, &current_path);
+            had_conflict |= conflict;
+            if let Some(val) = merged_val {
+                index += 1;
+                if self.build {
+                    merged.push(val);
+                }
+            }
+        }
+        (Value::Array(merged), had_conflict)
+    }
+}
+
+// The element of `items` whose id, out of `ids`, is `id`.
+fn find_by_id<'v>(items: &'v [Value], ids: &[&Value], id: &Value) -> Option<&'v Value> {
+    ids.iter()
+        .position(|other| *other == id)
+        .map(|index| &items[index])
+}
+
+// The value `value` wraps if it is a wrapper of `force_key`.
+fn forced_value<'v>(value: &'v Value, force_key: &str) -> Option<&'v Value> {
+    match value.as_object() {
+        Some(map) if map.len() == 1 => map.get(force_key),
+        _ => None,
+    }
+}
+
+fn unwrap_forced(value: Value, force_key: &str) -> Value {
+    match value {
+        Value::Object(mut map) if map.len() == 1 && map.contains_key(force_key) => {
+            unwrap_forced(map.remove(force_key).unwrap(), force_key)
+        }
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, val)| (key, unwrap_forced(val, force_key)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| unwrap_forced(item, force_key))
+                .collect(),
+        ),
+        other => other,
+    }
+}
+
+fn is_empty_string(value: &Value) -> bool {
+    value.as_str() == Some("")
+}
+
+// The lines of `s` without trailing whitespace, and without the empty lines
+// that trailing newlines leave at the end.
+fn normalize_whitespace(s: &str) -> impl Iterator<Item = &str> {
+    let trimmed = s.trim_end();
+    trimmed.lines().map(str::trim_end)
+}
+
+// Whether arrays merged in `mode` keep elements or the order of base where
+// the only branch changing the array removed or moved them, so that its array
+// cannot be taken whole.
+fn keeps_base_elements(mode: &ArrayMergeMode) -> bool {
+    matches!(
+        mode,
+        ArrayMergeMode::OrderedUnion | ArrayMergeMode::KeyedOrdered { .. }
+    )
+}
+
+fn modified_kind(a: &Value, b: &Value, same_type: ConflictKind) -> ConflictKind {
+    if std::mem::discriminant(a) == std::mem::discriminant(b) {
+        same_type
+    } else {
+        ConflictKind::TypeMismatch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_no_changes() {
+        let base = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2"
+        });
+        let a = base.clone();
+        let b = base.clone();
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, base);
+        assert!(!had_conflicts);
+    }
+
+    #[test]
+    fn test_changes_to_different_files() {
+        let base = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2"
+        });
+
+        let a = json!({
+            "file1.txt": "id1-modified",
+            "file2.txt": "id2"
+        });
+
+        let b = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-modified"
+        });
+
+        let expected = json!({
+            "file1.txt": "id1-modified",
+            "file2.txt": "id2-modified"
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(!had_conflicts);
+    }
+
+    #[test]
+    fn test_same_changes_in_both_branches() {
+        let base = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2"
+        });
+
+        let a = json!({
+            "file1.txt": "id1-same-change",
+            "file2.txt": "id2"
+        });
+
+        let b = json!({
+            "file1.txt": "id1-same-change",
+            "file2.txt": "id2"
+        });
+
+        let expected = json!({
+            "file1.txt": "id1-same-change",
+            "file2.txt": "id2"
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(!had_conflicts);
+    }
+
+    #[test]
+    fn test_conflict_in_same_file() {
+        let base = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2"
+        });
+
+        let a = json!({
+            "file1.txt": "id1-a-change",
+            "file2.txt": "id2"
+        });
+
+        let b = json!({
+            "file1.txt": "id1-b-change",
+            "file2.txt": "id2"
+        });
+
+        // In conflict, branch A's value is used
+        let expected = json!({
+            "file1.txt": "id1-a-change",
+            "file2.txt": "id2"
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(had_conflicts);
+    }
+
+    #[test]
+    fn test_file_added_in_one_branch() {
+        let base = json!({
+            "file1.txt": "id1"
+        });
+
+        let a = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-new"
+        });
+
+        let b = base.clone();
+
+        let expected = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-new"
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(!had_conflicts);
+    }
+
+    #[test]
+    fn test_file_added_in_both_branches_same_content() {
+        let base = json!({
+            "file1.txt": "id1"
+        });
+
+        let a = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-new"
+        });
+
+        let b = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-new"
+        });
+
+        let expected = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-new"
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(!had_conflicts);
+    }
+
+    #[test]
+    fn test_file_added_in_both_branches_different_content() {
+        let base = json!({
+            "file1.txt": "id1"
+        });
+
+        let a = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-new-a"
+        });
+
+        let b = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-new-b"
+        });
+
+        // In conflict, branch A's value is used
+        let expected = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-new-a"
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(had_conflicts);
+    }
+
+    #[test]
+    fn test_file_deleted_in_one_branch() {
+        let base = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2"
+        });
+
+        let a = json!({
+            "file1.txt": "id1"
+        });
+
+        let b = base.clone();
+
+        let expected = json!({
+            "file1.txt": "id1"
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(!had_conflicts);
+    }
+
+    #[test]
+    fn test_file_deleted_in_both_branches() {
+        let base = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2"
+        });
+
+        let a = json!({
+            "file1.txt": "id1"
+        });
+
+        let b = json!({
+            "file1.txt": "id1"
+        });
+
+        let expected = json!({
+            "file1.txt": "id1"
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(!had_conflicts);
+    }
+
+    #[test]
+    fn test_modified_in_one_branch_deleted_in_other() {
+        let base = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2"
+        });
+
+        let a = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-modified"
+        });
+
+        let b = json!({
+            "file1.txt": "id1"
+        });
+
+        // In conflict, branch A's change is kept
+        let expected = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2-modified"
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(had_conflicts);
+    }
+
+    #[test]
+    fn test_nested_directory_structure() {
+        let base = json!({
+            "dir1": {
+                "file1.txt": "id1",
+                "file2.txt": "id2"
+            },
+            "dir2": {
+                "file3.txt": "id3"
+            }
+        });
+
+        let a = json!({
+            "dir1": {
+                "file1.txt": "id1-modified",
+                "file2.txt": "id2"
+            },
+            "dir2": {
+                "file3.txt": "id3"
+            }
+        });
+
+        let b = json!({
+            "dir1": {
+                "file1.txt": "id1",
+                "file2.txt": "id2"
+            },
+            "dir2": {
+                "file3.txt": "id3-modified"
+            }
+        });
+
+        let expected = json!({
+            "dir1": {
+                "file1.txt": "id1-modified",
+                "file2.txt": "id2"
+            },
+            "dir2": {
+                "file3.txt": "id3-modified"
+            }
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(!had_conflicts);
+    }
+
+    #[test]
+    fn test_deeply_nested_conflicts_report_full_paths() {
+        let base = json!({"l1": {"l2": {"a/b": {"l4": [{"x": 1, "y": 1}]}}}});
+        let a = json!({"l1": {"l2": {"a/b": {"l4": [{"x": 2}]}}}});
+        let b = json!({"l1": {"l2": {"a/b": {"l4": [{"x": 3, "y": 2}]}}}});
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::Positional,
+            ..Default::default()
+        };
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        let reported: Vec<(&str, ConflictKind)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.kind))
+            .collect();
+        assert_eq!(
+            reported,
+            vec![
+                ("/l1/l2/a~1b/l4/0/x", ConflictKind::BothModified),
+                ("/l1/l2/a~1b/l4/0/y", ConflictKind::ModifiedBDeletedA),
+            ]
+        );
+        assert_eq!(conflicts[0].base, Some(json!(1)));
+        assert_eq!(conflicts[0].a, Some(json!(2)));
+        assert_eq!(conflicts[0].b, Some(json!(3)));
+    }
+
+    #[test]
+    fn test_nested_conflict() {
+        let base = json!({
+            "dir1": {
+                "file1.txt": "id1",
+                "file2.txt": "id2"
+            }
+        });
+
+        let a = json!({
+            "dir1": {
+                "file1.txt": "id1-a-change",
+                "file2.txt": "id2"
+            }
+        });
+
+        let b = json!({
+            "dir1": {
+                "file1.txt": "id1-b-change",
+                "file2.txt": "id2"
+            }
+        });
+
+        // In conflict, branch A's value is used
+        let expected = json!({
+            "dir1": {
+                "file1.txt": "id1-a-change",
+                "file2.txt": "id2"
+            }
+        });
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, expected);
+        assert!(had_conflicts);
+    }
+
+    #[test]
+    fn test_ignored_paths_keep_base() {
+        let base = json!({
+            "dir1": {
+                "file1.txt": "id1",
+                "file2.txt": "id2"
+            },
+            "build": {
+                "out.bin": "id3"
+            }
+        });
+
+        let a = json!({
+            "dir1": {
+                "file1.txt": "id1-a-change",
+                "file2.txt": "id2-a-change"
+            },
+            "build": {
+                "out.bin": "id3-a"
+            }
+        });
+
+        let b = json!({
+            "dir1": {
+                "file1.txt": "id1-b-change",
+                "file2.txt": "id2"
+            },
+            "build": {
+                "out.bin": "id3-b",
+                "extra.bin": "id4"
+            }
+        });
+
+        let opts = MergeOptions {
+            ignore: vec![
+                PathPattern::parse("/build").unwrap(),
+                PathPattern::parse("/dir1/file1.*").unwrap(),
+            ],
+            ..Default::default()
+        };
+
+        let expected = json!({
+            "dir1": {
+                "file1.txt": "id1",
+                "file2.txt": "id2-a-change"
+            },
+            "build": {
+                "out.bin": "id3"
+            }
+        });
+
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, expected);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_ignored_path_absent_from_base_is_dropped() {
+        let base = json!({
+            "file1.txt": "id1"
+        });
+
+        let a = json!({
+            "file1.txt": "id1",
+            "generated.txt": "id2-a"
+        });
+
+        let b = json!({
+            "file1.txt": "id1",
+            "generated.txt": "id2-b"
+        });
+
+        let opts = MergeOptions {
+            ignore: vec![PathPattern::parse("/generated.txt").unwrap()],
+            ..Default::default()
+        };
+
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, base);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_conflicts_are_reported_with_paths_and_kinds() {
+        let base = json!({
+            "dir1": {
+                "file1.txt": "id1",
+                "file2.txt": "id2"
+            },
+            "file3.txt": "id3"
+        });
+
+        let a = json!({
+            "dir1": {
+                "file1.txt": "id1-a-change"
+            },
+            "file3.txt": "id3-a-change"
+        });
+
+        let b = json!({
+            "dir1": {
+                "file1.txt": "id1-b-change",
+                "file2.txt": "id2-b-change"
+            }
+        });
+
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        let summary: Vec<(&str, ConflictKind)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.kind))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("/dir1/file1.txt", ConflictKind::BothModified),
+                ("/dir1/file2.txt", ConflictKind::ModifiedBDeletedA),
+                ("/file3.txt", ConflictKind::ModifiedADeletedB),
+            ]
+        );
+        assert_eq!(conflicts[1].base, Some(json!("id2")));
+        assert_eq!(conflicts[1].a, None);
+        assert_eq!(conflicts[1].b, Some(json!("id2-b-change")));
+    }
+
+    #[test]
+    fn test_visit_stops_on_break() {
+        let base = json!({
+            "file1.txt": "id1",
+            "file2.txt": "id2",
+            "file3.txt": "id3"
+        });
+
+        let a = json!({
+            "file1.txt": "id1-a-change",
+            "file2.txt": "id2-a-change",
+            "file3.txt": "id3-a-change"
+        });
+
+        let b = json!({
+            "file1.txt": "id1-b-change",
+            "file2.txt": "id2-b-change",
+            "file3.txt": "id3-b-change"
+        });
+
+        let mut visited = Vec::new();
+        let (merged, flow) =
+            three_way_merge_visit(&base, &a, &b, &MergeOptions::default(), &mut |conflict| {
+                visited.push(conflict.path);
+                if visited.len() == 2 {
+                    ControlFlow::Break(())
                 } else {
-                    format!("{}/{}", path, key)
-                };
+                    ControlFlow::Continue(())
+                }
+            });
+
+        assert!(flow.is_break());
+        assert_eq!(visited, vec!["/file1.txt", "/file2.txt"]);
+        assert_eq!(
+            merged,
+            json!({
+                "file1.txt": "id1-a-change",
+                "file2.txt": "id2-a-change"
+            })
+        );
+    }
+
+    #[test]
+    fn test_scalar_root_conflict_is_reported() {
+        let (merged, conflicts) =
+            three_way_merge_with_options(&json!(1), &json!(2), &json!(3), &MergeOptions::default());
+        assert_eq!(merged, json!(2));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "");
+    }
+
+    fn similarity_options() -> MergeOptions {
+        MergeOptions {
+            array_mode: ArrayMergeMode::Similarity { threshold: 0.5 },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_arrays_are_atomic_by_default() {
+        let base = json!({"list": [1, 2, 3]});
+        let a = json!({"list": [1, 20, 3]});
+        let b = json!({"list": [1, 2, 30]});
+
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert_eq!(merged, a);
+        assert!(had_conflicts);
+    }
+
+    #[test]
+    fn test_positional_array_merge() {
+        let base = json!({"list": [1, 2, 3]});
+        let a = json!({"list": [1, 20, 3, 4]});
+        let b = json!({"list": [1, 2, 30]});
+
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::Positional,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!({"list": [1, 20, 30, 4]}));
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_positional_conflicts_have_indexed_paths() {
+        // One element of a long array conflicts. Its siblings, and arrays
+        // nested inside objects inside the array, still merge element by element.
+        let mut list: Vec<Value> = (0..50).map(|i| json!(i)).collect();
+        list[3] = json!({"tags": ["x", "y"], "n": 0});
+        let base = json!({"outer": {"list": list.clone()}});
+        let mut a_list = list.clone();
+        let mut b_list = list;
+        a_list[2] = json!("a");
+        b_list[2] = json!("b");
+        a_list[3] = json!({"tags": ["x", "y2"], "n": 0});
+        b_list[3] = json!({"tags": ["x", "y"], "n": 1});
+        a_list[10] = json!(100);
+        b_list[40] = json!(400);
+        let a = json!({"outer": {"list": a_list.clone()}});
+        let b = json!({"outer": {"list": b_list}});
+
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::Positional,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        let paths: Vec<&str> = conflicts.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, vec!["/outer/list/2"]);
+        let mut expected = a_list;
+        expected[3] = json!({"tags": ["x", "y2"], "n": 1});
+        expected[40] = json!(400);
+        assert_eq!(merged, json!({"outer": {"list": expected}}));
+
+        let base = json!({"list": [[1, 2], [3]]});
+        let a = json!({"list": [[1, 20], [3]]});
+        let b = json!({"list": [[1, 21], [30]]});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        let paths: Vec<&str> = conflicts.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, vec!["/list/0/1"]);
+        assert_eq!(merged, json!({"list": [[1, 20], [30]]}));
+    }
+
+    #[test]
+    fn test_similarity_pairs_reordered_elements() {
+        let base = json!({
+            "services": [
+                {"name": "web", "port": 80, "replicas": 1},
+                {"name": "db", "port": 5432, "replicas": 1}
+            ]
+        });
+
+        // A bumps the web replicas; B reorders the list and changes the db port.
+        let a = json!({
+            "services": [
+                {"name": "web", "port": 80, "replicas": 3},
+                {"name": "db", "port": 5432, "replicas": 1}
+            ]
+        });
+
+        let b = json!({
+            "services": [
+                {"name": "db", "port": 5433, "replicas": 1},
+                {"name": "web", "port": 80, "replicas": 1}
+            ]
+        });
+
+        let expected = json!({
+            "services": [
+                {"name": "web", "port": 80, "replicas": 3},
+                {"name": "db", "port": 5433, "replicas": 1}
+            ]
+        });
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &similarity_options());
+        assert_eq!(merged, expected);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_similarity_handles_additions_and_deletions() {
+        let base = json!([
+            {"name": "web", "port": 80},
+            {"name": "db", "port": 5432}
+        ]);
+
+        let a = json!([
+            {"name": "db", "port": 5432},
+            {"name": "cache", "port": 6379}
+        ]);
+
+        let b = json!([
+            {"name": "web", "port": 80},
+            {"name": "db", "port": 5432, "tls": true},
+            {"name": "queue", "port": 5672}
+        ]);
+
+        let expected = json!([
+            {"name": "db", "port": 5432, "tls": true},
+            {"name": "cache", "port": 6379},
+            {"name": "queue", "port": 5672}
+        ]);
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &similarity_options());
+        assert_eq!(merged, expected);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_similarity_conflict_inside_paired_element() {
+        let base = json!([{"name": "web", "port": 80, "replicas": 1}]);
+        let a = json!([{"name": "web", "port": 81, "replicas": 1}]);
+        let b = json!([{"name": "web", "port": 82, "replicas": 1}]);
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &similarity_options());
+        assert_eq!(merged, a);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/0/port");
+    }
+
+    #[test]
+    fn test_similarity_falls_back_to_positional_when_ambiguous() {
+        let base = json!([{"name": "web", "port": 80}]);
+
+        // Both elements of A are equally similar to the base element.
+        let a = json!([
+            {"name": "web", "port": 81},
+            {"name": "api", "port": 80}
+        ]);
+
+        let b = json!([{"name": "web", "port": 80, "tls": true}]);
+
+        let (merged, conflicts) = three_way_merge_with_options(
+            &base,
+            &a,
+            &b,
+            &MergeOptions {
+                array_mode: ArrayMergeMode::Similarity { threshold: 0.3 },
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            merged,
+            json!([
+                {"name": "web", "port": 81, "tls": true},
+                {"name": "api", "port": 80}
+            ])
+        );
+        assert!(conflicts.is_empty());
+    }
+
+    fn type_mismatch_fixture() -> (Value, Value, Value) {
+        let base = json!({
+            "file1.txt": "id1",
+            "dir1": "id2"
+        });
+
+        let a = json!({
+            "file1.txt": "id1-a-change",
+            "dir1": "id2-a-change"
+        });
+
+        let b = json!({
+            "file1.txt": "id1-b-change",
+            "dir1": {
+                "file2.txt": "id3"
+            }
+        });
+
+        (base, a, b)
+    }
+
+    #[test]
+    fn test_type_mismatch_is_classified() {
+        let (base, a, b) = type_mismatch_fixture();
+
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        let kinds: Vec<ConflictKind> = conflicts.iter().map(|c| c.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![ConflictKind::TypeMismatch, ConflictKind::BothModified]
+        );
+    }
+
+    #[test]
+    fn test_empty_string_as_absent() {
+        let opts = MergeOptions {
+            treat_empty_string_as_absent: true,
+            ..Default::default()
+        };
+        let base = json!({"cleared": "x", "edited": "x", "both": "x", "added": "", "kept": ""});
+        let a = json!({"cleared": "", "edited": "", "both": "", "kept": ""});
+        let b = json!({"cleared": "x", "edited": "y", "added": "z", "kept": ""});
+
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        // A blanking `cleared` deletes it, and B deleting `both` agrees with A;
+        // A's blanks stand for the absences. `added` was absent in base, so B
+        // adds it, and deleting `edited` conflicts with B's edit.
+        assert_eq!(
+            merged,
+            json!({"cleared": "", "both": "", "edited": "y", "kept": "", "added": "z"})
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/edited");
+        assert_eq!(conflicts[0].kind, ConflictKind::ModifiedBDeletedA);
+        assert_eq!(conflicts[0].a, None);
+
+        // Without the option a blank is an ordinary value.
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(merged["added"], json!("z"));
+        assert_eq!(conflicts.len(), 3);
+    }
+
+    #[test]
+    fn test_value_transform_uppercases_strings() {
+        let base = json!({"name": "app", "owner": {"team": "core"}, "tags": ["a"]});
+        let a = json!({"name": "app", "owner": {"team": "core", "lead": "kim"}, "tags": ["a"]});
+        let b = json!({"name": "App", "owner": {"team": "core"}, "tags": ["a", "b"], "new": {"x": "y"}});
+        let opts = MergeOptions {
+            value_transform: Some(ValueTransform::new(|_, value| match value {
+                Value::String(s) => Value::String(s.to_uppercase()),
+                other => other,
+            })),
+            ..Default::default()
+        };
+
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({
+                "name": "APP",
+                "owner": {"team": "CORE", "lead": "KIM"},
+                "tags": ["A", "B"],
+                "new": {"x": "Y"}
+            })
+        );
+    }
+
+    #[test]
+    fn test_value_transform_sees_paths_children_first() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorder = Arc::clone(&seen);
+        let opts = MergeOptions {
+            value_transform: Some(ValueTransform::new(move |path, value| {
+                recorder.lock().unwrap().push(path.to_string());
+                if path == "/secret" {
+                    Value::String("***".to_string())
+                } else {
+                    value
+                }
+            })),
+            ..Default::default()
+        };
+        let doc = json!({"list": [1], "secret": "hunter2"});
+        let (merged, _) = three_way_merge_with_options(&doc, &doc, &doc, &opts);
+        assert_eq!(merged, json!({"list": [1], "secret": "***"}));
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["/list/0", "/list", "/secret", ""]
+        );
+    }
+
+    #[test]
+    fn test_path_rules_select_array_mode() {
+        let base = json!({
+            "users": [{"name": "ann", "age": 30, "role": "dev"}, {"name": "bob", "age": 40, "role": "ops"}],
+            "tags": ["a", "b", "c"],
+            "matrix": [[1, 2], [3, 4]]
+        });
+        let a = json!({
+            "users": [{"name": "cid", "age": 20, "role": "qa"}, {"name": "ann", "age": 31, "role": "dev"}, {"name": "bob", "age": 40, "role": "ops"}],
+            "tags": ["x", "b", "c"],
+            "matrix": [[1, 5], [3, 4]]
+        });
+        let b = json!({
+            "users": [{"name": "ann", "age": 30, "role": "dev"}, {"name": "bob", "age": 40, "role": "sre"}],
+            "tags": ["a", "b", "y"],
+            "matrix": [[1, 2], [3, 6]]
+        });
+        let rules = ["/users=similarity", "/tags=positional"]
+            .iter()
+            .map(|rule| PathRule::parse(rule).unwrap())
+            .collect();
+        let opts = MergeOptions {
+            rules,
+            ..Default::default()
+        };
+
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            merged["users"],
+            json!([{"name": "cid", "age": 20, "role": "qa"}, {"name": "ann", "age": 31, "role": "dev"}, {"name": "bob", "age": 40, "role": "sre"}])
+        );
+        assert_eq!(merged["tags"], json!(["x", "b", "y"]));
+        // `/matrix` has no rule, so it stays atomic and conflicts.
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/matrix");
+    }
+
+    #[test]
+    fn test_path_rules_select_strategy() {
+        let base = json!({"config": {"port": 80, "host": "a"}, "name": "x"});
+        let a = json!({"config": {"port": 81, "host": "b"}, "name": "y"});
+        let b = json!({"config": {"port": 82, "host": "c"}, "name": "z"});
+        let rules = ["/config/**=theirs", "/config/host=manual"]
+            .iter()
+            .map(|rule| PathRule::parse(rule).unwrap())
+            .collect();
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Ours,
+            rules,
+            ..Default::default()
+        };
+
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            merged,
+            json!({"config": {"port": 82, "host": "b"}, "name": "y"})
+        );
+        let resolutions: Vec<(&str, Option<Side>)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.resolution))
+            .collect();
+        assert_eq!(
+            resolutions,
+            vec![
+                ("/config/host", None),
+                ("/config/port", Some(Side::B)),
+                ("/name", Some(Side::A)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_path_rule_parse() {
+        assert_eq!(
+            PathRule::parse("/a/*=similarity:0.7").unwrap(),
+            PathRule {
+                pattern: PathPattern::parse("/a/*").unwrap(),
+                strategy: None,
+                array_mode: Some(ArrayMergeMode::Similarity { threshold: 0.7 }),
+            }
+        );
+        assert_eq!(
+            PathRule::parse("=ours").unwrap().strategy,
+            Some(ConflictStrategy::Ours)
+        );
+        assert!(PathRule::parse("/a").is_err());
+        assert!(PathRule::parse("/a=keyed").is_err());
+        assert!(PathRule::parse("/a=similarity:2").is_err());
+        assert_eq!(
+            PathRule::parse("/tags=ordered-union").unwrap().array_mode,
+            Some(ArrayMergeMode::OrderedUnion)
+        );
+        assert_eq!(
+            PathRule::parse("/users=keyed:name").unwrap().array_mode,
+            Some(ArrayMergeMode::Keyed {
+                key: "name".to_string()
+            })
+        );
+        assert!(PathRule::parse("/users=keyed:").is_err());
+        assert!(PathRule::parse("a=ours").is_err());
+        assert_eq!(
+            PathRule::parse("/metrics/*=average-numeric:0.25")
+                .unwrap()
+                .strategy,
+            Some(ConflictStrategy::AverageNumeric { weight_a: 0.25 })
+        );
+        assert!(PathRule::parse("/metrics/*=average-numeric:-1").is_err());
+        assert_eq!(
+            PathRule::parse(r"/version=prefer-matching:b:^\d+\.\d+$")
+                .unwrap()
+                .strategy,
+            Some(ConflictStrategy::PreferMatching {
+                pattern: ValuePattern::parse(r"^\d+\.\d+$").unwrap(),
+                prefer: Side::B,
+            })
+        );
+        assert!(PathRule::parse("/version=prefer-matching:c:x").is_err());
+        assert!(PathRule::parse("/version=prefer-matching:a:(").is_err());
+    }
+
+    #[test]
+    fn test_prefer_matching_strategy() {
+        let base = json!({"a_matches": "1.0.0", "b_matches": "1.0.0", "neither": "1.0.0", "number": 1, "tree": [1]});
+        let a = json!({"a_matches": "2.0.0", "b_matches": "latest", "neither": "dev", "number": 2, "tree": [2]});
+        let b = json!({"a_matches": "main", "b_matches": "3.0.0", "neither": "next", "number": 3, "tree": [3]});
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::PreferMatching {
+                pattern: ValuePattern::parse(r"^\d+\.\d+\.\d+$").unwrap(),
+                prefer: Side::A,
+            },
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            merged,
+            json!({"a_matches": "2.0.0", "b_matches": "3.0.0", "neither": "next", "number": 3, "tree": [2]})
+        );
+        let resolutions: Vec<(&str, Option<Side>)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.resolution))
+            .collect();
+        assert_eq!(
+            resolutions,
+            vec![
+                ("/a_matches", Some(Side::A)),
+                ("/b_matches", Some(Side::B)),
+                ("/neither", Some(Side::B)),
+                ("/number", Some(Side::B)),
+                ("/tree", None),
+            ]
+        );
+
+        // Numbers are matched as written.
+        let pattern = ValuePattern::parse("^2$").unwrap();
+        assert!(pattern.matches(&json!(2)));
+        assert!(!pattern.matches(&json!(2.5)));
+        assert!(!ValuePattern::parse("null").unwrap().matches(&Value::Null));
+    }
+
+    #[test]
+    fn test_average_numeric_strategy() {
+        let base = json!({"count": 10, "rate": 0.5, "label": "x", "gone": 1});
+        let a = json!({"count": 20, "rate": 1.0, "label": "y"});
+        let b = json!({"count": 40, "rate": 2, "label": "z", "gone": 2});
+        let merge_with = |weight_a| {
+            let opts = MergeOptions {
+                strategy: ConflictStrategy::AverageNumeric { weight_a },
+                ..Default::default()
+            };
+            three_way_merge_with_options(&base, &a, &b, &opts)
+        };
+
+        let (merged, conflicts) = merge_with(0.5);
+        assert_eq!(
+            merged,
+            json!({"count": 30, "rate": 1.5, "label": "y", "gone": 2})
+        );
+        let resolutions: Vec<(&str, Option<Side>)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.resolution))
+            .collect();
+        assert_eq!(
+            resolutions,
+            vec![
+                ("/count", Some(Side::Custom)),
+                ("/gone", None),
+                ("/label", None),
+                ("/rate", Some(Side::Custom)),
+            ]
+        );
+
+        let (merged, _) = merge_with(0.75);
+        assert_eq!(merged["count"], json!(25));
+        assert_eq!(merged["rate"], json!(1.25));
+        let (merged, _) = merge_with(1.0 / 3.0);
+        assert_eq!(merged["count"], json!(100.0 / 3.0));
+        let (merged, _) = merge_with(1.0);
+        assert_eq!(merged["count"], json!(20));
+        assert_eq!(merged["rate"], json!(1.0));
+
+        // Integers too large for an f64 or an i64 are averaged exactly.
+        let average = |weight_a, a: Value, b: Value| weighted_average(weight_a, &a, &b);
+        let max = u64::MAX;
+        assert_eq!(
+            average(0.5, json!(18446744073709551000u64), json!(max - 1)),
+            Some(json!(18446744073709551307u64))
+        );
+        assert_eq!(average(0.5, json!(max), json!(max)), Some(json!(max)));
+        assert_eq!(
+            average(0.25, json!(max - 3), json!(max - 7)),
+            Some(json!(max - 6))
+        );
+        assert_eq!(
+            average(0.5, json!(max - 1), json!(i64::MIN)),
+            Some(json!((1u64 << 62) - 1))
+        );
+        let big = 1i64 << 53;
+        assert_eq!(
+            average(0.5, json!(big + 1), json!(big + 5)),
+            Some(json!(big + 3))
+        );
+        assert_eq!(
+            average(0.5, json!(-big - 1), json!(-big - 5)),
+            Some(json!(-big - 3))
+        );
+        assert_eq!(average(0.5, json!(-3), json!(4)), Some(json!(0.5)));
+    }
+
+    #[test]
+    fn test_strategy_resolves_conflicts() {
+        let (base, a, b) = type_mismatch_fixture();
+
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Theirs,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, b);
+        assert!(conflicts.iter().all(|c| c.resolution == Some(Side::B)));
+    }
+
+    #[test]
+    fn test_strategy_resolves_modify_delete_as_deletion() {
+        let base = json!({"file1.txt": "id1"});
+        let a = json!({"file1.txt": "id1-a-change"});
+        let b = json!({});
+
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Theirs,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!({}));
+        assert!(conflicts[0].is_resolved());
+    }
+
+    #[test]
+    fn test_safe_types_leaves_type_mismatch_unresolved() {
+        let (base, a, b) = type_mismatch_fixture();
+
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Theirs,
+            safe_types: true,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+
+        let type_mismatch = &conflicts[0];
+        assert_eq!(type_mismatch.path, "/dir1");
+        assert_eq!(type_mismatch.resolution, None);
+        assert_eq!(merged["dir1"], json!("id2-a-change"));
+
+        let value_conflict = &conflicts[1];
+        assert_eq!(value_conflict.path, "/file1.txt");
+        assert_eq!(value_conflict.resolution, Some(Side::B));
+        assert_eq!(merged["file1.txt"], json!("id1-b-change"));
+
+        assert!(blocks_output(&conflicts, &opts));
+    }
+
+    #[test]
+    fn test_value_conflicts_do_not_block_output() {
+        let base = json!({"file1.txt": "id1"});
+        let a = json!({"file1.txt": "id1-a-change"});
+        let b = json!({"file1.txt": "id1-b-change"});
+
+        let opts = MergeOptions {
+            safe_types: true,
+            ..Default::default()
+        };
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(conflicts.len(), 1);
+        assert!(!blocks_output(&conflicts, &opts));
+
+        let (base, a, b) = type_mismatch_fixture();
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert!(!blocks_output(&conflicts, &MergeOptions::default()));
+    }
+
+    fn key_scope_fixture() -> (Value, Value, Value) {
+        let base = json!({
+            "kept.txt": "id1",
+            "deleted-in-a.txt": "id2",
+            "dir1": {
+                "file1.txt": "id3"
+            }
+        });
+
+        let a = json!({
+            "kept.txt": "id1-a-change",
+            "added-in-a.txt": "id4",
+            "dir1": {
+                "file1.txt": "id3",
+                "file2.txt": "id5"
+            }
+        });
+
+        let b = json!({
+            "kept.txt": "id1",
+            "deleted-in-a.txt": "id2",
+            "added-in-b.txt": "id6",
+            "dir1": {
+                "file1.txt": "id3-b-change"
+            }
+        });
+
+        (base, a, b)
+    }
 
-                let (merged_val, conflict) = merge_entry(base_val, a_val, b_val, &current_path);
-                if conflict {
-                    had_conflict = true;
+    fn merge_with_scope(key_scope: KeyScope) -> Value {
+        let (base, a, b) = key_scope_fixture();
+        let opts = MergeOptions {
+            key_scope,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        merged
+    }
+
+    #[test]
+    fn test_key_scope_union() {
+        assert_eq!(
+            merge_with_scope(KeyScope::Union),
+            json!({
+                "kept.txt": "id1-a-change",
+                "added-in-a.txt": "id4",
+                "added-in-b.txt": "id6",
+                "dir1": {
+                    "file1.txt": "id3-b-change",
+                    "file2.txt": "id5"
                 }
+            })
+        );
+    }
 
-                if let Some(val) = merged_val {
-                    merged.insert(key, val);
+    #[test]
+    fn test_key_scope_base() {
+        assert_eq!(
+            merge_with_scope(KeyScope::Base),
+            json!({
+                "kept.txt": "id1-a-change",
+                "dir1": {
+                    "file1.txt": "id3-b-change"
                 }
-            }
-            (Value::Object(merged), had_conflict)
-        }
+            })
+        );
+    }
 
-        _ => {
-            if a == b {
-                (a.clone(), false)
-            } else if a == base {
-                (b.clone(), false)
-            } else if b == base {
-                (a.clone(), false)
-            } else {
-                log::error!("Conflict detected: path '{}' has different contents", path);
-                (a.clone(), true)
-            }
+    #[test]
+    fn test_key_scope_intersection() {
+        let base = json!({
+            "everywhere.txt": "id1",
+            "not-in-b.txt": "id2"
+        });
+        let a = json!({
+            "everywhere.txt": "id1-a-change",
+            "not-in-b.txt": "id2-a-change"
+        });
+        let b = json!({
+            "everywhere.txt": "id1",
+            "only-in-b.txt": "id3"
+        });
+
+        let opts = MergeOptions {
+            key_scope: KeyScope::Intersection,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!({"everywhere.txt": "id1-a-change"}));
+        assert!(conflicts.is_empty());
+
+        let (base, a, b) = key_scope_fixture();
+        let (merged, _) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            merged,
+            json!({
+                "kept.txt": "id1-a-change",
+                "dir1": {
+                    "file1.txt": "id3-b-change"
+                }
+            })
+        );
+    }
+
+    fn records_options() -> MergeOptions {
+        MergeOptions {
+            record_key: Some("uid".to_string()),
+            ..Default::default()
         }
     }
-}
 
-fn merge_entry(
-    base: Option<&Value>,
-    a: Option<&Value>,
-    b: Option<&Value>,
-    path: &str,
-) -> (Option<Value>, bool) {
-    match (base, a, b) {
-        (Some(base_val), Some(a_val), Some(b_val)) => {
-            if a_val == b_val {
-                (Some(a_val.clone()), false)
-            } else if a_val == base_val {
-                (Some(b_val.clone()), false)
-            } else if b_val == base_val {
-                (Some(a_val.clone()), false)
-            } else if a_val.is_object() && b_val.is_object() && base_val.is_object() {
-                let (merged_val, conflict) =
-                    three_way_merge_recursive(base_val, a_val, b_val, path);
-                (Some(merged_val), conflict)
+    #[test]
+    fn test_record_rename_follows_edit() {
+        let base = json!({
+            "alice": {"uid": 1, "role": "dev"},
+            "bob": {"uid": 2, "role": "dev"}
+        });
+
+        // A renames alice; B promotes her under the old key.
+        let a = json!({
+            "alicia": {"uid": 1, "role": "dev"},
+            "bob": {"uid": 2, "role": "dev"}
+        });
+
+        let b = json!({
+            "alice": {"uid": 1, "role": "admin"},
+            "bob": {"uid": 2, "role": "dev"}
+        });
+
+        let expected = json!({
+            "alicia": {"uid": 1, "role": "admin"},
+            "bob": {"uid": 2, "role": "dev"}
+        });
+
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &records_options());
+        assert_eq!(merged, expected);
+        assert!(conflicts.is_empty());
+
+        // Without a record key the same edits are a modify/delete conflict.
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, ConflictKind::ModifiedBDeletedA);
+    }
+
+    #[test]
+    fn test_record_renamed_the_same_in_both_branches() {
+        let base = json!({"alice": {"uid": 1, "role": "dev"}});
+        let a = json!({"alicia": {"uid": 1, "role": "dev", "team": "x"}});
+        let b = json!({"alicia": {"uid": 1, "role": "admin"}});
+
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &records_options());
+        assert_eq!(
+            merged,
+            json!({"alicia": {"uid": 1, "role": "admin", "team": "x"}})
+        );
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_record_renamed_differently_in_both_branches() {
+        let base = json!({"alice": {"uid": 1, "role": "dev"}});
+        let a = json!({"alicia": {"uid": 1, "role": "dev"}});
+        let b = json!({"ali": {"uid": 1, "role": "dev"}});
+
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &records_options());
+        assert_eq!(merged, json!({"alicia": {"uid": 1, "role": "dev"}}));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/alice");
+        assert_eq!(conflicts[0].kind, ConflictKind::BothRenamed);
+        assert_eq!(conflicts[0].b, Some(json!("ali")));
+
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Theirs,
+            ..records_options()
+        };
+        let (merged, _) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!({"ali": {"uid": 1, "role": "dev"}}));
+    }
+
+    #[test]
+    fn test_record_rename_onto_key_used_by_other_branch() {
+        let base = json!({"alice": {"uid": 1}});
+        let a = json!({"alicia": {"uid": 1}});
+        let b = json!({"alice": {"uid": 1}, "alicia": {"uid": 7}});
+
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &records_options());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, ConflictKind::BothAdded);
+    }
+
+    fn array_object_fixture() -> (Value, Value, Value) {
+        let base = json!({
+            "list": [1, 2],
+            "settings": {"a": 1}
+        });
+
+        // A keeps both values; B swaps the array for an object and the object
+        // for an array.
+        let a = json!({
+            "list": [1, 2],
+            "settings": {"a": 1}
+        });
+
+        let b = json!({
+            "list": {"0": 1, "1": 2},
+            "settings": [["a", 1]]
+        });
+
+        (base, a, b)
+    }
+
+    #[test]
+    fn test_array_object_swap_conflicts_by_default() {
+        let (base, a, b) = array_object_fixture();
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(merged, a);
+        let summary: Vec<(&str, ConflictKind)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.kind))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("/list", ConflictKind::TypeMismatch),
+                ("/settings", ConflictKind::TypeMismatch),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_array_object_swap_take_side() {
+        let (base, a, b) = array_object_fixture();
+
+        let opts = MergeOptions {
+            array_object_mismatch: ArrayObjectMismatch::TakeB,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, b);
+        assert!(conflicts.is_empty());
+
+        let opts = MergeOptions {
+            array_object_mismatch: ArrayObjectMismatch::TakeA,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &b, &a, &opts);
+        assert_eq!(merged, b);
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_array_object_swap_at_root() {
+        let (merged, conflicts) = three_way_merge_with_options(
+            &json!([1]),
+            &json!([1, 2]),
+            &json!({"items": [1]}),
+            &MergeOptions::default(),
+        );
+        assert_eq!(merged, json!([1, 2]));
+        assert_eq!(conflicts[0].kind, ConflictKind::TypeMismatch);
+    }
+
+    #[test]
+    fn test_array_replaced_by_scalar_is_not_array_object_mismatch() {
+        let base = json!({"list": [1, 2]});
+        let a = json!({"list": [1, 2]});
+        let b = json!({"list": "none"});
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(merged, b);
+        assert!(conflicts.is_empty());
+    }
+
+    // Base has arrays; A nulls `/nulled_a` and edits `/edited_a`, B the other
+    // way round, and `/one_sided` is only nulled by A.
+    fn array_null_fixture() -> (Value, Value, Value) {
+        let base = json!({"nulled_a": [1], "edited_a": [1], "one_sided": [1]});
+        let a = json!({"nulled_a": null, "edited_a": [1, 2], "one_sided": null});
+        let b = json!({"nulled_a": [1, 3], "edited_a": null, "one_sided": [1]});
+        (base, a, b)
+    }
+
+    #[test]
+    fn test_array_null_mismatch_conflicts() {
+        let (base, a, b) = array_null_fixture();
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::Positional,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged["one_sided"], Value::Null);
+        let summary: Vec<(&str, ConflictKind)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.kind))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("/edited_a", ConflictKind::TypeMismatch),
+                ("/nulled_a", ConflictKind::TypeMismatch),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_array_null_mismatch_take_side() {
+        let (base, a, b) = array_null_fixture();
+
+        let opts = MergeOptions {
+            array_null_mismatch: ArrayNullMismatch::TakeEdit,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({"nulled_a": [1, 3], "edited_a": [1, 2], "one_sided": null})
+        );
+
+        let opts = MergeOptions {
+            array_null_mismatch: ArrayNullMismatch::TakeNull,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({"nulled_a": null, "edited_a": null, "one_sided": null})
+        );
+
+        // At the root too.
+        let (merged, conflicts) =
+            three_way_merge_with_options(&json!([1]), &json!(null), &json!([2]), &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, Value::Null);
+    }
+
+    #[test]
+    fn test_infer_base() {
+        let a =
+            json!({"name": "app", "server": {"port": 80, "tls": true}, "tags": [1], "only_a": 1});
+        let b =
+            json!({"name": "app", "server": {"port": 81, "tls": true}, "tags": [2], "only_b": 2});
+        assert_eq!(
+            infer_base(&a, &b),
+            json!({"name": "app", "server": {"tls": true}})
+        );
+        assert_eq!(infer_base(&json!([1]), &json!([1])), json!([1]));
+        assert_eq!(infer_base(&json!([1]), &json!({})), Value::Null);
+    }
+
+    #[test]
+    fn test_inferred_base_merges_more_than_empty_base() {
+        let a = json!({"server": {"port": 80, "host": "a.example", "tls": true}, "x": 1});
+        let b = json!({"server": {"port": 80, "host": "a.example", "tls": false, "debug": true}, "y": 2});
+        let opts = MergeOptions::default();
+
+        // Against an empty base both branches added a different `server`.
+        let (_, conflicts) = three_way_merge_with_options(&json!({}), &a, &b, &opts);
+        let paths: Vec<&str> = conflicts.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, vec!["/server"]);
+
+        let (merged, conflicts) = three_way_merge_with_options(&infer_base(&a, &b), &a, &b, &opts);
+        let paths: Vec<&str> = conflicts.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, vec!["/server/tls"]);
+        assert_eq!(
+            merged,
+            json!({"server": {"port": 80, "host": "a.example", "tls": true, "debug": true}, "x": 1, "y": 2})
+        );
+    }
+
+    #[test]
+    fn test_ignore_compare_keys() {
+        let opts = MergeOptions {
+            ignore_compare: Some(IgnoreCompare {
+                keys: vec![PathPattern::parse("/**/last_modified").unwrap()],
+                source: Side::A,
+            }),
+            ..Default::default()
+        };
+        // Only the timestamps differ, so nothing conflicts and A's is kept.
+        let base = json!({"doc": {"v": 1, "last_modified": 1}});
+        let a = json!({"doc": {"v": 1, "last_modified": 2}});
+        let b = json!({"doc": {"v": 1, "last_modified": 3}});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, a);
+
+        // B's real change is taken, with A's timestamp, and an object only B
+        // has keeps its own.
+        let b = json!({
+            "doc": {"v": 2, "last_modified": 3},
+            "new": [{"last_modified": 4, "x": 1}]
+        });
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({
+                "doc": {"v": 2, "last_modified": 2},
+                "new": [{"last_modified": 4, "x": 1}]
+            })
+        );
+
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 1);
+        assert!(detect_conflicts(&base, &a, &b, &opts).is_empty());
+    }
+
+    #[test]
+    fn test_objects_replacing_a_scalar_merge_as_added() {
+        let base = json!({"db": "postgres://localhost"});
+        let a = json!({"db": {"host": "localhost", "port": 5432}});
+        let b = json!({"db": {"host": "localhost", "user": "app"}});
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({"db": {"host": "localhost", "port": 5432, "user": "app"}})
+        );
+
+        // Keys both added differently conflict on their own, at the root too.
+        let a = json!({"host": "a", "port": 1});
+        let b = json!({"host": "b", "user": "app"});
+        let (merged, conflicts) =
+            three_way_merge_with_options(&json!("url"), &a, &b, &MergeOptions::default());
+        let kinds: Vec<(&str, ConflictKind)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.kind))
+            .collect();
+        assert_eq!(kinds, vec![("/host", ConflictKind::BothAdded)]);
+        assert_eq!(merged, json!({"host": "a", "port": 1, "user": "app"}));
+
+        let opts = MergeOptions {
+            key_scope: KeyScope::Base,
+            ..Default::default()
+        };
+        let (_, conflicts) = three_way_merge_with_options(&json!("url"), &a, &b, &opts);
+        let paths: Vec<&str> = conflicts.iter().map(|c| c.path.as_str()).collect();
+        assert_eq!(paths, vec![""]);
+    }
+
+    #[test]
+    fn test_timestamp_tolerance() {
+        let opts = MergeOptions {
+            timestamp_tolerances: vec![TimestampTolerance::parse("/**/updated=2").unwrap()],
+            ..Default::default()
+        };
+        let base = json!({"job": {"updated": "2024-05-01T10:00:00Z", "state": "new"}});
+        // A skews the timestamp by a second and a half, written in another
+        // zone, while B sets it properly: A's is no change, so B's is taken.
+        let a = json!({"job": {"updated": "2024-05-01T12:00:01.5+02:00", "state": "done"}});
+        let b = json!({"job": {"updated": "2024-05-01T10:05:00Z", "state": "new"}});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({"job": {"updated": "2024-05-01T10:05:00Z", "state": "done"}})
+        );
+
+        // Both branches stamping the same moment, within and beyond tolerance.
+        let stamped = |updated: &str| json!({"job": {"updated": updated, "state": "new"}});
+        let a = stamped("2024-05-01T11:00:00Z");
+        let inside = stamped("2024-05-01T11:00:02Z");
+        let outside = stamped("2024-05-01T11:00:02.5Z");
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &inside, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, a);
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &outside, &opts);
+        assert_eq!(conflicts.len(), 1);
+        let (_, conflicts) =
+            three_way_merge_with_options(&base, &a, &inside, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 1);
+
+        // Strings that are not timestamps compare exactly.
+        let (_, conflicts) =
+            three_way_merge_with_options(&base, &stamped("soon"), &stamped("later"), &opts);
+        assert_eq!(conflicts.len(), 1);
+        assert!(TimestampTolerance::parse("/updated").is_err());
+        assert!(TimestampTolerance::parse("/updated=-1").is_err());
+    }
+
+    #[test]
+    fn test_delete_wins() {
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::DeleteWins,
+            ..Default::default()
+        };
+        let base = json!({"x": 1, "y": 1, "z": 1, "keep": {"k": 1}});
+        let a = json!({"x": 2, "z": 2, "keep": {"k": 1}});
+        let b = json!({"y": 3, "z": 3, "keep": {}});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!({"z": 2, "keep": {}}));
+        let outcomes: Vec<(&str, Option<Side>)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.resolution))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![("/x", Some(Side::B)), ("/y", Some(Side::A)), ("/z", None)]
+        );
+        assert_eq!(
+            ConflictStrategy::parse("delete-wins"),
+            Ok(Some(ConflictStrategy::DeleteWins))
+        );
+    }
+
+    #[test]
+    fn test_prefer_longer_string() {
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::PreferLongerString,
+            ..Default::default()
+        };
+        let base = json!({"a": "Fix", "b": "Fix", "tie": "abc", "n": 1, "gone": "x"});
+        let a = json!({"a": "Fix the parser", "b": "Fixed", "tie": "déf", "n": 2});
+        let b = json!({"a": "Fixes", "b": "Fix the lexer", "tie": "ghi", "n": 3, "gone": "xy"});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            merged,
+            json!({"a": "Fix the parser", "b": "Fix the lexer", "tie": "déf", "n": 2, "gone": "xy"})
+        );
+        let outcomes: Vec<(&str, Option<Side>)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.resolution))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("/a", Some(Side::A)),
+                ("/b", Some(Side::B)),
+                ("/gone", None),
+                ("/n", None),
+                ("/tie", None)
+            ]
+        );
+        assert_eq!(
+            ConflictStrategy::parse("prefer-longer-string"),
+            Ok(Some(ConflictStrategy::PreferLongerString))
+        );
+    }
+
+    #[test]
+    fn test_wide_objects() {
+        let width = 100_000;
+        let base = Value::Object((0..width).map(|i| (format!("k{}", i), json!(i))).collect());
+        let mut a = base.clone();
+        let mut b = base.clone();
+        for i in (0..width).step_by(2) {
+            a[format!("k{}", i)] = json!("a");
+        }
+        for i in (1..width).step_by(2) {
+            b.as_object_mut().unwrap().remove(&format!("k{}", i));
+        }
+        for i in width..width + 10 {
+            b[format!("k{}", i)] = json!("b");
+        }
+        for key_scope in [KeyScope::Union, KeyScope::Base, KeyScope::Intersection] {
+            let opts = MergeOptions {
+                key_scope,
+                ..Default::default()
+            };
+            let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+            assert!(conflicts.is_empty());
+            let merged = merged.as_object().unwrap();
+            let expected = if key_scope == KeyScope::Union {
+                width / 2 + 10
             } else {
-                log::error!(
-                    "Conflict: file '{}' modified in both branches with different contents",
-                    path
-                );
-                (Some(a_val.clone()), true)
-            }
+                width / 2
+            };
+            assert_eq!(merged.len(), expected);
+            assert_eq!(merged["k0"], json!("a"));
+            assert!(!merged.contains_key("k1"));
         }
+    }
 
-        (None, Some(a_val), Some(b_val)) => {
-            if a_val == b_val {
-                (Some(a_val.clone()), false)
-            } else {
-                log::error!(
-                    "Conflict: file '{}' added in both branches with different contents",
-                    path
-                );
-                (Some(a_val.clone()), true)
-            }
+    #[test]
+    fn test_plan() {
+        let base = json!({"same": 1, "a": 1, "b": {"x": 1, "y": 1}, "clash": 1, "gone": 1, "list": [1, 2]});
+        let a = json!({"same": 1, "a": 2, "b": {"x": 1, "y": 1}, "clash": 2, "gone": 1, "list": [1, 2], "new": {}});
+        let b = json!({"same": 1, "a": 1, "b": {"x": 1, "y": 3, "z": [true]}, "clash": 3, "list": [1, 2, 9]});
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::Positional,
+            ..Default::default()
+        };
+        let steps = plan(&base, &a, &b, &opts);
+        let summary: Vec<(&str, PlanSource)> = steps
+            .iter()
+            .map(|step| (step.path.as_str(), step.source))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("/a", PlanSource::A),
+                ("/b/x", PlanSource::Base),
+                ("/b/y", PlanSource::B),
+                ("/b/z/0", PlanSource::B),
+                ("/clash", PlanSource::Conflict),
+                ("/list/0", PlanSource::Base),
+                ("/list/1", PlanSource::Base),
+                ("/list/2", PlanSource::B),
+                ("/new", PlanSource::A),
+                ("/same", PlanSource::Base),
+            ]
+        );
+
+        // Every value of the actual result is planned, from where it is.
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        let mut leaves = Vec::new();
+        taken_leaves(&merged, "", true, &HashSet::new(), &mut |path, _| {
+            leaves.push(path.to_string())
+        });
+        for path in leaves {
+            let step = steps
+                .iter()
+                .find(|step| step.path == path)
+                .unwrap_or_else(|| panic!("{} is not planned", path));
+            let version = match step.source {
+                PlanSource::A => &a,
+                PlanSource::B => &b,
+                PlanSource::Base => &base,
+                PlanSource::Conflict => {
+                    assert!(conflicts.iter().any(|c| c.path == step.path));
+                    continue;
+                }
+            };
+            assert_eq!(merged.pointer(&path), version.pointer(&path), "{}", path);
         }
+        assert_eq!(
+            serde_json::to_value(&steps[0]).unwrap(),
+            json!({"path": "/a", "take": "a"})
+        );
+    }
 
-        (None, Some(a_val), None) => (Some(a_val.clone()), false),
-        (None, None, Some(b_val)) => (Some(b_val.clone()), false),
+    #[test]
+    fn test_forced_values() {
+        let opts = MergeOptions {
+            force_key: Some("__force__".to_string()),
+            ..Default::default()
+        };
+        let base = json!({"port": 80, "host": "a", "same": 1, "deep": {"k": 1}, "list": [1, 2]});
+        let a = json!({
+            "port": {"__force__": 8080},
+            "host": {"__force__": "b"},
+            "same": {"__force__": 2},
+            "deep": {"k": 1},
+            "list": [1, {"__force__": {"x": {"__force__": 3}}}]
+        });
+        let b = json!({
+            "port": 9090,
+            "host": {"__force__": "c"},
+            "same": {"__force__": 2},
+            "deep": {"k": {"__force__": 5}},
+            "list": [1, 2]
+        });
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            merged,
+            json!({"port": 8080, "host": "b", "same": 2, "deep": {"k": 5}, "list": [1, {"x": 3}]})
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/host");
+        assert_eq!(conflicts[0].kind, ConflictKind::BothModified);
 
-        (Some(base_val), Some(a_val), None) => {
-            if a_val == base_val {
-                (None, false)
-            } else {
-                log::error!(
-                    "Conflict: file '{}' modified in branch A but deleted in branch B",
-                    path
-                );
-                (Some(a_val.clone()), true)
-            }
+        // A wrapper holding more than the key is an ordinary object, and
+        // without the option none are special.
+        let base = json!({"x": 1});
+        let a = json!({"x": {"__force__": 2, "other": 1}});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &json!({"x": 3}), &opts);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(merged, a);
+
+        // The value kept in a conflict is unwrapped too.
+        let a = json!({"x": [{"__force__": 2}]});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &json!({"x": 3}), &opts);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(merged, json!({"x": [2]}));
+        let a = json!({"x": {"__force__": 2}});
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &json!({"x": 1}), &MergeOptions::default());
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, a);
+    }
+
+    #[test]
+    fn test_null_coalesce() {
+        let opts = MergeOptions {
+            null_coalesce: true,
+            ..Default::default()
+        };
+        let base = json!({"x": 1, "y": 1, "z": {"k": 1}, "n": 1});
+        let a = json!({"x": null, "y": 2, "z": null, "n": null});
+        let b = json!({"x": 3, "y": 4, "z": {"k": 2}, "n": null, "new": null});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        let unresolved: Vec<&str> = conflicts
+            .iter()
+            .filter(|c| !c.is_resolved())
+            .map(|c| c.path.as_str())
+            .collect();
+        assert_eq!(unresolved, vec!["/y"]);
+        let x = conflicts.iter().find(|c| c.path == "/x").unwrap();
+        assert_eq!(x.resolution, Some(Side::B));
+        assert_eq!(
+            merged,
+            json!({"x": 3, "y": 2, "z": {"k": 2}, "n": null, "new": null})
+        );
+
+        // Both branches adding a key, one as null, and the mirror case.
+        let (merged, conflicts) = three_way_merge_with_options(
+            &json!({}),
+            &json!({"x": "a"}),
+            &json!({"x": null}),
+            &opts,
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].resolution, Some(Side::A));
+        assert_eq!(merged, json!({"x": "a"}));
+
+        // A null against a string is a type mismatch, left to a person with
+        // safe types.
+        let safe = MergeOptions {
+            safe_types: true,
+            ..opts.clone()
+        };
+        let (_, conflicts) = three_way_merge_with_options(
+            &json!({"x": 1}),
+            &json!({"x": null}),
+            &json!({"x": "s"}),
+            &safe,
+        );
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].resolution, None);
+        assert!(blocks_output(&conflicts, &safe));
+
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 3);
+    }
+
+    #[test]
+    fn test_trace_keys() {
+        let base =
+            json!({"kept": 1, "gone_a": 1, "gone_b": 1, "gone": 1, "edit": {"x": 1}, "clash": 1});
+        let a =
+            json!({"kept": 1, "gone_b": 1, "new_a": 1, "both": 1, "edit": {"x": 2}, "clash": 2});
+        let b =
+            json!({"kept": 1, "gone_a": 1, "new_b": 1, "both": 1, "edit": {"x": 1}, "clash": 3});
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink_events = events.clone();
+        let opts = MergeOptions {
+            trace_keys: true,
+            log_sink: Some(LogSink::new(move |event| {
+                if event.level == LogLevel::Trace {
+                    sink_events.lock().unwrap().push(event.message.clone());
+                }
+            })),
+            ..Default::default()
+        };
+        three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                r#"key="/both" base=absent a=present b=present decision=added-in-both"#,
+                r#"key="/clash" base=present a=present b=present decision=conflict"#,
+                r#"key="/edit" base=present a=present b=present decision=merged"#,
+                r#"key="/gone" base=present a=absent b=absent decision=deleted-in-both"#,
+                r#"key="/gone_a" base=present a=absent b=present decision=deleted-in-a"#,
+                r#"key="/gone_b" base=present a=present b=absent decision=deleted-in-b"#,
+                r#"key="/kept" base=present a=present b=present decision=unchanged"#,
+                r#"key="/new_a" base=absent a=present b=absent decision=added-in-a"#,
+                r#"key="/new_b" base=absent a=absent b=present decision=added-in-b"#,
+            ]
+        );
+
+        // Without the option nothing is traced.
+        events.lock().unwrap().clear();
+        let opts = MergeOptions {
+            trace_keys: false,
+            ..opts
+        };
+        three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_deadline_stops_merge_at_object_entries() {
+        let base = json!({"a": 1, "b": 1, "c": {"x": 1}, "d": 1});
+        let a = json!({"a": 2, "b": 2, "c": {"x": 2}, "d": 2});
+        let b = json!({"a": 1, "b": 3, "c": {"x": 1}, "d": 1});
+
+        // A deadline already passed stops the merge before the first entry.
+        let opts = MergeOptions {
+            deadline: Some(Instant::now()),
+            ..Default::default()
+        };
+        let (merged, flow) =
+            three_way_merge_visit(&base, &a, &b, &opts, &mut |_| ControlFlow::Continue(()));
+        assert_eq!(merged, json!({}));
+        assert!(flow.is_break());
+
+        // A conflict slow to handle runs past a tight deadline: the entries
+        // merged before it are kept, and the rest are omitted.
+        let opts = MergeOptions {
+            deadline: Some(Instant::now() + std::time::Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let (merged, flow) = three_way_merge_visit(&base, &a, &b, &opts, &mut |_| {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            ControlFlow::Continue(())
+        });
+        assert_eq!(merged, json!({"a": 2, "b": 2}));
+        assert!(flow.is_break());
+
+        let opts = MergeOptions {
+            deadline: Some(Instant::now() + std::time::Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let (merged, flow) =
+            three_way_merge_visit(&base, &a, &b, &opts, &mut |_| ControlFlow::Continue(()));
+        assert_eq!(merged, json!({"a": 2, "b": 2, "c": {"x": 2}, "d": 2}));
+        assert!(flow.is_continue());
+    }
+
+    #[test]
+    fn test_merge_strings() {
+        let (merged, conflicts) = merge_strings(
+            r#"{"x": 1, "y": 1}"#,
+            r#"{"x": 2, "y": 1}"#,
+            r#"{"x": 1, "y": 3}"#,
+            &MergeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(merged, "{\n  \"x\": 2,\n  \"y\": 3\n}");
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_merge_strings_with_shared_base() {
+        let base = Arc::new(json!({"x": 1, "y": 1}));
+        let branches = [
+            (r#"{"x": 2, "y": 1}"#, "{\n  \"x\": 2,\n  \"y\": 3\n}"),
+            (r#"{"x": 1, "y": 1}"#, "{\n  \"x\": 1,\n  \"y\": 3\n}"),
+        ];
+        let merges: Vec<_> = branches
+            .into_iter()
+            .map(|(a, expected)| {
+                let base = Arc::clone(&base);
+                std::thread::spawn(move || {
+                    let (merged, conflicts) = merge_strings_with_base(
+                        &base,
+                        a,
+                        r#"{"x": 1, "y": 3}"#,
+                        &MergeOptions::default(),
+                    )
+                    .unwrap();
+                    assert!(conflicts.is_empty());
+                    assert_eq!(merged, expected);
+                })
+            })
+            .collect();
+        for merge in merges {
+            merge.join().unwrap();
         }
+        assert_eq!(*base, json!({"x": 1, "y": 1}));
 
-        (Some(base_val), None, Some(b_val)) => {
-            if b_val == base_val {
-                (None, false)
-            } else {
-                log::error!(
-                    "Conflict: file '{}' modified in branch B but deleted in branch A",
-                    path
-                );
-                (Some(b_val.clone()), true)
+        let error =
+            merge_strings_with_base(&base, "{}", "[", &MergeOptions::default()).unwrap_err();
+        assert!(matches!(
+            error,
+            MergeError::Parse {
+                input: "branch B",
+                ..
             }
-        }
+        ));
+    }
 
-        (Some(_), None, None) => (None, false),
+    #[test]
+    fn test_merge_strings_returns_conflicts() {
+        let (merged, conflicts) = merge_strings(
+            r#"{"x": 1}"#,
+            r#"{"x": 2}"#,
+            r#"{"x": 3}"#,
+            &MergeOptions::default(),
+        )
+        .unwrap();
+        let merged: Value = serde_json::from_str(&merged).unwrap();
+        assert_eq!(merged, json!({"x": 2}));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/x");
+        assert_eq!(conflicts[0].kind, ConflictKind::BothModified);
+    }
 
-        (None, None, None) => panic!(
-            "Unexpected case: file '{}' doesn't exist in any version",
-            path
-        ),
+    #[test]
+    fn test_merge_strings_with_edits() {
+        let a = "{\"x\": 2,\n \"y\": 1, \"keep\": [1,2]}\n";
+        let (merged, edits, conflicts) = merge_strings_with_edits(
+            r#"{"x": 1, "y": 1, "keep": [1, 2]}"#,
+            a,
+            r#"{"x": 1, "y": 3, "keep": [1, 2]}"#,
+            &MergeOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(merged, json!({"x": 2, "y": 3, "keep": [1, 2]}));
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            line_diff::apply_edits(a, &edits),
+            serde_json::to_string_pretty(&merged).unwrap()
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
+    #[test]
+    fn test_merge_strings_parse_errors() {
+        let opts = MergeOptions::default();
+        let err = merge_strings("{}", "{", "{}", &opts).unwrap_err();
+        assert!(matches!(
+            err,
+            MergeError::Parse {
+                input: "branch A",
+                ..
+            }
+        ));
+        assert!(
+            err.to_string().starts_with("failed to parse branch A: "),
+            "{}",
+            err
+        );
+
+        let err = merge_strings("not json", "{}", "{}", &opts).unwrap_err();
+        assert!(matches!(err, MergeError::Parse { input: "base", .. }));
+
+        let err = merge_strings("{}", "{}", "[1,", &opts).unwrap_err();
+        assert!(matches!(
+            err,
+            MergeError::Parse {
+                input: "branch B",
+                ..
+            }
+        ));
+    }
 
+    #[cfg(feature = "arbitrary-precision")]
     #[test]
-    fn test_no_changes() {
-        let base = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2"
-        });
-        let a = base.clone();
-        let b = base.clone();
+    fn test_large_numbers_round_trip_exactly() {
+        let (merged, conflicts) = merge_strings(
+            r#"{"amount": 1234567890123456789012345678901234567890, "rate": 1}"#,
+            r#"{"amount": 1234567890123456789012345678901234567891, "rate": 1}"#,
+            r#"{"amount": 1234567890123456789012345678901234567890, "rate": 0.1000000000000000000000000000000000000001}"#,
+            &MergeOptions::default(),
+        )
+        .unwrap();
+        assert!(conflicts.is_empty());
+        assert!(merged.contains("\"amount\": 1234567890123456789012345678901234567891"));
+        assert!(merged.contains("\"rate\": 0.1000000000000000000000000000000000000001"));
+    }
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, base);
-        assert_eq!(had_conflicts, false);
+    #[cfg(feature = "arbitrary-precision")]
+    #[test]
+    fn test_large_numbers_differing_past_f64_precision_conflict() {
+        let base: Value = serde_json::from_str("1234567890123456789012345678901234567890").unwrap();
+        let a: Value = serde_json::from_str("1234567890123456789012345678901234567891").unwrap();
+        let b: Value = serde_json::from_str("1234567890123456789012345678901234567892").unwrap();
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, ConflictKind::BothModified);
     }
 
     #[test]
-    fn test_changes_to_different_files() {
+    fn test_string_whitespace_insensitive() {
         let base = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2"
+            "script": "set -e\nmake\n",
+            "other": "x"
         });
 
+        // A only adds trailing spaces and drops the final newline, B switches to
+        // CRLF line endings and edits another field.
         let a = json!({
-            "file1.txt": "id1-modified",
-            "file2.txt": "id2"
+            "script": "set -e  \nmake",
+            "other": "x"
         });
 
         let b = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-modified"
+            "script": "set -e\r\nmake\r\n\r\n",
+            "other": "y"
         });
 
-        let expected = json!({
-            "file1.txt": "id1-modified",
-            "file2.txt": "id2-modified"
-        });
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/script");
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, false);
+        let opts = MergeOptions {
+            string_whitespace_insensitive: true,
+            ..Default::default()
+        };
+        let (merged_insensitive, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        // A's only change does not count, so B's document is taken.
+        assert_eq!(merged_insensitive, b);
     }
 
     #[test]
-    fn test_same_changes_in_both_branches() {
+    fn test_string_whitespace_insensitive_keeps_real_changes() {
+        let base = json!({"script": "make\n"});
+        let a = json!({"script": "make  \n"});
+        let b = json!({"script": "make test\n"});
+
+        let opts = MergeOptions {
+            string_whitespace_insensitive: true,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, b);
+
+        // Leading whitespace and inner blank lines still count.
+        let (_, conflicts) =
+            three_way_merge_with_options(&json!("a\nb"), &json!("  a\nb"), &json!("a\n\nb"), &opts);
+        assert_eq!(conflicts.len(), 1);
+    }
+
+    fn preference_fixture() -> (Value, Value, Value) {
         let base = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2"
+            "both": 1,
+            "deleted_in_a": 1
         });
 
         let a = json!({
-            "file1.txt": "id1-same-change",
-            "file2.txt": "id2"
+            "both": 2
         });
 
         let b = json!({
-            "file1.txt": "id1-same-change",
-            "file2.txt": "id2"
+            "both": 3,
+            "deleted_in_a": 3
         });
 
-        let expected = json!({
-            "file1.txt": "id1-same-change",
-            "file2.txt": "id2"
-        });
+        (base, a, b)
+    }
+
+    fn prefer(order: &[Side], changed_only: bool) -> MergeOptions {
+        MergeOptions {
+            strategy: ConflictStrategy::Prefer {
+                order: order.to_vec(),
+                changed_only,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn resolutions(conflicts: &[Conflict]) -> Vec<(&str, Option<Side>)> {
+        conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.resolution))
+            .collect()
+    }
+
+    #[test]
+    fn test_prefer_skips_deletions() {
+        let (base, a, b) = preference_fixture();
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &prefer(&[Side::A, Side::B], false));
+        assert_eq!(merged, json!({"both": 2, "deleted_in_a": 3}));
+        assert_eq!(
+            resolutions(&conflicts),
+            vec![("/both", Some(Side::A)), ("/deleted_in_a", Some(Side::B))]
+        );
+    }
+
+    #[test]
+    fn test_prefer_base() {
+        let (base, a, b) = preference_fixture();
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &prefer(&[Side::Base, Side::A], false));
+        assert_eq!(merged["both"], json!(1));
+        assert_eq!(merged["deleted_in_a"], json!(1));
+        assert!(conflicts.iter().all(|c| c.resolution == Some(Side::Base)));
+    }
+
+    #[test]
+    fn test_prefer_changed_only() {
+        // A keeps base's array while B turns it into an object, which conflicts
+        // with A's value even though A did not change it.
+        let base = json!({"list": [1], "x": 1});
+        let a = json!({"list": [1], "x": 2});
+        let b = json!({"list": {"0": 1}, "x": 3});
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, false);
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &prefer(&[Side::A, Side::B], false));
+        assert_eq!(merged, a);
+        assert_eq!(
+            resolutions(&conflicts),
+            vec![("/list", Some(Side::A)), ("/x", Some(Side::A))]
+        );
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &prefer(&[Side::A, Side::B], true));
+        assert_eq!(merged, json!({"list": {"0": 1}, "x": 2}));
+        assert_eq!(
+            resolutions(&conflicts),
+            vec![("/list", Some(Side::B)), ("/x", Some(Side::A))]
+        );
+
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &prefer(&[Side::A], true));
+        assert_eq!(
+            resolutions(&conflicts),
+            vec![("/list", None), ("/x", Some(Side::A))]
+        );
     }
 
     #[test]
-    fn test_conflict_in_same_file() {
+    fn test_prefer_respects_safe_types() {
+        let opts = MergeOptions {
+            safe_types: true,
+            ..prefer(&[Side::B], false)
+        };
+        let (_, conflicts) = three_way_merge_with_options(
+            &json!({"x": 1}),
+            &json!({"x": "a"}),
+            &json!({"x": [1]}),
+            &opts,
+        );
+        assert_eq!(resolutions(&conflicts), vec![("/x", None)]);
+    }
+
+    #[test]
+    fn test_key_scope_values_only() {
         let base = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2"
+            "kept": 1,
+            "deleted_in_a": 1,
+            "deleted_in_a_modified_in_b": 1,
+            "nested": {"x": 1, "y": 1}
         });
 
         let a = json!({
-            "file1.txt": "id1-a-change",
-            "file2.txt": "id2"
+            "kept": 2,
+            "nested": {"x": 1, "added": true},
+            "added_in_a": 1
         });
 
         let b = json!({
-            "file1.txt": "id1-b-change",
-            "file2.txt": "id2"
+            "kept": 1,
+            "deleted_in_a": 1,
+            "deleted_in_a_modified_in_b": 3,
+            "nested": {"x": 3, "y": 1},
+            "added_in_b": 1
         });
 
-        // In conflict, branch A's value is used
-        let expected = json!({
-            "file1.txt": "id1-a-change",
-            "file2.txt": "id2"
-        });
+        let opts = MergeOptions {
+            key_scope: KeyScope::ValuesOnly,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({
+                "kept": 2,
+                "deleted_in_a": 1,
+                "deleted_in_a_modified_in_b": 3,
+                "nested": {"x": 3, "y": 1}
+            })
+        );
+    }
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, true);
+    // A chain of single-key objects `depth` levels deep ending in `leaf`, with a
+    // sibling key at every level.
+    fn narrow_tree(depth: usize, leaf: Value) -> Value {
+        (0..depth).fold(leaf, |inner, level| json!({"next": inner, "level": level}))
     }
 
     #[test]
-    fn test_file_added_in_one_branch() {
-        let base = json!({
-            "file1.txt": "id1"
-        });
+    fn test_unchanged_subtree_fast_path_matches_descending() {
+        let base = json!({"deep": narrow_tree(200, json!(0)), "other": 1});
+        let cases = [
+            (
+                json!({"deep": narrow_tree(200, json!(1)), "other": 1}),
+                base.clone(),
+            ),
+            (
+                base.clone(),
+                json!({"deep": narrow_tree(200, json!(2)), "other": 2}),
+            ),
+            (
+                json!({"deep": narrow_tree(200, json!(1)), "other": 2}),
+                json!({"deep": narrow_tree(200, json!(0)), "other": 3, "new": true}),
+            ),
+        ];
 
-        let a = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-new"
-        });
+        // An ignore pattern that matches nothing makes the merge descend into
+        // every object, so it yields the result of the slow path.
+        let descending = MergeOptions {
+            ignore: vec![PathPattern::parse("/no/such/path").unwrap()],
+            ..Default::default()
+        };
+        for (a, b) in cases {
+            assert_eq!(
+                three_way_merge_with_options(&base, &a, &b, &MergeOptions::default()),
+                three_way_merge_with_options(&base, &a, &b, &descending)
+            );
+        }
+    }
 
-        let b = base.clone();
+    #[test]
+    fn test_ignore_and_key_scope_apply_inside_subtree_one_branch_changed() {
+        let base = json!({"dir": {"file.txt": "id1", "build.log": "id2"}});
+        let a = base.clone();
+        let b = json!({"dir": {"file.txt": "id1-b", "build.log": "id2-b", "new.txt": "id3"}});
 
-        let expected = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-new"
-        });
+        let opts = MergeOptions {
+            ignore: vec![PathPattern::parse("/dir/build.log").unwrap()],
+            ..Default::default()
+        };
+        let (merged, _) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            merged,
+            json!({"dir": {"file.txt": "id1-b", "build.log": "id2", "new.txt": "id3"}})
+        );
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, false);
+        let opts = MergeOptions {
+            key_scope: KeyScope::Base,
+            ..Default::default()
+        };
+        let (merged, _) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            merged,
+            json!({"dir": {"file.txt": "id1-b", "build.log": "id2-b"}})
+        );
     }
 
     #[test]
-    fn test_file_added_in_both_branches_same_content() {
-        let base = json!({
-            "file1.txt": "id1"
-        });
+    fn test_nested_array_object_swap_in_one_branch_conflicts() {
+        let base = json!({"dir": {"list": [1], "file.txt": "id1"}});
+        let a = base.clone();
+        let b = json!({"dir": {"list": {"0": 1}, "file.txt": "id1-b"}});
 
-        let a = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-new"
-        });
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(merged, json!({"dir": {"list": [1], "file.txt": "id1-b"}}));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/dir/list");
+        assert_eq!(conflicts[0].kind, ConflictKind::TypeMismatch);
+    }
 
-        let b = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-new"
-        });
+    fn assert_detects_merge_conflicts(base: &Value, a: &Value, b: &Value, opts: &MergeOptions) {
+        let (_, conflicts) = three_way_merge_with_options(base, a, b, opts);
+        assert!(!conflicts.is_empty());
+        assert_eq!(detect_conflicts(base, a, b, opts), conflicts);
+    }
 
-        let expected = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-new"
-        });
+    #[test]
+    fn test_detect_conflicts_matches_merge() {
+        let base = json!({"port": 80, "tags": ["x", "y"], "gone": {"k": 1}, "list": [1]});
+        let a = json!({"port": 81, "tags": ["x", "z"], "list": {"0": 1}, "new": 1});
+        let b = json!({"port": 82, "tags": ["w", "y"], "gone": {"k": 2}, "new": 2, "list": [1]});
+        for opts in [
+            MergeOptions::default(),
+            MergeOptions {
+                strategy: ConflictStrategy::Theirs,
+                ..Default::default()
+            },
+            MergeOptions {
+                array_mode: ArrayMergeMode::Atomic,
+                treat_empty_string_as_absent: true,
+                ..Default::default()
+            },
+        ] {
+            assert_detects_merge_conflicts(&base, &a, &b, &opts);
+        }
+        assert!(detect_conflicts(&base, &a, &a, &MergeOptions::default()).is_empty());
+    }
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, false);
+    #[test]
+    fn test_detect_conflicts_paths_follow_merged_positions() {
+        // Similarity matching numbers elements by their place in the result,
+        // and renamed records by their chosen key.
+        let base = json!([{"id": 1, "v": 1}, {"id": 2, "v": 1}, {"id": 3, "v": 1}]);
+        let a = json!([{"id": 2, "v": 2}, {"id": 3, "v": 2}]);
+        let b = json!([{"id": 2, "v": 3}, {"id": 3, "v": 3}]);
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::Similarity { threshold: 0.3 },
+            ..Default::default()
+        };
+        assert_detects_merge_conflicts(&base, &a, &b, &opts);
+
+        let base = json!({"alice": {"uid": 1, "role": "dev"}});
+        let a = json!({"alicia": {"uid": 1, "role": "ops"}});
+        let b = json!({"ali": {"uid": 1, "role": "admin"}});
+        assert_detects_merge_conflicts(&base, &a, &b, &records_options());
     }
 
     #[test]
-    fn test_file_added_in_both_branches_different_content() {
-        let base = json!({
-            "file1.txt": "id1"
-        });
+    fn test_entry_missing_everywhere() {
+        let opts = MergeOptions::default();
+        let mut visitor = |_| ControlFlow::Continue(());
+        let mut merger = Merger::new(&opts, &mut visitor, true);
+        assert_eq!(merger.merge_versions(None, None, None, "/x"), (None, false));
+    }
 
-        let a = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-new-a"
-        });
+    #[test]
+    fn test_ordered_union_keeps_first_seen_order() {
+        let base = json!({"tags": ["x", "y", "z"]});
+        let a = json!({"tags": ["w", "y", "x", "w"]});
+        let b = json!({"tags": ["z", "v", "w", "x"]});
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::OrderedUnion,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!({"tags": ["x", "y", "z", "w", "v"]}));
+        assert!(conflicts.is_empty());
+    }
 
-        let b = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-new-b"
-        });
+    #[test]
+    fn test_ordered_union_keeps_elements_removed_in_one_branch() {
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::OrderedUnion,
+            ..Default::default()
+        };
+        let (merged, _) =
+            three_way_merge_with_options(&json!([1, 2]), &json!([1, 2]), &json!([1]), &opts);
+        assert_eq!(merged, json!([1, 2]));
+        // Nested, neither the array nor the objects around it are taken whole.
+        let base = json!({"t": [1, 2], "u": {"v": [3]}});
+        let a = json!({"t": [1, 2], "u": {"v": [3]}});
+        let b = json!({"t": [1], "u": {"v": []}});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, base);
+        assert!(conflicts.is_empty());
 
-        // In conflict, branch A's value is used
-        let expected = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-new-a"
-        });
+        let opts = MergeOptions {
+            rules: vec![PathRule::parse("/t=ordered-union").unwrap()],
+            ..Default::default()
+        };
+        let (merged, _) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!({"t": [1, 2], "u": {"v": []}}));
+    }
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, true);
+    #[test]
+    fn test_ordered_union_length_limit_on_one_side() {
+        let base = json!({"doc": {"tags": ["x"]}});
+        let b = json!({"doc": {"tags": ["x", "y", "z"]}});
+        let mut opts = MergeOptions {
+            array_mode: ArrayMergeMode::OrderedUnion,
+            array_length_limits: vec![ArrayLengthLimit::parse("/doc/tags=2").unwrap()],
+            ..Default::default()
+        };
+        let (_, conflicts) = three_way_merge_with_options(&base, &base, &b, &opts);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/doc/tags");
+        assert_eq!(conflicts[0].kind, ConflictKind::TooLong);
+
+        opts.truncate_long_arrays = true;
+        let (merged, conflicts) = three_way_merge_with_options(&base, &base, &b, &opts);
+        assert_eq!(merged, json!({"doc": {"tags": ["x", "y"]}}));
+        assert!(conflicts.is_empty());
     }
 
     #[test]
-    fn test_file_deleted_in_one_branch() {
-        let base = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2"
-        });
+    fn test_ordered_union_compares_whole_elements() {
+        let base = json!([{"id": 1}, "b"]);
+        let a = json!([{"id": 1}, {"id": 1, "v": 2}, "b\n"]);
+        let b = json!([3, {"id": 1}, {"id": 1, "v": 2}]);
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::OrderedUnion,
+            string_whitespace_insensitive: true,
+            ..Default::default()
+        };
+        let (merged, _) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!([{"id": 1}, "b", {"id": 1, "v": 2}, 3]));
+    }
 
-        let a = json!({
-            "file1.txt": "id1"
-        });
+    #[test]
+    fn test_ordered_union_length_limit() {
+        let base = json!({"tags": ["x"], "ids": [1]});
+        let a = json!({"tags": ["x", "y"], "ids": [1, 2]});
+        let b = json!({"tags": ["x", "z"], "ids": [1, 3]});
+        let mut opts = MergeOptions {
+            array_mode: ArrayMergeMode::OrderedUnion,
+            array_length_limits: vec![
+                ArrayLengthLimit::parse("2").unwrap(),
+                ArrayLengthLimit::parse("/ids=3").unwrap(),
+            ],
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!({"tags": ["x", "y"], "ids": [1, 2, 3]}));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/tags");
+        assert_eq!(conflicts[0].kind, ConflictKind::TooLong);
+        assert_eq!(conflicts[0].b, Some(json!(["x", "z"])));
+        assert!(!conflicts[0].is_resolved());
+        assert_detects_merge_conflicts(&base, &a, &b, &opts);
 
-        let b = base.clone();
+        opts.strategy = ConflictStrategy::Theirs;
+        let (merged, _) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged["tags"], json!(["x", "z"]));
 
-        let expected = json!({
-            "file1.txt": "id1"
-        });
+        opts.strategy = ConflictStrategy::Manual;
+        opts.truncate_long_arrays = true;
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!({"tags": ["x", "y"], "ids": [1, 2, 3]}));
+        assert!(conflicts.is_empty());
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, false);
+        assert!(ArrayLengthLimit::parse("/tags=many").is_err());
+        assert!(ArrayLengthLimit::parse("tags=2").is_err());
     }
 
-    #[test]
-    fn test_file_deleted_in_both_branches() {
-        let base = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2"
-        });
+    fn set_semantics() -> MergeOptions {
+        MergeOptions {
+            array_set_semantics: true,
+            ..Default::default()
+        }
+    }
 
-        let a = json!({
-            "file1.txt": "id1"
-        });
+    #[test]
+    fn test_array_set_semantics_reordered_arrays_are_equal() {
+        let base = json!({"ids": [1, 2, 3], "nested": [{"k": [1, 1, 2]}], "name": "x"});
+        let a = json!({"ids": [3, 2, 1], "nested": [{"k": [2, 1, 1]}], "name": "x"});
+        let b = json!({"ids": [2, 3, 1], "nested": [{"k": [1, 2, 1]}], "name": "y"});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &set_semantics());
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({"ids": [3, 2, 1], "nested": [{"k": [2, 1, 1]}], "name": "y"})
+        );
 
-        let b = json!({
-            "file1.txt": "id1"
-        });
+        // Only a reordering in A: B's edit is taken as it is.
+        let b = json!({"ids": [1, 2, 3, 4], "nested": [{"k": [1, 1, 2]}], "name": "x"});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &set_semantics());
+        assert!(conflicts.is_empty());
+        assert_eq!(merged["ids"], json!([1, 2, 3, 4]));
+    }
 
-        let expected = json!({
-            "file1.txt": "id1"
-        });
+    #[test]
+    fn test_array_set_semantics_different_arrays_conflict() {
+        let base = json!({"ids": [1, 2, 3]});
+        let a = json!({"ids": [3, 2, 2]});
+        let b = json!({"ids": [1, 3, 3]});
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &set_semantics());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/ids");
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, false);
+        // Without the option a reordering is a change like any other.
+        let b = json!({"ids": [2, 1, 3]});
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 1);
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &set_semantics());
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, a);
     }
 
     #[test]
-    fn test_modified_in_one_branch_deleted_in_other() {
+    fn test_authoritative_paths_take_the_owner_value() {
         let base = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2"
+            "networking": {"port": 80, "host": "a"},
+            "storage": {"size": 1, "disks": [1]},
+            "name": "x"
         });
-
         let a = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-modified"
+            "networking": {"port": 81, "host": "a"},
+            "storage": {"size": 2, "disks": [1]},
+            "name": "x"
         });
-
         let b = json!({
-            "file1.txt": "id1"
+            "networking": {"port": 82, "host": "b"},
+            "storage": {"size": 3},
+            "name": "y"
         });
+        let opts = MergeOptions {
+            authoritative_a: vec![PathPattern::parse("/networking/*").unwrap()],
+            authoritative_b: vec![PathPattern::parse("/storage/*").unwrap()],
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        // B's edit to /networking/host is dropped even though A left it alone,
+        // and B's deletion of /storage/disks is taken.
+        assert_eq!(
+            merged,
+            json!({
+                "networking": {"port": 81, "host": "a"},
+                "storage": {"size": 3},
+                "name": "y"
+            })
+        );
+    }
 
-        // In conflict, branch A's change is kept
-        let expected = json!({
-            "file1.txt": "id1",
-            "file2.txt": "id2-modified"
-        });
+    #[test]
+    fn test_authoritative_a_wins_over_b() {
+        let base = json!({"k": {"v": 1}});
+        let a = json!({"k": {"v": 2}});
+        let b = json!({"k": {"v": 3, "w": 1}});
+        let opts = MergeOptions {
+            authoritative_a: vec![PathPattern::parse("/k").unwrap()],
+            authoritative_b: vec![PathPattern::parse("/**").unwrap()],
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, a);
+    }
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, true);
+    fn keyed() -> MergeOptions {
+        MergeOptions {
+            array_mode: ArrayMergeMode::Keyed {
+                key: "id".to_string(),
+            },
+            ..Default::default()
+        }
     }
 
     #[test]
-    fn test_nested_directory_structure() {
-        let base = json!({
-            "dir1": {
-                "file1.txt": "id1",
-                "file2.txt": "id2"
-            },
-            "dir2": {
-                "file3.txt": "id3"
-            }
-        });
+    fn test_keyed_array_add_modify_delete() {
+        let base = json!([{"id": 1, "v": 1}, {"id": 2, "v": 1}, {"id": 3, "v": 1}]);
+        // A deletes 1, edits 3 and adds 4; B reorders, deletes 2 and adds 5.
+        let a = json!([{"id": 2, "v": 1}, {"id": 3, "v": 2}, {"id": 4, "v": 1}]);
+        let b = json!([{"id": 3, "v": 1}, {"id": 1, "v": 1}, {"id": 5, "v": 1}]);
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &keyed());
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!([{"id": 3, "v": 2}, {"id": 4, "v": 1}, {"id": 5, "v": 1}])
+        );
+    }
 
-        let a = json!({
-            "dir1": {
-                "file1.txt": "id1-modified",
-                "file2.txt": "id2"
-            },
-            "dir2": {
-                "file3.txt": "id3"
-            }
-        });
+    #[test]
+    fn test_keyed_array_delete_against_modify_conflicts() {
+        let base = json!({"users": [{"id": "a", "role": "dev"}, {"id": "b", "role": "dev"}]});
+        let a = json!({"users": [{"id": "b", "role": "dev"}]});
+        let b = json!({"users": [{"id": "a", "role": "admin"}, {"id": "b", "role": "ops"}]});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &keyed());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, ConflictKind::ModifiedBDeletedA);
+        assert_eq!(conflicts[0].path, "/users/1");
+        // B's edit of the element A kept is merged; the conflicting one stays.
+        assert_eq!(
+            merged,
+            json!({"users": [{"id": "b", "role": "ops"}, {"id": "a", "role": "admin"}]})
+        );
 
-        let b = json!({
-            "dir1": {
-                "file1.txt": "id1",
-                "file2.txt": "id2"
-            },
-            "dir2": {
-                "file3.txt": "id3-modified"
-            }
-        });
+        // The same id added with different contents in both branches.
+        let a = json!({"users": [{"id": "c", "role": "dev"}]});
+        let b = json!({"users": [{"id": "c", "role": "ops"}]});
+        let (_, conflicts) = three_way_merge_with_options(&json!({"users": []}), &a, &b, &keyed());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, ConflictKind::BothAdded);
+    }
 
-        let expected = json!({
-            "dir1": {
-                "file1.txt": "id1-modified",
-                "file2.txt": "id2"
+    #[test]
+    fn test_keyed_array_without_ids_merges_positionally() {
+        let base = json!([{"id": 1, "v": 1}, {"v": 2}]);
+        let a = json!([{"id": 1, "v": 3}, {"v": 2}]);
+        let b = json!([{"id": 1, "v": 1}, {"v": 4}]);
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &keyed());
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, json!([{"id": 1, "v": 3}, {"v": 4}]));
+    }
+
+    #[test]
+    fn test_keyed_ordered_arrays_keep_base_order() {
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::KeyedOrdered {
+                key: "id".to_string(),
             },
-            "dir2": {
-                "file3.txt": "id3-modified"
-            }
-        });
+            ..Default::default()
+        };
+        let items = |ids: &[&str]| Value::Array(ids.iter().map(|id| json!({"id": id})).collect());
+        let merge = |base: &[&str], a: &[&str], b: &[&str]| {
+            let (merged, conflicts) =
+                three_way_merge_with_options(&items(base), &items(a), &items(b), &opts);
+            assert!(conflicts.is_empty());
+            merged
+        };
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, false);
+        // A's reordering does not count, and each addition follows its
+        // neighbor in the branch that added it.
+        assert_eq!(
+            merge(
+                &["1", "2", "3"],
+                &["3", "1", "x", "2"],
+                &["1", "2", "y", "3", "z"]
+            ),
+            items(&["1", "x", "2", "y", "3", "z"])
+        );
+        // At the same place A's additions come first, runs stay together, and
+        // additions with nothing before them go first.
+        assert_eq!(
+            merge(
+                &["1", "2"],
+                &["s", "1", "p", "q", "2"],
+                &["t", "1", "r", "2"]
+            ),
+            items(&["s", "t", "1", "p", "q", "r", "2"])
+        );
+        // An anchor deleted in the other branch still places the addition, and
+        // an element added in both branches anchors B's next addition.
+        assert_eq!(
+            merge(&["1", "2"], &["2", "n"], &["1", "r", "2", "n", "m"]),
+            items(&["r", "2", "n", "m"])
+        );
+
+        let base = json!([{"id": 1, "v": 1}, {"id": 2, "v": 1}]);
+        let a = json!([{"id": 2, "v": 2}, {"id": 1, "v": 1}]);
+        let b = json!([{"id": 1, "v": 3}, {"id": 2, "v": 3}]);
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(merged, json!([{"id": 1, "v": 3}, {"id": 2, "v": 2}]));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/1/v");
+        assert_eq!(
+            ArrayMergeMode::parse("keyed-ordered:id"),
+            Ok(Some(opts.array_mode.clone()))
+        );
+        assert!(ArrayMergeMode::parse("keyed-ordered:").is_err());
+
+        // Nested, a reordering in only one branch does not count either.
+        let base = json!({"list": {"items": items(&["1", "2", "3"])}});
+        let a = json!({"list": {"items": items(&["3", "2", "1"])}});
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &base, &opts);
+        assert_eq!(merged, base);
+        assert!(conflicts.is_empty());
     }
 
     #[test]
-    fn test_nested_conflict() {
-        let base = json!({
-            "dir1": {
-                "file1.txt": "id1",
-                "file2.txt": "id2"
-            }
-        });
+    fn test_log_sink_receives_events() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let captured = Arc::clone(&events);
+        let opts = MergeOptions {
+            rules: vec![PathRule::parse("/b=ours").unwrap()],
+            log_sink: Some(LogSink::new(move |event| {
+                captured.lock().unwrap().push(event.clone())
+            })),
+            ..Default::default()
+        };
+        let base = json!({"a": 1, "b": 1, "c": 1});
+        let a = json!({"a": 2, "b": 2, "c": 1});
+        let b = json!({"a": 3, "b": 3, "c": 1});
+        three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                LogEvent {
+                    level: LogLevel::Error,
+                    message: "Conflict: file '/a' modified in both branches with different contents"
+                        .to_string(),
+                },
+                LogEvent {
+                    level: LogLevel::Warn,
+                    message: "Conflict: file '/b' modified in both branches with different contents; taking branch A"
+                        .to_string(),
+                },
+            ]
+        );
+    }
 
-        let a = json!({
-            "dir1": {
-                "file1.txt": "id1-a-change",
-                "file2.txt": "id2"
-            }
-        });
+    // The messages of the warnings a merge with `warn_on_drop` logs.
+    fn drop_warnings(base: &Value, a: &Value, b: &Value, opts: MergeOptions) -> Vec<String> {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let captured = Arc::clone(&events);
+        let opts = MergeOptions {
+            warn_on_drop: true,
+            log_sink: Some(LogSink::new(move |event| {
+                if event.message.starts_with("Dropped") {
+                    captured.lock().unwrap().push(event.message.clone());
+                }
+            })),
+            ..opts
+        };
+        three_way_merge_with_options(base, a, b, &opts);
+        let warnings = events.lock().unwrap().clone();
+        warnings
+    }
 
-        let b = json!({
-            "dir1": {
-                "file1.txt": "id1-b-change",
-                "file2.txt": "id2"
-            }
-        });
+    #[test]
+    fn test_warn_on_drop_deletions() {
+        // Branch A only deleted keys, so without the option B's object would
+        // be taken whole.
+        let base = json!({"keep": 1, "db": {"host": "h", "port": 1}, "gone": 1, "list": [1, 2]});
+        let a = json!({"keep": 1, "db": {"host": "h", "port": 1}, "list": [1]});
+        let b = json!({"keep": 2, "db": {"host": "h"}, "gone": 1, "list": [1, 2, 3]});
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::Positional,
+            ..Default::default()
+        };
+        assert_eq!(
+            drop_warnings(&base, &a, &b, opts.clone()),
+            vec![
+                "Dropped file '/db/port': deleted in branch B",
+                "Dropped file '/gone': deleted in branch A",
+                "Dropped file '/list/1': deleted in branch A",
+            ]
+        );
 
-        // In conflict, branch A's value is used
-        let expected = json!({
-            "dir1": {
-                "file1.txt": "id1-a-change",
-                "file2.txt": "id2"
-            }
-        });
+        let base = json!({"x": 1, "y": 1});
+        let a = json!({"y": 1});
+        let b = json!({"x": 2});
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::DeleteWins,
+            authoritative_b: vec![PathPattern::parse("/y").unwrap()],
+            ..opts
+        };
+        assert_eq!(
+            drop_warnings(&base, &a, &b, opts),
+            vec![
+                "Dropped file '/x': modified in branch B but deleted in branch A by taking branch A",
+                "Dropped file '/y': absent from branch B, which owns it",
+            ]
+        );
+    }
 
-        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
-        assert_eq!(merged, expected);
-        assert_eq!(had_conflicts, true);
+    #[test]
+    fn test_warn_on_drop_key_scopes() {
+        let base = json!({"old": 1, "kept": 1});
+        let a = json!({"old": 1, "kept": 1, "new": 1});
+        let b = json!({"kept": 2});
+        let scope = |key_scope| MergeOptions {
+            key_scope,
+            ..Default::default()
+        };
+        assert_eq!(
+            drop_warnings(&base, &a, &b, scope(KeyScope::Base)),
+            vec![
+                "Dropped file '/new': not in base, whose keys alone are merged",
+                "Dropped file '/old': deleted in branch B",
+            ]
+        );
+        assert_eq!(
+            drop_warnings(&base, &a, &b, scope(KeyScope::Intersection)),
+            vec![
+                "Dropped file '/new': missing from base and branch B, and only keys of every version are merged",
+                "Dropped file '/old': missing from branch B, and only keys of every version are merged",
+            ]
+        );
+        assert!(drop_warnings(&base, &a, &base, scope(KeyScope::Union)).is_empty());
+    }
+
+    #[test]
+    fn test_coerced_booleans_do_not_conflict() {
+        let base = json!({"debug": false, "cache": "yes", "ssl": 1, "tls": 0});
+        let a = json!({"debug": "yes", "cache": true, "ssl": "true", "tls": "yes"});
+        let b = json!({"debug": true, "cache": 1, "ssl": "no", "tls": "no"});
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 4);
+
+        let opts = MergeOptions {
+            coercions: Coercions::booleans(),
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({"debug": "yes", "cache": true, "ssl": "no", "tls": "yes"})
+        );
+    }
+
+    #[test]
+    fn test_conflict_changes() {
+        let describe = |base: Value, a: Value, b: Value, opts: &MergeOptions| {
+            let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, opts);
+            conflicts
+                .iter()
+                .map(|c| (c.kind, c.changes, c.describe_changes()))
+                .collect::<Vec<_>>()
+        };
+        let divergence = |a, b| Divergence { a, b };
+        use Change::*;
+
+        let base = json!({"n": 1, "gone_a": 1, "gone_b": {"x": 1}, "both": [1], "t": 1});
+        let a = json!({"n": 2, "gone_b": {"x": 2}, "both": [2], "new": "a", "t": "1"});
+        let b = json!({"n": 3, "gone_a": 2, "both": [3], "new": [], "t": true});
+        assert_eq!(
+            describe(base, a, b, &MergeOptions::default()),
+            vec![
+                (
+                    ConflictKind::BothModified,
+                    divergence(Modified, Modified),
+                    "A: modified, B: modified".to_string()
+                ),
+                (
+                    ConflictKind::ModifiedBDeletedA,
+                    divergence(Deleted, Modified),
+                    "A: deleted, B: 1 → 2".to_string()
+                ),
+                (
+                    ConflictKind::ModifiedADeletedB,
+                    divergence(Modified, Deleted),
+                    "A: modified, B: deleted".to_string()
+                ),
+                (
+                    ConflictKind::BothModified,
+                    divergence(Modified, Modified),
+                    "A: 1 → 2, B: 1 → 3".to_string()
+                ),
+                (
+                    ConflictKind::TypeMismatch,
+                    divergence(Added, Added),
+                    "A: added \"a\", B: added".to_string()
+                ),
+                (
+                    ConflictKind::TypeMismatch,
+                    divergence(Modified, Modified),
+                    "A: 1 → \"1\", B: 1 → true".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            describe(
+                json!({}),
+                json!({"x": 1}),
+                json!({"x": 2}),
+                &MergeOptions::default()
+            ),
+            vec![(
+                ConflictKind::BothAdded,
+                divergence(Added, Added),
+                "A: added 1, B: added 2".to_string()
+            )]
+        );
+
+        let base = json!({"alice": {"uid": 1}});
+        let a = json!({"alicia": {"uid": 1}});
+        let b = json!({"ali": {"uid": 1}});
+        assert_eq!(
+            describe(base, a, b, &records_options()),
+            vec![(
+                ConflictKind::BothRenamed,
+                divergence(Modified, Modified),
+                "A: \"alice\" → \"alicia\", B: \"alice\" → \"ali\"".to_string()
+            )]
+        );
+
+        let opts = MergeOptions {
+            array_mode: ArrayMergeMode::OrderedUnion,
+            array_length_limits: vec![ArrayLengthLimit::parse("2").unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(
+            describe(json!(["x"]), json!(["x", "y"]), json!(["z"]), &opts),
+            vec![(
+                ConflictKind::TooLong,
+                divergence(Modified, Modified),
+                "A: modified, B: modified".to_string()
+            )]
+        );
+
+        let opts = MergeOptions {
+            review_numbers: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            describe(json!({"n": 1}), json!({"n": 1}), json!({"n": 2}), &opts),
+            vec![(
+                ConflictKind::NumberChanged,
+                divergence(Unchanged, Modified),
+                "A: unchanged, B: 1 → 2".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_review_numbers() {
+        let base = json!({"port": 8080, "limits": {"ms": 100, "n": 1}, "name": "x", "n": 1});
+        let a = json!({"port": 8080, "limits": {"ms": 100, "n": 2}, "name": "y", "n": 2});
+        let b = json!({"port": 9090, "limits": {"ms": 200, "n": 1}, "name": "x", "n": 3});
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(merged["port"], 9090);
+
+        let opts = MergeOptions {
+            review_numbers: true,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        let kinds: Vec<(&str, ConflictKind)> = conflicts
+            .iter()
+            .map(|c| (c.path.as_str(), c.kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ("/limits/ms", ConflictKind::NumberChanged),
+                ("/limits/n", ConflictKind::NumberChanged),
+                ("/n", ConflictKind::BothModified),
+                ("/port", ConflictKind::NumberChanged),
+            ]
+        );
+        // Numbers held for review keep base's value; other changes still apply.
+        assert_eq!(
+            merged,
+            json!({"port": 8080, "limits": {"ms": 100, "n": 1}, "name": "y", "n": 2})
+        );
+
+        // A strategy acknowledges the changes, like any conflict.
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Theirs,
+            ..opts
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert!(conflicts.iter().all(Conflict::is_resolved));
+        assert_eq!(merged["port"], 9090);
+        assert_eq!(merged["limits"], json!({"ms": 200, "n": 1}));
+    }
+
+    #[test]
+    fn test_coerce_numeric_strings() {
+        let base = json!({"port": 8080, "retries": "3", "name": "x", "tag": "v1"});
+        let a = json!({"port": "8080", "retries": 5, "name": "1", "tag": "042"});
+        let b = json!({"port": 8080, "retries": "5", "name": 1, "tag": 42});
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 3);
+
+        let opts = MergeOptions {
+            coerce_numeric_strings: true,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        // "042" is not a clean literal, so it still conflicts with 42.
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/tag");
+        // Where the versions hold the same number written both ways, A's form
+        // is kept.
+        assert_eq!(
+            merged,
+            json!({"port": "8080", "retries": 5, "name": "1", "tag": "042"})
+        );
     }
 }
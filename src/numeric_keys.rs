@@ -0,0 +1,176 @@
+//! Objects standing for arrays, keyed by index as `{"0": .., "1": ..}`, read as
+//! arrays so that appends to them merge as array changes, then written back as
+//! objects.
+
+use crate::pointer;
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+/// Whether `map` has keys `"0"` to `"n-1"` and no others, written without
+/// leading zeros. An empty object is not one.
+pub fn is_pseudo_array(map: &Map<String, Value>) -> bool {
+    !map.is_empty() && (0..map.len()).all(|index| map.contains_key(&index.to_string()))
+}
+
+/// Replaces the pseudo-arrays of `documents` with arrays, returning their paths
+/// for `to_objects`. At a path where any of the documents has a pseudo-array,
+/// an empty object in the others is replaced too, so that a branch appending
+/// to an empty one is merged as an append.
+pub fn to_arrays(documents: [&mut Value; 3]) -> HashSet<String> {
+    let mut paths = HashSet::new();
+    for document in &documents {
+        collect(document, "", &mut paths);
+    }
+    for document in documents {
+        convert(document, "", &paths);
+    }
+    paths
+}
+
+fn collect(value: &Value, path: &str, paths: &mut HashSet<String>) {
+    match value {
+        Value::Object(map) => {
+            if is_pseudo_array(map) {
+                paths.insert(path.to_string());
+            }
+            for (key, child) in map {
+                collect(child, &pointer::push(path, key), paths);
+            }
+        }
+        Value::Array(items) => {
+            for (index, child) in items.iter().enumerate() {
+                collect(child, &pointer::push(path, &index.to_string()), paths);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Element `i` of an array has the same path as key `"i"` of the object it
+// replaces, so the paths found in any document apply to the others as they are
+// converted.
+fn convert(value: &mut Value, path: &str, paths: &HashSet<String>) {
+    if let Value::Object(map) = value {
+        if paths.contains(path) && (map.is_empty() || is_pseudo_array(map)) {
+            let items = (0..map.len())
+                .map(|index| map.remove(&index.to_string()).unwrap_or(Value::Null))
+                .collect();
+            *value = Value::Array(items);
+        }
+    }
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                convert(child, &pointer::push(path, key), paths);
+            }
+        }
+        Value::Array(items) => {
+            for (index, child) in items.iter_mut().enumerate() {
+                convert(child, &pointer::push(path, &index.to_string()), paths);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Writes the arrays found at `paths` back as objects keyed by index.
+pub fn to_objects(value: &mut Value, paths: &HashSet<String>) {
+    to_objects_at(value, "", paths);
+}
+
+fn to_objects_at(value: &mut Value, path: &str, paths: &HashSet<String>) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map.iter_mut() {
+                to_objects_at(child, &pointer::push(path, key), paths);
+            }
+        }
+        Value::Array(items) => {
+            for (index, child) in items.iter_mut().enumerate() {
+                to_objects_at(child, &pointer::push(path, &index.to_string()), paths);
+            }
+        }
+        _ => {}
+    }
+
+    if let Value::Array(items) = value {
+        if paths.contains(path) {
+            let map = std::mem::take(items)
+                .into_iter()
+                .enumerate()
+                .map(|(index, item)| (index.to_string(), item))
+                .collect();
+            *value = Value::Object(map);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{three_way_merge_with_options, ArrayMergeMode, MergeOptions};
+    use serde_json::json;
+
+    #[test]
+    fn test_is_pseudo_array() {
+        let cases = [
+            (json!({"0": "a", "1": "b"}), true),
+            (json!({"1": "b", "0": "a"}), true),
+            (json!({"0": "a", "2": "c"}), false),
+            (json!({"1": "b"}), false),
+            (json!({"0": "a", "01": "b"}), false),
+            (json!({"0": "a", "x": "b"}), false),
+            (json!({}), false),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(
+                is_pseudo_array(value.as_object().unwrap()),
+                expected,
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut base = json!({"log": {"0": {"0": 1}}, "empty": {}, "other": {"0": 1, "x": 2}});
+        let mut a = json!({"log": {"0": {"0": 1}, "1": "b"}, "empty": {}});
+        let mut b = json!({"log": ["not", "touched"], "empty": {}});
+        let original = base.clone();
+        let paths = to_arrays([&mut base, &mut a, &mut b]);
+        assert_eq!(
+            paths,
+            HashSet::from(["/log".to_string(), "/log/0".to_string()])
+        );
+        assert_eq!(
+            base,
+            json!({"log": [[1]], "empty": {}, "other": {"0": 1, "x": 2}})
+        );
+        assert_eq!(a, json!({"log": [[1], "b"], "empty": {}}));
+        to_objects(&mut base, &paths);
+        assert_eq!(base, original);
+    }
+
+    #[test]
+    fn test_merge_appends() {
+        let mut base = json!({"events": {"0": "start"}, "notes": {}});
+        let mut a = json!({"events": {"0": "start", "1": "a1"}, "notes": {"0": "a"}});
+        let mut b = json!({"events": {"0": "start", "1": "b1", "2": "b2"}, "notes": {}});
+        let paths = to_arrays([&mut base, &mut a, &mut b]);
+        let options = MergeOptions {
+            array_mode: ArrayMergeMode::OrderedUnion,
+            ..Default::default()
+        };
+        let (mut merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &options);
+        assert!(conflicts.is_empty());
+        to_objects(&mut merged, &paths);
+        assert_eq!(
+            merged,
+            json!({
+                "events": {"0": "start", "1": "a1", "2": "b1", "3": "b2"},
+                "notes": {"0": "a"}
+            })
+        );
+    }
+}
@@ -0,0 +1,556 @@
+//! Objects whose key order is significant. `serde_json::Value` does not keep
+//! key order, so it is read from the source text, and an object a branch
+//! reordered is replaced by its serialization in source order, which the merge
+//! then compares and takes as a whole.
+
+use crate::patch::{self, Operation};
+use crate::pattern::PathPattern;
+use crate::pointer;
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+// The key serde_json uses to hand numbers through the map visitor when built
+// with `arbitrary_precision`.
+const NUMBER_TOKEN: &str = "$serde_json::private::Number";
+
+/// The keys, in source order, of every object in the JSON document `text` whose
+/// path matches `patterns`, keyed by path.
+pub fn key_orders(
+    text: &str,
+    patterns: &[PathPattern],
+) -> Result<HashMap<String, Vec<String>>, String> {
+    let mut orders = HashMap::new();
+    let mut deserializer = serde_json::Deserializer::from_str(text);
+    Collector {
+        path: String::new(),
+        patterns,
+        orders: &mut orders,
+    }
+    .deserialize(&mut deserializer)
+    .map_err(|e| e.to_string())?;
+    Ok(orders)
+}
+
+/// `key_orders` for the document `text` once `operations` are applied to
+/// `document`, parsed from it. Keys keep their order through the patch, and
+/// keys it adds or moves into an object come after the ones the object has.
+/// The keys of objects written in the patch itself, whose order is lost when
+/// it is parsed, come in lexicographic order.
+pub fn patched_key_orders(
+    text: &str,
+    document: &Value,
+    operations: &[Operation],
+    patterns: &[PathPattern],
+) -> Result<HashMap<String, Vec<String>>, String> {
+    // Objects can be moved from anywhere to a matching path.
+    let any_path = [PathPattern::parse("/**")?];
+    let mut orders = key_orders(text, &any_path)?;
+    let mut document = document.clone();
+    for operation in operations {
+        // A failing patch is reported where it is applied to the document.
+        let Ok(patched) = patch::apply(&document, std::slice::from_ref(operation)) else {
+            break;
+        };
+        follow(&mut orders, &document, operation);
+        document = patched;
+    }
+    let mut matching = HashMap::new();
+    collect_patched(&document, "", &orders, patterns, &mut matching);
+    Ok(matching)
+}
+
+type Orders = HashMap<String, Vec<String>>;
+
+// The orders at `path` and below it, by path relative to `path`.
+type Subtree = Vec<(String, Vec<String>)>;
+
+// Updates `orders` of `document` for `operation` applied to it.
+fn follow(orders: &mut Orders, document: &Value, operation: &Operation) {
+    match operation {
+        Operation::Add { path, .. } => added(orders, document, path, Vec::new()),
+        Operation::Remove { path } => {
+            removed(orders, document, path);
+        }
+        Operation::Replace { path, .. } => {
+            take(orders, path);
+        }
+        Operation::Move { from, path } => {
+            let moved = removed(orders, document, from);
+            let remove = Operation::Remove { path: from.clone() };
+            if let Ok(document) = patch::apply(document, &[remove]) {
+                added(orders, &document, path, moved);
+            }
+        }
+        Operation::Copy { from, path } => {
+            let copied = relative_paths(orders, from)
+                .into_iter()
+                .map(|relative| {
+                    let order = orders[&format!("{}{}", from, relative)].clone();
+                    (relative, order)
+                })
+                .collect();
+            added(orders, document, path, copied);
+        }
+        Operation::Test { .. } => {}
+    }
+}
+
+// Records a value with the orders `subtree` added at `path` of `document`.
+fn added(orders: &mut Orders, document: &Value, path: &str, subtree: Subtree) {
+    let Some((parent, key)) = split(path) else {
+        return;
+    };
+    let target = match document.pointer(&parent) {
+        Some(Value::Object(_)) => {
+            take(orders, path);
+            if let Some(order) = orders.get_mut(&parent) {
+                if !order.contains(&key) {
+                    order.push(key);
+                }
+            }
+            path.to_string()
+        }
+        Some(Value::Array(items)) => {
+            let index = key.parse().unwrap_or(items.len());
+            shift(orders, &parent, index, true);
+            pointer::push(&parent, &index.to_string())
+        }
+        _ => return,
+    };
+    for (relative, order) in subtree {
+        orders.insert(format!("{}{}", target, relative), order);
+    }
+}
+
+// Records the removal of the value at `path` of `document`, returning its
+// orders.
+fn removed(orders: &mut Orders, document: &Value, path: &str) -> Subtree {
+    let Some((parent, key)) = split(path) else {
+        return Vec::new();
+    };
+    let removed = take(orders, path);
+    match document.pointer(&parent) {
+        Some(Value::Object(_)) => {
+            if let Some(order) = orders.get_mut(&parent) {
+                order.retain(|other| *other != key);
+            }
+        }
+        Some(Value::Array(_)) => {
+            if let Ok(index) = key.parse::<usize>() {
+                shift(orders, &parent, index + 1, false);
+            }
+        }
+        _ => {}
+    }
+    removed
+}
+
+// The paths of `orders` at `path` or below it, relative to `path`.
+fn relative_paths(orders: &Orders, path: &str) -> Vec<String> {
+    let prefix = format!("{}/", path);
+    orders
+        .keys()
+        .filter(|other| *other == path || other.starts_with(&prefix))
+        .map(|other| other[path.len()..].to_string())
+        .collect()
+}
+
+// Removes the orders at `path` and below it.
+fn take(orders: &mut Orders, path: &str) -> Subtree {
+    relative_paths(orders, path)
+        .into_iter()
+        .map(|relative| {
+            let order = orders
+                .remove(&format!("{}{}", path, relative))
+                .unwrap_or_default();
+            (relative, order)
+        })
+        .collect()
+}
+
+// Moves the orders below elements `from` and after of the array at `array`
+// one index up, or down.
+fn shift(orders: &mut Orders, array: &str, from: usize, up: bool) {
+    let prefix = format!("{}/", array);
+    let element = |path: &str| {
+        let rest = path.strip_prefix(&prefix)?;
+        let end = rest.find('/').unwrap_or(rest.len());
+        let index = rest[..end].parse::<usize>().ok()?;
+        Some((index, rest[end..].to_string()))
+    };
+    let paths: Vec<String> = orders
+        .keys()
+        .filter(|path| element(path).is_some_and(|(index, _)| index >= from))
+        .cloned()
+        .collect();
+    // All are removed before any is inserted, as the new paths are old ones.
+    let shifted: Vec<(String, Vec<String>)> = paths
+        .into_iter()
+        .map(|path| {
+            let order = orders.remove(&path).unwrap_or_default();
+            (path, order)
+        })
+        .collect();
+    for (path, order) in shifted {
+        let Some((index, rest)) = element(&path) else {
+            continue;
+        };
+        let index = if up { index + 1 } else { index - 1 };
+        orders.insert(format!("{}{}{}", prefix, index, rest), order);
+    }
+}
+
+// The parent of `path` and its last token.
+fn split(path: &str) -> Option<(String, String)> {
+    let mut tokens = pointer::tokens(path);
+    let key = tokens.pop()?;
+    let parent = tokens
+        .iter()
+        .fold(String::new(), |parent, token| pointer::push(&parent, token));
+    Some((parent, key))
+}
+
+// The orders of the objects of `value` at paths matching `patterns`: their
+// keys in `orders`, then the keys `orders` lacks.
+fn collect_patched(
+    value: &Value,
+    path: &str,
+    orders: &Orders,
+    patterns: &[PathPattern],
+    matching: &mut Orders,
+) {
+    match value {
+        Value::Object(map) => {
+            if patterns.iter().any(|pattern| pattern.matches(path)) {
+                let known = orders.get(path).map(Vec::as_slice).unwrap_or_default();
+                let mut order: Vec<String> = known
+                    .iter()
+                    .filter(|key| map.contains_key(*key))
+                    .cloned()
+                    .collect();
+                order.extend(map.keys().filter(|key| !known.contains(key)).cloned());
+                matching.insert(path.to_string(), order);
+            }
+            for (key, child) in map {
+                collect_patched(child, &pointer::push(path, key), orders, patterns, matching);
+            }
+        }
+        Value::Array(items) => {
+            for (index, child) in items.iter().enumerate() {
+                let child_path = pointer::push(path, &index.to_string());
+                collect_patched(child, &child_path, orders, patterns, matching);
+            }
+        }
+        _ => {}
+    }
+}
+
+struct Collector<'a> {
+    path: String,
+    patterns: &'a [PathPattern],
+    orders: &'a mut HashMap<String, Vec<String>>,
+}
+
+impl<'de> DeserializeSeed<'de> for Collector<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Collector<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a JSON value")
+    }
+
+    fn visit_bool<E>(self, _: bool) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_i64<E>(self, _: i64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_u64<E>(self, _: u64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_f64<E>(self, _: f64) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_str<E>(self, _: &str) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_unit<E>(self) -> Result<(), E> {
+        Ok(())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let mut index = 0;
+        while seq
+            .next_element_seed(Collector {
+                path: pointer::push(&self.path, &index.to_string()),
+                patterns: self.patterns,
+                orders: &mut *self.orders,
+            })?
+            .is_some()
+        {
+            index += 1;
+        }
+        Ok(())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut keys = Vec::new();
+        while let Some(key) = map.next_key::<String>()? {
+            if key == NUMBER_TOKEN {
+                map.next_value::<IgnoredAny>()?;
+                return Ok(());
+            }
+            map.next_value_seed(Collector {
+                path: pointer::push(&self.path, &key),
+                patterns: self.patterns,
+                orders: &mut *self.orders,
+            })?;
+            keys.push(key);
+        }
+
+        if self
+            .patterns
+            .iter()
+            .any(|pattern| pattern.matches(&self.path))
+        {
+            self.orders.insert(self.path, keys);
+        }
+        Ok(())
+    }
+}
+
+/// Replaces each object found at a path in base's `orders` with a string
+/// serializing it in source order, in all three documents, when branch A or B
+/// reordered the keys it shares with base. `documents` and `orders` are given
+/// as base, A, B. Returns the paths that were replaced, for `unmark`.
+///
+/// Objects at matching paths nested in each other are not supported: only the
+/// outer one keeps its order.
+pub fn mark(
+    mut documents: [&mut Value; 3],
+    orders: &[HashMap<String, Vec<String>>; 3],
+) -> HashSet<String> {
+    let [base_orders, a_orders, b_orders] = orders;
+    let mut paths: Vec<&String> = base_orders.keys().collect();
+    paths.sort();
+
+    let mut marked = HashSet::new();
+    for path in paths {
+        let base_order = &base_orders[path];
+        let reordered = [a_orders, b_orders].iter().any(|branch_orders| {
+            branch_orders
+                .get(path)
+                .is_some_and(|branch_order| reordered(base_order, branch_order))
+        });
+        if !reordered {
+            continue;
+        }
+
+        for (document, document_orders) in documents.iter_mut().zip(orders) {
+            if let (Some(value), Some(order)) =
+                (document.pointer_mut(path), document_orders.get(path))
+            {
+                if let Value::Object(map) = value {
+                    *value = Value::String(canonical(map, order));
+                }
+            }
+        }
+        marked.insert(path.clone());
+    }
+    marked
+}
+
+/// Parses the strings `mark` left at `marked` paths back into objects.
+pub fn unmark(value: &mut Value, marked: &HashSet<String>) {
+    for path in marked {
+        if let Some(target) = value.pointer_mut(path) {
+            if let Value::String(text) = target {
+                if let Ok(parsed @ Value::Object(_)) = serde_json::from_str::<Value>(text) {
+                    *target = parsed;
+                }
+            }
+        }
+    }
+}
+
+// Whether the keys both orders have appear in a different relative order.
+fn reordered(base: &[String], branch: &[String]) -> bool {
+    let shared_base = base.iter().filter(|key| branch.contains(key));
+    let shared_branch = branch.iter().filter(|key| base.contains(key));
+    !shared_base.eq(shared_branch)
+}
+
+fn canonical(map: &serde_json::Map<String, Value>, order: &[String]) -> String {
+    let entries: Vec<String> = order
+        .iter()
+        .filter_map(|key| {
+            map.get(key)
+                .map(|value| format!("{}:{}", Value::String(key.clone()), value))
+        })
+        .collect();
+    format!("{{{}}}", entries.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{three_way_merge_with_options, ConflictStrategy, MergeOptions};
+    use serde_json::json;
+
+    fn patterns(specs: &[&str]) -> Vec<PathPattern> {
+        specs
+            .iter()
+            .map(|s| PathPattern::parse(s).unwrap())
+            .collect()
+    }
+
+    // Parses and marks the three texts, then merges them.
+    fn merge_texts(texts: [&str; 3], specs: &[&str]) -> (Value, usize) {
+        let patterns = patterns(specs);
+        let [mut base, mut a, mut b] =
+            texts.map(|text| serde_json::from_str::<Value>(text).unwrap());
+        let orders = texts.map(|text| key_orders(text, &patterns).unwrap());
+        let marked = mark([&mut base, &mut a, &mut b], &orders);
+        let (mut merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        unmark(&mut merged, &marked);
+        (merged, conflicts.len())
+    }
+
+    #[test]
+    fn test_key_orders() {
+        let text = r#"{"z": {"b": 1, "a": {"y": 1, "x": 2}}, "list": [{"q": 1, "p": 2}]}"#;
+        let orders = key_orders(text, &patterns(&["/z", "/list/*"])).unwrap();
+        assert_eq!(orders.len(), 2);
+        assert_eq!(orders["/z"], vec!["b", "a"]);
+        assert_eq!(orders["/list/0"], vec!["q", "p"]);
+        assert!(key_orders("{", &patterns(&["/z"])).is_err());
+    }
+
+    #[test]
+    fn test_patched_key_orders() {
+        let text = r#"{"steps": {"b": 1, "a": 2}, "list": [{"q": 1, "p": 2}, {"z": 1, "y": 2}]}"#;
+        let document: Value = serde_json::from_str(text).unwrap();
+        let operations: Vec<Operation> = serde_json::from_value(json!([
+            {"op": "add", "path": "/steps/c", "value": 3},
+            {"op": "move", "from": "/steps/b", "path": "/steps/b"},
+            {"op": "move", "from": "/steps", "path": "/moved"},
+            {"op": "remove", "path": "/list/0"},
+            {"op": "add", "path": "/list/-", "value": {"k": 1, "j": 2}},
+            {"op": "copy", "from": "/list/0", "path": "/list/0"}
+        ]))
+        .unwrap();
+        let orders = patched_key_orders(
+            text,
+            &document,
+            &operations,
+            &patterns(&["/moved", "/steps", "/list/*"]),
+        )
+        .unwrap();
+        assert_eq!(orders.len(), 4);
+        assert_eq!(orders["/moved"], vec!["a", "c", "b"]);
+        assert_eq!(orders["/list/0"], vec!["z", "y"]);
+        assert_eq!(orders["/list/1"], vec!["z", "y"]);
+        assert_eq!(orders["/list/2"], vec!["j", "k"]);
+    }
+
+    #[test]
+    fn test_reordering_conflicts_with_edit() {
+        let texts = [
+            r#"{"steps": {"build": 1, "test": 2}, "other": 1}"#,
+            r#"{"steps": {"test": 2, "build": 1}, "other": 1}"#,
+            r#"{"steps": {"build": 1, "test": 3}, "other": 2}"#,
+        ];
+
+        // Without the mark the reordering is invisible and B's edit merges.
+        let (merged, conflicts) = merge_texts(texts, &[]);
+        assert_eq!(conflicts, 0);
+        assert_eq!(
+            merged,
+            json!({"steps": {"build": 1, "test": 3}, "other": 2})
+        );
+
+        let (merged, conflicts) = merge_texts(texts, &["/steps"]);
+        assert_eq!(conflicts, 1);
+        assert_eq!(
+            merged,
+            json!({"steps": {"build": 1, "test": 2}, "other": 2})
+        );
+    }
+
+    #[test]
+    fn test_reordering_in_one_branch_is_taken() {
+        let texts = [
+            r#"{"steps": {"build": 1, "test": 2}}"#,
+            r#"{"steps": {"test": 2, "build": 1}}"#,
+            r#"{"steps": {"build": 1, "test": 2}}"#,
+        ];
+        let patterns = patterns(&["/steps"]);
+        let [mut base, mut a, mut b] =
+            texts.map(|text| serde_json::from_str::<Value>(text).unwrap());
+        let orders = texts.map(|text| key_orders(text, &patterns).unwrap());
+        let marked = mark([&mut base, &mut a, &mut b], &orders);
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, json!({"steps": r#"{"test":2,"build":1}"#}));
+
+        let mut merged = merged;
+        unmark(&mut merged, &marked);
+        assert_eq!(merged, json!({"steps": {"build": 1, "test": 2}}));
+    }
+
+    #[test]
+    fn test_both_reordering_goes_to_the_strategy() {
+        let texts = [
+            r#"{"priority": {"high": 1, "mid": 2, "low": 3}}"#,
+            r#"{"priority": {"low": 3, "high": 1, "mid": 2}}"#,
+            r#"{"priority": {"mid": 2, "high": 1, "low": 3}}"#,
+        ];
+        let patterns = patterns(&["/priority"]);
+        let [mut base, mut a, mut b] =
+            texts.map(|text| serde_json::from_str::<Value>(text).unwrap());
+        let orders = texts.map(|text| key_orders(text, &patterns).unwrap());
+        mark([&mut base, &mut a, &mut b], &orders);
+
+        let (_, conflicts) = three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path, "/priority");
+        assert!(!conflicts[0].is_resolved());
+
+        let options = MergeOptions {
+            strategy: ConflictStrategy::Theirs,
+            ..MergeOptions::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &options);
+        assert!(conflicts[0].is_resolved());
+        assert_eq!(merged, json!({"priority": r#"{"mid":2,"high":1,"low":3}"#}));
+    }
+
+    #[test]
+    fn test_unreordered_objects_merge_key_by_key() {
+        let texts = [
+            r#"{"steps": {"build": 1, "test": 2}}"#,
+            r#"{"steps": {"build": 5, "test": 2, "lint": 0}}"#,
+            r#"{"steps": {"build": 1, "test": 3}}"#,
+        ];
+        let (merged, conflicts) = merge_texts(texts, &["/steps"]);
+        assert_eq!(conflicts, 0);
+        assert_eq!(merged, json!({"steps": {"build": 5, "test": 3, "lint": 0}}));
+    }
+}
@@ -0,0 +1,214 @@
+//! Merges as OpenTelemetry spans, written in the OTLP/JSON encoding so that a
+//! collector can pick them up from a file, such as with the `otlpjsonfile`
+//! receiver, without the tool linking an exporter. A merge run from a traced
+//! pipeline joins the pipeline's trace through the W3C `traceparent` format.
+
+use serde_json::{json, Value};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A finished span.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub name: String,
+    pub start: SystemTime,
+    pub end: SystemTime,
+    /// Attributes by key. Strings, booleans and integers are kept; other
+    /// values are written as their JSON text.
+    pub attributes: Vec<(String, Value)>,
+    /// Set when the operation failed, with why.
+    pub error: Option<String>,
+}
+
+/// The trace a span belongs to, and the span it is a child of, as lowercase
+/// hex ids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Context {
+    pub trace_id: String,
+    pub parent_span_id: Option<String>,
+}
+
+impl Context {
+    /// Continues the trace of a `traceparent` value such as
+    /// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`, or starts a
+    /// new trace if there is none or it is malformed.
+    pub fn from_traceparent(traceparent: Option<&str>) -> Context {
+        let parsed = traceparent.and_then(|value| {
+            let fields: Vec<&str> = value.trim().split('-').collect();
+            let [version, trace_id, parent_id, _flags] = fields[..] else {
+                return None;
+            };
+            let valid = |id: &str, len: usize| {
+                id.len() == len
+                    && id
+                        .bytes()
+                        .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+                    && id.bytes().any(|b| b != b'0')
+            };
+            (version == "00" && valid(trace_id, 32) && valid(parent_id, 16))
+                .then(|| (trace_id.to_string(), parent_id.to_string()))
+        });
+        match parsed {
+            Some((trace_id, parent_span_id)) => Context {
+                trace_id,
+                parent_span_id: Some(parent_span_id),
+            },
+            None => Context {
+                trace_id: random_id(16),
+                parent_span_id: None,
+            },
+        }
+    }
+}
+
+/// A random id of `bytes` bytes in hex, as trace and span ids are written.
+pub fn random_id(bytes: usize) -> String {
+    let mut id = String::new();
+    while id.len() < bytes * 2 {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u128(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos(),
+        );
+        id.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    id.truncate(bytes * 2);
+    id
+}
+
+/// The OTLP/JSON export request holding `span`, with id `span_id`, as sent by
+/// the service `service_name`.
+pub fn to_otlp(span: &Span, context: &Context, span_id: &str, service_name: &str) -> Value {
+    let attributes: Vec<Value> = span
+        .attributes
+        .iter()
+        .map(|(key, value)| json!({"key": key, "value": attribute_value(value)}))
+        .collect();
+    let status = match &span.error {
+        // STATUS_CODE_ERROR
+        Some(message) => json!({"code": 2, "message": message}),
+        // STATUS_CODE_OK
+        None => json!({"code": 1}),
+    };
+    let mut otlp_span = json!({
+        "traceId": context.trace_id,
+        "spanId": span_id,
+        "name": span.name,
+        // SPAN_KIND_INTERNAL
+        "kind": 1,
+        "startTimeUnixNano": unix_nanos(span.start),
+        "endTimeUnixNano": unix_nanos(span.end),
+        "attributes": attributes,
+        "status": status,
+    });
+    if let Some(parent) = &context.parent_span_id {
+        otlp_span["parentSpanId"] = json!(parent);
+    }
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [{"key": "service.name", "value": {"stringValue": service_name}}]
+            },
+            "scopeSpans": [{
+                "scope": {"name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION")},
+                "spans": [otlp_span]
+            }]
+        }]
+    })
+}
+
+// 64-bit integers are strings in OTLP/JSON, as in the protobuf JSON mapping.
+fn attribute_value(value: &Value) -> Value {
+    match value {
+        Value::String(text) => json!({"stringValue": text}),
+        Value::Bool(flag) => json!({"boolValue": flag}),
+        Value::Number(number) if number.is_i64() || number.is_u64() => {
+            json!({"intValue": number.to_string()})
+        }
+        Value::Number(number) => json!({"doubleValue": number}),
+        _ => json!({"stringValue": value.to_string()}),
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_traceparent() {
+        let context = Context::from_traceparent(Some(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ));
+        assert_eq!(context.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(context.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+
+        for malformed in [
+            None,
+            Some("garbage"),
+            Some("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            Some("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            Some("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"),
+        ] {
+            let context = Context::from_traceparent(malformed);
+            assert_eq!(context.trace_id.len(), 32, "{:?}", malformed);
+            assert_eq!(context.parent_span_id, None);
+        }
+        assert_ne!(random_id(8), random_id(8));
+        assert_eq!(random_id(3).len(), 6);
+    }
+
+    #[test]
+    fn test_to_otlp() {
+        let start = UNIX_EPOCH + Duration::from_millis(1_700_000_000_000);
+        let span = Span {
+            name: "merge".to_string(),
+            start,
+            end: start + Duration::from_micros(1500),
+            attributes: vec![
+                ("merge.conflicts".to_string(), json!(2)),
+                ("merge.partial".to_string(), json!(false)),
+                ("merge.output".to_string(), json!("out.json")),
+            ],
+            error: Some("2 unresolved conflicts".to_string()),
+        };
+        let context = Context {
+            trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+            parent_span_id: Some("00f067aa0ba902b7".to_string()),
+        };
+        let otlp = to_otlp(&span, &context, "b7ad6b7169203331", "ci");
+        let resource = &otlp["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0],
+            json!({"key": "service.name", "value": {"stringValue": "ci"}})
+        );
+        assert_eq!(
+            resource["scopeSpans"][0]["spans"][0],
+            json!({
+                "traceId": "4bf92f3577b34da6a3ce929d0e0e4736",
+                "spanId": "b7ad6b7169203331",
+                "parentSpanId": "00f067aa0ba902b7",
+                "name": "merge",
+                "kind": 1,
+                "startTimeUnixNano": "1700000000000000000",
+                "endTimeUnixNano": "1700000000001500000",
+                "attributes": [
+                    {"key": "merge.conflicts", "value": {"intValue": "2"}},
+                    {"key": "merge.partial", "value": {"boolValue": false}},
+                    {"key": "merge.output", "value": {"stringValue": "out.json"}}
+                ],
+                "status": {"code": 2, "message": "2 unresolved conflicts"}
+            })
+        );
+    }
+}
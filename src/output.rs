@@ -0,0 +1,85 @@
+//! Everything a merge produced, bundled into one value for tools that want the
+//! whole result as a single JSON document.
+//!
+//! Fields are serialized in declaration order, and their names are part of the
+//! format: new fields may be added, but existing ones are not renamed.
+
+use crate::merge::Conflict;
+use crate::report::{self, Stats};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Where each of the three versions was read from, such as a file path.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Provenance {
+    pub base: String,
+    pub a: String,
+    pub b: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MergeOutput {
+    pub merged: Value,
+    /// Every conflict, resolved or not, in document order.
+    pub conflicts: Vec<Conflict>,
+    pub stats: Stats,
+    pub provenance: Provenance,
+    /// The automatic resolutions, see `report::resolutions`.
+    pub resolutions: Value,
+}
+
+impl MergeOutput {
+    /// Bundles a merge's results; `document_depth` is the depth of the deepest
+    /// input, see `report::depth`.
+    pub fn new(
+        merged: Value,
+        conflicts: Vec<Conflict>,
+        document_depth: usize,
+        provenance: Provenance,
+    ) -> MergeOutput {
+        MergeOutput {
+            merged,
+            stats: report::stats(&conflicts, document_depth),
+            resolutions: report::resolutions(&conflicts),
+            conflicts,
+            provenance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{three_way_merge_with_options, ConflictStrategy, MergeOptions};
+    use serde_json::json;
+
+    #[test]
+    fn test_serialized_shape() {
+        let base = json!({"port": 80, "host": "a"});
+        let a = json!({"port": 81, "host": "a"});
+        let b = json!({"port": 82, "host": "b"});
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Ours,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        let provenance = Provenance {
+            base: "base.json".to_string(),
+            a: "a.json".to_string(),
+            b: "b.json".to_string(),
+        };
+        let output = MergeOutput::new(merged, conflicts, 1, provenance);
+
+        assert_eq!(
+            serde_json::to_string(&output).unwrap(),
+            concat!(
+                r#"{"merged":{"host":"b","port":81},"#,
+                r#""conflicts":[{"path":"/port","kind":"both_modified","base":80,"a":81,"b":82,"#,
+                r#""changes":{"a":"modified","b":"modified"},"resolution":"a"}],"#,
+                r#""stats":{"conflicts":1,"unresolved":0,"deepest_conflict":"/port","document_depth":1},"#,
+                r#""provenance":{"base":"base.json","a":"a.json","b":"b.json"},"#,
+                r#""resolutions":[{"kind":"both_modified","path":"/port","side":"a"}]}"#
+            )
+        );
+    }
+}
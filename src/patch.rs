@@ -0,0 +1,415 @@
+//! RFC 6902 JSON patches: computing them between documents and applying them.
+//! Also RFC 7386 merge patches, which show the same changes as a sparse object.
+
+use crate::pointer;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+/// Computes a patch that turns `from` into `to`, using only `add`, `remove` and
+/// `replace`. Objects are compared key by key and arrays index by index, with
+/// trailing elements added or removed.
+pub fn diff(from: &Value, to: &Value) -> Vec<Operation> {
+    let mut operations = Vec::new();
+    diff_at(from, to, "", &mut operations);
+    operations
+}
+
+fn diff_at(from: &Value, to: &Value, path: &str, operations: &mut Vec<Operation>) {
+    if from == to {
+        return;
+    }
+
+    match (from, to) {
+        (Value::Object(from_map), Value::Object(to_map)) => {
+            for (key, from_val) in from_map {
+                let current_path = pointer::push(path, key);
+                match to_map.get(key) {
+                    Some(to_val) => diff_at(from_val, to_val, &current_path, operations),
+                    None => operations.push(Operation::Remove { path: current_path }),
+                }
+            }
+            for (key, to_val) in to_map {
+                if !from_map.contains_key(key) {
+                    operations.push(Operation::Add {
+                        path: pointer::push(path, key),
+                        value: to_val.clone(),
+                    });
+                }
+            }
+        }
+        (Value::Array(from_arr), Value::Array(to_arr)) => {
+            let common = from_arr.len().min(to_arr.len());
+            for index in 0..common {
+                let current_path = pointer::push(path, &index.to_string());
+                diff_at(&from_arr[index], &to_arr[index], &current_path, operations);
+            }
+            for (index, to_val) in to_arr.iter().enumerate().skip(common) {
+                operations.push(Operation::Add {
+                    path: pointer::push(path, &index.to_string()),
+                    value: to_val.clone(),
+                });
+            }
+            for index in (common..from_arr.len()).rev() {
+                operations.push(Operation::Remove {
+                    path: pointer::push(path, &index.to_string()),
+                });
+            }
+        }
+        _ => operations.push(Operation::Replace {
+            path: path.to_string(),
+            value: to.clone(),
+        }),
+    }
+}
+
+/// Computes an RFC 7386 merge patch that turns `from` into `to`: an object
+/// holding only the keys whose values changed, nested for objects changed in
+/// both, with `null` for removed keys. Anything that is not an object in both
+/// is replaced whole, arrays included. Unchanged documents give `{}`.
+///
+/// A merge patch cannot set a value to `null`; such a change comes out as a
+/// removal.
+pub fn merge_patch(from: &Value, to: &Value) -> Value {
+    match (from, to) {
+        (Value::Object(from_map), Value::Object(to_map)) => {
+            let mut patch = Map::new();
+            for (key, from_val) in from_map {
+                match to_map.get(key) {
+                    Some(to_val) if to_val == from_val => {}
+                    Some(to_val) => {
+                        patch.insert(key.clone(), merge_patch(from_val, to_val));
+                    }
+                    None => {
+                        patch.insert(key.clone(), Value::Null);
+                    }
+                }
+            }
+            for (key, to_val) in to_map {
+                if !from_map.contains_key(key) {
+                    patch.insert(key.clone(), to_val.clone());
+                }
+            }
+            Value::Object(patch)
+        }
+        _ if from == to => Value::Object(Map::new()),
+        _ => to.clone(),
+    }
+}
+
+/// Applies `operations` in order to a copy of `document`. Fails on the first
+/// operation that cannot be applied, leaving `document` untouched.
+pub fn apply(document: &Value, operations: &[Operation]) -> Result<Value, String> {
+    let mut result = document.clone();
+    for (index, operation) in operations.iter().enumerate() {
+        apply_operation(&mut result, operation)
+            .map_err(|e| format!("operation {}: {}", index, e))?;
+    }
+    Ok(result)
+}
+
+fn apply_operation(document: &mut Value, operation: &Operation) -> Result<(), String> {
+    match operation {
+        Operation::Add { path, value } => add(document, path, value.clone()),
+        Operation::Remove { path } => remove(document, path).map(|_| ()),
+        Operation::Replace { path, value } => {
+            *lookup_mut(document, path)? = value.clone();
+            Ok(())
+        }
+        Operation::Move { from, path } => {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(format!("cannot move '{}' into itself", from));
+            }
+            let value = remove(document, from)?;
+            add(document, path, value)
+        }
+        Operation::Copy { from, path } => {
+            let value = lookup(document, from)?.clone();
+            add(document, path, value)
+        }
+        Operation::Test { path, value } => {
+            if lookup(document, path)? == value {
+                Ok(())
+            } else {
+                Err(format!("test failed at '{}'", path))
+            }
+        }
+    }
+}
+
+fn lookup<'a>(document: &'a Value, path: &str) -> Result<&'a Value, String> {
+    document
+        .pointer(path)
+        .ok_or_else(|| format!("path '{}' does not exist", path))
+}
+
+fn lookup_mut<'a>(document: &'a mut Value, path: &str) -> Result<&'a mut Value, String> {
+    document
+        .pointer_mut(path)
+        .ok_or_else(|| format!("path '{}' does not exist", path))
+}
+
+// Splits a pointer into its parent pointer and last token.
+fn split_last(path: &str) -> Result<(&str, String), String> {
+    match path.rfind('/') {
+        Some(index) => Ok((&path[..index], pointer::unescape(&path[index + 1..]))),
+        None => Err(format!("path '{}' has no parent", path)),
+    }
+}
+
+fn array_index(token: &str, len: usize, path: &str) -> Result<usize, String> {
+    match token.parse::<usize>() {
+        Ok(index) if index < len && (token == "0" || !token.starts_with('0')) => Ok(index),
+        _ => Err(format!("path '{}' is not a valid array index", path)),
+    }
+}
+
+fn add(document: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *document = value;
+        return Ok(());
+    }
+
+    let (parent_path, token) = split_last(path)?;
+    match lookup_mut(document, parent_path)? {
+        Value::Object(map) => {
+            map.insert(token, value);
+            Ok(())
+        }
+        Value::Array(items) => {
+            let index = if token == "-" {
+                items.len()
+            } else {
+                array_index(&token, items.len() + 1, path)?
+            };
+            items.insert(index, value);
+            Ok(())
+        }
+        _ => Err(format!("parent of '{}' is not a container", path)),
+    }
+}
+
+fn remove(document: &mut Value, path: &str) -> Result<Value, String> {
+    let (parent_path, token) = split_last(path)?;
+    match lookup_mut(document, parent_path)? {
+        Value::Object(map) => map
+            .remove(&token)
+            .ok_or_else(|| format!("path '{}' does not exist", path)),
+        Value::Array(items) => {
+            let index = array_index(&token, items.len(), path)?;
+            Ok(items.remove(index))
+        }
+        _ => Err(format!("parent of '{}' is not a container", path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_diff_objects() {
+        let from = json!({"a": 1, "b": {"c": 2}, "d": 3});
+        let to = json!({"a": 1, "b": {"c": 20}, "e": 4});
+        assert_eq!(
+            diff(&from, &to),
+            vec![
+                Operation::Replace {
+                    path: "/b/c".to_string(),
+                    value: json!(20)
+                },
+                Operation::Remove {
+                    path: "/d".to_string()
+                },
+                Operation::Add {
+                    path: "/e".to_string(),
+                    value: json!(4)
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let from = json!({
+            "name": "app",
+            "server": {"port": 80, "tls": {"enabled": false, "cert": "x"}},
+            "tags": [1, 2],
+            "old": true,
+            "limits": {"cpu": 1}
+        });
+        let to = json!({
+            "name": "app",
+            "server": {"port": 80, "tls": {"enabled": true, "cert": "x"}},
+            "tags": [1, 2, 3],
+            "limits": {"cpu": 1},
+            "new": {"a": 1}
+        });
+        assert_eq!(
+            merge_patch(&from, &to),
+            json!({
+                "server": {"tls": {"enabled": true}},
+                "tags": [1, 2, 3],
+                "old": null,
+                "new": {"a": 1}
+            })
+        );
+        assert_eq!(merge_patch(&from, &from), json!({}));
+        assert_eq!(merge_patch(&json!([1]), &json!({"a": 1})), json!({"a": 1}));
+    }
+
+    #[test]
+    fn test_diff_round_trips() {
+        let cases = [
+            (json!({"a": [1, 2, 3]}), json!({"a": [1, 5]})),
+            (json!({"a": [1]}), json!({"a": [1, 2, 3], "b~/": null})),
+            (json!({"a": {"x": 1}}), json!({"a": "scalar"})),
+            (json!(1), json!({"root": true})),
+        ];
+        for (from, to) in cases {
+            let operations = diff(&from, &to);
+            assert_eq!(apply(&from, &operations).unwrap(), to);
+        }
+    }
+
+    #[test]
+    fn test_operations_serialize_as_rfc6902() {
+        let operations = vec![
+            Operation::Add {
+                path: "/a".to_string(),
+                value: json!(1),
+            },
+            Operation::Move {
+                from: "/a".to_string(),
+                path: "/b".to_string(),
+            },
+        ];
+        let serialized = serde_json::to_value(&operations).unwrap();
+        assert_eq!(
+            serialized,
+            json!([
+                {"op": "add", "path": "/a", "value": 1},
+                {"op": "move", "from": "/a", "path": "/b"}
+            ])
+        );
+        let parsed: Vec<Operation> = serde_json::from_value(serialized).unwrap();
+        assert_eq!(parsed, operations);
+    }
+
+    #[test]
+    fn test_apply_all_operations() {
+        let document = json!({"a": {"b": 1}, "list": [1, 2]});
+        let operations: Vec<Operation> = serde_json::from_value(json!([
+            {"op": "test", "path": "/a/b", "value": 1},
+            {"op": "copy", "from": "/a/b", "path": "/c"},
+            {"op": "move", "from": "/a", "path": "/moved"},
+            {"op": "add", "path": "/list/-", "value": 3},
+            {"op": "add", "path": "/list/0", "value": 0},
+            {"op": "remove", "path": "/list/1"},
+            {"op": "replace", "path": "/c", "value": "x"}
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            apply(&document, &operations).unwrap(),
+            json!({"moved": {"b": 1}, "list": [0, 2, 3], "c": "x"})
+        );
+    }
+
+    #[test]
+    fn test_apply_errors() {
+        let document = json!({"a": 1, "list": [1]});
+        let failing = [
+            json!({"op": "remove", "path": "/missing"}),
+            json!({"op": "replace", "path": "/missing/x", "value": 1}),
+            json!({"op": "add", "path": "/list/5", "value": 1}),
+            json!({"op": "test", "path": "/a", "value": 2}),
+            json!({"op": "add", "path": "/a/b", "value": 1}),
+        ];
+        for operation in failing {
+            let operation: Operation = serde_json::from_value(operation).unwrap();
+            let err = apply(&document, &[operation]).unwrap_err();
+            assert!(err.starts_with("operation 0: "), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_undo_patch_recovers_branch_a() {
+        let base = json!({"file1.txt": "id1", "file2.txt": "id2", "dir1": {"x": [1, 2]}});
+        let a = json!({"file1.txt": "id1-a", "file2.txt": "id2", "dir1": {"x": [1, 2]}});
+        let b = json!({"file1.txt": "id1", "dir1": {"x": [1, 2, 3], "y": true}});
+
+        let (merged, _) = crate::merge::three_way_merge(&base, &a, &b);
+        let undo = diff(&merged, &a);
+        assert!(!undo.is_empty());
+        assert_eq!(apply(&merged, &undo).unwrap(), a);
+    }
+
+    #[test]
+    fn test_branch_diffs_from_base() {
+        let base = json!({"file1.txt": "id1", "file2.txt": "id2", "list": [1]});
+        let a = json!({"file1.txt": "id1-a", "file2.txt": "id2", "list": [1]});
+        let b = json!({"file1.txt": "id1", "list": [1, 2], "new.txt": "id3"});
+
+        let diff_a = diff(&base, &a);
+        let diff_b = diff(&base, &b);
+        assert_eq!(
+            diff_a,
+            vec![Operation::Replace {
+                path: "/file1.txt".to_string(),
+                value: json!("id1-a")
+            }]
+        );
+        assert_eq!(
+            diff_b,
+            vec![
+                Operation::Remove {
+                    path: "/file2.txt".to_string()
+                },
+                Operation::Add {
+                    path: "/list/1".to_string(),
+                    value: json!(2)
+                },
+                Operation::Add {
+                    path: "/new.txt".to_string(),
+                    value: json!("id3")
+                },
+            ]
+        );
+        assert_eq!(apply(&base, &diff_a).unwrap(), a);
+        assert_eq!(apply(&base, &diff_b).unwrap(), b);
+    }
+
+    #[test]
+    fn test_migration_patch_on_merged_document() {
+        let base = json!({"name": "svc", "port": 80});
+        let a = json!({"name": "svc", "port": 81});
+        let b = json!({"name": "svc", "port": 80, "debug": true});
+        let (merged, had_conflicts) = crate::merge::three_way_merge(&base, &a, &b);
+        assert!(!had_conflicts);
+
+        let migration: Vec<Operation> = serde_json::from_str(
+            r#"[
+                {"op": "add", "path": "/server", "value": {}},
+                {"op": "move", "from": "/port", "path": "/server/port"},
+                {"op": "add", "path": "/version", "value": 2}
+            ]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            apply(&merged, &migration).unwrap(),
+            json!({"name": "svc", "debug": true, "server": {"port": 81}, "version": 2})
+        );
+    }
+}
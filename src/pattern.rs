@@ -0,0 +1,150 @@
+use crate::pointer;
+
+/// A glob over JSON pointers. Each segment is matched against one pointer token,
+/// where `*` matches any run of characters and `?` a single character. A segment
+/// consisting of `**` matches any number of tokens, including none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathPattern {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Glob(String),
+    AnyDepth,
+}
+
+impl PathPattern {
+    pub fn parse(pattern: &str) -> Result<PathPattern, String> {
+        if !pattern.is_empty() && !pattern.starts_with('/') {
+            return Err(format!(
+                "pattern '{}' must be a JSON pointer starting with '/'",
+                pattern
+            ));
+        }
+
+        let segments = pointer::tokens(pattern)
+            .into_iter()
+            .map(|token| {
+                if token == "**" {
+                    Segment::AnyDepth
+                } else {
+                    Segment::Glob(token)
+                }
+            })
+            .collect();
+
+        Ok(PathPattern { segments })
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        match_segments(&self.segments, &pointer::tokens(path))
+    }
+}
+
+fn match_segments(segments: &[Segment], tokens: &[String]) -> bool {
+    match segments.split_first() {
+        None => tokens.is_empty(),
+        Some((Segment::AnyDepth, rest)) => {
+            (0..=tokens.len()).any(|skip| match_segments(rest, &tokens[skip..]))
+        }
+        Some((Segment::Glob(glob), rest)) => match tokens.split_first() {
+            Some((token, remaining)) => glob_match(glob, token) && match_segments(rest, remaining),
+            None => false,
+        },
+    }
+}
+
+fn glob_match(glob: &str, text: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut g, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if g < glob.len() && (glob[g] == '?' || glob[g] == text[t]) {
+            g += 1;
+            t += 1;
+        } else if g < glob.len() && glob[g] == '*' {
+            backtrack = Some((g, t));
+            g += 1;
+        } else if let Some((star, matched)) = backtrack {
+            g = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// Parses a pattern file: one pattern per line, blank lines and lines starting
+/// with `#` are skipped.
+pub fn parse_pattern_list(contents: &str) -> Result<Vec<PathPattern>, String> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line_number, line)| {
+            PathPattern::parse(line).map_err(|e| format!("line {}: {}", line_number, e))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        PathPattern::parse(pattern).unwrap().matches(path)
+    }
+
+    #[test]
+    fn test_literal_pattern() {
+        assert!(matches("/dir1/file1.txt", "/dir1/file1.txt"));
+        assert!(!matches("/dir1/file1.txt", "/dir1/file2.txt"));
+        assert!(!matches("/dir1", "/dir1/file1.txt"));
+    }
+
+    #[test]
+    fn test_wildcards_within_segment() {
+        assert!(matches("/dir1/*.txt", "/dir1/file1.txt"));
+        assert!(matches("/dir1/file?.txt", "/dir1/file2.txt"));
+        assert!(!matches("/dir1/*.txt", "/dir1/file1.json"));
+        assert!(!matches("/*", "/dir1/file1.txt"));
+    }
+
+    #[test]
+    fn test_any_depth_segment() {
+        assert!(matches("/**/secret", "/secret"));
+        assert!(matches("/**/secret", "/a/b/c/secret"));
+        assert!(matches("/dir1/**", "/dir1"));
+        assert!(matches("/dir1/**", "/dir1/a/b"));
+        assert!(!matches("/**/secret", "/a/secret/b"));
+    }
+
+    #[test]
+    fn test_escaped_tokens() {
+        assert!(matches("/a~1b", "/a~1b"));
+        assert!(!matches("/a~1b", "/a/b"));
+    }
+
+    #[test]
+    fn test_parse_rejects_relative_pattern() {
+        assert!(PathPattern::parse("dir1").is_err());
+    }
+
+    #[test]
+    fn test_parse_pattern_list() {
+        let contents = "# generated files\n/build/**\n\n  /cache  \n";
+        let patterns = parse_pattern_list(contents).unwrap();
+        assert_eq!(patterns.len(), 2);
+        assert!(patterns[1].matches("/cache"));
+
+        let err = parse_pattern_list("/ok\nbad").unwrap_err();
+        assert!(err.starts_with("line 2:"));
+    }
+}
@@ -0,0 +1,57 @@
+//! Helpers for building and splitting RFC 6901 JSON pointers.
+
+pub fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+pub fn unescape(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+pub fn push(path: &str, token: &str) -> String {
+    format!("{}/{}", path, escape(token))
+}
+
+pub fn tokens(pointer: &str) -> Vec<String> {
+    if pointer.is_empty() {
+        return Vec::new();
+    }
+    pointer[1..].split('/').map(unescape).collect()
+}
+
+/// A key sorting pointers in document order, with array indices compared as
+/// numbers, so `/list/2` comes before `/list/10`.
+pub fn order_key(pointer: &str) -> Vec<Result<usize, String>> {
+    tokens(pointer)
+        .into_iter()
+        .map(|token| token.parse().map_err(|_| token))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_escapes_special_characters() {
+        assert_eq!(push("", "dir1"), "/dir1");
+        assert_eq!(push("/dir1", "a/b~c"), "/dir1/a~1b~0c");
+    }
+
+    #[test]
+    fn test_tokens_round_trip() {
+        assert!(tokens("").is_empty());
+        assert_eq!(tokens("/dir1/a~1b~0c"), vec!["dir1", "a/b~c"]);
+        assert_eq!(tokens("/"), vec![""]);
+    }
+
+    #[test]
+    fn test_order_key() {
+        let mut paths = vec!["/list/10", "/b", "/list/2", "/a/x", "", "/list/x"];
+        paths.sort_by_key(|path| order_key(path));
+        assert_eq!(
+            paths,
+            vec!["", "/a/x", "/b", "/list/2", "/list/10", "/list/x"]
+        );
+    }
+}
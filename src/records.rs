@@ -0,0 +1,124 @@
+//! Records identified by an id field: rename detection for top-level maps of id
+//! to record, and the ids of records held in arrays.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Finds keys that `branch` renamed: a key of `base` missing from `branch`,
+/// paired with the one key `branch` added whose record has the same value for
+/// `record_key`. Returns a map from old key to new key. Records without the
+/// field, or whose value matches several added keys, are not renames.
+pub fn detect_renames(
+    base: &Map<String, Value>,
+    branch: &Map<String, Value>,
+    record_key: &str,
+) -> HashMap<String, String> {
+    let added: Vec<&String> = branch.keys().filter(|k| !base.contains_key(*k)).collect();
+
+    let mut renames = HashMap::new();
+    for (old_key, record) in base {
+        if branch.contains_key(old_key) {
+            continue;
+        }
+        let Some(id) = record.get(record_key) else {
+            continue;
+        };
+        let candidates: Vec<&&String> = added
+            .iter()
+            .filter(|k| branch[k.as_str()].get(record_key) == Some(id))
+            .collect();
+        if let [new_key] = candidates.as_slice() {
+            renames.insert(old_key.clone(), (**new_key).clone());
+        }
+    }
+
+    // Two old keys claiming the same new key is not a rename either.
+    let mut claims: HashMap<&String, usize> = HashMap::new();
+    for new_key in renames.values() {
+        *claims.entry(new_key).or_default() += 1;
+    }
+    let ambiguous: Vec<String> = claims
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(k, _)| k.clone())
+        .collect();
+    renames.retain(|_, new_key| !ambiguous.contains(new_key));
+    renames
+}
+
+/// The value of `key` in each element of `items`, in order. Returns `None`
+/// unless every element is an object with the field and no two share a value,
+/// as the elements could not be told apart otherwise.
+pub fn element_ids<'a>(items: &'a [Value], key: &str) -> Option<Vec<&'a Value>> {
+    let mut ids: Vec<&Value> = Vec::with_capacity(items.len());
+    for item in items {
+        let id = item.get(key).filter(|_| item.is_object())?;
+        if ids.contains(&id) {
+            return None;
+        }
+        ids.push(id);
+    }
+    Some(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn map(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn test_detect_rename() {
+        let base = map(json!({
+            "alice": {"uid": 1, "role": "admin"},
+            "bob": {"uid": 2, "role": "dev"}
+        }));
+        let branch = map(json!({
+            "alicia": {"uid": 1, "role": "admin"},
+            "bob": {"uid": 2, "role": "dev"}
+        }));
+        let renames = detect_renames(&base, &branch, "uid");
+        assert_eq!(renames.len(), 1);
+        assert_eq!(renames["alice"], "alicia");
+    }
+
+    #[test]
+    fn test_delete_and_unrelated_add_is_not_rename() {
+        let base = map(json!({"alice": {"uid": 1}}));
+        let branch = map(json!({"carol": {"uid": 3}}));
+        assert!(detect_renames(&base, &branch, "uid").is_empty());
+    }
+
+    #[test]
+    fn test_ambiguous_renames_are_ignored() {
+        let base = map(json!({"alice": {"uid": 1}}));
+        let branch = map(json!({"alicia": {"uid": 1}, "ali": {"uid": 1}}));
+        assert!(detect_renames(&base, &branch, "uid").is_empty());
+
+        let base = map(json!({"alice": {"uid": 1}, "alice2": {"uid": 1}}));
+        let branch = map(json!({"alicia": {"uid": 1}}));
+        assert!(detect_renames(&base, &branch, "uid").is_empty());
+    }
+
+    #[test]
+    fn test_element_ids() {
+        let items = [json!({"id": 1, "v": "a"}), json!({"id": "x"})];
+        assert_eq!(
+            element_ids(&items, "id"),
+            Some(vec![&json!(1), &json!("x")])
+        );
+        assert_eq!(element_ids(&[], "id"), Some(vec![]));
+        assert_eq!(
+            element_ids(&[json!({"id": 1}), json!({"id": 1})], "id"),
+            None
+        );
+        assert_eq!(
+            element_ids(&[json!({"id": 1}), json!({"name": 2})], "id"),
+            None
+        );
+        assert_eq!(element_ids(&[json!([1])], "0"), None);
+    }
+}
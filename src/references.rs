@@ -0,0 +1,112 @@
+//! Referential integrity: checking that every id a document refers to is still
+//! defined somewhere in it, since merging one branch's deletion of a record
+//! with the other branch's new reference to it gives no conflict.
+
+use crate::pointer;
+use serde_json::Value;
+
+/// The references in `document` that no object defines: an object defines the
+/// value of its `id_field`, and every value of a `ref_fields` field, or each
+/// element if it is an array, refers to one. Returned as the path of each
+/// dangling reference and the id it names, in document order.
+pub fn dangling(document: &Value, id_field: &str, ref_fields: &[String]) -> Vec<(String, Value)> {
+    let mut ids = Vec::new();
+    let mut references = Vec::new();
+    collect(
+        document,
+        "",
+        id_field,
+        ref_fields,
+        &mut ids,
+        &mut references,
+    );
+    references.retain(|(_, reference)| !ids.contains(&reference));
+    references
+}
+
+fn collect<'a>(
+    value: &'a Value,
+    path: &str,
+    id_field: &str,
+    ref_fields: &[String],
+    ids: &mut Vec<&'a Value>,
+    references: &mut Vec<(String, Value)>,
+) {
+    match value {
+        Value::Object(map) => {
+            for (key, child) in map {
+                let child_path = pointer::push(path, key);
+                if key == id_field && !(child.is_object() || child.is_array()) {
+                    ids.push(child);
+                }
+                if ref_fields.contains(key) {
+                    match child {
+                        Value::Array(items) => {
+                            for (index, item) in items.iter().enumerate() {
+                                if !(item.is_null() || item.is_object() || item.is_array()) {
+                                    let item_path = pointer::push(&child_path, &index.to_string());
+                                    references.push((item_path, item.clone()));
+                                }
+                            }
+                        }
+                        Value::Object(_) | Value::Null => {}
+                        _ => references.push((child_path.clone(), child.clone())),
+                    }
+                }
+                collect(child, &child_path, id_field, ref_fields, ids, references);
+            }
+        }
+        Value::Array(items) => {
+            for (index, child) in items.iter().enumerate() {
+                let child_path = pointer::push(path, &index.to_string());
+                collect(child, &child_path, id_field, ref_fields, ids, references);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::three_way_merge;
+    use serde_json::json;
+
+    #[test]
+    fn test_dangling_references() {
+        let document = json!({
+            "users": [{"id": 1}, {"id": "bob"}],
+            "orders": [
+                {"id": 10, "user": 1},
+                {"id": 11, "user": 2, "watchers": ["bob", "eve", null]},
+                {"id": 12, "user": null}
+            ]
+        });
+        let refs = ["user".to_string(), "watchers".to_string()];
+        assert_eq!(
+            dangling(&document, "id", &refs),
+            vec![
+                ("/orders/1/user".to_string(), json!(2)),
+                ("/orders/1/watchers/1".to_string(), json!("eve")),
+            ]
+        );
+        assert!(dangling(&document, "id", &[]).is_empty());
+    }
+
+    #[test]
+    fn test_merge_can_introduce_dangling_references() {
+        let base = json!({"users": {"a": {"id": "a"}, "b": {"id": "b"}}, "owner": "a"});
+        let a = json!({"users": {"a": {"id": "a"}}, "owner": "a"});
+        let b = json!({"users": {"a": {"id": "a"}, "b": {"id": "b"}}, "owner": "b"});
+        let refs = ["owner".to_string()];
+        for branch in [&base, &a, &b] {
+            assert!(dangling(branch, "id", &refs).is_empty());
+        }
+        let (merged, had_conflicts) = three_way_merge(&base, &a, &b);
+        assert!(!had_conflicts);
+        assert_eq!(
+            dangling(&merged, "id", &refs),
+            vec![("/owner".to_string(), json!("b"))]
+        );
+    }
+}
@@ -0,0 +1,750 @@
+//! Human-readable conflict reports.
+
+use crate::merge::Conflict;
+use crate::patch::{self, Operation};
+use crate::pointer;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+
+/// The first token of a conflict path, used to route conflicts to the owners of
+/// a top-level section. Conflicts at the document root belong to `(root)`.
+pub fn section(path: &str) -> String {
+    pointer::tokens(path)
+        .into_iter()
+        .next()
+        .unwrap_or_else(|| "(root)".to_string())
+}
+
+pub fn render(conflicts: &[Conflict], group_by_section: bool) -> String {
+    let unresolved = conflicts.iter().filter(|c| !c.is_resolved()).count();
+    let mut report = format!(
+        "{} conflicts ({} unresolved)\n",
+        conflicts.len(),
+        unresolved
+    );
+
+    if group_by_section {
+        let mut sections: BTreeMap<String, Vec<&Conflict>> = BTreeMap::new();
+        for conflict in conflicts {
+            sections
+                .entry(section(&conflict.path))
+                .or_default()
+                .push(conflict);
+        }
+        for (name, section_conflicts) in sections {
+            report.push_str(&format!("\n{} ({})\n", name, section_conflicts.len()));
+            for conflict in section_conflicts {
+                report.push_str(&format!("  {}\n", render_line(conflict)));
+            }
+        }
+    } else {
+        for conflict in conflicts {
+            report.push_str(&format!("{}\n", render_line(conflict)));
+        }
+    }
+
+    report
+}
+
+/// One report line: `<path>: <description> (<resolution>)`, with the root shown
+/// as `/`.
+pub fn render_line(conflict: &Conflict) -> String {
+    let path = if conflict.path.is_empty() {
+        "/"
+    } else {
+        &conflict.path
+    };
+    let status = match conflict.resolution {
+        Some(side) => format!("taking {}", side),
+        None => "unresolved".to_string(),
+    };
+    format!("{}: {} ({})", path, conflict.kind.description(), status)
+}
+
+/// A JUnit XML report with a test case per conflict, named by its path and
+/// classed by its section: unresolved conflicts are failures whose type is the
+/// kind's `name` and whose message is its description, and resolved ones pass.
+pub fn render_junit(conflicts: &[Conflict]) -> String {
+    let unresolved = conflicts.iter().filter(|c| !c.is_resolved()).count();
+    let mut report = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuite name=\"three-way-merge\" tests=\"{}\" failures=\"{}\">\n",
+        conflicts.len(),
+        unresolved
+    );
+    for conflict in conflicts {
+        let path = if conflict.path.is_empty() {
+            "/"
+        } else {
+            &conflict.path
+        };
+        let testcase = format!(
+            "  <testcase classname=\"{}\" name=\"{}\"",
+            xml_escape(&section(&conflict.path)),
+            xml_escape(path)
+        );
+        if conflict.is_resolved() {
+            report.push_str(&format!("{}/>\n", testcase));
+        } else {
+            report.push_str(&format!(
+                "{}>\n    <failure type=\"{}\" message=\"{}\"/>\n  </testcase>\n",
+                testcase,
+                conflict.kind.name(),
+                conflict.kind.description()
+            ));
+        }
+    }
+    report.push_str("</testsuite>\n");
+    report
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The conflicts that were resolved automatically, as a JSON array of
+/// `{"path", "kind", "side"}` objects in document order, for `--resolutions-out`.
+/// `kind` and `side` are the `name` of the conflict kind and the chosen version.
+pub fn resolutions(conflicts: &[Conflict]) -> Value {
+    conflicts
+        .iter()
+        .filter_map(|conflict| {
+            conflict.resolution.map(|side| {
+                json!({
+                    "path": conflict.path,
+                    "kind": conflict.kind.name(),
+                    "side": side.name(),
+                })
+            })
+        })
+        .collect()
+}
+
+/// What the strategy did with each conflict, a line per conflict, for
+/// `--dry-run`: the value the merged document took there, taking it to be
+/// removed where it has none, or that it was left unresolved.
+pub fn render_dry_run(merged: &Value, conflicts: &[Conflict]) -> String {
+    let mut out = String::new();
+    for conflict in conflicts {
+        let path = if conflict.path.is_empty() {
+            "/"
+        } else {
+            &conflict.path
+        };
+        let outcome = match (conflict.resolution, merged.pointer(&conflict.path)) {
+            (Some(side), Some(value)) => format!("would take {}: {}", side, value),
+            (Some(side), None) => format!("would take {}, removing it", side),
+            (None, _) => "left unresolved".to_string(),
+        };
+        out.push_str(&format!(
+            "{}: {}; {}\n",
+            path,
+            conflict.kind.description(),
+            outcome
+        ));
+    }
+    out
+}
+
+/// A sparse document holding, at the path of each conflict, the value the
+/// merged document has there, for `--conflicts-resolved-out`. Paths are
+/// rebuilt with objects, so an array index becomes a key. Conflicts whose
+/// value the merge removed are left out, and a conflict at the root gives the
+/// whole document.
+pub fn conflicting_subtrees(merged: &Value, conflicts: &[Conflict]) -> Value {
+    let mut sparse = Value::Object(Map::new());
+    for conflict in conflicts {
+        let Some(value) = merged.pointer(&conflict.path) else {
+            continue;
+        };
+        let tokens = pointer::tokens(&conflict.path);
+        let Some((last, parents)) = tokens.split_last() else {
+            return value.clone();
+        };
+        let mut node = &mut sparse;
+        for token in parents {
+            if !node.is_object() {
+                *node = Value::Object(Map::new());
+            }
+            node = node
+                .as_object_mut()
+                .unwrap()
+                .entry(token.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+        }
+        if let Value::Object(map) = node {
+            map.insert(last.clone(), value.clone());
+        }
+    }
+    sparse
+}
+
+/// The status object printed by `--status-json`: whether the merge left no
+/// unresolved conflict, how many it left, and the file the output was written
+/// to, or `null` if it was not written.
+pub fn status(conflicts: &[Conflict], output: Option<&str>) -> Value {
+    let unresolved = conflicts.iter().filter(|c| !c.is_resolved()).count();
+    json!({"clean": unresolved == 0, "conflicts": unresolved, "output": output})
+}
+
+/// The number of pointer tokens in the path of the deepest value inside `value`,
+/// so 0 for a scalar and 1 for an object of scalars.
+pub fn depth(value: &Value) -> usize {
+    let children: Box<dyn Iterator<Item = &Value>> = match value {
+        Value::Object(map) => Box::new(map.values()),
+        Value::Array(items) => Box::new(items.iter()),
+        _ => return 0,
+    };
+    children.map(|child| 1 + depth(child)).max().unwrap_or(0)
+}
+
+/// A summary of the merge: conflict counts, the deepest conflict path (the
+/// first one in document order if several are equally deep) and the depth of
+/// the deepest input document.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub conflicts: usize,
+    pub unresolved: usize,
+    pub deepest_conflict: Option<String>,
+    pub document_depth: usize,
+}
+
+pub fn stats(conflicts: &[Conflict], document_depth: usize) -> Stats {
+    let mut deepest: Option<(&Conflict, usize)> = None;
+    for conflict in conflicts {
+        let conflict_depth = pointer::tokens(&conflict.path).len();
+        if deepest.is_none_or(|(_, max)| conflict_depth > max) {
+            deepest = Some((conflict, conflict_depth));
+        }
+    }
+    Stats {
+        conflicts: conflicts.len(),
+        unresolved: conflicts.iter().filter(|c| !c.is_resolved()).count(),
+        deepest_conflict: deepest.map(|(conflict, _)| conflict.path.clone()),
+        document_depth,
+    }
+}
+
+/// The `stats` for `--stats`, one per line.
+pub fn render_stats(conflicts: &[Conflict], document_depth: usize) -> String {
+    let stats = stats(conflicts, document_depth);
+    let deepest = match stats.deepest_conflict.as_deref() {
+        Some(path) => {
+            let conflict_depth = pointer::tokens(path).len();
+            format!(
+                "{} (depth {})",
+                if path.is_empty() { "/" } else { path },
+                conflict_depth
+            )
+        }
+        None => "none".to_string(),
+    };
+    format!(
+        "conflicts: {} ({} unresolved)\ndeepest conflict: {}\ndocument depth: {}\n",
+        stats.conflicts, stats.unresolved, deepest, stats.document_depth
+    )
+}
+
+/// What the merge did, in sentences for a pull request description: each
+/// change from base to `merged`, credited to the branch or branches whose
+/// value it is, then each conflict and how it ended, then the totals. Changes
+/// are those of `patch::diff`, so an array element is written by index.
+pub fn changelog(
+    base: &Value,
+    a: &Value,
+    b: &Value,
+    merged: &Value,
+    conflicts: &[Conflict],
+) -> String {
+    let mut lines = Vec::new();
+    let operations = patch::diff(base, merged);
+    for operation in &operations {
+        let (verb, path) = match operation {
+            Operation::Add { path, .. } => ("Added", path),
+            Operation::Remove { path } => ("Removed", path),
+            Operation::Replace { path, .. } => ("Changed", path),
+            _ => continue,
+        };
+        // The document the path was in, to say whether it names a key.
+        let holder = if verb == "Removed" { base } else { merged };
+        let parent = pointer::tokens(path);
+        let parent = parent[..parent.len().saturating_sub(1)]
+            .iter()
+            .fold(String::new(), |parent, token| pointer::push(&parent, token));
+        let what = match holder.pointer(&parent) {
+            Some(Value::Array(_)) => "element",
+            _ if path.is_empty() => "the whole document",
+            _ => "key",
+        };
+        let target = merged.pointer(path);
+        let by = match (a.pointer(path) == target, b.pointer(path) == target) {
+            (true, true) => "by both branches",
+            (true, false) => "from branch A",
+            (false, true) => "from branch B",
+            (false, false) => "by the merge",
+        };
+        lines.push(if path.is_empty() {
+            format!("{} {} {}", verb, what, by)
+        } else {
+            format!("{} {} {} {}", verb, what, path, by)
+        });
+    }
+    for conflict in conflicts {
+        let location = if conflict.path.is_empty() {
+            "/"
+        } else {
+            &conflict.path
+        };
+        lines.push(match conflict.resolution {
+            Some(side) => format!("Resolved conflict at {} in favor of {}", location, side),
+            None => format!(
+                "Left conflict at {} unresolved: {}",
+                location,
+                conflict.kind.description()
+            ),
+        });
+    }
+    let unresolved = conflicts.iter().filter(|c| !c.is_resolved()).count();
+    lines.push(format!(
+        "{} changes, {} conflicts ({} unresolved)",
+        operations.len(),
+        conflicts.len(),
+        unresolved
+    ));
+    lines.iter().map(|line| format!("{}.\n", line)).collect()
+}
+
+const HTML_STYLE: &str = "\
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }
+td.changed { background: #e6f4ea; }
+td.absent { color: #888; font-style: italic; }
+tr.conflict td { background: #fde2e1; }
+tr.resolved td { background: #fff4ce; }
+code { white-space: pre-wrap; }
+";
+
+/// A self-contained HTML page, for `--html-out`, with a table row per value
+/// that either branch or the merge changed and per conflict, in document
+/// order, showing it in base, both branches and the merged document. Values
+/// that differ from base are highlighted, as are rows of unresolved conflicts
+/// and, more softly, of resolved ones.
+pub fn render_html(
+    base: &Value,
+    a: &Value,
+    b: &Value,
+    merged: &Value,
+    conflicts: &[Conflict],
+) -> String {
+    let mut paths: Vec<&str> = Vec::new();
+    let operations = [a, b, merged].map(|document| patch::diff(base, document));
+    for operation in operations.iter().flatten() {
+        match operation {
+            Operation::Add { path, .. }
+            | Operation::Remove { path }
+            | Operation::Replace { path, .. } => paths.push(path),
+            _ => {}
+        }
+    }
+    paths.extend(conflicts.iter().map(|conflict| conflict.path.as_str()));
+    paths.sort_by_cached_key(|path| pointer::order_key(path));
+    paths.dedup();
+
+    let mut rows = String::new();
+    for path in &paths {
+        let base_value = base.pointer(path);
+        let cell = |document: &Value, highlight: bool| match document.pointer(path) {
+            Some(value) => format!(
+                "<td{}><code>{}</code></td>",
+                if highlight && Some(value) != base_value {
+                    " class=\"changed\""
+                } else {
+                    ""
+                },
+                xml_escape(&value.to_string())
+            ),
+            None => "<td class=\"absent\">absent</td>".to_string(),
+        };
+        let conflict = conflicts.iter().find(|conflict| conflict.path == *path);
+        let (class, status) = match conflict {
+            Some(conflict) => match conflict.resolution {
+                Some(side) => (
+                    " class=\"resolved\"",
+                    format!("resolved in favor of {}", side),
+                ),
+                None => (
+                    " class=\"conflict\"",
+                    format!("unresolved: {}", conflict.kind.description()),
+                ),
+            },
+            None => ("", String::new()),
+        };
+        rows.push_str(&format!(
+            "<tr{}><td><code>{}</code></td>{}{}{}{}<td>{}</td></tr>\n",
+            class,
+            xml_escape(if path.is_empty() { "/" } else { path }),
+            cell(base, false),
+            cell(a, true),
+            cell(b, true),
+            cell(merged, true),
+            xml_escape(&status)
+        ));
+    }
+
+    let unresolved = conflicts.iter().filter(|c| !c.is_resolved()).count();
+    format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>Merge report</title>\n<style>\n{}</style>\n</head>\n<body>\n\
+         <h1>Merge report</h1>\n<p>{} values changed, {} conflicts ({} unresolved).</p>\n\
+         <table>\n<tr><th>Path</th><th>Base</th><th>Branch A</th><th>Branch B</th>\
+         <th>Merged</th><th>Conflict</th></tr>\n{}</table>\n</body>\n</html>\n",
+        HTML_STYLE,
+        paths.len(),
+        conflicts.len(),
+        unresolved,
+        rows
+    )
+}
+
+/// How many values a branch added, removed and modified relative to base,
+/// counted as the operations of `patch::diff`: a changed object counts each
+/// changed key, but a value replaced with another type counts once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub added: usize,
+    pub removed: usize,
+    pub modified: usize,
+}
+
+pub fn changes(base: &Value, branch: &Value) -> Changes {
+    let mut changes = Changes::default();
+    for operation in patch::diff(base, branch) {
+        match operation {
+            Operation::Add { .. } => changes.added += 1,
+            Operation::Remove { .. } => changes.removed += 1,
+            _ => changes.modified += 1,
+        }
+    }
+    changes
+}
+
+/// The preflight printed by `--summary-only`: each branch's changes and the
+/// conflicts merging them gives, with how many of those the strategy resolves.
+pub fn render_summary(a: Changes, b: Changes, conflicts: &[Conflict]) -> String {
+    let unresolved = conflicts.iter().filter(|c| !c.is_resolved()).count();
+    let mut summary = String::new();
+    for (name, changes) in [("branch A", a), ("branch B", b)] {
+        summary.push_str(&format!(
+            "{}: {} added, {} removed, {} modified\n",
+            name, changes.added, changes.removed, changes.modified
+        ));
+    }
+    summary.push_str(&format!(
+        "conflicts: {} ({} unresolved)\n",
+        conflicts.len(),
+        unresolved
+    ));
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{
+        detect_conflicts, three_way_merge_with_options, ArrayMergeMode, ConflictKind,
+        ConflictStrategy, Divergence, MergeOptions, PathRule, Side,
+    };
+    use serde_json::json;
+
+    fn conflict(path: &str, kind: ConflictKind, resolution: Option<Side>) -> Conflict {
+        Conflict {
+            path: path.to_string(),
+            kind,
+            base: None,
+            a: None,
+            b: None,
+            changes: Divergence::of(None, None, None),
+            resolution,
+        }
+    }
+
+    fn fixture() -> Vec<Conflict> {
+        vec![
+            conflict("/storage/size", ConflictKind::BothModified, None),
+            conflict(
+                "/networking/port",
+                ConflictKind::BothModified,
+                Some(Side::A),
+            ),
+            conflict("/storage/disks/0", ConflictKind::ModifiedADeletedB, None),
+            conflict("", ConflictKind::TypeMismatch, None),
+        ]
+    }
+
+    #[test]
+    fn test_section() {
+        assert_eq!(section("/storage/size"), "storage");
+        assert_eq!(section("/a~1b/c"), "a/b");
+        assert_eq!(section(""), "(root)");
+    }
+
+    #[test]
+    fn test_render_flat() {
+        let report = render(&fixture(), false);
+        assert_eq!(
+            report,
+            "4 conflicts (3 unresolved)\n\
+             /storage/size: modified in both branches with different contents (unresolved)\n\
+             /networking/port: modified in both branches with different contents (taking branch A)\n\
+             /storage/disks/0: modified in branch A but deleted in branch B (unresolved)\n\
+             /: changed to different types in both branches (unresolved)\n"
+        );
+    }
+
+    #[test]
+    fn test_render_grouped_by_section() {
+        let report = render(&fixture(), true);
+        assert_eq!(
+            report,
+            "4 conflicts (3 unresolved)\n\
+             \n(root) (1)\n\
+             \x20 /: changed to different types in both branches (unresolved)\n\
+             \nnetworking (1)\n\
+             \x20 /networking/port: modified in both branches with different contents (taking branch A)\n\
+             \nstorage (2)\n\
+             \x20 /storage/size: modified in both branches with different contents (unresolved)\n\
+             \x20 /storage/disks/0: modified in branch A but deleted in branch B (unresolved)\n"
+        );
+    }
+
+    #[test]
+    fn test_render_junit() {
+        let mut conflicts = fixture();
+        conflicts[0].path = "/storage/<size> & \"more\"".to_string();
+        assert_eq!(
+            render_junit(&conflicts),
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <testsuite name=\"three-way-merge\" tests=\"4\" failures=\"3\">\n\
+             \x20 <testcase classname=\"storage\" name=\"/storage/&lt;size&gt; &amp; &quot;more&quot;\">\n\
+             \x20   <failure type=\"both_modified\" message=\"modified in both branches with different contents\"/>\n\
+             \x20 </testcase>\n\
+             \x20 <testcase classname=\"networking\" name=\"/networking/port\"/>\n\
+             \x20 <testcase classname=\"storage\" name=\"/storage/disks/0\">\n\
+             \x20   <failure type=\"modified_a_deleted_b\" message=\"modified in branch A but deleted in branch B\"/>\n\
+             \x20 </testcase>\n\
+             \x20 <testcase classname=\"(root)\" name=\"/\">\n\
+             \x20   <failure type=\"type_mismatch\" message=\"changed to different types in both branches\"/>\n\
+             \x20 </testcase>\n\
+             </testsuite>\n"
+        );
+    }
+
+    #[test]
+    fn test_resolutions() {
+        let mut conflicts = fixture();
+        conflicts[3].resolution = Some(Side::Custom);
+        assert_eq!(
+            resolutions(&conflicts),
+            json!([
+                {"path": "/networking/port", "kind": "both_modified", "side": "a"},
+                {"path": "", "kind": "type_mismatch", "side": "custom"}
+            ])
+        );
+        assert_eq!(resolutions(&[]), json!([]));
+    }
+
+    #[test]
+    fn test_conflicting_subtrees() {
+        let base = json!({"a": {"x": 1, "y": 1}, "list": [1, 2, 3], "same": 0, "gone": 1});
+        let a = json!({"a": {"x": 2, "y": 2}, "list": [1, 2, 30], "same": 0});
+        let b = json!({"a": {"x": 3, "y": 1}, "list": [1, 2, 31], "same": 0, "gone": 2});
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Theirs,
+            array_mode: ArrayMergeMode::Positional,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            conflicting_subtrees(&merged, &conflicts),
+            json!({"a": {"x": 3}, "list": {"2": 31}, "gone": 2})
+        );
+
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Ours,
+            ..opts
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            conflicting_subtrees(&merged, &conflicts),
+            json!({"a": {"x": 2}, "list": {"2": 30}})
+        );
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&json!(1), &json!(2), &json!(3), &opts);
+        assert_eq!(conflicting_subtrees(&merged, &conflicts), json!(2));
+        assert_eq!(conflicting_subtrees(&merged, &[]), json!({}));
+    }
+
+    #[test]
+    fn test_changelog() {
+        let base = json!({"db": {"port": 5432, "host": "x"}, "tags": ["a"], "old": 1, "same": 1});
+        let a = json!({"db": {"port": 5433, "host": "x"}, "tags": ["a", "b"], "same": 2, "features": {"x": true}});
+        let b = json!({"db": {"port": 5434, "host": "y"}, "tags": ["a"], "same": 2, "old": 1});
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Ours,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            changelog(&base, &a, &b, &merged, &conflicts),
+            "Changed key /db/host from branch B.\n\
+             Changed key /db/port from branch A.\n\
+             Removed key /old from branch A.\n\
+             Changed key /same by both branches.\n\
+             Added element /tags/1 from branch A.\n\
+             Added key /features from branch A.\n\
+             Resolved conflict at /db/port in favor of branch A.\n\
+             6 changes, 1 conflicts (0 unresolved).\n"
+        );
+
+        let (merged, conflicts) =
+            three_way_merge_with_options(&json!(1), &json!(2), &json!(3), &MergeOptions::default());
+        assert_eq!(
+            changelog(&json!(1), &json!(2), &json!(3), &merged, &conflicts),
+            "Changed the whole document from branch A.\n\
+             Left conflict at / unresolved: modified in both branches with different contents.\n\
+             1 changes, 1 conflicts (1 unresolved).\n"
+        );
+    }
+
+    #[test]
+    fn test_render_html() {
+        let base = json!({"db": {"port": 5432, "host": "x"}, "list": [0, 1], "old": "<a>"});
+        let a = json!({"db": {"port": 5433, "host": "x"}, "list": [0, 1], "old": "<a>"});
+        let b = json!({"db": {"port": 5434, "host": "y"}, "list": [0, 1, 2]});
+        let (merged, conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+        let html = render_html(&base, &a, &b, &merged, &conflicts);
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert!(html.contains("<p>4 values changed, 1 conflicts (1 unresolved).</p>"));
+        let rows: Vec<&str> = html
+            .lines()
+            .filter(|line| line.starts_with("<tr"))
+            .collect();
+        assert_eq!(
+            rows,
+            [
+                "<tr><th>Path</th><th>Base</th><th>Branch A</th><th>Branch B</th><th>Merged</th><th>Conflict</th></tr>",
+                "<tr><td><code>/db/host</code></td><td><code>&quot;x&quot;</code></td><td><code>&quot;x&quot;</code></td><td class=\"changed\"><code>&quot;y&quot;</code></td><td class=\"changed\"><code>&quot;y&quot;</code></td><td></td></tr>",
+                "<tr class=\"conflict\"><td><code>/db/port</code></td><td><code>5432</code></td><td class=\"changed\"><code>5433</code></td><td class=\"changed\"><code>5434</code></td><td class=\"changed\"><code>5433</code></td><td>unresolved: modified in both branches with different contents</td></tr>",
+                "<tr><td><code>/list/2</code></td><td class=\"absent\">absent</td><td class=\"absent\">absent</td><td class=\"changed\"><code>2</code></td><td class=\"changed\"><code>2</code></td><td></td></tr>",
+                "<tr><td><code>/old</code></td><td><code>&quot;&lt;a&gt;&quot;</code></td><td><code>&quot;&lt;a&gt;&quot;</code></td><td class=\"absent\">absent</td><td class=\"absent\">absent</td><td></td></tr>",
+            ]
+        );
+
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Theirs,
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        let html = render_html(&base, &a, &b, &merged, &conflicts);
+        assert!(html.contains("<tr class=\"resolved\"><td><code>/db/port</code></td>"));
+        assert!(html.contains("<td>resolved in favor of branch B</td></tr>"));
+    }
+
+    #[test]
+    fn test_render_dry_run() {
+        let base = json!({"port": 1, "gone": 1, "name": "x"});
+        let a = json!({"port": 2, "gone": 2, "name": "y"});
+        let b = json!({"port": 3, "name": "z"});
+        let opts = MergeOptions {
+            strategy: ConflictStrategy::Theirs,
+            rules: vec![PathRule::parse("/name=manual").unwrap()],
+            ..Default::default()
+        };
+        let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        assert_eq!(
+            render_dry_run(&merged, &conflicts),
+            "/gone: modified in branch A but deleted in branch B; would take branch B, removing it\n\
+             /name: modified in both branches with different contents; left unresolved\n\
+             /port: modified in both branches with different contents; would take branch B: 3\n"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        let base = json!({"a": 1, "b": {"x": 1, "y": 1}, "list": [1, 2], "gone": true});
+        let a = json!({"a": 2, "b": {"x": 1}, "list": [1, 2, 3], "gone": true, "new": {}});
+        let b = json!({"a": 3, "b": {"x": 1, "y": 1}, "list": [1], "gone": true});
+        let changes_a = changes(&base, &a);
+        let changes_b = changes(&base, &b);
+        assert_eq!(
+            changes_a,
+            Changes {
+                added: 2,
+                removed: 1,
+                modified: 1
+            }
+        );
+        assert_eq!(
+            changes_b,
+            Changes {
+                added: 0,
+                removed: 1,
+                modified: 1
+            }
+        );
+        assert_eq!(changes(&base, &base), Changes::default());
+
+        let conflicts = detect_conflicts(&base, &a, &b, &MergeOptions::default());
+        assert_eq!(
+            render_summary(changes_a, changes_b, &conflicts),
+            "branch A: 2 added, 1 removed, 1 modified\n\
+             branch B: 0 added, 1 removed, 1 modified\n\
+             conflicts: 2 (2 unresolved)\n"
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        let mut conflicts = fixture();
+        assert_eq!(
+            status(&conflicts, Some("out.json")),
+            json!({"clean": false, "conflicts": 3, "output": "out.json"})
+        );
+        for conflict in &mut conflicts {
+            conflict.resolution = Some(Side::B);
+        }
+        assert_eq!(
+            status(&conflicts, None),
+            json!({"clean": true, "conflicts": 0, "output": null})
+        );
+    }
+
+    #[test]
+    fn test_depth() {
+        assert_eq!(depth(&json!(1)), 0);
+        assert_eq!(depth(&json!({})), 0);
+        assert_eq!(depth(&json!({"a": 1, "b": {}})), 1);
+        assert_eq!(depth(&json!({"a": [1, {"b": [true]}]})), 4);
+    }
+
+    #[test]
+    fn test_render_stats() {
+        assert_eq!(
+            render_stats(&fixture(), 5),
+            "conflicts: 4 (3 unresolved)\n\
+             deepest conflict: /storage/disks/0 (depth 3)\n\
+             document depth: 5\n"
+        );
+        assert_eq!(
+            render_stats(&[], 2),
+            "conflicts: 0 (0 unresolved)\ndeepest conflict: none\ndocument depth: 2\n"
+        );
+    }
+}
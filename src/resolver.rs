@@ -0,0 +1,151 @@
+//! Resolving conflicts with an external command.
+//!
+//! The command is run through `sh -c` once per conflict. It reads a JSON object
+//! on stdin with the conflict's `path`, `kind` (see `ConflictKind::name`) and
+//! `description`, plus `base`, `a` and `b` for the versions that have a value.
+//! It prints the value to use as JSON on stdout, or nothing to leave the
+//! conflict unresolved.
+
+use crate::merge::{Conflict, Side};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Runs `command` for `conflict` and returns the value it chose, if any.
+pub fn run(command: &str, conflict: &Conflict) -> Result<Option<Value>, String> {
+    let mut input = json!({
+        "path": conflict.path,
+        "kind": conflict.kind.name(),
+        "description": conflict.kind.description(),
+    });
+    for (field, value) in [
+        ("base", &conflict.base),
+        ("a", &conflict.a),
+        ("b", &conflict.b),
+    ] {
+        if let Some(value) = value {
+            input[field] = value.clone();
+        }
+    }
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run resolver: {}", e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A resolver that exits without reading its input is not an error.
+        let _ = stdin.write_all(input.to_string().as_bytes());
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| format!("failed to run resolver: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!(
+            "resolver failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.trim().is_empty() {
+        return Ok(None);
+    }
+    serde_json::from_str(&stdout)
+        .map(Some)
+        .map_err(|e| format!("resolver printed invalid JSON: {}", e))
+}
+
+/// Offers every unresolved conflict in `conflicts` to `command`, writing the
+/// values it chooses into `merged` and marking those conflicts resolved with
+/// `Side::Custom`. Failures are logged and leave the conflict unresolved.
+pub fn resolve_all(command: &str, merged: &mut Value, conflicts: &mut [Conflict]) {
+    for conflict in conflicts.iter_mut().filter(|c| !c.is_resolved()) {
+        let location = if conflict.path.is_empty() {
+            "/"
+        } else {
+            &conflict.path
+        };
+        match run(command, conflict) {
+            Ok(Some(value)) => match merged.pointer_mut(&conflict.path) {
+                Some(target) => {
+                    debug!("Conflict: file '{}' resolved by resolver", location);
+                    *target = value;
+                    conflict.resolution = Some(Side::Custom);
+                }
+                None => error!("Conflict: file '{}' is not in the merged output", location),
+            },
+            Ok(None) => {}
+            Err(e) => error!("Conflict: file '{}': {}", location, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{three_way_merge_with_options, ConflictKind, Divergence, MergeOptions};
+
+    fn conflict() -> Conflict {
+        Conflict {
+            path: "/x".to_string(),
+            kind: ConflictKind::ModifiedADeletedB,
+            base: Some(json!(1)),
+            a: Some(json!(2)),
+            b: None,
+            changes: Divergence::of(Some(&json!(1)), Some(&json!(2)), None),
+            resolution: None,
+        }
+    }
+
+    #[test]
+    fn test_resolver_reads_conflict_and_prints_value() {
+        let command = r#"grep -q '"kind":"modified_a_deleted_b"' && echo '{"chosen": true}'"#;
+        assert_eq!(
+            run(command, &conflict()).unwrap(),
+            Some(json!({"chosen": true}))
+        );
+        assert_eq!(
+            run(r#"grep -q '"b"' && echo 1 || echo 2"#, &conflict()).unwrap(),
+            Some(json!(2))
+        );
+    }
+
+    #[test]
+    fn test_resolver_failures() {
+        assert_eq!(run("true", &conflict()).unwrap(), None);
+
+        let err = run("echo oops >&2; exit 3", &conflict()).unwrap_err();
+        assert!(err.starts_with("resolver failed with "), "{}", err);
+        assert!(err.ends_with(": oops"), "{}", err);
+
+        let err = run("echo not json", &conflict()).unwrap_err();
+        assert!(
+            err.starts_with("resolver printed invalid JSON: "),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_resolve_all() {
+        let base = json!({"x": 1, "y": 1});
+        let a = json!({"x": 2, "y": 2});
+        let b = json!({"x": 3, "y": 3});
+        let (mut merged, mut conflicts) =
+            three_way_merge_with_options(&base, &a, &b, &MergeOptions::default());
+
+        resolve_all(
+            r#"grep -q '"path":"/x"' && echo 10"#,
+            &mut merged,
+            &mut conflicts,
+        );
+        assert_eq!(merged, json!({"x": 10, "y": 2}));
+        assert_eq!(conflicts[0].resolution, Some(Side::Custom));
+        assert_eq!(conflicts[1].resolution, None);
+    }
+}
@@ -0,0 +1,116 @@
+//! Scoring conflicts by how risky they are to leave in place, so a run can
+//! accept a few harmless conflicts and still fail on dangerous ones.
+//!
+//! Each conflict kind has a score; the severity of a merge is the sum of the
+//! scores of its unresolved conflicts. By default a value edited or added
+//! differently in both branches, or a number held for review, scores 1, an edit against a deletion or an
+//! array over its length limit 3, a record renamed differently 5 and a type
+//! mismatch 10.
+
+use crate::merge::{Conflict, ConflictKind};
+
+/// The score of each conflict kind, starting from the defaults above.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Severities {
+    scores: [u64; ConflictKind::ALL.len()],
+}
+
+impl Default for Severities {
+    fn default() -> Self {
+        Severities {
+            scores: ConflictKind::ALL.map(|kind| match kind {
+                ConflictKind::BothModified | ConflictKind::BothAdded => 1,
+                ConflictKind::NumberChanged => 1,
+                ConflictKind::ModifiedADeletedB | ConflictKind::ModifiedBDeletedA => 3,
+                ConflictKind::TooLong => 3,
+                ConflictKind::BothRenamed => 5,
+                ConflictKind::TypeMismatch => 10,
+            }),
+        }
+    }
+}
+
+impl Severities {
+    pub fn score(&self, kind: ConflictKind) -> u64 {
+        self.scores[kind as usize]
+    }
+
+    /// Overrides one score from `<kind>=<score>`, the kind given by its
+    /// `ConflictKind::name`.
+    pub fn set(&mut self, assignment: &str) -> Result<(), String> {
+        let (name, score) = assignment
+            .split_once('=')
+            .ok_or_else(|| format!("'{}' must look like <kind>=<score>", assignment))?;
+        let kind = ConflictKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+            .ok_or_else(|| format!("unknown conflict kind '{}'", name))?;
+        self.scores[kind as usize] = score
+            .parse()
+            .map_err(|_| format!("'{}' is not a non-negative integer", score))?;
+        Ok(())
+    }
+
+    /// The sum of the scores of the unresolved conflicts among `conflicts`.
+    pub fn total(&self, conflicts: &[Conflict]) -> u64 {
+        conflicts
+            .iter()
+            .filter(|c| !c.is_resolved())
+            .map(|c| self.score(c.kind))
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{Divergence, Side};
+
+    fn conflict(kind: ConflictKind, resolution: Option<Side>) -> Conflict {
+        Conflict {
+            path: "/x".to_string(),
+            kind,
+            base: None,
+            a: None,
+            b: None,
+            changes: Divergence::of(None, None, None),
+            resolution,
+        }
+    }
+
+    #[test]
+    fn test_total_of_mixed_conflicts() {
+        let conflicts = [
+            conflict(ConflictKind::BothModified, None),
+            conflict(ConflictKind::BothModified, None),
+            conflict(ConflictKind::ModifiedBDeletedA, None),
+            conflict(ConflictKind::TypeMismatch, Some(Side::A)),
+        ];
+        let severities = Severities::default();
+        // The resolved type mismatch does not count.
+        assert_eq!(severities.total(&conflicts), 5);
+        assert_eq!(severities.total(&conflicts[..3]), 5);
+        assert_eq!(
+            severities.total(&[conflict(ConflictKind::TypeMismatch, None)]),
+            10
+        );
+        assert_eq!(severities.total(&[]), 0);
+    }
+
+    #[test]
+    fn test_overridden_scores() {
+        let mut severities = Severities::default();
+        severities.set("both_modified=0").unwrap();
+        severities.set("modified_b_deleted_a=20").unwrap();
+        let conflicts = [
+            conflict(ConflictKind::BothModified, None),
+            conflict(ConflictKind::ModifiedBDeletedA, None),
+            conflict(ConflictKind::BothAdded, None),
+        ];
+        assert_eq!(severities.total(&conflicts), 21);
+
+        assert!(severities.set("both_modified").is_err());
+        assert!(severities.set("conflict=1").is_err());
+        assert!(severities.set("both_added=-1").is_err());
+    }
+}
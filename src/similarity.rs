@@ -0,0 +1,147 @@
+//! Heuristic pairing of array elements that have no stable identity.
+
+use serde_json::{Map, Value};
+
+/// Scores how alike two values are, from 0.0 to 1.0. Objects score the share of
+/// keys (out of all keys in either object) that map to equal values in both;
+/// every other pair scores 1.0 when equal and 0.0 otherwise.
+pub fn similarity(x: &Value, y: &Value) -> f64 {
+    match (x, y) {
+        (Value::Object(x_map), Value::Object(y_map)) => {
+            let shared = x_map
+                .iter()
+                .filter(|(key, value)| y_map.get(*key) == Some(*value))
+                .count();
+            let total = x_map.len() + y_map.len() - shared_keys(x_map, y_map);
+            if total == 0 {
+                1.0
+            } else {
+                shared as f64 / total as f64
+            }
+        }
+        _ => {
+            if x == y {
+                1.0
+            } else {
+                0.0
+            }
+        }
+    }
+}
+
+fn shared_keys(x: &Map<String, Value>, y: &Map<String, Value>) -> usize {
+    x.keys().filter(|key| y.contains_key(*key)).count()
+}
+
+/// Pairs each element of `base` with at most one element of `other` whose
+/// similarity is at least `threshold`. Returns, per base index, the index of its
+/// counterpart in `other`.
+///
+/// Pairs are chosen greedily from the highest score down, ties broken by the
+/// lowest base index and then the lowest `other` index, so the result is
+/// deterministic. Returns `None` when the pairing is ambiguous: an element has
+/// two distinct candidates with the same best score, so there is no telling
+/// which one is its counterpart.
+pub fn match_elements(
+    base: &[Value],
+    other: &[Value],
+    threshold: f64,
+) -> Option<Vec<Option<usize>>> {
+    let mut pairs: Vec<(f64, usize, usize)> = Vec::new();
+    for (i, base_val) in base.iter().enumerate() {
+        for (j, other_val) in other.iter().enumerate() {
+            let score = similarity(base_val, other_val);
+            if score >= threshold && score > 0.0 {
+                pairs.push((score, i, j));
+            }
+        }
+    }
+    pairs.sort_by(|x, y| y.0.total_cmp(&x.0).then(x.1.cmp(&y.1)).then(x.2.cmp(&y.2)));
+
+    let mut base_match: Vec<Option<usize>> = vec![None; base.len()];
+    let mut other_taken = vec![false; other.len()];
+
+    for &(score, i, j) in &pairs {
+        if base_match[i].is_some() || other_taken[j] {
+            continue;
+        }
+
+        let rival = pairs.iter().any(|&(s, i2, j2)| {
+            s == score
+                && ((i2 == i && j2 != j && !other_taken[j2] && other[j2] != other[j])
+                    || (j2 == j && i2 != i && base_match[i2].is_none() && base[i2] != base[i]))
+        });
+        if rival {
+            return None;
+        }
+
+        base_match[i] = Some(j);
+        other_taken[j] = true;
+    }
+
+    Some(base_match)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_similarity_of_objects() {
+        let x = json!({"name": "db", "port": 5432, "host": "a"});
+        let y = json!({"name": "db", "port": 5433, "host": "a"});
+        assert_eq!(similarity(&x, &y), 2.0 / 3.0);
+        assert_eq!(similarity(&x, &json!({"other": 1})), 0.0);
+        assert_eq!(similarity(&json!({}), &json!({})), 1.0);
+    }
+
+    #[test]
+    fn test_similarity_of_scalars() {
+        assert_eq!(similarity(&json!("a"), &json!("a")), 1.0);
+        assert_eq!(similarity(&json!("a"), &json!("b")), 0.0);
+    }
+
+    #[test]
+    fn test_match_elements_follows_reordering() {
+        let base = vec![
+            json!({"name": "web", "port": 80}),
+            json!({"name": "db", "port": 5432}),
+        ];
+        let other = vec![
+            json!({"name": "db", "port": 5433}),
+            json!({"name": "web", "port": 80}),
+        ];
+        assert_eq!(
+            match_elements(&base, &other, 0.5),
+            Some(vec![Some(1), Some(0)])
+        );
+    }
+
+    #[test]
+    fn test_match_elements_below_threshold() {
+        let base = vec![json!({"name": "web", "port": 80})];
+        let other = vec![json!({"name": "api", "port": 8080})];
+        assert_eq!(match_elements(&base, &other, 0.5), Some(vec![None]));
+    }
+
+    #[test]
+    fn test_match_elements_ambiguous() {
+        let base = vec![json!({"name": "web", "port": 80})];
+        let other = vec![
+            json!({"name": "web", "port": 81}),
+            json!({"name": "api", "port": 80}),
+        ];
+        assert_eq!(match_elements(&base, &other, 0.3), None);
+    }
+
+    #[test]
+    fn test_match_elements_identical_duplicates_are_not_ambiguous() {
+        let base = vec![json!("x"), json!("x")];
+        let other = vec![json!("x"), json!("x")];
+        assert_eq!(
+            match_elements(&base, &other, 0.5),
+            Some(vec![Some(0), Some(1)])
+        );
+    }
+}
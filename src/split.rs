@@ -0,0 +1,100 @@
+//! Writing a document that maps keys to records as one file per top-level key.
+
+use crate::format::{self, FormatRule};
+use crate::pointer;
+use serde_json::Value;
+
+/// The file name and contents for each entry of the root object of `merged`,
+/// formatted with `rules` as at its path in the whole document. Returns `None`
+/// if the root is not an object.
+///
+/// Files are named `<key>.json`, with every character other than ASCII letters,
+/// digits, `-`, `_` and `.` replaced by `_`. Keys that would still share a
+/// name, ignoring case, get `-2`, `-3` and so on appended, in key order.
+pub fn files(merged: &Value, rules: &[FormatRule]) -> Option<Vec<(String, String)>> {
+    let map = merged.as_object()?;
+    let mut files: Vec<(String, String)> = Vec::new();
+    for (key, value) in map {
+        let stem = file_stem(key);
+        let mut name = format!("{}.json", stem);
+        let mut suffix = 1;
+        while files
+            .iter()
+            .any(|(taken, _)| taken.eq_ignore_ascii_case(&name))
+        {
+            suffix += 1;
+            name = format!("{}-{}.json", stem, suffix);
+        }
+        let contents = format::to_string_at(value, rules, &pointer::push("", key));
+        files.push((name, contents));
+    }
+    Some(files)
+}
+
+fn file_stem(key: &str) -> String {
+    let stem: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    // Names made only of dots, including the empty one, are not usable, and
+    // hidden files are easy to miss.
+    if stem.starts_with('.') || stem.is_empty() {
+        format!("_{}", stem)
+    } else {
+        stem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_one_file_per_key() {
+        let merged = json!({"alice": {"uid": 1}, "bob": [1, 2]});
+        let rules = [FormatRule::parse("/bob=compact").unwrap()];
+        assert_eq!(
+            files(&merged, &rules).unwrap(),
+            vec![
+                ("alice.json".to_string(), "{\n  \"uid\": 1\n}".to_string()),
+                ("bob.json".to_string(), "[1,2]".to_string()),
+            ]
+        );
+        assert_eq!(files(&json!([1]), &[]), None);
+    }
+
+    #[test]
+    fn test_unsafe_and_colliding_names() {
+        let merged = json!({
+            "../etc/passwd": 1,
+            "": 2,
+            "a b": 3,
+            "a/b": 4,
+            "A_b": 5,
+            "ok-1.x": 6
+        });
+        let names: Vec<String> = files(&merged, &[])
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "_.json",
+                "_.._etc_passwd.json",
+                "A_b.json",
+                "a_b-2.json",
+                "a_b-3.json",
+                "ok-1.x.json"
+            ]
+        );
+    }
+}
@@ -0,0 +1,128 @@
+//! ISO 8601 timestamps, read as points in time so that they can be compared
+//! with a tolerance.
+
+/// Reads an RFC 3339 timestamp such as `2024-05-01T12:00:00.5+02:00` as
+/// seconds since the Unix epoch. The separator may also be a space or a
+/// lowercase `t`, and a timestamp without an offset is taken as UTC. Anything
+/// else, including dates without a time, is `None`.
+pub fn parse(text: &str) -> Option<f64> {
+    let bytes = text.as_bytes();
+    if bytes.len() < 19 || !text.is_ascii() {
+        return None;
+    }
+    let number = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = &text[range];
+        digits
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| digits.parse().ok())
+            .flatten()
+    };
+    if bytes[4] != b'-' || bytes[7] != b'-' || bytes[13] != b':' || bytes[16] != b':' {
+        return None;
+    }
+    if !matches!(bytes[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let (year, month, day) = (number(0..4)?, number(5..7)?, number(8..10)?);
+    let (hour, minute, second) = (number(11..13)?, number(14..16)?, number(17..19)?);
+    if !(1..=12).contains(&month)
+        || day < 1
+        || day > days_in_month(year, month)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &text[19..];
+    let mut fraction = 0.0;
+    if let Some(digits) = rest.strip_prefix('.') {
+        let end = digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len());
+        if end == 0 {
+            return None;
+        }
+        fraction = format!("0.{}", &digits[..end]).parse().ok()?;
+        rest = &digits[end..];
+    }
+    let offset = match rest {
+        "" | "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let offset = &rest[1..];
+            let digits = offset
+                .bytes()
+                .enumerate()
+                .all(|(i, b)| i == 2 || b.is_ascii_digit());
+            if offset.len() != 5 || offset.as_bytes()[2] != b':' || !digits {
+                return None;
+            }
+            let (hours, minutes) = (
+                offset[..2].parse::<i64>().ok()?,
+                offset[3..].parse::<i64>().ok()?,
+            );
+            if hours > 23 || minutes > 59 {
+                return None;
+            }
+            sign * (hours * 3600 + minutes * 60)
+        }
+    };
+
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+    Some(seconds as f64 + fraction)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let cases = [
+            ("1970-01-01T00:00:00Z", Some(0.0)),
+            ("2024-02-29T12:30:15Z", Some(1709209815.0)),
+            ("2024-02-29T14:30:15+02:00", Some(1709209815.0)),
+            ("2024-02-29 12:30:15", Some(1709209815.0)),
+            ("2024-02-29t12:30:15.25z", Some(1709209815.25)),
+            ("1969-12-31T23:59:59-00:00", Some(-1.0)),
+            ("2023-02-29T12:30:15Z", None),
+            ("2024-02-29", None),
+            ("2024-02-29T12:30Z", None),
+            ("2024-02-29T12:30:15.Z", None),
+            ("2024-02-29T12:30:15+0200", None),
+            ("2024-02-29T12:30:15+-1:00", None),
+            ("2024-13-01T00:00:00Z", None),
+            ("not a timestamp at all", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(parse(text), expected, "{}", text);
+        }
+    }
+}
@@ -0,0 +1,266 @@
+//! Directory trees as JSON documents, so that whole directories can be merged:
+//! a directory becomes an object with an entry per file or subdirectory, keyed
+//! by its name, and a `.json` file becomes its parsed contents. Conflicts and
+//! patterns then address paths like `/config/app.json/port`.
+//!
+//! Other files, and `.json` files that do not parse, are opaque: their contents
+//! become a string, the text itself if it is UTF-8 and hex otherwise, so they
+//! are taken whole from a branch that changed them and conflict when both did.
+//!
+//! For people, such paths are labelled with the file they are in and the path
+//! within it, as in `config/app.json#/port`.
+
+use crate::format;
+use crate::pointer;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// What a path of the document was on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Entry {
+    Directory,
+    Json,
+    Text,
+    Binary,
+}
+
+/// A directory read as a document, with what each of its paths was.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tree {
+    pub document: Value,
+    pub entries: HashMap<String, Entry>,
+}
+
+/// Reads the directory `dir` and everything below it.
+pub fn read(dir: &Path) -> Result<Tree, String> {
+    let mut entries = HashMap::new();
+    let document = read_dir(dir, "", &mut entries)?;
+    Ok(Tree { document, entries })
+}
+
+fn read_dir(dir: &Path, path: &str, entries: &mut HashMap<String, Entry>) -> Result<Value, String> {
+    let mut map = Map::new();
+    let listing =
+        fs::read_dir(dir).map_err(|e| format!("cannot read '{}': {}", dir.display(), e))?;
+    for item in listing {
+        let item = item.map_err(|e| format!("cannot read '{}': {}", dir.display(), e))?;
+        let file_path = item.path();
+        let name = item
+            .file_name()
+            .into_string()
+            .map_err(|_| format!("'{}' is not a UTF-8 name", file_path.display()))?;
+        let entry_path = pointer::push(path, &name);
+        let (value, entry) = if file_path.is_dir() {
+            let value = read_dir(&file_path, &entry_path, entries)?;
+            (value, Entry::Directory)
+        } else {
+            let bytes = fs::read(&file_path)
+                .map_err(|e| format!("cannot read '{}': {}", file_path.display(), e))?;
+            read_file(&name, bytes)
+        };
+        entries.insert(entry_path, entry);
+        map.insert(name, value);
+    }
+    Ok(Value::Object(map))
+}
+
+fn read_file(name: &str, bytes: Vec<u8>) -> (Value, Entry) {
+    if name.ends_with(".json") {
+        if let Ok(value) = serde_json::from_slice(&bytes) {
+            return (value, Entry::Json);
+        }
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) => (Value::String(text), Entry::Text),
+        Err(e) => {
+            let hex = e.as_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+            (Value::String(hex), Entry::Binary)
+        }
+    }
+}
+
+/// Writes `document` to the directory `dir` as a tree, which must not exist or
+/// be empty. The kind of each path is looked up in `entries`, one map per input
+/// in order of precedence; a path found in none is written as a JSON file, as
+/// is a directory or opaque file whose merged value no longer fits its kind.
+pub fn write(
+    dir: &Path,
+    document: &Value,
+    entries: &[&HashMap<String, Entry>],
+) -> Result<(), String> {
+    let Value::Object(map) = document else {
+        return Err("the merged document is not an object".to_string());
+    };
+    if fs::read_dir(dir).is_ok_and(|mut listing| listing.next().is_some()) {
+        return Err(format!("'{}' is not empty", dir.display()));
+    }
+    write_dir(dir, "", map, entries)
+}
+
+fn write_dir(
+    dir: &Path,
+    path: &str,
+    map: &Map<String, Value>,
+    entries: &[&HashMap<String, Entry>],
+) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("cannot create '{}': {}", dir.display(), e))?;
+    for (name, value) in map {
+        if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+            return Err(format!("'{}' is not a file name", name));
+        }
+        let entry_path = pointer::push(path, name);
+        let file_path = dir.join(name);
+        let entry = entries
+            .iter()
+            .find_map(|kinds| kinds.get(&entry_path))
+            .copied()
+            .unwrap_or(Entry::Json);
+        let bytes = match (entry, value) {
+            (Entry::Directory, Value::Object(children)) => {
+                write_dir(&file_path, &entry_path, children, entries)?;
+                continue;
+            }
+            (Entry::Text, Value::String(text)) => text.clone().into_bytes(),
+            (Entry::Binary, Value::String(hex)) => match decode_hex(hex) {
+                Some(bytes) => bytes,
+                None => format::to_string(value, &[]).into_bytes(),
+            },
+            _ => format::to_string(value, &[]).into_bytes(),
+        };
+        fs::write(&file_path, bytes)
+            .map_err(|e| format!("cannot write '{}': {}", file_path.display(), e))?;
+    }
+    Ok(())
+}
+
+/// The path `path` of a tree document labelled as the relative path of the
+/// file it is in and, inside a JSON file, the pointer within it, such as
+/// `config/db.json#/pool/size`. The kind of each path is looked up in
+/// `entries` as in `write`.
+pub fn label(path: &str, entries: &[&HashMap<String, Entry>]) -> String {
+    let tokens = pointer::tokens(path);
+    let mut prefix = String::new();
+    for (depth, token) in tokens.iter().enumerate() {
+        prefix = pointer::push(&prefix, token);
+        let entry = entries.iter().find_map(|kinds| kinds.get(&prefix));
+        if entry != Some(&Entry::Directory) {
+            let file = tokens[..=depth].join("/");
+            let within = tokens[depth + 1..]
+                .iter()
+                .fold(String::new(), |within, token| pointer::push(&within, token));
+            return if within.is_empty() {
+                file
+            } else {
+                format!("{}#{}", file, within)
+            };
+        }
+    }
+    tokens.join("/")
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::path::PathBuf;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("twm-tree-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = scratch("round-trip");
+        fs::create_dir_all(dir.join("conf")).unwrap();
+        fs::write(dir.join("conf/app.json"), "{\"port\": 80}").unwrap();
+        fs::write(dir.join("notes.txt"), "hello\n").unwrap();
+        fs::write(dir.join("logo.png"), [0x89, 0x50, 0xff]).unwrap();
+        fs::write(dir.join("broken.json"), "{").unwrap();
+
+        let tree = read(&dir).unwrap();
+        assert_eq!(
+            tree.document,
+            json!({
+                "broken.json": "{",
+                "conf": {"app.json": {"port": 80}},
+                "logo.png": "8950ff",
+                "notes.txt": "hello\n"
+            })
+        );
+        assert_eq!(tree.entries["/conf"], Entry::Directory);
+        assert_eq!(tree.entries["/conf/app.json"], Entry::Json);
+        assert_eq!(tree.entries["/broken.json"], Entry::Text);
+        assert_eq!(tree.entries["/logo.png"], Entry::Binary);
+
+        let out = scratch("round-trip-out");
+        write(&out, &tree.document, &[&tree.entries]).unwrap();
+        assert_eq!(read(&out).unwrap(), tree);
+        assert_eq!(fs::read(out.join("logo.png")).unwrap(), [0x89, 0x50, 0xff]);
+        assert!(write(&out, &tree.document, &[&tree.entries])
+            .unwrap_err()
+            .ends_with("is not empty"));
+
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&out).unwrap();
+    }
+
+    #[test]
+    fn test_labels() {
+        let a = HashMap::from([
+            ("/config".to_string(), Entry::Directory),
+            ("/config/db.json".to_string(), Entry::Json),
+            ("/notes.txt".to_string(), Entry::Text),
+        ]);
+        let b = HashMap::from([
+            ("/config".to_string(), Entry::Directory),
+            ("/config/extra".to_string(), Entry::Directory),
+            ("/config/extra/x.json".to_string(), Entry::Json),
+        ]);
+        let entries = [&a, &b];
+        assert_eq!(
+            label("/config/db.json/pool/size", &entries),
+            "config/db.json#/pool/size"
+        );
+        assert_eq!(
+            label("/config/db.json/a~1b/0", &entries),
+            "config/db.json#/a~1b/0"
+        );
+        assert_eq!(label("/config/db.json", &entries), "config/db.json");
+        assert_eq!(label("/notes.txt", &entries), "notes.txt");
+        assert_eq!(label("/config", &entries), "config");
+        assert_eq!(
+            label("/config/extra/x.json/k", &entries),
+            "config/extra/x.json#/k"
+        );
+    }
+
+    #[test]
+    fn test_unknown_paths_are_json_files() {
+        let out = scratch("unknown");
+        write(&out, &json!({"new.json": [1], "dir": {"x": 1}}), &[]).unwrap();
+        assert_eq!(
+            fs::read_to_string(out.join("new.json")).unwrap(),
+            "[\n  1\n]"
+        );
+        assert!(out.join("dir").is_file());
+        let bad = scratch("bad");
+        assert!(write(&bad, &json!({"..": 1}), &[]).is_err());
+        assert!(write(&bad, &json!([1]), &[]).is_err());
+        fs::remove_dir_all(&out).unwrap();
+        let _ = fs::remove_dir_all(&bad);
+    }
+}
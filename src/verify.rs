@@ -0,0 +1,224 @@
+//! A self-check of a merge result, for catching bugs in merge modes and
+//! strategies.
+//!
+//! The invariant: a merge result holds every change of both branches, so
+//! merging it again, against the same base, with either branch gives back the
+//! result itself without conflicts. That is, with `merged` the result of
+//! merging `a` and `b` over `base`, both `merge(base, merged, a)` and
+//! `merge(base, merged, b)` must return `merged` and report no conflict, using
+//! the same options. Paths at or under a conflict of the original merge are
+//! exempt, as the value chosen there need not contain either branch's change.
+//!
+//! Merging with the result as base instead does not work: a change only one
+//! branch made would then look like the other branch reverting it.
+//!
+//! Separately, `roundtrip` checks that the output as written reads back as the
+//! document it was written from, catching lossy formatting.
+
+use crate::merge::{three_way_merge_with_options, Conflict, MergeOptions};
+use crate::patch::{self, Operation};
+use crate::pointer;
+use serde_json::Value;
+
+/// Checks the invariant above, returning a description of each violation.
+pub fn check(
+    base: &Value,
+    a: &Value,
+    b: &Value,
+    merged: &Value,
+    conflicts: &[Conflict],
+    opts: &MergeOptions,
+) -> Vec<String> {
+    let exempt = |path: &str| {
+        conflicts.iter().any(|conflict| {
+            path == conflict.path
+                || path
+                    .strip_prefix(conflict.path.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    };
+    let location = |path: &str| if path.is_empty() { "/" } else { path }.to_string();
+
+    let mut violations = Vec::new();
+    for (branch, name) in [(a, "branch A"), (b, "branch B")] {
+        let (remerged, new_conflicts) = three_way_merge_with_options(base, merged, branch, opts);
+        for conflict in new_conflicts.iter().filter(|c| !exempt(&c.path)) {
+            violations.push(format!(
+                "re-merging with {} conflicts at '{}'",
+                name,
+                location(&conflict.path)
+            ));
+        }
+        for operation in patch::diff(merged, &remerged) {
+            let path = operation_path(&operation);
+            if !exempt(path) {
+                violations.push(format!(
+                    "re-merging with {} changes '{}'",
+                    name,
+                    location(path)
+                ));
+            }
+        }
+    }
+    violations
+}
+
+/// Checks that `read`, the output parsed back, is `expected`, the document it
+/// was written from, or says where it first differs. Floats may differ in their
+/// last bit, as serde_json does not always parse them to the nearest double,
+/// except with `arbitrary-precision`, which keeps numbers as written.
+pub fn roundtrip(expected: &Value, read: &Value) -> Result<(), String> {
+    match first_difference(expected, read, "") {
+        None => Ok(()),
+        Some((path, expected, read)) => {
+            let show =
+                |value: Option<&Value>| value.map_or("nothing".to_string(), Value::to_string);
+            Err(format!(
+                "the output reads back differently at '{}': {} instead of {}",
+                if path.is_empty() { "/" } else { &path },
+                show(read),
+                show(expected)
+            ))
+        }
+    }
+}
+
+type Difference<'v> = (String, Option<&'v Value>, Option<&'v Value>);
+
+fn first_difference<'v>(
+    expected: &'v Value,
+    read: &'v Value,
+    path: &str,
+) -> Option<Difference<'v>> {
+    match (expected, read) {
+        (Value::Object(x), Value::Object(y)) => {
+            let mut keys: Vec<&String> = x.keys().chain(y.keys()).collect();
+            keys.sort_unstable();
+            keys.dedup();
+            keys.into_iter().find_map(|key| {
+                let key_path = pointer::push(path, key);
+                match (x.get(key), y.get(key)) {
+                    (Some(x), Some(y)) => first_difference(x, y, &key_path),
+                    (x, y) => Some((key_path, x, y)),
+                }
+            })
+        }
+        (Value::Array(x), Value::Array(y)) if x.len() == y.len() => {
+            x.iter().zip(y).enumerate().find_map(|(index, (x, y))| {
+                first_difference(x, y, &pointer::push(path, &index.to_string()))
+            })
+        }
+        (Value::Number(x), Value::Number(y))
+            if cfg!(not(feature = "arbitrary-precision")) && x.is_f64() && y.is_f64() =>
+        {
+            let (x_bits, y_bits) = (x.as_f64()?.to_bits(), y.as_f64()?.to_bits());
+            (x_bits.abs_diff(y_bits) > 1).then(|| (path.to_string(), Some(expected), Some(read)))
+        }
+        _ => (expected != read).then(|| (path.to_string(), Some(expected), Some(read))),
+    }
+}
+
+fn operation_path(operation: &Operation) -> &str {
+    match operation {
+        Operation::Add { path, .. }
+        | Operation::Remove { path }
+        | Operation::Replace { path, .. }
+        | Operation::Move { path, .. }
+        | Operation::Copy { path, .. }
+        | Operation::Test { path, .. } => path,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merge::{ArrayMergeMode, ConflictStrategy};
+    use serde_json::json;
+
+    fn fixture() -> (Value, Value, Value) {
+        let base = json!({"port": 80, "tags": ["x"], "users": [{"id": 1, "v": 1}], "gone": 1});
+        let a = json!({"port": 81, "tags": ["x", "y"], "users": [{"id": 1, "v": 2}]});
+        let b = json!({"port": 82, "tags": ["z"], "users": [{"id": 2}, {"id": 1, "v": 1}], "gone": 1, "new": 1});
+        (base, a, b)
+    }
+
+    #[test]
+    fn test_merges_satisfy_the_invariant() {
+        let (base, a, b) = fixture();
+        for opts in [
+            MergeOptions::default(),
+            MergeOptions {
+                strategy: ConflictStrategy::Theirs,
+                array_mode: ArrayMergeMode::Positional,
+                ..Default::default()
+            },
+            MergeOptions {
+                array_mode: ArrayMergeMode::Keyed {
+                    key: "id".to_string(),
+                },
+                ..Default::default()
+            },
+            MergeOptions {
+                array_mode: ArrayMergeMode::OrderedUnion,
+                ..Default::default()
+            },
+        ] {
+            let (merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+            assert_eq!(
+                check(&base, &a, &b, &merged, &conflicts, &opts),
+                Vec::<String>::new()
+            );
+        }
+    }
+
+    #[test]
+    fn test_lost_change_is_reported() {
+        let (base, a, b) = fixture();
+        let opts = MergeOptions::default();
+        let (mut merged, conflicts) = three_way_merge_with_options(&base, &a, &b, &opts);
+        // Drop B's addition, and tamper with a conflicting value, which is exempt.
+        merged.as_object_mut().unwrap().remove("new");
+        merged["port"] = json!(0);
+        assert_eq!(
+            check(&base, &a, &b, &merged, &conflicts, &opts),
+            vec!["re-merging with branch B changes '/new'"]
+        );
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let read_back = |text: &str| serde_json::from_str::<Value>(text).unwrap();
+        let document = json!({"id": 9007199254740993u64, "ratio": 0.1, "tags": ["x", "y"]});
+        let pretty = serde_json::to_string_pretty(&document).unwrap();
+        assert_eq!(roundtrip(&document, &read_back(&pretty)), Ok(()));
+
+        // Canonical JSON writes numbers as doubles, losing the large integer.
+        let canonical = crate::canonical::to_string(&document);
+        assert_eq!(
+            roundtrip(&document, &read_back(&canonical)),
+            Err("the output reads back differently at '/id': 9007199254740992 instead of 9007199254740993".to_string())
+        );
+        assert_eq!(
+            roundtrip(
+                &document,
+                &json!({"id": 9007199254740993u64, "ratio": 0.1, "tags": ["x"]})
+            ),
+            Err(
+                "the output reads back differently at '/tags': [\"x\"] instead of [\"x\",\"y\"]"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            roundtrip(&json!({"a": 1}), &json!({})),
+            Err("the output reads back differently at '/a': nothing instead of 1".to_string())
+        );
+
+        let next = f64::from_bits(0.1f64.to_bits() + 1);
+        let off_by_one_bit = roundtrip(&json!(0.1), &json!(next));
+        assert_eq!(
+            off_by_one_bit.is_ok(),
+            cfg!(not(feature = "arbitrary-precision"))
+        );
+        assert!(roundtrip(&json!(0.1), &json!(0.2)).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! Version fields bumped by a merge, so that consumers can tell the merged
+//! document changed.
+
+use crate::pointer;
+use serde_json::Value;
+
+/// The new version where a branch already changed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BumpMode {
+    /// The higher of the branches' versions, taking the bump they made.
+    #[default]
+    Max,
+    /// One more than the higher of the branches' versions.
+    Increment,
+}
+
+/// The version the merge of `base`, `a` and `b` has at `path`, an integer of
+/// at least 0. Where neither branch changed it, it is one more than base's, or
+/// `1` if base has none; otherwise `mode` decides from the branches' versions.
+pub fn bumped(path: &str, [base, a, b]: [&Value; 3], mode: BumpMode) -> Result<u64, String> {
+    let version = |document: &Value| match document.pointer(path) {
+        None => Ok(None),
+        Some(value) => value
+            .as_u64()
+            .map(Some)
+            .ok_or_else(|| format!("'{}' is not a version number: {}", path, value)),
+    };
+    let (base, a, b) = (version(base)?, version(a)?, version(b)?);
+    if a == base && b == base {
+        return Ok(base.map_or(1, |version| version + 1));
+    }
+    let highest = a.max(b).unwrap_or(0);
+    Ok(match mode {
+        BumpMode::Max => highest,
+        BumpMode::Increment => highest + 1,
+    })
+}
+
+/// Sets the value at `path` in `document` to `version`. The parent of `path`
+/// must exist and be an object.
+pub fn set(document: &mut Value, path: &str, version: u64) -> Result<(), String> {
+    let mut tokens = pointer::tokens(path);
+    let key = tokens
+        .pop()
+        .ok_or_else(|| "the version cannot be the whole document".to_string())?;
+    let parent = tokens
+        .iter()
+        .fold(String::new(), |parent, token| pointer::push(&parent, token));
+    match document.pointer_mut(&parent) {
+        Some(Value::Object(map)) => {
+            map.insert(key, Value::from(version));
+            Ok(())
+        }
+        _ => Err(format!(
+            "'{}' is not an object in the merged document",
+            parent
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const PATH: &str = "/meta/schema_version";
+
+    fn bump(versions: [Value; 3], mode: BumpMode) -> Result<u64, String> {
+        let [base, a, b] = versions.map(|version| json!({"meta": {"schema_version": version}}));
+        bumped(PATH, [&base, &a, &b], mode)
+    }
+
+    #[test]
+    fn test_increment_unchanged_version() {
+        for mode in [BumpMode::Max, BumpMode::Increment] {
+            assert_eq!(bump([json!(3), json!(3), json!(3)], mode), Ok(4));
+        }
+        let empty = json!({});
+        assert_eq!(bumped(PATH, [&empty, &empty, &empty], BumpMode::Max), Ok(1));
+    }
+
+    #[test]
+    fn test_versions_changed_by_branches() {
+        let versions = || [json!(3), json!(5), json!(4)];
+        assert_eq!(bump(versions(), BumpMode::Max), Ok(5));
+        assert_eq!(bump(versions(), BumpMode::Increment), Ok(6));
+        assert_eq!(bump([json!(3), json!(3), json!(4)], BumpMode::Max), Ok(4));
+
+        // A version a branch deleted counts as none.
+        let base = json!({"meta": {"schema_version": 3}});
+        let a = json!({"meta": {}});
+        assert_eq!(bumped(PATH, [&base, &a, &a], BumpMode::Increment), Ok(1));
+    }
+
+    #[test]
+    fn test_invalid_versions() {
+        assert_eq!(
+            bump([json!(3), json!("4"), json!(3)], BumpMode::Max),
+            Err("'/meta/schema_version' is not a version number: \"4\"".to_string())
+        );
+        assert!(bump([json!(-1), json!(3), json!(3)], BumpMode::Max).is_err());
+    }
+
+    #[test]
+    fn test_set() {
+        let mut document = json!({"meta": {"schema_version": 3}, "list": []});
+        set(&mut document, PATH, 4).unwrap();
+        assert_eq!(document["meta"]["schema_version"], 4);
+        set(&mut document, "/version", 1).unwrap();
+        assert_eq!(document["version"], 1);
+
+        assert_eq!(
+            set(&mut document, "/list/version", 1),
+            Err("'/list' is not an object in the merged document".to_string())
+        );
+        assert_eq!(
+            set(&mut document, "/missing/version", 1),
+            Err("'/missing' is not an object in the merged document".to_string())
+        );
+        assert!(set(&mut document, "", 1).is_err());
+    }
+}
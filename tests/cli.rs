@@ -0,0 +1,406 @@
+mod common;
+
+use common::Fixture;
+
+#[test]
+fn test_clean_merge() {
+    let fixture = Fixture::with_inputs(
+        "cli-clean",
+        [
+            r#"{"x": 1, "y": 1}"#,
+            r#"{"x": 2, "y": 1}"#,
+            r#"{"x": 1, "y": 3}"#,
+        ],
+    );
+    let run = fixture.merge(&[]);
+    assert_eq!(run.code, Some(0), "{:?}", run);
+    assert_eq!(
+        run.stdout,
+        "Merge completed. Output written to merged.json\n"
+    );
+    assert!(run
+        .stderr
+        .contains("Merge completed successfully with no conflicts."));
+    assert_eq!(fixture.read("merged.json"), "{\n  \"x\": 2,\n  \"y\": 3\n}");
+}
+
+#[test]
+fn test_conflicting_merge() {
+    let fixture = Fixture::with_inputs(
+        "cli-conflict",
+        [
+            r#"{"x": 1, "y": 1}"#,
+            r#"{"x": 2, "y": 1}"#,
+            r#"{"x": 3, "y": 2}"#,
+        ],
+    );
+    let run = fixture.merge(&[]);
+    assert_eq!(run.code, Some(1), "{:?}", run);
+    assert!(run
+        .stdout
+        .ends_with("Note: Conflicts occurred during merge. See logs for details.\n"));
+    assert!(run
+        .stderr
+        .contains("Conflict: file '/x' modified in both branches with different contents"));
+    // A's side of the conflict is kept in the output.
+    assert_eq!(fixture.read("merged.json"), "{\n  \"x\": 2,\n  \"y\": 2\n}");
+
+    let run = fixture.merge(&["--status-json"]);
+    assert_eq!(run.code, Some(1), "{:?}", run);
+    assert_eq!(
+        run.stdout,
+        "{\"clean\":false,\"conflicts\":1,\"output\":\"merged.json\"}\n"
+    );
+
+    let run = fixture.merge(&["--max-output-bytes", "3"]);
+    assert_eq!(run.code, Some(3), "{:?}", run);
+}
+
+#[test]
+fn test_missing_input() {
+    let fixture = Fixture::new("cli-missing");
+    fixture.write("base.json", "{}");
+    fixture.write("a.json", "{}");
+    let run = fixture.merge(&[]);
+    assert_eq!(run.code, Some(1), "{:?}", run);
+    assert!(
+        run.stderr.contains("File 'b.json' does not exist"),
+        "{}",
+        run.stderr
+    );
+    assert!(!fixture.exists("merged.json"));
+}
+
+#[test]
+fn test_unknown_argument() {
+    let fixture = Fixture::with_inputs("cli-usage", ["{}", "{}", "{}"]);
+    let run = fixture.merge(&["--no-such-flag"]);
+    assert_eq!(run.code, Some(2), "{:?}", run);
+    assert!(run.stderr.contains("unexpected argument '--no-such-flag'"));
+    assert!(!fixture.exists("merged.json"));
+
+    let run = fixture.merge(&["--blame", "--jsonl-key", "id"]);
+    assert_eq!(run.code, Some(2), "{:?}", run);
+    assert!(run
+        .stderr
+        .contains("'--blame' cannot be used with '--jsonl-key <field,...>'"));
+}
+
+#[test]
+fn test_dry_run_reports_what_the_merge_takes() {
+    let fixture = Fixture::with_inputs(
+        "cli-dry-run",
+        [
+            r#"{"port": 1, "gone": 1, "size": 10}"#,
+            r#"{"port": 2, "gone": 2, "size": 20}"#,
+            r#"{"port": 3, "size": 31}"#,
+        ],
+    );
+    let strategy = ["--strategy", "average-numeric"];
+    let run = fixture.merge(&[&strategy[..], &["--dry-run"]].concat());
+    assert_eq!(run.code, Some(1), "{:?}", run);
+    assert_eq!(
+        run.stdout,
+        "/gone: modified in branch A but deleted in branch B; left unresolved\n\
+         /port: modified in both branches with different contents; would take a custom value: 2.5\n\
+         /size: modified in both branches with different contents; would take a custom value: 25.5\n"
+    );
+    assert!(!fixture.exists("merged.json"));
+
+    // The real merge takes the same values.
+    fixture.merge(&strategy);
+    let merged: serde_json::Value = serde_json::from_str(&fixture.read("merged.json")).unwrap();
+    for line in run
+        .stdout
+        .lines()
+        .filter(|line| line.contains("would take"))
+    {
+        let (path, rest) = line.split_once(": ").unwrap();
+        let value: serde_json::Value =
+            serde_json::from_str(rest.rsplit_once(": ").unwrap().1).unwrap();
+        assert_eq!(merged.pointer(path), Some(&value), "{}", line);
+    }
+}
+
+#[test]
+fn test_output_to_stdout() {
+    let fixture = Fixture::with_inputs(
+        "cli_stdout",
+        [r#"{"x": 1}"#, r#"{"x": 2}"#, r#"{"x": 1, "y": 1}"#],
+    );
+    let inputs = ["-b", "base.json", "-a", "a.json", "-c", "b.json"];
+    let run = fixture.run(&[&inputs[..], &["-o", "-"]].concat());
+    assert!(run.success(), "{:?}", run);
+    let merged: serde_json::Value = serde_json::from_str(&run.stdout).unwrap();
+    assert_eq!(merged, serde_json::json!({"x": 2, "y": 1}));
+    assert!(run.stderr.contains("Output written to stdout"));
+    assert!(!fixture.exists("-"));
+
+    let run = fixture.run(&[&inputs[..], &["-o", "-", "--status-json"]].concat());
+    assert_eq!(run.code, Some(1));
+    assert!(run.stderr.contains("--status-json"), "{:?}", run);
+}
+
+#[test]
+fn test_jsonl_records() {
+    let fixture = Fixture::with_inputs(
+        "cli_jsonl",
+        [
+            "{\"r\":\"eu\",\"id\":1,\"v\":1}\n{\"r\":\"us\",\"id\":1,\"v\":1}\n",
+            "{\"r\":\"us\",\"id\":1,\"v\":2}\n{\"r\":\"eu\",\"id\":1,\"v\":1}\n",
+            "{\"r\":\"us\",\"id\":2}\n{\"r\":\"eu\",\"id\":1,\"v\":1,\"w\":1}\n{\"r\":\"us\",\"id\":1,\"v\":1}\n",
+        ],
+    );
+    let run = fixture.merge(&["--jsonl-key", "r,id"]);
+    assert!(run.success(), "{:?}", run);
+    assert_eq!(
+        fixture.read("merged.json"),
+        "{\"id\":1,\"r\":\"us\",\"v\":2}\n{\"id\":1,\"r\":\"eu\",\"v\":1,\"w\":1}\n{\"id\":2,\"r\":\"us\"}\n"
+    );
+
+    fixture.write("b.json", "{\"r\":\"eu\"}\n");
+    let run = fixture.merge(&["--jsonl-key", "r,id"]);
+    assert!(
+        run.stderr
+            .contains("Failed to parse branch B JSON Lines: line 1: record has no field 'id'"),
+        "{:?}",
+        run
+    );
+}
+
+#[test]
+fn test_verify_roundtrip() {
+    let fixture = Fixture::with_inputs(
+        "cli_roundtrip",
+        [
+            r#"{"id": 9007199254740993, "v": 1}"#,
+            r#"{"id": 9007199254740993, "v": 2}"#,
+            r#"{"id": 9007199254740993, "v": 1, "w": 0.1}"#,
+        ],
+    );
+    let run = fixture.merge(&["--verify-roundtrip"]);
+    assert!(run.success(), "{:?}", run);
+
+    // Canonical JSON writes numbers as doubles, which loses the id.
+    std::fs::remove_file(fixture.path("merged.json")).unwrap();
+    let run = fixture.merge(&["--verify-roundtrip", "--canonical"]);
+    assert_eq!(run.code, Some(1));
+    assert!(
+        run.stderr.contains(
+            "Round trip: the output reads back differently at '/id': 9007199254740992 instead of 9007199254740993"
+        ),
+        "{:?}",
+        run
+    );
+    assert!(!fixture.exists("merged.json"));
+}
+
+#[test]
+fn test_profile() {
+    let fixture = Fixture::with_inputs(
+        "cli_profile",
+        [
+            r#"{"x": 1, "y": 1}"#,
+            r#"{"x": 2, "y": 2}"#,
+            r#"{"x": 3, "y": 3}"#,
+        ],
+    );
+    fixture.write(
+        ".twmprofiles.json",
+        r#"{"profiles": {"lenient": {"strategy": "theirs", "rule": ["/y=ours"]}}}"#,
+    );
+    let run = fixture.merge(&["--options-profile", "lenient"]);
+    assert!(run.success(), "{:?}", run);
+    let merged: serde_json::Value = serde_json::from_str(&fixture.read("merged.json")).unwrap();
+    assert_eq!(merged, serde_json::json!({"x": 3, "y": 2}));
+
+    // An option given on the command line overrides the profile's.
+    let run = fixture.merge(&["--options-profile", "lenient", "--strategy", "ours"]);
+    assert!(run.success(), "{:?}", run);
+    let merged: serde_json::Value = serde_json::from_str(&fixture.read("merged.json")).unwrap();
+    assert_eq!(merged, serde_json::json!({"x": 2, "y": 2}));
+
+    let run = fixture.merge(&["--options-profile", "strict"]);
+    assert_eq!(run.code, Some(1));
+    assert!(
+        run.stderr
+            .contains("Failed to parse profiles file: profiles: no profile named 'strict'"),
+        "{:?}",
+        run
+    );
+}
+
+#[test]
+fn test_bump_version() {
+    let fixture = Fixture::with_inputs(
+        "cli_bump_version",
+        [
+            r#"{"meta": {"version": 3}, "x": 1, "y": 1}"#,
+            r#"{"meta": {"version": 4}, "x": 2, "y": 1}"#,
+            r#"{"meta": {"version": 5}, "x": 1, "y": 2}"#,
+        ],
+    );
+    let merged = |extra: &[&str]| {
+        let mut args = vec!["--bump-version", "/meta/version"];
+        args.extend_from_slice(extra);
+        let run = fixture.merge(&args);
+        assert!(run.success(), "{:?}", run);
+        serde_json::from_str::<serde_json::Value>(&fixture.read("merged.json")).unwrap()
+    };
+    assert_eq!(
+        merged(&[]),
+        serde_json::json!({"meta": {"version": 5}, "x": 2, "y": 2})
+    );
+    assert_eq!(
+        merged(&["--bump-version-mode", "increment"])["meta"]["version"],
+        6
+    );
+
+    // With a conflict elsewhere, the version is not bumped.
+    fixture.write("b.json", r#"{"meta": {"version": 3}, "x": 3, "y": 2}"#);
+    let run = fixture.merge(&["--bump-version", "/meta/version"]);
+    assert_eq!(run.code, Some(1));
+    let merged: serde_json::Value = serde_json::from_str(&fixture.read("merged.json")).unwrap();
+    assert_eq!(merged["meta"]["version"], 4);
+
+    // Identical inputs are bumped too.
+    for file in ["a.json", "b.json"] {
+        fixture.write(file, fixture.read("base.json"));
+    }
+    let run = fixture.merge(&["--bump-version", "/meta/version"]);
+    assert!(run.success(), "{:?}", run);
+    let merged: serde_json::Value = serde_json::from_str(&fixture.read("merged.json")).unwrap();
+    assert_eq!(merged["meta"]["version"], 4);
+}
+
+#[test]
+fn test_pre_patches() {
+    // Branch A renamed `host` to `hostname`, which B edited under its old name.
+    let fixture = Fixture::with_inputs(
+        "cli_pre_patches",
+        [
+            r#"{"host": "a", "port": 1, "noise": 1}"#,
+            r#"{"hostname": "a", "port": 2, "noise": 2}"#,
+            r#"{"host": "b", "port": 1, "noise": 3}"#,
+        ],
+    );
+    let run = fixture.merge(&[]);
+    assert_eq!(run.code, Some(1));
+
+    fixture.write(
+        "rename.json",
+        r#"[{"op": "move", "from": "/host", "path": "/hostname"}, {"op": "remove", "path": "/noise"}]"#,
+    );
+    fixture.write("drop.json", r#"[{"op": "remove", "path": "/noise"}]"#);
+    let run = fixture.merge(&[
+        "--pre-patch-base",
+        "rename.json",
+        "--pre-patch-a",
+        "drop.json",
+        "--pre-patch-b",
+        "rename.json",
+    ]);
+    assert!(run.success(), "{:?}", run);
+    let merged: serde_json::Value = serde_json::from_str(&fixture.read("merged.json")).unwrap();
+    assert_eq!(merged, serde_json::json!({"hostname": "b", "port": 2}));
+
+    let run = fixture.merge(&["--pre-patch-a", "rename.json"]);
+    assert_eq!(run.code, Some(1));
+    assert!(
+        run.stderr
+            .contains("Failed to apply pre-patch for branch A: operation 0:"),
+        "{:?}",
+        run
+    );
+}
+
+#[test]
+fn test_force_key_in_identical_inputs() {
+    let input = r#"{"x": {"__force__": 1}}"#;
+    let fixture = Fixture::with_inputs("cli_force_key_identical", [input, input, input]);
+    let run = fixture.merge(&["--force-key", "__force__"]);
+    assert!(run.success(), "{:?}", run);
+    let merged: serde_json::Value = serde_json::from_str(&fixture.read("merged.json")).unwrap();
+    assert_eq!(merged, serde_json::json!({"x": 1}));
+}
+
+#[test]
+fn test_read_bytes() {
+    let fixture = Fixture::with_inputs(
+        "cli_read_bytes",
+        [
+            r#"{"x": 1, "y": "caf\u00e9"}"#,
+            r#"{"x": 2, "y": "caf\u00e9"}"#,
+            r#"{"x": 1, "y": "thé"}"#,
+        ],
+    );
+    let run = fixture.merge(&["--read-bytes"]);
+    assert!(run.success(), "{:?}", run);
+    let merged: serde_json::Value = serde_json::from_str(&fixture.read("merged.json")).unwrap();
+    assert_eq!(merged, serde_json::json!({"x": 2, "y": "thé"}));
+
+    // Identical inputs are merged, as their text is not kept to compare.
+    fixture.write("b.json", r#"{"x": 2, "y": "caf\u00e9"}"#);
+    fixture.write("base.json", r#"{"x": 2, "y": "caf\u00e9"}"#);
+    let run = fixture.merge(&["--read-bytes"]);
+    assert!(run.success(), "{:?}", run);
+    assert_eq!(
+        fixture.read("merged.json"),
+        "{\n  \"x\": 2,\n  \"y\": \"café\"\n}"
+    );
+
+    fixture.write("b.json", " \n");
+    let run = fixture.merge(&["--read-bytes"]);
+    assert_eq!(run.code, Some(1));
+    assert!(
+        run.stderr.contains("The branch B file is empty"),
+        "{:?}",
+        run
+    );
+    let run = fixture.merge(&["--read-bytes", "--allow-empty"]);
+    assert!(run.success(), "{:?}", run);
+
+    let run = fixture.merge(&["--read-bytes", "--preserve-layout"]);
+    assert_eq!(run.code, Some(2));
+    assert!(run.stderr.contains("cannot be used with"), "{:?}", run);
+}
+
+#[test]
+fn test_pre_patch_with_ordered_object() {
+    let fixture = Fixture::with_inputs(
+        "cli_pre_patch_ordered",
+        [
+            r#"{"steps": {"build": 1, "test": 2}}"#,
+            r#"{"steps": {"build": 1, "test": 2}}"#,
+            r#"{"steps": {"build": 1, "test": 3}}"#,
+        ],
+    );
+    // Moving a key to where it is puts it last, so the patch reorders A.
+    fixture.write(
+        "reorder.json",
+        r#"[{"op": "move", "from": "/steps/build", "path": "/steps/build"}]"#,
+    );
+    let args = [
+        "--ordered-object",
+        "/steps",
+        "--pre-patch-a",
+        "reorder.json",
+    ];
+    let run = fixture.merge(&args);
+    assert_eq!(run.code, Some(1), "{:?}", run);
+    assert!(run.stderr.contains("'/steps'"), "{:?}", run);
+
+    // A key the patch adds is kept where B's reordering is compared.
+    fixture.write("b.json", r#"{"steps": {"test": 2, "build": 1}}"#);
+    fixture.write(
+        "add.json",
+        r#"[{"op": "add", "path": "/steps/lint", "value": 0}]"#,
+    );
+    let run = fixture.merge(&["--ordered-object", "/steps", "--pre-patch-a", "add.json"]);
+    assert_eq!(run.code, Some(1), "{:?}", run);
+    let merged: serde_json::Value = serde_json::from_str(&fixture.read("merged.json")).unwrap();
+    assert_eq!(
+        merged,
+        serde_json::json!({"steps": {"build": 1, "test": 2, "lint": 0}})
+    );
+}
@@ -0,0 +1,41 @@
+mod common;
+
+use common::{Fixture, Run};
+
+fn merge_combined(fixture: &Fixture, extra: &[&str]) -> Run {
+    let mut args = vec!["--combined", "combined.json", "-o", "merged.json"];
+    args.extend_from_slice(extra);
+    fixture.run(&args)
+}
+
+#[test]
+fn test_merges_sections_of_one_file() {
+    let fixture = Fixture::new("combined-clean");
+    fixture.write(
+        "combined.json",
+        r#"{"base": {"x": 1, "y": 1}, "a": {"x": 2, "y": 1}, "b": {"x": 1, "y": 3}}"#,
+    );
+    let run = merge_combined(&fixture, &[]);
+    assert!(run.success(), "{:?}", run);
+    assert_eq!(fixture.read("merged.json"), "{\n  \"x\": 2,\n  \"y\": 3\n}");
+}
+
+#[test]
+fn test_custom_keys_and_missing_sections() {
+    let fixture = Fixture::new("combined-keys");
+    fixture.write(
+        "combined.json",
+        r#"{"old": [1], "ours": [1], "theirs": [1, 2]}"#,
+    );
+    let run = merge_combined(&fixture, &["--combined-keys", "old,ours,theirs"]);
+    assert!(run.success(), "{:?}", run);
+    assert_eq!(fixture.read("merged.json"), "[\n  1,\n  2\n]");
+
+    let run = merge_combined(&fixture, &["--combined-keys", "old,mine,theirs"]);
+    assert!(!run.success());
+    assert!(
+        run.stderr.contains("has no 'mine' section"),
+        "{}",
+        run.stderr
+    );
+}
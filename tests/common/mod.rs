@@ -0,0 +1,113 @@
+//! Fixtures for tests that run the binary: a scratch directory to write input
+//! files to, run the merge in and read outputs back from.
+
+// Each test crate uses only some of the helpers.
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A scratch directory, removed when the fixture is dropped.
+pub struct Fixture {
+    pub dir: PathBuf,
+}
+
+/// What a run of the binary printed, and how it exited.
+#[derive(Debug)]
+pub struct Run {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+impl Run {
+    pub fn success(&self) -> bool {
+        self.code == Some(0)
+    }
+}
+
+impl Fixture {
+    /// An empty directory, named after `name` and the test process so that
+    /// test binaries running at once do not share one.
+    pub fn new(name: &str) -> Fixture {
+        let dir = std::env::temp_dir().join(format!("twm-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Fixture { dir }
+    }
+
+    /// A directory holding the inputs `base.json`, `a.json` and `b.json`.
+    pub fn with_inputs(name: &str, [base, a, b]: [&str; 3]) -> Fixture {
+        let fixture = Fixture::new(name);
+        fixture.write("base.json", base);
+        fixture.write("a.json", a);
+        fixture.write("b.json", b);
+        fixture
+    }
+
+    pub fn write(&self, file: &str, contents: impl AsRef<[u8]>) {
+        let path = self.path(file);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).unwrap();
+        }
+        fs::write(path, contents).unwrap();
+    }
+
+    pub fn path(&self, file: &str) -> PathBuf {
+        self.dir.join(file)
+    }
+
+    pub fn read(&self, file: &str) -> String {
+        fs::read_to_string(self.path(file)).unwrap()
+    }
+
+    pub fn exists(&self, file: &str) -> bool {
+        self.path(file).exists()
+    }
+
+    /// The names in the directory, sorted.
+    pub fn listing(&self) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(&self.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Runs the binary in the directory with `args`, ignoring any ignore
+    /// file.
+    pub fn run(&self, args: &[&str]) -> Run {
+        run_in(&self.dir, args)
+    }
+
+    /// Merges the three inputs into `merged.json`, with `extra` arguments.
+    pub fn merge(&self, extra: &[&str]) -> Run {
+        let mut args = vec!["-b", "base.json", "-a", "a.json", "-c", "b.json"];
+        args.extend_from_slice(&["-o", "merged.json"]);
+        args.extend_from_slice(extra);
+        self.run(&args)
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Runs the binary in `dir` with `args`, ignoring any ignore file.
+pub fn run_in(dir: &Path, args: &[&str]) -> Run {
+    let output = Command::new(env!("CARGO_BIN_EXE_three-way-merge"))
+        .current_dir(dir)
+        .arg("--no-ignore-file")
+        .args(args)
+        .output()
+        .unwrap();
+    Run {
+        code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
@@ -0,0 +1,134 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/dir_mode");
+
+fn scratch(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("twm-dir-mode-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn merge_dirs(base: &Path, a: &Path, b: &Path, out: &Path) -> Output {
+    merge_dirs_with(base, a, b, out, &[])
+}
+
+fn merge_dirs_with(base: &Path, a: &Path, b: &Path, out: &Path, extra: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_three-way-merge"))
+        .arg("--dir-mode")
+        .arg("--no-ignore-file")
+        .arg("-b")
+        .arg(base)
+        .arg("-a")
+        .arg(a)
+        .arg("-c")
+        .arg(b)
+        .arg("-o")
+        .arg(out)
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn test_merges_directory_trees() {
+    let fixtures = Path::new(FIXTURES);
+    let out = scratch("clean");
+    let output = merge_dirs(
+        &fixtures.join("base"),
+        &fixtures.join("a"),
+        &fixtures.join("b"),
+        &out,
+    );
+    assert!(output.status.success(), "{:?}", output);
+
+    assert_eq!(
+        read(&out.join("config/app.json")),
+        "{\n  \"host\": \"b\",\n  \"port\": 81\n}"
+    );
+    assert_eq!(read(&out.join("README.txt")), "hello world\n");
+    assert_eq!(read(&out.join("new.json")), "{\n  \"added\": true\n}");
+    assert!(!out.join("old.txt").exists());
+
+    // The output directory is never written over.
+    let output = merge_dirs(
+        &fixtures.join("base"),
+        &fixtures.join("a"),
+        &fixtures.join("b"),
+        &out,
+    );
+    assert!(!output.status.success());
+    fs::remove_dir_all(&out).unwrap();
+}
+
+#[test]
+fn test_conflicting_files_keep_branch_a() {
+    let fixtures = Path::new(FIXTURES);
+    let b = scratch("conflict-b");
+    fs::create_dir_all(b.join("config")).unwrap();
+    fs::write(b.join("config/app.json"), "{\"host\": \"a\", \"port\": 82}").unwrap();
+    fs::write(b.join("README.txt"), "bonjour\n").unwrap();
+    fs::write(b.join("old.txt"), "old\n").unwrap();
+
+    let out = scratch("conflict");
+    let output = merge_dirs(&fixtures.join("base"), &fixtures.join("a"), &b, &out);
+    assert!(!output.status.success());
+    let log = String::from_utf8_lossy(&output.stderr);
+    assert!(log.contains("'config/app.json#/port'"), "{}", log);
+    assert!(!log.contains("README.txt"), "{}", log);
+
+    assert_eq!(
+        read(&out.join("config/app.json")),
+        "{\n  \"host\": \"a\",\n  \"port\": 81\n}"
+    );
+    assert_eq!(read(&out.join("README.txt")), "bonjour\n");
+    fs::remove_dir_all(&b).unwrap();
+    fs::remove_dir_all(&out).unwrap();
+}
+
+#[test]
+fn test_rejects_file_outputs() {
+    let fixtures = Path::new(FIXTURES);
+    let out = scratch("rejected");
+    for extra in [
+        &["--report", "report.txt"][..],
+        &["--stats"],
+        &["--canonical"],
+    ] {
+        let output = merge_dirs_with(
+            &fixtures.join("base"),
+            &fixtures.join("a"),
+            &fixtures.join("b"),
+            &out,
+            extra,
+        );
+        assert_eq!(output.status.code(), Some(2), "{:?}", output);
+        let log = String::from_utf8_lossy(&output.stderr);
+        assert!(log.contains("cannot be used with"), "{}", log);
+        assert!(!out.exists());
+    }
+}
+
+#[test]
+fn test_profile() {
+    let fixtures = Path::new(FIXTURES);
+    let out = scratch("profile");
+    let output = merge_dirs_with(
+        &fixtures.join("base"),
+        &fixtures.join("a"),
+        &fixtures.join("b"),
+        &out,
+        &["--profile"],
+    );
+    assert!(output.status.success(), "{:?}", output);
+    let log = String::from_utf8_lossy(&output.stderr);
+    for phase in ["read ", "merge ", "write ", "total "] {
+        assert!(log.contains(phase), "{}", log);
+    }
+    fs::remove_dir_all(&out).unwrap();
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::{Fixture, Run};
+
+fn fixture(name: &str) -> Fixture {
+    Fixture::with_inputs(
+        name,
+        [
+            r#"{"x": 1, "y": 1}"#,
+            r#"{"x": 2, "y": 1}"#,
+            r#"{"x": 1, "y": 3}"#,
+        ],
+    )
+}
+
+fn merge(fixture: &Fixture, output: &str, extra: &[&str]) -> Run {
+    let mut args = vec![
+        "-b",
+        "base.json",
+        "-a",
+        "a.json",
+        "-c",
+        "b.json",
+        "-o",
+        output,
+    ];
+    args.extend_from_slice(&["--report", "report.txt"]);
+    args.extend_from_slice(&["--resolutions-out", "resolutions.json"]);
+    args.extend_from_slice(extra);
+    fixture.run(&args)
+}
+
+#[test]
+fn test_failed_write_leaves_no_outputs() {
+    // The merged output goes to a directory that does not exist, so writing it
+    // fails after both reports have been written.
+    let fixture = fixture("outputs-failed");
+    let unwritable = "missing/merged.json";
+    let run = merge(&fixture, unwritable, &["--atomic-outputs"]);
+    assert!(!run.success(), "{:?}", run);
+    assert_eq!(fixture.listing(), ["a.json", "b.json", "base.json"]);
+
+    let run = merge(&fixture, unwritable, &[]);
+    assert!(!run.success(), "{:?}", run);
+    assert_eq!(
+        fixture.listing(),
+        [
+            "a.json",
+            "b.json",
+            "base.json",
+            "report.txt",
+            "resolutions.json"
+        ]
+    );
+}
+
+#[test]
+fn test_atomic_outputs_are_all_written() {
+    let fixture = fixture("outputs-written");
+    let run = merge(&fixture, "merged.json", &["--atomic-outputs"]);
+    assert!(run.success(), "{:?}", run);
+    assert_eq!(
+        fixture.listing(),
+        [
+            "a.json",
+            "b.json",
+            "base.json",
+            "merged.json",
+            "report.txt",
+            "resolutions.json"
+        ]
+    );
+    assert_eq!(fixture.read("merged.json"), "{\n  \"x\": 2,\n  \"y\": 3\n}");
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use common::Fixture;
+
+#[test]
+fn test_one_branch_changes_a_scalar() {
+    let fixture = Fixture::with_inputs("scalars-clean", ["1\n", "1\n", "\"two\"\n"]);
+    let run = fixture.merge(&[]);
+    assert!(run.success(), "{:?}", run);
+    assert_eq!(fixture.read("merged.json"), "\"two\"");
+}
+
+#[test]
+fn test_scalars_conflict_at_the_root() {
+    let fixture = Fixture::with_inputs("scalars-conflict", ["1", "2", "3"]);
+    let run = fixture.merge(&["--list-conflict-paths"]);
+    assert_eq!(run.code, Some(1));
+    assert_eq!(run.stdout, "\n");
+
+    let run = fixture.merge(&[]);
+    assert!(!run.success());
+    assert!(
+        run.stderr.contains(
+            "Conflict: the whole document modified in both branches with different contents"
+        ),
+        "{}",
+        run.stderr
+    );
+
+    let run = fixture.merge(&["--strategy", "theirs"]);
+    assert!(run.success(), "{:?}", run);
+    assert_eq!(fixture.read("merged.json"), "3");
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::{Fixture, Run};
+
+fn validate(fixture: &Fixture, extra: &[&str]) -> Run {
+    let mut args = vec![
+        "--validate-only",
+        "-b",
+        "base.json",
+        "-a",
+        "a.json",
+        "-c",
+        "b.json",
+    ];
+    args.extend_from_slice(extra);
+    fixture.run(&args)
+}
+
+#[test]
+fn test_reports_every_input() {
+    let fixture = Fixture::with_inputs("validate-inputs", ["{\"x\": 1}", "{\"x\": ", "[1, 2]"]);
+    let run = validate(&fixture, &[]);
+    assert!(!run.success(), "{:?}", run);
+    assert_eq!(
+        run.stdout,
+        "base.json: ok\na.json: EOF while parsing a value at line 1 column 6\nb.json: ok\n"
+    );
+    assert!(run.stderr.contains("1 of 3 files failed to parse"));
+    assert!(!fixture.exists("merged.json"));
+}
+
+#[test]
+fn test_checks_option_files() {
+    let fixture = Fixture::with_inputs("validate-options", ["{}", "{}", "{}"]);
+    fixture.write("allowed.txt", "/name\n/port\n");
+    let run = validate(&fixture, &["--allowed-keys", "allowed.txt"]);
+    assert!(run.success(), "{:?}", run);
+    assert_eq!(
+        run.stdout,
+        "base.json: ok\na.json: ok\nb.json: ok\nallowed.txt: ok\n"
+    );
+
+    let run = validate(&fixture, &["--rules", "missing.json"]);
+    assert!(!run.success(), "{:?}", run);
+    assert!(run
+        .stdout
+        .ends_with("b.json: ok\nmissing.json: No such file or directory (os error 2)\n"));
+}
+
+#[test]
+fn test_profile() {
+    let fixture = Fixture::with_inputs("validate-profile", ["{}", "{}", "{}"]);
+    let run = validate(&fixture, &["--profile"]);
+    assert!(run.success(), "{:?}", run);
+    assert!(run.stderr.contains("parse "), "{:?}", run);
+    assert!(run.stderr.contains("total "), "{:?}", run);
+}